@@ -11,6 +11,26 @@ use std::io::Write;
 use std::path::PathBuf;
 
 fn main() {
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+
+    // the QEMU build links for the emulated board, and has no LCD to drive, so no C
+    if env::var_os("CARGO_FEATURE_QEMU").is_some() {
+        File::create(out.join("memory.x"))
+            .unwrap()
+            .write_all(include_bytes!("memory-qemu.x"))
+            .unwrap();
+        println!("cargo:rustc-link-search={}", out.display());
+        println!("cargo:rerun-if-changed=build.rs");
+        println!("cargo:rerun-if-changed=memory-qemu.x");
+        return;
+    }
+
+    // the host build draws into a frame buffer rather than the LCD, and links no firmware
+    if env::var_os("CARGO_FEATURE_HOST").is_some() {
+        println!("cargo:rerun-if-changed=build.rs");
+        return;
+    }
+
     //gcc::compile_library("libold_c.a", &["src/old_c/main.c"]);
     gcc::Config::new()
         .file("src/old_c/ST7735.c")
@@ -24,7 +44,6 @@ fn main() {
         .compile("libold_c.a");
 
     // Put the linker script somewhere the linker can find it
-    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
     File::create(out.join("memory.x"))
         .unwrap()
         .write_all(include_bytes!("memory.x"))
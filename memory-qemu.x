@@ -0,0 +1,9 @@
+MEMORY
+{
+    /* QEMU's mps2-an385 machine, for the QEMU build, see src/qemu/main.rs: its code memory
+       at 0, where it loads the image and finds the vector table, and its data memory */
+    FLASH : ORIGIN = 0x00000000, LENGTH = 4M
+    RAM : ORIGIN = 0x20000000, LENGTH = 4M
+}
+
+_stack_start = ORIGIN(RAM) + LENGTH(RAM);
@@ -1,8 +1,21 @@
 MEMORY
 {
-    FLASH : ORIGIN = 0x08000000, LENGTH = 256K
+    /* the last 2K page of flash is reserved for stored data, and the 32K before it for the
+       data log, see src/flash.rs, and the word before that for the image's CRC, see
+       src/integrity.rs */
+    FLASH : ORIGIN = 0x08000000, LENGTH = 222K - 4
     CCRAM : ORIGIN = 0x10000000, LENGTH = 8K
     RAM : ORIGIN = 0x20000000, LENGTH = 40K
 }
 
+SECTIONS
+{
+    /* the sweep history, see src/history.rs; not initialized at reset */
+    .ccmram (NOLOAD) : ALIGN(4)
+    {
+        *(.ccmram .ccmram.*);
+    } > CCRAM
+}
+
 _stack_start = ORIGIN(RAM) + LENGTH(RAM);
+_firmware_crc = ORIGIN(FLASH) + LENGTH(FLASH);
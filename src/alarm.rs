@@ -0,0 +1,244 @@
+// stm32f3-oscilloscope - src/alarm.rs
+// threshold alarms on measurements, latching an LED, pulsing a pin, and showing a banner
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// An alarm gives a measurement (see measure.rs) a low limit, a high limit, or both, in
+// thousandths of the unit the measurement is shown in, voltages being those at the input (see
+// input.rs). Alarms are checked against each
+// measured record; the first to trip is latched, turning on LD10 (the south red LED), pulsing
+// board::ALARM_OUT_PIN high for PULSE_MS, sounding the buzzer, and showing a red banner across
+// the top of the trace area, until it is acknowledged. Until then, no other alarm trips.
+//
+// A high limit on the maximum or a low limit on the minimum is watched by the ADC's analog
+// watchdog instead, which sees every sample of the sweep, including those before the record
+// begins. If both are set, the watchdog can't tell which was crossed, and the maximum's alarm
+// is the one reported.
+
+use board::ALARM_OUT_PIN;
+use buzzer;
+use calibration;
+use capture;
+use console;
+use input;
+use delay::Deadline;
+use gpio::Mode;
+use led::{led_init, led_off, led_on};
+use led::Led::LD10;
+use measure::{self, Measurement, Results};
+use screen;
+use serial;
+use st7735::*;
+
+pub const ALARMS: usize = 4;
+
+const PULSE_MS: u32 = 100;
+const BANNER_HEIGHT: i16 = 12;
+
+#[derive(Clone, Copy)]
+struct Alarm {
+    measurement: Measurement,
+    low: Option<i64>,  // trip below this
+    high: Option<i64>, // trip above this
+}
+
+static mut ALARM_LIST: [Option<Alarm>; ALARMS] = [None; ALARMS];
+// the alarm which tripped, and whether it went above its high limit, until acknowledged
+static mut LATCHED: Option<(Measurement, bool)> = None;
+static mut PULSE_END: Option<Deadline> = None;
+
+/// Sets up the LED and output pin.
+pub fn setup() {
+    led_init(LD10);
+    ALARM_OUT_PIN.enable_port_clock();
+    ALARM_OUT_PIN.set_low();
+    ALARM_OUT_PIN.configure(Mode::Output);
+}
+
+/// Sets `measurement`'s limits, replacing any it had, or with neither, removes its alarm.
+/// Returns false if there are already ALARMS alarms on other measurements.
+pub fn set(measurement: Measurement, low: Option<i64>, high: Option<i64>) -> bool {
+    unsafe {
+        for slot in ALARM_LIST.iter_mut() {
+            if slot.map_or(false, |a| a.measurement == measurement) {
+                *slot = None;
+            }
+        }
+        let mut added = low.is_none() && high.is_none();
+        if !added {
+            if let Some(slot) = ALARM_LIST.iter_mut().find(|slot| slot.is_none()) {
+                *slot = Some(Alarm { measurement: measurement, low: low, high: high });
+                added = true;
+            }
+        }
+        update_watchdog();
+        added
+    }
+}
+
+/// Removes all the alarms.
+pub fn clear_all() {
+    unsafe { ALARM_LIST = [None; ALARMS]; }
+    update_watchdog();
+}
+
+// the limits watched by the analog watchdog
+fn watchdog_limits() -> (Option<i64>, Option<i64>) {
+    let (mut low, mut high) = (None, None);
+    for alarm in unsafe { ALARM_LIST.iter().filter_map(|a| *a) } {
+        match alarm.measurement {
+            Measurement::Min => low = alarm.low,
+            Measurement::Max => high = alarm.high,
+            _ => {}
+        }
+    }
+    (low, high)
+}
+
+fn update_watchdog() {
+    let raw = |millivolts: i64| {
+        calibration::raw_conversion(input::adc_microvolts(millivolts * 1000))
+    };
+    let (low, high) = watchdog_limits();
+    capture::set_watchdog(low.map(&raw), high.map(&raw));
+}
+
+/// Returns whether any alarms are set, so records need measuring.
+pub fn enabled() -> bool {
+    unsafe { ALARM_LIST.iter().any(|a| a.is_some()) }
+}
+
+/// Checks the alarms against a measured record, and the analog watchdog, latching the first
+/// to trip.
+pub fn check(results: Option<&Results>) {
+    if latched() {
+        return;
+    }
+    if capture::watchdog_tripped() {
+        match watchdog_limits() {
+            (_, Some(_)) => return trip(Measurement::Max, true),
+            (Some(_), None) => return trip(Measurement::Min, false),
+            (None, None) => {}
+        }
+    }
+    let results = match results {
+        Some(results) => results,
+        None => return,
+    };
+    for alarm in unsafe { ALARM_LIST.iter().filter_map(|a| *a) } {
+        let (low, high) = match alarm.measurement {
+            Measurement::Max => (alarm.low, None), // the high limit is the watchdog's
+            Measurement::Min => (None, alarm.high), // and the low limit
+            _ => (alarm.low, alarm.high),
+        };
+        if let Some(value) = measure::value(alarm.measurement, results) {
+            if high.map_or(false, |high| value > high) {
+                return trip(alarm.measurement, true);
+            }
+            if low.map_or(false, |low| value < low) {
+                return trip(alarm.measurement, false);
+            }
+        }
+    }
+}
+
+fn trip(measurement: Measurement, above: bool) {
+    unsafe {
+        LATCHED = Some((measurement, above));
+        PULSE_END = Some(Deadline::after_ms(PULSE_MS));
+    }
+    led_on(LD10);
+    ALARM_OUT_PIN.set_high();
+    buzzer::alarm();
+    let mut message = [0u8; 32];
+    let len = banner_text(measurement, above, &mut message);
+    console::log(&message[..len]);
+}
+
+/// Ends the output pulse when it is due. Called from the main loop.
+pub fn poll() {
+    unsafe {
+        if let Some(end) = PULSE_END {
+            if end.passed() {
+                PULSE_END = None;
+                ALARM_OUT_PIN.set_low();
+            }
+        }
+    }
+}
+
+/// Returns whether an alarm has tripped, and not been acknowledged.
+pub fn latched() -> bool {
+    unsafe { LATCHED.is_some() }
+}
+
+/// Acknowledges the latched alarm, turning off the LED. The banner is left for the caller to
+/// draw over.
+pub fn acknowledge() {
+    unsafe { LATCHED = None; }
+    led_off(LD10);
+}
+
+// the banner's text, e.g. "ALARM Vpp high", returning its length
+fn banner_text(measurement: Measurement, above: bool, buf: &mut [u8; 32]) -> usize {
+    let mut len = 0;
+    let direction: &[u8] = if above { b" high" } else { b" low" };
+    let parts: [&[u8]; 3] = [b"ALARM ", measurement.label(), direction];
+    for part in &parts {
+        buf[len..len + part.len()].copy_from_slice(part);
+        len += part.len();
+    }
+    len
+}
+
+/// Draws the latched alarm's banner across the top of the trace area.
+pub fn draw_banner() {
+    if let Some((measurement, above)) = unsafe { LATCHED } {
+        let mut text = [0u8; 32];
+        let len = banner_text(measurement, above, &mut text);
+        st7735_fill_rect(0, 0, screen::width(), BANNER_HEIGHT, St7735Color::Red as u16);
+        st7735_print(&text[..len], 0, 0, St7735Color::White, St7735Color::Red);
+    }
+}
+
+/// Writes the alarms, and the latched alarm if there is one, on the serial port.
+pub fn report() {
+    for alarm in unsafe { ALARM_LIST.iter().filter_map(|a| *a) } {
+        serial::write(alarm.measurement.label());
+        if let Some(low) = alarm.low {
+            serial::write(b" below ");
+            serial::write_signed_fixed(low, 3);
+        }
+        if let Some(high) = alarm.high {
+            serial::write(b" above ");
+            serial::write_signed_fixed(high, 3);
+        }
+        serial::write(b"\r\n");
+    }
+    if let Some((measurement, above)) = unsafe { LATCHED } {
+        let mut text = [0u8; 32];
+        let len = banner_text(measurement, above, &mut text);
+        serial::write(&text[..len]);
+        serial::write(b", not acknowledged\r\n");
+    }
+}
@@ -0,0 +1,93 @@
+// stm32f3-oscilloscope - src/autorange.rs
+// vertical auto-ranging: stepping the gain to fit the signal to the screen
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// After each record, the lowest and highest samples of the displayed screen are compared with
+// the range of voltages shown. The gain steps down as soon as the signal fills more than 90%
+// of the screen height, or goes off the top or bottom, and up when it fills less than 60%.
+// Each gain is no more than 1.5 times the one before, so a step up leaves the signal filling
+// less than 90%, and a step down leaves it filling at least 60%, and a signal near either
+// threshold can't keep the gain hunting back and forth. The screen is centered on the signal
+// when the gain changes, and again when the signal gets within 5% of the top or bottom.
+//
+// The gain is only a magnification of the display: the input doesn't go through one of the
+// STM32F303's op-amps, so there is no PGA to set. With an external attenuator (frontend.rs),
+// the steps carry on through its ranges, so it switches range when the gain runs off either
+// end.
+
+use calibration;
+use frontend;
+use screen;
+
+// the signal's share of the screen height to fit within, in percent
+const LOW_PERCENT: u64 = 60;
+const HIGH_PERCENT: u64 = 90;
+// how close the signal may get to the top or bottom before recentering, in percent
+const MARGIN_PERCENT: u64 = 5;
+
+static mut ENABLED: bool = false;
+
+pub fn set_enabled(enabled: bool) {
+    unsafe { ENABLED = enabled; }
+}
+
+pub fn enabled() -> bool {
+    unsafe { ENABLED }
+}
+
+/// Adjusts the vertical gain and center to fit `screen`, the samples of the displayed screen.
+/// Returns whether they changed, so the screen needs repainting.
+pub fn adjust(screen: &[u16]) -> bool {
+    if screen.is_empty() {
+        return false;
+    }
+    let mut min = 4095;
+    let mut max = 0;
+    for &sample in screen {
+        if sample < min { min = sample; }
+        if sample > max { max = sample; }
+    }
+    let low = calibration::microvolts(min) as u64;
+    let high = calibration::microvolts(max) as u64;
+    let (bottom, top) = screen::vertical_range();
+    let (bottom, top) = (bottom as u64, top as u64);
+    let span = top - bottom;
+    let percent = (high - low) * 100 / span;
+    let position = frontend::position();
+    let position = if percent > HIGH_PERCENT || low < bottom || high > top {
+        if position > 0 { position - 1 } else { position }
+    } else if percent < LOW_PERCENT && position + 1 < frontend::positions() {
+        position + 1
+    } else {
+        position
+    };
+    let margin = span * MARGIN_PERCENT / 100;
+    if position != frontend::position() || low < bottom + margin || high + margin > top {
+        // the center may be held back from the signal's by the ends of the ADC's range
+        let before = (frontend::position(), screen::center_microvolts());
+        frontend::set_position(position, ((low + high) / 2) as u32);
+        return (frontend::position(), screen::center_microvolts()) != before;
+    }
+    false
+}
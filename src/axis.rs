@@ -0,0 +1,86 @@
+// stm32f3-oscilloscope - src/axis.rs
+// the graticule's axes: where a sample is drawn, and the labels for each division
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// The trace area is scaled in volts at the input, not ADC steps, so a sample is placed by its
+// calibrated voltage, and the labels are worked out again whenever the timebase, trigger delay,
+// or vertical scale change (main.rs keeps track of which they were last set for). These are
+// shared with the host build's renderer (see src/host/render.rs), so its screens are labelled
+// just as the board's are.
+
+use calibration;
+use input;
+use measure;
+use screen;
+use trace;
+
+/// Converts a raw 12-bit conversion to a screen y coordinate, which may be off-screen.
+pub fn sample_to_y(raw_conversion: u16) -> i16 {
+    // the input voltage, corrected by the offset and gain calibration, so the screen is
+    // scaled in volts rather than ADC steps
+    screen::microvolts_to_y(calibration::microvolts(raw_conversion))
+}
+
+/// Labels each division of the time axis with its time from the trigger, for `sample_rate`
+/// with `factor` pixels per sample, and the displayed window `delay` samples after the trigger.
+pub fn set_time_labels(sample_rate: u32, factor: usize, delay: usize) {
+    let delay_ns = delay as u64 * 1_000_000_000 / sample_rate as u64;
+    let division_ns = 32_000_000_000 / (sample_rate as u64 * factor as u64);
+    for division in 0..screen::MAX_WIDTH / 32 {
+        let mut text = [0u8; 8];
+        let len = format_axis_time(delay_ns + division as u64 * division_ns, &mut text);
+        trace::set_time_label(division, &text[..len]);
+    }
+}
+
+/// Labels each row of graticule dots with its voltage, in hundredths of a volt.
+pub fn set_voltage_labels() {
+    let mut row = 0;
+    while 32 * (row as i16 + 1) < screen::height() {
+        // at the input, to three figures, e.g. "1.65V", "-12.5V"
+        let microvolts = input::microvolts(screen::microvolts_at(32 * (row as i16 + 1)) as i64);
+        let centivolts = (microvolts.abs() + 5_000) / 10_000;
+        let (value, decimals) = match centivolts {
+            0...999 => (centivolts, 2),
+            1_000...9_999 => ((centivolts + 5) / 10, 1),
+            _ => ((centivolts + 50) / 100, 0),
+        };
+        let mut text = [b' '; 7];
+        measure::format_signed_fixed(if microvolts < 0 { -value } else { value }, decimals,
+                                     &mut text[..6]);
+        text[6] = b'V';
+        let start = text.iter().position(|&c| c != b' ').unwrap_or(0);
+        trace::set_voltage_label(row, &text[start..]);
+        row += 1;
+    }
+}
+
+/// Formats a time axis label of `ns` nanoseconds compactly, to three significant figures with
+/// any trailing zeros dropped, e.g. "2ms" or "6.4ms", returning the length.
+pub fn format_axis_time(ns: u64, text: &mut [u8; 8]) -> usize {
+    if ns == 0 {
+        text[0] = b'0';
+        return 1;
+    }
+    measure::format_si(ns as i64, b"s", text)
+}
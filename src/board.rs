@@ -0,0 +1,303 @@
+// stm32f3-oscilloscope - src/board.rs
+// the board wiring: which pins the LEDs, buttons, analog I/O, and LCD are connected to
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Each supported board has its own set of tables here, selected by a cargo feature; without one,
+// the board is the STM32F3 Discovery. To port to another STM32F303 board, add a feature to
+// Cargo.toml and a set of tables for it, or just edit the Discovery tables; the other modules
+// use only these tables for their pins.
+//
+// The analog pins are fixed by the chip: a signal input must be on an ADC1 channel, and the
+// signal generator outputs are the DAC1 pins, PA4 and PA5. With hardware SPI, the LCD's SCK and
+// MOSI must be pins that SPI2 can use, with LCD_SPI_AF their alternate function. With the
+// software-spi feature, SPI2 is left free, and any pins will do. The serial port's pins must
+// be ones its USART can use, with SERIAL_AF their alternate function, the probe compensation
+// output must be on TIM16's channel 1, the buzzer on TIM4's channel 4, and the gate input on
+// TIM15's channel 1. The front end's range select outputs can be any GPIO pins, and the DC
+// inputs any ADC1 channels' pins. The SPI flash is driven in software, so its pins can be any
+// GPIO pins.
+
+use gpio::{Pin, Port};
+
+pub struct LcdPins {
+    pub cs: Pin,   // CSE/CS
+    pub dc: Pin,   // A0/RS/DC
+    pub rst: Pin,  // RST
+    pub sck: Pin,  // SCK/SCL
+    pub mosi: Pin, // SDA/MOSI
+}
+
+/// An ADC1 input, and the pin it is on.
+pub struct AnalogInput {
+    pub pin: Pin,
+    pub channel: u8,
+    pub label: &'static [u8], // the pin's name
+}
+
+/// A USART that can be the serial port.
+#[allow(unused)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Usart {
+    Usart1, // on APB2
+    Usart2, // on APB1
+}
+
+/// A TIM15 channel 1 input, which can gate sampling.
+pub struct GateInput {
+    pub pin: Pin,
+    pub af: u8, // the alternate function selecting TIM15_CH1
+}
+
+/// The pins of an external SPI flash chip (see spiflash.rs).
+pub struct SpiFlashPins {
+    pub cs: Pin,
+    pub sck: Pin,
+    pub mosi: Pin, // to the chip's DI
+    pub miso: Pin, // from its DO
+}
+
+pub struct SerialPort {
+    pub usart: Usart,
+    pub tx: Pin,
+    pub rx: Pin,
+}
+
+/// The external clock, from the ST-Link's MCO output on both boards.
+pub const HSE_HZ: u32 = 8_000_000;
+
+/// The alternate function selecting SPI2 on the LCD's SCK and MOSI pins.
+#[allow(unused)]
+pub const LCD_SPI_AF: u8 = 5;
+
+/// The fastest SPI clock to try for the LCD. Lower this if long wires make the display
+/// unreliable at the speed st7735_probe_spi_clock() picks.
+pub const LCD_SPI_MAX_HZ: u32 = 18_000_000;
+
+/// The alternate function selecting USART1 or USART2 on the serial port's pins.
+pub const SERIAL_AF: u8 = 7;
+
+/// The signal generator's outputs, DAC1 channels 1 and 2.
+pub const DAC_PINS: [Pin; 2] = [
+    Pin { port: Port::A, number: 4 },
+    Pin { port: Port::A, number: 5 },
+];
+
+/// The probe compensation output, TIM16 channel 1. PB4 is free on the Discovery, and is D5 on
+/// the Nucleo's Arduino header.
+pub const PROBE_COMP_PIN: Pin = Pin { port: Port::B, number: 4 };
+
+/// The alternate function selecting TIM16_CH1 on the probe compensation pin.
+pub const PROBE_COMP_AF: u8 = 1;
+
+/// The probe compensation output's menu value, naming its pin.
+pub const PROBE_COMP_LABEL: &'static [u8] = b"1kHz PB4";
+
+/// The trigger output, any GPIO pin. PB5 is free on the Discovery, and is D4 on the Nucleo.
+pub const TRIGGER_OUT_PIN: Pin = Pin { port: Port::B, number: 5 };
+
+/// The alarm output, any GPIO pin. PB8 is free on the Discovery, and is D15 on the Nucleo.
+pub const ALARM_OUT_PIN: Pin = Pin { port: Port::B, number: 8 };
+
+/// The piezo buzzer, TIM4 channel 4. PB9 is free on the Discovery, and is D14 on the Nucleo.
+pub const BUZZER_PIN: Pin = Pin { port: Port::B, number: 9 };
+
+/// The alternate function selecting TIM4_CH4 on the buzzer pin.
+pub const BUZZER_AF: u8 = 2;
+
+/// The PWM analyzer's timer input, TIM1 channel 1. PA8 is free on the Discovery, and is D7 on
+/// the Nucleo.
+pub const PWM_INPUT_PIN: Pin = Pin { port: Port::A, number: 8 };
+
+/// The alternate function selecting TIM1_CH1 on the PWM input pin.
+pub const PWM_INPUT_AF: u8 = 6;
+
+/// The I2C EEPROM's clock line, any GPIO pin, with the i2c-eeprom feature (see eeprom.rs).
+/// PA9 is free on the Discovery, and is D8 on the Nucleo.
+#[allow(unused)]
+pub const EEPROM_SCL_PIN: Pin = Pin { port: Port::A, number: 9 };
+
+/// The I2C EEPROM's data line, any GPIO pin. PA10 is free on the Discovery, and is D2 on the
+/// Nucleo.
+#[allow(unused)]
+pub const EEPROM_SDA_PIN: Pin = Pin { port: Port::A, number: 10 };
+
+// ======== STM32F3 Discovery ========
+
+#[cfg(not(feature = "nucleo-f303re"))]
+pub const BOARD_NAME: &'static [u8] = b"STM32F3 Discovery";
+
+#[cfg(not(feature = "nucleo-f303re"))]
+pub const LCD_PINS: LcdPins = LcdPins {
+    cs: Pin { port: Port::B, number: 10 },
+    dc: Pin { port: Port::B, number: 12 },
+    rst: Pin { port: Port::B, number: 14 },
+    sck: Pin { port: Port::B, number: 13 },  // SPI2_SCK
+    mosi: Pin { port: Port::B, number: 15 }, // SPI2_MOSI
+};
+
+/// The status LEDs, in the order of led::Led: LD3 through LD10, the ring of LEDs around the
+/// Discovery's gyroscope.
+#[cfg(not(feature = "nucleo-f303re"))]
+pub const LED_PINS: [Option<Pin>; 8] = [
+    Some(Pin { port: Port::E, number: 9 }),  // LD3, north, red
+    Some(Pin { port: Port::E, number: 8 }),  // LD4, northwest, blue
+    Some(Pin { port: Port::E, number: 10 }), // LD5, northeast, orange
+    Some(Pin { port: Port::E, number: 15 }), // LD6, west, green
+    Some(Pin { port: Port::E, number: 11 }), // LD7, east, green
+    Some(Pin { port: Port::E, number: 14 }), // LD8, southwest, orange
+    Some(Pin { port: Port::E, number: 12 }), // LD9, southeast, blue
+    Some(Pin { port: Port::E, number: 13 }), // LD10, south, red
+];
+
+/// The pushbuttons on the LCD breakout board, 1 through 4. They short to ground, and the pins
+/// are pulled up.
+#[cfg(not(feature = "nucleo-f303re"))]
+pub const BUTTON_PINS: [Pin; 4] = [
+    Pin { port: Port::D, number: 12 },
+    Pin { port: Port::D, number: 13 },
+    Pin { port: Port::D, number: 14 },
+    Pin { port: Port::D, number: 15 },
+];
+
+/// The sampled input.
+#[cfg(not(feature = "nucleo-f303re"))]
+pub const SIGNAL_INPUT: AnalogInput = AnalogInput {
+    pin: Pin { port: Port::C, number: 1 },
+    channel: 7,
+    label: b"PC1",
+};
+
+/// The pins the auxiliary DC input can be on, the default first. PA0 is the user button, so
+/// the others are PC2, PC3, and PA1, all free.
+#[cfg(not(feature = "nucleo-f303re"))]
+pub const DC_INPUTS: [AnalogInput; 4] = [
+    AnalogInput { pin: Pin { port: Port::C, number: 0 }, channel: 6, label: b"PC0" },
+    AnalogInput { pin: Pin { port: Port::C, number: 2 }, channel: 8, label: b"PC2" },
+    AnalogInput { pin: Pin { port: Port::C, number: 3 }, channel: 9, label: b"PC3" },
+    AnalogInput { pin: Pin { port: Port::A, number: 1 }, channel: 2, label: b"PA1" },
+];
+
+/// The serial port, for the command shell. The Discovery's ST-Link has no virtual COM port, so
+/// connect a 3.3V USB-serial adapter here.
+#[cfg(not(feature = "nucleo-f303re"))]
+pub const SERIAL: SerialPort = SerialPort {
+    usart: Usart::Usart1,
+    tx: Pin { port: Port::C, number: 4 },
+    rx: Pin { port: Port::C, number: 5 },
+};
+
+/// The gate input, for sampling only while it is at a chosen level.
+#[cfg(not(feature = "nucleo-f303re"))]
+pub const GATE_INPUT: Option<GateInput> = Some(GateInput {
+    pin: Pin { port: Port::F, number: 9 },
+    af: 3,
+});
+
+/// An external input attenuator's range select outputs, the code's bit 0 first (see
+/// frontend.rs).
+#[cfg(not(feature = "nucleo-f303re"))]
+pub const FRONT_END_PINS: [Pin; 2] = [
+    Pin { port: Port::D, number: 8 },
+    Pin { port: Port::D, number: 9 },
+];
+
+/// The SPI flash chip's pins, PD0 to PD3, all free.
+#[cfg(not(feature = "nucleo-f303re"))]
+pub const SPI_FLASH_PINS: SpiFlashPins = SpiFlashPins {
+    cs: Pin { port: Port::D, number: 0 },
+    sck: Pin { port: Port::D, number: 1 },
+    mosi: Pin { port: Port::D, number: 2 },
+    miso: Pin { port: Port::D, number: 3 },
+};
+
+// ======== Nucleo-F303RE ========
+
+// The Nucleo's 64-pin package has no port D or E pins to speak of, so the buttons move to port
+// C. Its one user LED, LD2, shares PA5 with DAC channel 2, so there are no status LEDs. The
+// analog inputs are the Arduino header's A4 (PC1) and A5 (PC0), the same pins as on the
+// Discovery. The serial port is the ST-Link's virtual COM port, on the board's USB connector.
+// TIM15's channel 1 pins are taken by the serial port (PA2) and the LCD (PB14), or missing
+// (PF9), so there is no gate input.
+
+#[cfg(feature = "nucleo-f303re")]
+pub const BOARD_NAME: &'static [u8] = b"Nucleo-F303RE";
+
+#[cfg(feature = "nucleo-f303re")]
+pub const LCD_PINS: LcdPins = LcdPins {
+    cs: Pin { port: Port::B, number: 10 },   // D6
+    dc: Pin { port: Port::B, number: 12 },   // CN10-16
+    rst: Pin { port: Port::B, number: 14 },  // CN10-28
+    sck: Pin { port: Port::B, number: 13 },  // CN10-30, SPI2_SCK
+    mosi: Pin { port: Port::B, number: 15 }, // CN10-26, SPI2_MOSI
+};
+
+#[cfg(feature = "nucleo-f303re")]
+pub const LED_PINS: [Option<Pin>; 8] = [None; 8];
+
+#[cfg(feature = "nucleo-f303re")]
+pub const BUTTON_PINS: [Pin; 4] = [
+    Pin { port: Port::C, number: 6 }, // CN10-4
+    Pin { port: Port::C, number: 7 }, // CN10-19
+    Pin { port: Port::C, number: 8 }, // CN10-2
+    Pin { port: Port::C, number: 9 }, // CN10-1
+];
+
+#[cfg(feature = "nucleo-f303re")]
+pub const SIGNAL_INPUT: AnalogInput = AnalogInput {
+    pin: Pin { port: Port::C, number: 1 }, // A4
+    channel: 7,
+    label: b"PC1",
+};
+
+#[cfg(feature = "nucleo-f303re")]
+pub const DC_INPUTS: [AnalogInput; 4] = [
+    AnalogInput { pin: Pin { port: Port::C, number: 0 }, channel: 6, label: b"PC0" }, // A5
+    AnalogInput { pin: Pin { port: Port::A, number: 0 }, channel: 1, label: b"PA0" }, // A0
+    AnalogInput { pin: Pin { port: Port::A, number: 1 }, channel: 2, label: b"PA1" }, // A1
+    AnalogInput { pin: Pin { port: Port::C, number: 2 }, channel: 8, label: b"PC2" },
+];
+
+#[cfg(feature = "nucleo-f303re")]
+pub const SERIAL: SerialPort = SerialPort {
+    usart: Usart::Usart2,
+    tx: Pin { port: Port::A, number: 2 },
+    rx: Pin { port: Port::A, number: 3 },
+};
+
+#[cfg(feature = "nucleo-f303re")]
+pub const GATE_INPUT: Option<GateInput> = None;
+
+#[cfg(feature = "nucleo-f303re")]
+pub const FRONT_END_PINS: [Pin; 2] = [
+    Pin { port: Port::B, number: 1 }, // CN10-24
+    Pin { port: Port::B, number: 2 }, // CN10-22
+];
+
+#[cfg(feature = "nucleo-f303re")]
+pub const SPI_FLASH_PINS: SpiFlashPins = SpiFlashPins {
+    cs: Pin { port: Port::B, number: 6 },    // D10
+    sck: Pin { port: Port::A, number: 12 },  // CN10-12
+    mosi: Pin { port: Port::A, number: 11 }, // CN10-14
+    miso: Pin { port: Port::B, number: 11 }, // CN10-18
+};
@@ -0,0 +1,92 @@
+// stm32f3-oscilloscope - src/bootloader.rs
+// jumping to the STM32 system memory bootloader, for updates without an ST-Link
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The bootloader in system memory (see ST's AN2606) accepts new firmware over USART1 (PA9 and
+// PA10), USART2 (PA2 and PA3, the Nucleo's virtual COM port), or USB DFU (the Discovery's user
+// USB connector). It expects to be entered from reset, so before jumping to it, everything is
+// put back as reset left it: interrupts off and cleared, SysTick stopped, the peripherals reset
+// through the RCC, and the clock back on the HSI. Then system memory is mapped at address 0,
+// and the bootloader is started with its own stack pointer and reset vector, the first two
+// words of system memory.
+
+use core::ptr;
+
+use cortex_m;
+use cortex_m::peripheral::{NVIC, SYST};
+use stm32f30x::{RCC, SYSCFG};
+
+use sysclk;
+
+const SYSTEM_MEMORY: u32 = 0x1fff_d800;
+
+// reset values of the clock enable registers: SRAM and FLITF clocks only
+const AHBENR_RESET: u32 = 0x0000_0014;
+
+/// Jumps to the system memory bootloader. Doesn't return.
+pub fn enter() -> ! {
+    unsafe {
+        cortex_m::interrupt::disable();
+
+        // stop SysTick, and disable and clear every interrupt
+        let syst = &*SYST.get();
+        syst.disable_interrupt();
+        syst.disable_counter();
+        let nvic = &*NVIC.get();
+        for i in 0..8 {
+            nvic.icer[i].write(0xffff_ffff);
+            nvic.icpr[i].write(0xffff_ffff);
+        }
+
+        sysclk::reset_sys_clock();
+
+        // reset every peripheral, and turn their clocks off, except SYSCFG's, for the remap
+        let rcc = &*RCC.get();
+        rcc.ahbrstr.write(|w| w.bits(0xffff_ffff));
+        rcc.ahbrstr.write(|w| w.bits(0));
+        rcc.apb1rstr.write(|w| w.bits(0xffff_ffff));
+        rcc.apb1rstr.write(|w| w.bits(0));
+        rcc.apb2rstr.write(|w| w.bits(0xffff_ffff));
+        rcc.apb2rstr.write(|w| w.bits(0));
+        rcc.ahbenr.write(|w| w.bits(AHBENR_RESET));
+        rcc.apb1enr.write(|w| w.bits(0));
+        rcc.apb2enr.write(|w| w.bits(0));
+        rcc.apb2enr.modify(|_, w| w.syscfgen().enabled());
+
+        // map system memory at address 0
+        (*SYSCFG.get()).cfgr1.modify(|r, w| w.bits(r.bits() & !0b11 | 0b01));
+
+        let stack_pointer = ptr::read_volatile(SYSTEM_MEMORY as *const u32);
+        let reset_vector = ptr::read_volatile((SYSTEM_MEMORY + 4) as *const u32);
+        // nothing is left to interrupt, and the bootloader expects interrupts enabled
+        cortex_m::interrupt::enable();
+        asm!("msr MSP, $0
+              bx $1"
+             :
+             : "r"(stack_pointer), "r"(reset_vector)
+             :
+             : "volatile");
+    }
+    loop {}
+}
@@ -0,0 +1,169 @@
+// stm32f3-oscilloscope - src/buzzer.rs
+// piezo buzzer: key clicks and alarm tones, from TIM4 PWM
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// A passive piezo buzzer on board::BUZZER_PIN, TIM4 channel 4, is driven with a square wave
+// at the tone's frequency. A sound is a short list of tones and silences, stepped through by
+// poll() from the main loop, so playing one never blocks. A new sound replaces any still
+// playing.
+
+use cortex_m;
+use stm32f30x::{RCC, TIM4};
+
+use board::{BUZZER_AF, BUZZER_PIN};
+use clocks;
+use delay::Deadline;
+use gpio::Mode;
+use timer;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum BuzzerMode {
+    Off,
+    Alarms,          // alarm tones only
+    ClicksAndAlarms, // and a click for each button press
+}
+
+impl BuzzerMode {
+    pub fn next(self) -> BuzzerMode {
+        match self {
+            BuzzerMode::Off => BuzzerMode::Alarms,
+            BuzzerMode::Alarms => BuzzerMode::ClicksAndAlarms,
+            BuzzerMode::ClicksAndAlarms => BuzzerMode::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static [u8] {
+        match self {
+            BuzzerMode::Off => b"off",
+            BuzzerMode::Alarms => b"alarms",
+            BuzzerMode::ClicksAndAlarms => b"keys+alarms",
+        }
+    }
+}
+
+// a tone's frequency in Hz, or 0 for silence, and its length in milliseconds
+type Tone = (u32, u32);
+
+const CLICK: &'static [Tone] = &[(4000, 4)];
+// three falling pairs, unlike anything else the scope might be near
+const ALARM: &'static [Tone] = &[
+    (2500, 120), (1800, 120), (0, 80),
+    (2500, 120), (1800, 120), (0, 80),
+    (2500, 120), (1800, 120),
+];
+
+static mut MODE: BuzzerMode = BuzzerMode::Off;
+static mut SOUND: &'static [Tone] = &[];
+static mut STEP: usize = 0;
+static mut STEP_END: Option<Deadline> = None;
+
+/// Sets up TIM4 and the buzzer pin, silent.
+pub fn setup() {
+    BUZZER_PIN.enable_port_clock();
+    BUZZER_PIN.set_alternate_function(BUZZER_AF);
+    BUZZER_PIN.configure(Mode::Alternate);
+    cortex_m::interrupt::free(|cs| {
+        let rcc = RCC.borrow(cs);
+        let tim4 = TIM4.borrow(cs);
+
+        rcc.apb1enr.modify(|_, w| w.tim4en().enabled());
+        tim4.cr1.write(|w| unsafe { w.arpe().bits(1) }); // ARR register is buffered
+        // channel 4 output, PWM mode 1, with CCR4 preloaded
+        tim4.ccmr2_output.write(|w| unsafe { w.bits(0b110 << 12 | 1 << 11) }); // OC4M, OC4PE
+        tim4.ccr4.write(|w| unsafe { w.bits(0) }); // silent
+        tim4.ccer.write(|w| unsafe { w.bits(1 << 12) }); // CC4E, active high
+        tim4.cr1.modify(|_, w| unsafe { w.cen().bits(1) });
+    });
+}
+
+pub fn set_mode(mode: BuzzerMode) {
+    unsafe { MODE = mode; }
+    if mode == BuzzerMode::Off {
+        play(&[]);
+    }
+}
+
+pub fn mode() -> BuzzerMode {
+    unsafe { MODE }
+}
+
+/// Clicks, for a button press, if clicks are on.
+pub fn click() {
+    if mode() == BuzzerMode::ClicksAndAlarms {
+        play(CLICK);
+    }
+}
+
+/// Sounds the alarm, unless the buzzer is off.
+pub fn alarm() {
+    if mode() != BuzzerMode::Off {
+        play(ALARM);
+    }
+}
+
+fn play(sound: &'static [Tone]) {
+    unsafe {
+        SOUND = sound;
+        STEP = 0;
+    }
+    start_step();
+}
+
+// start the current step of the sound, or go silent at the end
+fn start_step() {
+    let (hz, ms) = unsafe {
+        match SOUND.get(STEP) {
+            Some(&tone) => tone,
+            None => (0, 0),
+        }
+    };
+    set_tone(hz);
+    unsafe { STEP_END = if ms > 0 { Some(Deadline::after_ms(ms)) } else { None }; }
+}
+
+// set the PWM for a square wave at `hz`, or a steady low for 0
+fn set_tone(hz: u32) {
+    let rate = timer::solve(clocks::apb1_timer_hz(), if hz > 0 { hz } else { 1000 },
+                            timer::MAX_ARR_16);
+    let ccr = if hz > 0 { (rate.arr + 1) / 2 } else { 0 };
+    cortex_m::interrupt::free(|cs| {
+        let tim4 = TIM4.borrow(cs);
+        tim4.arr.write(|w| unsafe { w.bits(rate.arr) });
+        tim4.psc.write(|w| unsafe { w.psc().bits(rate.psc) });
+        tim4.ccr4.write(|w| unsafe { w.bits(ccr) });
+        tim4.egr.write(|w| unsafe { w.ug().bits(1) }); // immediately update registers
+    });
+}
+
+/// Moves on to the sound's next step when it is due. Called from the main loop.
+pub fn poll() {
+    unsafe {
+        if let Some(end) = STEP_END {
+            if end.passed() {
+                STEP += 1;
+                start_step();
+            }
+        }
+    }
+}
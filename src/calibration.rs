@@ -0,0 +1,267 @@
+// stm32f3-oscilloscope - src/calibration.rs
+// input offset calibration, stored in flash
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// The offset is measured with the input grounded, averaging the samples of CALIBRATION_SWEEPS
+// sweeps, and is subtracted from each raw conversion before it is converted to a voltage or
+// screen position. The gain is then measured the same way with a known reference voltage
+// applied, the Discovery board's 3V supply pin, giving the voltage per ADC step in place of the
+// nominal 3.3V / 4096. There is one offset and scale per vertical gain setting, though for now
+// there is only the one gain.
+//
+// The ADC's full scale is its reference, VDDA, nominally NOMINAL_REFERENCE_MV. It can be set to
+// what a board actually runs at, e.g. 3.0V, or follow the supply as measured through VREFINT
+// (see capture::auxiliary()), ignoring changes of less than TRACKING_STEP_MV so the screen isn't
+// relabelled for every millivolt of noise. The scales are kept as if for the nominal reference,
+// and are scaled by the reference in use, so a gain calibration made at one supply voltage still
+// holds at another.
+//
+// The calibration wizard (wizard.rs) makes the measurements, which are only stored, by save(),
+// once all have been made and found plausible; revert() abandons them.
+//
+// The stored record is: MAGIC, the offsets, the scales (low half-word first), then a checksum,
+// chosen so that the sum of all the half-words is 0xffff. A missing or corrupt record leaves the
+// nominal calibration in place.
+
+use flash;
+use flash::FlashError;
+
+/// The number of vertical gain settings, each with its own calibration.
+pub const GAINS: usize = 1;
+
+/// The number of sweeps averaged when measuring the offset or gain.
+pub const CALIBRATION_SWEEPS: u32 = 32;
+
+/// The reference voltage to be applied for gain calibration, in microvolts.
+pub const REFERENCE_MICROVOLTS: u32 = 3_000_000;
+
+/// The nominal ADC reference voltage, in millivolts.
+pub const NOMINAL_REFERENCE_MV: u32 = 3300;
+// the range of references allowed, VDDA's limits for the ADC
+const MIN_REFERENCE_MV: u32 = 2000;
+const MAX_REFERENCE_MV: u32 = 3600;
+const TRACKING_STEP_MV: u32 = 10;
+
+const NOMINAL_NANOVOLTS_PER_LSB: u32 = 805_664; // 3.3v / 2^12 bits * 10^9
+// a gain measurement further than this from nominal is assumed to be a mistake
+const SCALE_TOLERANCE_PERCENT: u32 = 10;
+// and an offset more than this, around 160mV, means the input wasn't grounded
+const MAX_OFFSET: u64 = 200;
+
+const MAGIC: u16 = 0x5344; // "SD"
+const RECORD_LENGTH: usize = 1 + GAINS + 2 * GAINS + 1;
+
+static mut OFFSETS: [i16; GAINS] = [0; GAINS];
+static mut SCALES: [u32; GAINS] = [NOMINAL_NANOVOLTS_PER_LSB; GAINS]; // nanovolts per LSB
+static mut REFERENCE_MV: u32 = NOMINAL_REFERENCE_MV;
+static mut TRACKING: bool = false; // the reference follows the measured supply
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CalibrationError {
+    OutOfRange, // the measurement was too far from nominal to be believed
+    Flash(FlashError),
+}
+
+/// Loads the calibration stored in flash, if there is a valid one. Returns whether there was.
+pub fn load() -> bool {
+    let mut sum = 0u16;
+    for i in 0..RECORD_LENGTH {
+        sum = sum.wrapping_add(flash::read(i));
+    }
+    if flash::read(0) != MAGIC || sum != 0xffff {
+        return false;
+    }
+    for gain in 0..GAINS {
+        let scale_offset = 1 + GAINS + 2 * gain;
+        unsafe {
+            OFFSETS[gain] = flash::read(1 + gain) as i16;
+            SCALES[gain] = flash::read(scale_offset) as u32 |
+                           (flash::read(scale_offset + 1) as u32) << 16;
+        }
+    }
+    true
+}
+
+/// Stores the calibration in flash.
+pub fn save() -> Result<(), CalibrationError> {
+    let mut record = [0u16; RECORD_LENGTH];
+    record[0] = MAGIC;
+    for gain in 0..GAINS {
+        let scale_offset = 1 + GAINS + 2 * gain;
+        record[1 + gain] = unsafe { OFFSETS[gain] } as u16;
+        record[scale_offset] = unsafe { SCALES[gain] } as u16;
+        record[scale_offset + 1] = (unsafe { SCALES[gain] } >> 16) as u16;
+    }
+    let mut sum = 0u16;
+    for i in 0..RECORD_LENGTH - 1 {
+        sum = sum.wrapping_add(record[i]);
+    }
+    record[RECORD_LENGTH - 1] = 0xffff - sum;
+    flash::store(0, &record).map_err(CalibrationError::Flash)
+}
+
+/// Abandons any offsets and scales set since the calibration was last loaded or saved, going
+/// back to the stored calibration, or if there is none, the nominal one.
+pub fn revert() {
+    unsafe {
+        OFFSETS = [0; GAINS];
+        SCALES = [NOMINAL_NANOVOLTS_PER_LSB; GAINS];
+    }
+    load();
+}
+
+/// Sets the offset for `gain`, as the mean of `count` raw conversions summing to `sum`, measured
+/// with the input grounded.
+pub fn set_offset(gain: usize, sum: u64, count: u64) -> Result<(), CalibrationError> {
+    let mean = (sum + count / 2) / count;
+    if mean > MAX_OFFSET {
+        return Err(CalibrationError::OutOfRange);
+    }
+    unsafe { OFFSETS[gain] = mean as i16; }
+    Ok(())
+}
+
+/// Sets the scale for `gain`, from `count` raw conversions summing to `sum`,
+/// measured with REFERENCE_MICROVOLTS applied to the input.
+pub fn set_scale(gain: usize, sum: u64, count: u64) -> Result<(), CalibrationError> {
+    let mean = ((sum + count / 2) / count) as i32 - offset(gain) as i32;
+    if mean <= 0 {
+        return Err(CalibrationError::OutOfRange);
+    }
+    // as if for the nominal reference
+    let scale = (REFERENCE_MICROVOLTS as u64 * 1000 * NOMINAL_REFERENCE_MV as u64 /
+                 reference_mv() as u64 / mean as u64) as u32;
+    let tolerance = NOMINAL_NANOVOLTS_PER_LSB / 100 * SCALE_TOLERANCE_PERCENT;
+    if scale < NOMINAL_NANOVOLTS_PER_LSB - tolerance ||
+       scale > NOMINAL_NANOVOLTS_PER_LSB + tolerance {
+        return Err(CalibrationError::OutOfRange);
+    }
+    unsafe { SCALES[gain] = scale; }
+    Ok(())
+}
+
+/// Returns the offset for `gain`, in raw conversion units.
+pub fn offset(gain: usize) -> i16 {
+    unsafe { OFFSETS[gain] }
+}
+
+/// Returns the scale for `gain` relative to nominal, in thousandths, e.g. 1003 for 0.3% more
+/// volts per raw conversion unit than nominal.
+pub fn scale_permille(gain: usize) -> u32 {
+    ((unsafe { SCALES[gain] } as u64 * 1000 + NOMINAL_NANOVOLTS_PER_LSB as u64 / 2) /
+     NOMINAL_NANOVOLTS_PER_LSB as u64) as u32
+}
+
+/// Returns the scale for `gain`, in nanovolts per raw conversion unit, at the reference in use.
+pub fn nanovolts_per_lsb(gain: usize) -> u32 {
+    (unsafe { SCALES[gain] } as u64 * reference_mv() as u64 / NOMINAL_REFERENCE_MV as u64) as u32
+}
+
+/// Returns the input voltage at the top of the ADC's range, in microvolts.
+pub fn full_scale_microvolts() -> u32 {
+    (4096 * nanovolts_per_lsb(0) as u64 / 1000) as u32
+}
+
+/// Sets the ADC reference to `reference_mv` millivolts, or with None, has it follow the
+/// measured supply. Returns false, leaving it as it was, if the voltage is out of range.
+pub fn set_reference(reference_mv: Option<u32>) -> bool {
+    match reference_mv {
+        Some(mv) if !reference_in_range(mv) => false,
+        Some(mv) => {
+            unsafe {
+                REFERENCE_MV = mv;
+                TRACKING = false;
+            }
+            true
+        }
+        None => {
+            unsafe { TRACKING = true; }
+            true
+        }
+    }
+}
+
+/// Returns whether `reference_mv` millivolts is a possible ADC reference, within VDDA's limits.
+pub fn reference_in_range(reference_mv: u32) -> bool {
+    reference_mv >= MIN_REFERENCE_MV && reference_mv <= MAX_REFERENCE_MV
+}
+
+/// Returns the reference setting: the voltage in millivolts, or None if it follows the supply.
+pub fn reference() -> Option<u32> {
+    if unsafe { TRACKING } { None } else { Some(reference_mv()) }
+}
+
+/// Returns the ADC reference voltage in use, in millivolts.
+pub fn reference_mv() -> u32 {
+    unsafe { REFERENCE_MV }
+}
+
+/// Follows the measured supply, `supply_mv` millivolts, if the reference is set to. Returns
+/// whether the reference changed.
+pub fn track_supply(supply_mv: u32) -> bool {
+    let supply_mv = if supply_mv < MIN_REFERENCE_MV {
+        MIN_REFERENCE_MV
+    } else if supply_mv > MAX_REFERENCE_MV {
+        MAX_REFERENCE_MV
+    } else {
+        supply_mv
+    };
+    unsafe {
+        let change = if supply_mv > REFERENCE_MV {
+            supply_mv - REFERENCE_MV
+        } else {
+            REFERENCE_MV - supply_mv
+        };
+        if !TRACKING || change < TRACKING_STEP_MV {
+            return false;
+        }
+        REFERENCE_MV = supply_mv;
+    }
+    true
+}
+
+/// Returns a raw conversion corrected for the input offset.
+pub fn correct(raw_conversion: u16) -> u16 {
+    let corrected = raw_conversion as i32 - offset(0) as i32;
+    if corrected < 0 { 0 } else if corrected > 4095 { 4095 } else { corrected as u16 }
+}
+
+/// Converts a raw conversion to the input voltage in microvolts, correcting for offset and gain.
+pub fn microvolts(raw_conversion: u16) -> u32 {
+    (correct(raw_conversion) as u64 * nanovolts_per_lsb(0) as u64 / 1000) as u32
+}
+
+/// Returns the smallest raw conversion that microvolts() converts to at least `microvolts`, or
+/// 4095 if none does.
+pub fn raw_conversion(microvolts_wanted: u32) -> u16 {
+    let (mut low, mut high) = (0u16, 4095u16);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if microvolts(middle) < microvolts_wanted {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    low
+}
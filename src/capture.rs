@@ -22,44 +22,279 @@
 // OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
 // WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-// This uses ADC1 channel 7, input on GPIO PC1
+// This uses ADC1 channel 7, input on GPIO PC1 (the board's SIGNAL_INPUT)
 // - ADC12 is clocked by AHB clock to minimize jitter
 // TIM15 triggers the ADC conversions
-// - this is a 16-bit counter with 16-bit prescaler, clocked directly from APB2 (72MHz)
+// - this is a 16-bit counter with 16-bit prescaler, clocked directly from APB2 (72MHz, see clocks.rs)
 // - TIM15 outputs TIM15_TRGO, which is ADC1's EXT14
 // DMA1 channel 1 moves converted data to RAM
+// The ADC1 injected group converts the auxiliary channels: channel 16 (the internal temperature
+// sensor), channel 18 (VREFINT, giving the supply voltage), and the DC input, one of the board's
+// DC_INPUTS, channel 6 on PC0 unless another is selected.
+// The injected conversions are started by software, and interrupt the regular conversions, so
+// to avoid disturbing the sample timing they are only run between sweeps, or during sweeps slow
+// enough that delaying one sample by their conversion time makes no visible difference.
+// Sampling can be gated by an external signal on the board's GATE_INPUT, TIM15_CH1: in gated
+// slave mode, TIM15 counts, and so triggers conversions, only while TI1 is at the chosen level.
+// The ADC's analog watchdog 1 can flag any sample of the signal input outside a pair of limits.
+// Its settings can only be changed while conversions are stopped, so they are applied at the
+// start of the next sweep.
+// Sweeps can be chained, so there is no dead time between them at fast timebases: when two or
+// more sweeps fit in the capture buffer, it is divided into slots of a sweep each (see Pool),
+// and the DMA transfer complete interrupt points the DMA channel at a free slot and starts the
+// next sweep straight away, while the main loop is still displaying the one just finished.
+// TIM15 runs freely, so restarting the DMA within one sample interval loses no samples. With
+// three slots or more, the sweeps carry on while the main loop is busy, the latest kept
+// waiting for it. `begin_sweep` then just takes the latest sweep, complete or under way, if it
+// is of the length asked for; anything that changes the sampling (the timebase, the gate, the
+// watchdog) or reuses the buffer (segments, tests) discards the chained sweeps instead. The
+// main loop indexes the current sweep's samples from the start of its slot.
+// The spare DMA channel, COPY_DMA_CHANNEL, copies samples memory to memory: within the capture
+// buffer, for keeping segments (see segments.rs), and out of it, to snapshot each finished
+// record. Once the main loop has the record it will display and measure, `snapshot` copies it
+// to a buffer of its own and releases the sweep's slot, so with chaining, the next sweep can
+// start there straight away, rather than after the main loop has finished with the record;
+// the rest of the sweep past the record, if still under way, is dropped for it. Records longer
+// than SNAPSHOT_LENGTH are read in place, as a second buffer that long wouldn't fit in RAM.
+// The main loop learns how many samples have arrived from interrupts, rather than by reading
+// the DMA channel's count itself: at sample rates up to EVENT_MAX_SAMPLE_RATE, TIM15's update
+// interrupt, as each conversion is triggered, announces the sample before it, so each sample
+// is announced one sample interval after it was taken, and the time of each is known; above
+// that, the DMA half transfer and transfer complete interrupts announce each half of a sweep.
+// ADC1 and ADC2 run in dual regular simultaneous mode, ADC2 converting LOOPBACK_CHANNEL, the
+// siggen's sine output on PA5, at the same moment as each ADC1 conversion. ADC2 is set up just
+// as ADC1 is, and DMA2 channel 1 moves its first LOOPBACK_LENGTH samples of each sweep to a
+// buffer of their own, enough for the trigger search window, the longest trigger delay, and a
+// screen, indexed just as the sweep's samples are. They are only shown with the CH2 trace on,
+// which is for demonstrating two channels with no wiring; the buffer isn't divided into slots,
+// so the main loop turns chaining off while it is on.
+
+use core::intrinsics::{volatile_load, volatile_store};
+use core::ptr;
 
 use cortex_m;
-use stm32f30x::{ADC1, ADC1_2, DMA1, GPIOC, RCC, TIM15};
-use stm32f30x::interrupt::Interrupt;
+use stm32f30x::{ADC1, ADC1_2, ADC2, Interrupt, RCC, TIM15};
+use stm32f30x::adc1;
+
+use board::{DC_INPUTS, GATE_INPUT, SIGNAL_INPUT};
+use clocks;
+use delay::delay_us;
+use dma;
+use gpio::{Mode, Pull};
+use priority;
+use time::millis;
+use timer;
+use trigger;
+
+/// The capacity of the capture buffer, in samples. This holds the trigger search window, the
+/// trigger delay, and the record itself, and takes up most of the RAM not needed for the stack.
+pub const BUFFER_LENGTH: usize = 12 * 1024;
+
+/// The longest record which fits in the capture buffer along with the trigger search window and
+/// the longest trigger delay.
+pub const MAX_RECORD_LENGTH: usize = BUFFER_LENGTH - trigger::SEARCH_LENGTH - trigger::MAX_DELAY;
+
+pub static mut CAPTURE_CHANNEL_1: [u16; BUFFER_LENGTH] = [0; BUFFER_LENGTH];
+
+/// The number of samples of each sweep kept from ADC2, for the loopback trace.
+pub const LOOPBACK_LENGTH: usize =
+    trigger::SEARCH_LENGTH + trigger::MAX_DELAY + trigger::DISPLAY_LENGTH;
+
+static mut CAPTURE_CHANNEL_2: [u16; LOOPBACK_LENGTH] = [0; LOOPBACK_LENGTH];
+
+// ADC2_IN2, on PA5, which is DAC channel 2's output
+const LOOPBACK_CHANNEL: u8 = 2;
+
+// the DMA channel serving ADC2
+const LOOPBACK_DMA_CHANNEL: dma::Channel = dma::Channel {
+    controller: dma::Controller::Dma2,
+    number: 1,
+};
+
+/// The DMA channel serving ADC1. Its transfer complete interrupt marks the end of a sweep.
+pub const DMA_CHANNEL: dma::Channel = dma::Channel {
+    controller: dma::Controller::Dma1,
+    number: 1,
+};
+
+// the DMA channel copying samples within the capture buffer, memory to memory
+const COPY_DMA_CHANNEL: dma::Channel = dma::Channel {
+    controller: dma::Controller::Dma1,
+    number: 2,
+};
+
+/// The longest record that `snapshot` copies out of the capture buffer: the 1k record length.
+pub const SNAPSHOT_LENGTH: usize = 1024;
+
+static mut SNAPSHOT: [u16; SNAPSHOT_LENGTH] = [0; SNAPSHOT_LENGTH];
+
+// the length of the record in SNAPSHOT, and its sweep's watchdog flag, if it holds the current
+// sweep's
+static mut SNAPSHOT_TAKEN: Option<(usize, bool)> = None;
+
+/// The highest sample rate at which each sample is announced by TIM15's update interrupt. The
+/// interrupt takes around 1µs, so this costs at most 2% of the CPU.
+pub const EVENT_MAX_SAMPLE_RATE: u32 = 20_000;
+
+// the samples of the sweep the DMA channel is writing (or last wrote) that have been announced,
+// and when the last of them was, in milliseconds
+static mut READY: usize = 0;
+static mut READY_AT: u32 = 0;
+
+/// The most sweeps the capture buffer is divided into, when chaining.
+const MAX_SLOTS: usize = 4;
+
+// The capture buffer, divided into slots of one sweep each. The main loop works on the current
+// slot's sweep, while with chaining, the DMA channel fills another, and the latest sweep filled
+// since the current one waits in a third, to be taken next; an older one waiting is dropped,
+// and its slot filled again.
+struct Pool {
+    length: usize,          // the number of samples captured per sweep
+    slots: usize,           // up to MAX_SLOTS, as many as fit; 1 without chaining
+    current: usize,         // the main loop's sweep
+    filling: Option<usize>, // the sweep the DMA channel is filling
+    ready: Option<usize>,   // a complete sweep, later than the current one, not yet taken
+    released: bool,         // the current sweep's record is in the snapshot, so its slot is free
+    tripped: [bool; MAX_SLOTS], // each complete sweep's watchdog flag
+    done_at: [u32; MAX_SLOTS],  // and when it ended, in milliseconds
+}
+
+impl Pool {
+    // the index in the capture buffer of the start of `slot`
+    fn base(&self, slot: usize) -> usize {
+        slot * self.length
+    }
+
+    // whether the DMA channel is filling the current sweep, rather than a later one
+    fn filling_current(&self) -> bool {
+        self.filling == Some(self.current) && !self.released
+    }
+
+    // whether a sweep later than the current one is being filled, or waiting
+    fn chained(&self) -> bool {
+        self.ready.is_some() || (self.filling.is_some() && !self.filling_current())
+    }
+
+    // a slot holding neither the current sweep, unless released, nor a waiting one, to fill next
+    fn free_slot(&self) -> Option<usize> {
+        (0..self.slots).find(|&slot| {
+            (slot != self.current || self.released) && Some(slot) != self.ready
+        })
+    }
+}
+
+static mut POOL: Pool = Pool {
+    length: 160,
+    slots: 1,
+    current: 0,
+    filling: None,
+    ready: None,
+    released: false,
+    tripped: [false; MAX_SLOTS],
+    done_at: [0; MAX_SLOTS],
+};
+
+// whether the main loop allows chaining
+static mut CHAINING: bool = false;
+
+// the sample rate achieved by TIM15's programmed prescaler and reload values, rounded to the
+// nearest Hz
+static mut SAMPLE_RATE: u32 = 1000;
+
+// temperature sensor factory calibration values, converted at 3.3V and 30°C and 110°C
+const TS_CAL1: u32 = 0x1fff_f7b8;
+const TS_CAL2: u32 = 0x1fff_f7c2;
+// VREFINT factory calibration value, converted at 3.3V
+const VREFINT_CAL: u32 = 0x1fff_f7ba;
+
+/// The highest sample rate at which auxiliary conversions are run during sweeps. The three
+/// conversions take about 40µs, a small fraction of a sample interval at this rate.
+pub const AUXILIARY_MAX_SAMPLE_RATE: u32 = 1000;
+
+// the analog watchdog's limits, as raw conversions, and whether they have changed since they
+// were last applied
+static mut WATCHDOG: (Option<u16>, Option<u16>) = (None, None);
+static mut WATCHDOG_CHANGED: bool = false;
+
+// the latest raw auxiliary conversions: temperature, VREFINT, and DC input
+static mut AUXILIARY_RAW: [u16; 3] = [0; 3];
+// the index into DC_INPUTS of the DC input converted
+static mut DC_INPUT_INDEX: usize = 0;
+
+/// The latest auxiliary measurements.
+#[derive(Clone, Copy)]
+pub struct Auxiliary {
+    pub temperature: i32, // tenths of a degree Celsius
+    pub supply_mv: u32,   // VDDA, the ADC reference, in millivolts
+    pub input_mv: u32,    // the auxiliary DC input, in millivolts
+}
+
+/// Whether sampling runs freely, or only while the gate input is at a level.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Gate {
+    Off = 0,
+    High = 1, // sample while the gate input is high
+    Low = 2,  // sample while the gate input is low
+}
+
+impl Gate {
+    pub fn next(self) -> Gate {
+        match self {
+            Gate::Off => Gate::High,
+            Gate::High => Gate::Low,
+            Gate::Low => Gate::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static [u8] {
+        match self {
+            Gate::Off => b"off",
+            Gate::High => b"high",
+            Gate::Low => b"low",
+        }
+    }
+}
 
-use delay_ms;
+/// The temperature change since the last ADC calibration which calls for a recalibration, in
+/// tenths of a degree.
+pub const RECALIBRATION_DRIFT: i32 = 50;
 
-pub static mut CAPTURE_CHANNEL_1: [u16; 160] = [0; 160];
+// set the sample time of an ADC1 channel, given as the SMPx field bits
+fn set_sample_time(adc1: &adc1::RegisterBlock, channel: u8, smp: u8) {
+    let shift = 3 * (channel as u32 % 10);
+    let bits = |r: u32| r & !(0b111 << shift) | (smp as u32) << shift;
+    if channel < 10 {
+        adc1.smpr1.modify(|r, w| unsafe { w.bits(bits(r.bits())) });
+    } else {
+        adc1.smpr2.modify(|r, w| unsafe { w.bits(bits(r.bits())) });
+    }
+}
 
 /// Prepares the hardware for sample capture, by configuring the ADC, timer, DMA channel, and
 /// GPIO pin. Each of those peripherals will be ready for a new sampling sweep, except for the
 /// ADC start and DMA enabling, which is done by `begin_sweep`.
 pub fn setup() {
     cortex_m::interrupt::free(|cs| {
-        // enable clock to ADC1, DMA1, and GPIOC
+        // enable clock to ADC1 and DMA1
         let rcc = RCC.borrow(cs);
-        rcc.ahbenr.modify(|_, w|
-            w.adc12en().enabled()
-             .dmaen().enabled() // should be 'dma1en'
-             .iopcen().enabled()
-        );
+        rcc.ahbenr.modify(|_, w| w.adc12en().enabled());
+        DMA_CHANNEL.enable_clock();
         // enable clock to TIM15
         rcc.apb2enr.modify(|_, w| w.tim15en().enabled());
 
-        // configure PC1 and PC? as analog inputs with no pull
-        let gpioc = GPIOC.borrow(cs);
-        gpioc.moder.modify(|_, w|
-            w.moder1().analog()
-        );
-        gpioc.pupdr.modify(|_, w| unsafe {
-            w.pupdr1().bits(0b00) // no pull
-        });
+        // configure the signal and auxiliary DC input pins as analog inputs with no pull
+        for pin in Some(SIGNAL_INPUT.pin).iter().chain(DC_INPUTS.iter().map(|input| &input.pin)) {
+            pin.enable_port_clock();
+            pin.configure(Mode::Analog);
+            pin.set_pull(Pull::None);
+        }
+        // and the gate input as TIM15_CH1, pulled down so it reads as low when unconnected
+        if let Some(ref gate) = GATE_INPUT {
+            gate.pin.enable_port_clock();
+            gate.pin.set_alternate_function(gate.af);
+            gate.pin.set_pull(Pull::Down);
+            gate.pin.configure(Mode::Alternate);
+        }
 
         // configure ADC clock
         // -FIX- adjust sample time with sample rate
@@ -71,32 +306,35 @@ pub fn setup() {
         adc12.ccr.modify(|_, w| unsafe { w.ckmode().bits(0b10) });
 
         // ADC calibration procedure
-        // - turn on voltage regulator
-        let adc1 = ADC1.borrow(cs);
-        adc1.cr.modify(|_, w| unsafe { w.advregen().bits(0b00) }); // set to intermediate state first
-        adc1.cr.modify(|_, w| unsafe { w.advregen().bits(0b01) }); // then enable
+        // - turn on voltage regulators
+        for adc in &[&**ADC1.borrow(cs), &**ADC2.borrow(cs)] {
+            adc.cr.modify(|_, w| unsafe { w.advregen().bits(0b00) }); // intermediate state first
+            adc.cr.modify(|_, w| unsafe { w.advregen().bits(0b01) }); // then enable
+        }
         // - leave critical section and wait for at least 10µs (the hardware requirement)
     });
-        delay_ms(2); // delay at least 1ms (convenient, but longer than required)
+        delay_us(10);
         // - enter critical section again
     cortex_m::interrupt::free(|cs| {
-        // - select calibration mode
-        let adc1 = ADC1.borrow(cs);
-        adc1.cr.modify(|_, w| unsafe { w.adcaldif().bits(0) }); // single-ended
-        // - start calibration
-        adc1.cr.modify(|_, w| unsafe { w.adcal().bits(1) });
-        // - wait for calibration to finish
-        while adc1.cr.read().adcal().bits() != 0 {}
+        // - select calibration mode, start calibration, and wait for it to finish, for each
+        for adc in &[&**ADC1.borrow(cs), &**ADC2.borrow(cs)] {
+            adc.cr.modify(|_, w| unsafe { w.adcaldif().bits(0) }); // single-ended
+            adc.cr.modify(|_, w| unsafe { w.adcal().bits(1) });
+            while adc.cr.read().adcal().bits() != 0 {}
+        }
         // - calibration complete
+        let adc1 = ADC1.borrow(cs);
 
         // configure ADC1 for TIM15-driven sampling
         let adc12 = ADC1_2.borrow(cs);
         adc12.ccr.modify(|_, w| unsafe {
             w.ckmode().bits(0b10) // ADC clock is AHB/2
+             .tsen().bits(1)      // temperature sensor enabled
+             .vrefen().bits(1)    // VREFINT enabled
              .mdma().bits(0b00)   // dual DMA mode: disabled
              .dmacfg().bits(0)    // dual DMA mode: one-shot
              .delay().bits(0)     // no delay between phases (for interleaved mode only)
-             .mult().bits(0)      // independent mode -FIX- for dual channel
+             .mult().bits(0b00110) // dual mode: regular simultaneous only
         });
         adc1.cfgr.modify(|_, w| unsafe {
             w.jauto().bits(0)       // no auto inject group conversion
@@ -110,92 +348,432 @@ pub fn setup() {
              .dmaen().bits(1)       // DMA enabled
         });
         adc1.sqr1.modify(|_, w| unsafe {
-            w.sq1().bits(7)     // 1st conversion in sequence: channel 7
+            w.sq1().bits(SIGNAL_INPUT.channel) // 1st conversion in sequence
              .l3().bits(0b0000) // 1 conversion in sequence  (typo in SVD, should be "l", not "l3")
         });
-        adc1.smpr1.modify(|_, w| unsafe { w.smp7().bits(0b011) }); // sample time 7.5 cycles -FIX-
+        set_sample_time(adc1, SIGNAL_INPUT.channel, 0b011); // 7.5 cycles -FIX-
+        // configure ADC2 as the dual mode slave, converting the loopback channel, with the same
+        // sample time; its conversions are started with ADC1's
+        let adc2 = ADC2.borrow(cs);
+        adc2.cfgr.modify(|_, w| unsafe {
+            w.cont().bits(0)
+             .ovrmod().bits(1)
+             .exten().bits(0b00) // no trigger of its own
+             .align().bits(0)
+             .res().bits(0b00)
+             .dmacfg().bits(0)
+             .dmaen().bits(1)
+        });
+        adc2.sqr1.modify(|_, w| unsafe { w.sq1().bits(LOOPBACK_CHANNEL).l3().bits(0b0000) });
+        set_sample_time(adc2, LOOPBACK_CHANNEL, 0b011);
+        // configure the injected group for the auxiliary channels, started by software
+        // - the temperature sensor and VREFINT need at least 2.2µs sample time: use 601.5
+        //   cycles, or 16.7µs
+        adc1.smpr2.modify(|_, w| unsafe { w.smp16().bits(0b111).smp18().bits(0b111) });
+        for input in DC_INPUTS.iter() {
+            set_sample_time(adc1, input.channel, 0b101); // 181.5 cycles
+        }
+        adc1.jsqr.write(|w| unsafe {
+            w.jl().bits(0b10)    // 3 conversions in sequence
+             .jsq1().bits(16)    // temperature sensor
+             .jsq2().bits(18)    // VREFINT
+             .jsq3().bits(DC_INPUTS[0].channel)
+             .jexten().bits(0b00) // software trigger
+        });
 
         // configure TIM15 to trigger sampling
         let tim15 = TIM15.borrow(cs);
         tim15.cr1.modify(|_, w| unsafe {
             w.ckd().bits(0b00) // no sampling filter clock division
              .arpe().bits(1)   // ARR register is buffered
+             .urs().bits(1)    // only overflow interrupts, not UG
         });
         tim15.cr2.modify(|_, w| unsafe { w.mms().bits(0b010) }); // trigger output: update event
-        tim15.arr.write(|w| unsafe { w.bits(999) }); // 1kHz
-        tim15.psc.write(|w| unsafe { w.psc().bits(71) }); // prescaler of 72
+        // channel 1 is an input, TI1, for gating; the gate itself is set by set_gate()
+        tim15.ccmr1_input.modify(|r, w| unsafe { w.bits(r.bits() & !0b11 | 0b01) }); // CC1S
+        let rate = timer::solve(clocks::apb2_timer_hz(), 1000, timer::MAX_ARR_16); // 1kHz
+        tim15.arr.write(|w| unsafe { w.bits(rate.arr) });
+        tim15.psc.write(|w| unsafe { w.psc().bits(rate.psc) });
         tim15.egr.write(|w| unsafe { w.ug().bits(1) }); // immediately update registers
+        unsafe { SAMPLE_RATE = rate.hz(); }
+        tim15.dier.write(|w| unsafe { w.uie().bits(1) }); // announce each sample, at 1kHz
+        // TIM15 shares its interrupt with TIM1's break interrupt, which is unused
+        let nvic = cortex_m::peripheral::NVIC.borrow(cs);
+        unsafe { nvic.set_priority(Interrupt::Tim1BrkTim15, priority::CAPTURE); }
+        nvic.enable(Interrupt::Tim1BrkTim15);
 
         // configure DMA1 channel 1 for ADC1
-        // - assuming reset state
-        let dma1 = DMA1.borrow(cs);
-        dma1.ccr1.modify(|_, w| unsafe {
-            w.mem2mem().bits(0)  // memory-to-memory mode disabled
-             .pl().bits(0b10)    // high priority
-             .msize().bits(0b01) // memory data size 16 bits
-             .psize().bits(0b01) // peripheral data size 16 bits
-             .minc().bits(1)     // memory increment enabled
-             .pinc().bits(0)     // peripheral increment disabled
-             .circ().bits(0)     // one-shot (not circular) mode
-             .dir().bits(0)      // transfer direction: peripheral -> memory
-             .tcie().bits(1)     // trigger interrupt on transfer completion
-        });
-        dma1.cndtr1.write(|w| unsafe { w.ndt().bits(160) });  // buffer size
         let adc1_dr_address: u32 = &adc1.dr as *const _ as u32;
         debug_assert_eq!(adc1_dr_address, 0x50000040);
-        dma1.cpar1.write(|w| unsafe {
-            w.bits(adc1_dr_address) // peripheral base address
-        });
-        dma1.cmar1.write(|w| unsafe {
-            w.bits(&CAPTURE_CHANNEL_1 as *const _ as u32) // memory base address
-        });
-        // - enable DMA1_Channel1 interrupt
-        let nvic = cortex_m::peripheral::NVIC.borrow(cs);
-        unsafe { nvic.set_priority(Interrupt::Dma1Ch1, 0); }
-        nvic.enable(Interrupt::Dma1Ch1);
+        let buffer_address = unsafe { &CAPTURE_CHANNEL_1 as *const _ as u32 };
+        let config = dma::Config {
+            direction: dma::Direction::PeripheralToMemory,
+            priority: dma::Priority::High,
+            size: dma::Size::Bits16,
+            circular: false, // one-shot
+            interrupt_on_complete: true,
+            interrupt_on_half: true,
+            memory_to_memory: false,
+        };
+        DMA_CHANNEL.configure(&config, adc1_dr_address, buffer_address, sweep_length() as u16);
+        DMA_CHANNEL.enable_interrupt(priority::CAPTURE);
 
-        // enable ADC1
-        adc1.cr.modify(|_, w| unsafe { w.aden().bits(1) });
-        // wait for ADRDY
-        while adc1.isr.read().adrdy().bits() == 0 {}
+        // configure DMA1 channel 2 for copies within the capture buffer, given their addresses
+        // as they are started
+        let config = dma::Config {
+            direction: dma::Direction::PeripheralToMemory,
+            priority: dma::Priority::Medium, // below the ADC's
+            size: dma::Size::Bits16,
+            circular: false,
+            interrupt_on_complete: false,
+            interrupt_on_half: false,
+            memory_to_memory: true,
+        };
+        COPY_DMA_CHANNEL.configure(&config, buffer_address, buffer_address, 0);
+
+        // configure DMA2 channel 1 for ADC2, restarted at the start of each sweep
+        LOOPBACK_DMA_CHANNEL.enable_clock();
+        let config = dma::Config {
+            direction: dma::Direction::PeripheralToMemory,
+            priority: dma::Priority::High,
+            size: dma::Size::Bits16,
+            circular: false,
+            interrupt_on_complete: false,
+            interrupt_on_half: false,
+            memory_to_memory: false,
+        };
+        LOOPBACK_DMA_CHANNEL.configure(&config, &adc2.dr as *const _ as u32,
+                                       unsafe { &CAPTURE_CHANNEL_2 as *const _ as u32 }, 0);
+
+        // enable ADC2, then ADC1, waiting for ADRDY
+        for adc in &[&**adc2, &**adc1] {
+            adc.cr.modify(|_, w| unsafe { w.aden().bits(1) });
+            while adc.isr.read().adrdy().bits() == 0 {}
+        }
 
         // enable TIM15
         tim15.cr1.modify(|_, w| unsafe { w.cen().bits(1) });
     });
+
+    // make a first set of auxiliary conversions, so there are always measurements available
+    start_auxiliary_conversions();
+    wait_for_auxiliary_conversions();
+}
+
+/// Returns the number of samples being captured in the current sweep.
+pub fn sweep_length() -> usize {
+    unsafe { POOL.length }
+}
+
+/// Sets whether sweeps may be chained, each started by the interrupt handler as the one before
+/// ends, in another slot of the capture buffer. Turning chaining off discards any chained
+/// sweeps, and the next sweep starts at the beginning of the buffer.
+pub fn set_chaining(chaining: bool) {
+    if !chaining {
+        cancel_chain();
+    }
+    unsafe { CHAINING = chaining; }
+}
+
+/// Begins a new sampling sweep of `samples` samples by enabling DMA and starting ADC
+/// conversions, or takes the latest chained sweep, complete or under way, if it is that long.
+pub fn begin_sweep(samples: usize) {
+    debug_assert!(samples > 0 && samples <= BUFFER_LENGTH);
+    wait_for_copy(); // the copy may be from where this sweep will go
+    unsafe { SNAPSHOT_TAKEN = None; }
+    cortex_m::interrupt::free(|cs| {
+        let adc1 = ADC1.borrow(cs);
+        let pool = unsafe { &mut POOL };
+        if pool.chained() && samples == pool.length && unsafe { !WATCHDOG_CHANGED } {
+            pool.current = match pool.ready.take() {
+                Some(slot) => slot,
+                None => pool.filling.unwrap_or(pool.current),
+            };
+            pool.released = false;
+            // the slot just finished with is free, if nothing is filling one already
+            if pool.filling.is_none() {
+                if let Some(slot) = pool.free_slot() {
+                    start_dma(adc1, pool, slot);
+                }
+            }
+            return;
+        }
+        DMA_CHANNEL.stop();
+        pool.filling = None;
+        pool.ready = None;
+        pool.released = false;
+        pool.length = samples;
+        let fit = BUFFER_LENGTH / samples;
+        pool.slots = match unsafe { CHAINING } {
+            false => 1,
+            true if fit < MAX_SLOTS => fit,
+            true => MAX_SLOTS,
+        };
+        if pool.current >= pool.slots {
+            pool.current = 0;
+        }
+        if unsafe { WATCHDOG_CHANGED } {
+            apply_watchdog(adc1);
+        }
+        let slot = pool.current;
+        start_dma(adc1, pool, slot);
+    });
+}
+
+// start the DMA channel filling `slot`, and start ADC conversions (the timer is already
+// running), with the watchdog flag cleared for this sweep's samples
+fn start_dma(adc1: &adc1::RegisterBlock, pool: &mut Pool, slot: usize) {
+    adc1.isr.write(|w| unsafe { w.bits(1 << 7) }); // AWD1
+    unsafe {
+        let buffer_address = &CAPTURE_CHANNEL_1[pool.base(slot)] as *const _ as u32;
+        DMA_CHANNEL.set_memory(buffer_address);
+        DMA_CHANNEL.set_count(pool.length as u16);
+        READY = 0;
+        READY_AT = millis();
+    }
+    // ADC2's samples are kept whether or not they are shown, so it runs just as ADC1 does
+    LOOPBACK_DMA_CHANNEL.stop();
+    let count = if pool.length < LOOPBACK_LENGTH { pool.length } else { LOOPBACK_LENGTH };
+    LOOPBACK_DMA_CHANNEL.set_count(count as u16);
+    LOOPBACK_DMA_CHANNEL.start();
+    DMA_CHANNEL.start();
+    pool.filling = Some(slot);
+    adc1.cr.modify(|_, w| unsafe { w.adstart().bits(1) });
 }
 
-/// Begins a new sampling sweep by enabling DMA and starting ADC conversions.
-pub fn begin_sweep() {
-    // begin the next sweep of 160 samples
+/// Handles the DMA channel's interrupts. At half a sweep, this announces the samples so far. At
+/// the end of a sweep, it announces the rest, keeps the sweep's watchdog flag and end time, and
+/// if chaining, starts the next sweep in a free slot. Returns whether a sweep ended.
+pub fn dma_interrupt() -> bool {
+    if DMA_CHANNEL.take_half_complete() {
+        announce(DMA_CHANNEL.remaining() as usize);
+    }
+    if !DMA_CHANNEL.is_complete() {
+        return false;
+    }
+    DMA_CHANNEL.clear_complete();
+    DMA_CHANNEL.stop();
+    announce(0);
     cortex_m::interrupt::free(|cs| {
-        // enable DMA
-        let dma1 = DMA1.borrow(cs);
-        unsafe { dma1.ccr1.modify(|_, w| w.en().bits(1)); }
-        // start ADC conversions (timer is already running)
         let adc1 = ADC1.borrow(cs);
-        unsafe { (*adc1).cr.modify(|_, w| w.adstart().bits(1)); }
+        let pool = unsafe { &mut POOL };
+        if let Some(slot) = pool.filling.take() {
+            pool.tripped[slot] = adc1.isr.read().bits() & 1 << 7 != 0;
+            pool.done_at[slot] = millis();
+            if slot != pool.current || pool.released {
+                pool.ready = Some(slot); // the latest, and any older one is now free
+            }
+            if unsafe { CHAINING } {
+                if let Some(next) = pool.free_slot() {
+                    start_dma(adc1, pool, next);
+                }
+            }
+        }
     });
+    true
 }
 
-/// Returns the number of samples transferred by DMA to RAM.
+/// Handles TIM15's update interrupt, as a conversion is triggered, by announcing the samples
+/// transferred so far.
+pub fn timer_interrupt() {
+    cortex_m::interrupt::free(|cs| {
+        let tim15 = TIM15.borrow(cs);
+        if priority::measuring() {
+            // the counter has counted from 0 since the update event raised this interrupt
+            let ticks = tim15.cnt.read().bits() as u64 * (tim15.psc.read().bits() as u64 + 1);
+            let ns = ticks * 1_000_000_000 / clocks::apb2_timer_hz() as u64;
+            priority::record_latency(ns as u32);
+        }
+        tim15.sr.modify(|_, w| unsafe { w.uif().bits(0) });
+    });
+    if unsafe { POOL.filling.is_some() } {
+        announce(DMA_CHANNEL.remaining() as usize);
+    }
+}
+
+// record the samples of the sweep being filled transferred, given the DMA channel's remaining
+// count
+fn announce(remaining: usize) {
+    unsafe {
+        let ready = POOL.length - remaining;
+        if ready > READY {
+            volatile_store(&mut READY, ready);
+            volatile_store(&mut READY_AT, millis());
+        }
+    }
+}
+
+/// Returns whether a chained sweep is under way, or finished, and not yet taken.
+pub fn chained() -> bool {
+    cortex_m::interrupt::free(|_| unsafe { POOL.chained() })
+}
+
+/// Stops and discards any chained sweeps, e.g. before a change to the sampling which would
+/// make their samples wrong, or to make auxiliary conversions without disturbing them.
+pub fn cancel_chain() {
+    cortex_m::interrupt::free(|_| {
+        let pool = unsafe { &mut POOL };
+        if pool.filling.is_some() && !pool.filling_current() {
+            DMA_CHANNEL.stop();
+            pool.filling = None;
+        }
+        pool.ready = None;
+    });
+}
+
+/// Returns the number of samples of the current sweep transferred by DMA to RAM, as announced
+/// by the interrupt handlers.
 pub fn get_transferred_sample_count() -> usize {
-    let dma1 = DMA1.get();
-    160 - unsafe { (*dma1).cndtr1.read().ndt().bits() } as usize
+    cortex_m::interrupt::free(|_| {
+        unsafe {
+            if POOL.filling_current() {
+                volatile_load(&READY)
+            } else {
+                POOL.length // finished, or stopped
+            }
+        }
+    })
 }
 
-/// Returns a reference to the sampled data for channel 1. Use `get_transferred_sample_count()` to
-/// determine how many samples are valid.
+/// Returns when sample `i` of the current sweep, one already announced, was taken, in
+/// milliseconds, from the time it was announced, or the sweep ended, and the sample rate. At
+/// sample rates up to EVENT_MAX_SAMPLE_RATE this is exact to a millisecond; above it, samples
+/// are only announced every half sweep, but sweeps then take a fraction of a second.
+pub fn sample_time_ms(i: usize) -> u32 {
+    let (ready, ready_at) = cortex_m::interrupt::free(|_| unsafe {
+        if POOL.filling_current() {
+            (READY, READY_AT)
+        } else {
+            (POOL.length, POOL.done_at[POOL.current])
+        }
+    });
+    let ready = if ready > i { ready } else { i + 1 };
+    let age_ms = ((ready - i) as u64 * 1000 / sample_rate() as u64) as u32;
+    ready_at.wrapping_sub(age_ms)
+}
+
+/// Returns a reference to the sampled data for channel 1, from the start of the current sweep.
+/// Use `get_transferred_sample_count()` to determine how many samples are valid.
 pub fn channel_1_data() -> &'static [u16] {
-    unsafe { &CAPTURE_CHANNEL_1 }
+    unsafe { &CAPTURE_CHANNEL_1[POOL.base(POOL.current)..] }
+}
+
+/// Returns ADC2's samples of the siggen's output, indexed as `channel_1_data()` is, for as many
+/// of them as it has, up to LOOPBACK_LENGTH.
+pub fn channel_2_data() -> &'static [u16] {
+    unsafe { &CAPTURE_CHANNEL_2 }
+}
+
+/// Copies the current sweep's record, `len` samples from `start`, out of the capture buffer to
+/// the snapshot by DMA, memory to memory, unless it is longer than SNAPSHOT_LENGTH, and releases
+/// the sweep's slot: with chaining, the next sweep then starts in a free slot, if one isn't
+/// already under way, dropping the rest of this sweep if it hasn't finished. Call it once the
+/// record is complete, and read the record with `record` afterwards.
+pub fn snapshot(start: usize, len: usize) {
+    if len > SNAPSHOT_LENGTH || unsafe { SNAPSHOT_TAKEN.is_some() } {
+        return;
+    }
+    wait_for_copy();
+    unsafe {
+        let from = POOL.base(POOL.current) + start;
+        COPY_DMA_CHANNEL.set_peripheral(&CAPTURE_CHANNEL_1[from] as *const _ as u32);
+        COPY_DMA_CHANNEL.set_memory(&SNAPSHOT[0] as *const _ as u32);
+    }
+    COPY_DMA_CHANNEL.set_count(len as u16);
+    COPY_DMA_CHANNEL.start();
+    wait_for_copy();
+    cortex_m::interrupt::free(|cs| {
+        let adc1 = ADC1.borrow(cs);
+        let pool = unsafe { &mut POOL };
+        if pool.filling_current() {
+            // the record is complete, so the rest of the sweep isn't needed
+            DMA_CHANNEL.stop();
+            pool.tripped[pool.current] = adc1.isr.read().bits() & 1 << 7 != 0;
+            pool.done_at[pool.current] = millis();
+            pool.filling = None;
+        }
+        unsafe { SNAPSHOT_TAKEN = Some((len, pool.tripped[pool.current])); }
+        pool.released = true;
+        if unsafe { CHAINING } && pool.filling.is_none() {
+            if let Some(slot) = pool.free_slot() {
+                start_dma(adc1, pool, slot);
+            }
+        }
+    });
+}
+
+/// Returns samples `start` to `end` of the current sweep: from the snapshot, if `snapshot` has
+/// copied them there, or otherwise from the capture buffer.
+pub fn record(start: usize, end: usize) -> &'static [u16] {
+    unsafe {
+        match SNAPSHOT_TAKEN {
+            Some((len, _)) => {
+                debug_assert!(end - start == len);
+                &SNAPSHOT[..len]
+            }
+            None => &channel_1_data()[start..end],
+        }
+    }
+}
+
+/// Starts copying `len` samples within the capture buffer, from `from` to `to`, for keeping
+/// part of a record somewhere the following sweeps won't overwrite. The copy is made by DMA,
+/// memory to memory, leaving the CPU free; the next sweep waits for it, and so should anything
+/// reading the copy, with `wait_for_copy`.
+pub fn copy_samples(from: usize, to: usize, len: usize) {
+    debug_assert!(from + len <= to || to + len <= from);
+    debug_assert!(len > 0 && len <= 0xffff);
+    wait_for_copy();
+    unsafe {
+        COPY_DMA_CHANNEL.set_peripheral(&CAPTURE_CHANNEL_1[from] as *const _ as u32);
+        COPY_DMA_CHANNEL.set_memory(&CAPTURE_CHANNEL_1[to] as *const _ as u32);
+    }
+    COPY_DMA_CHANNEL.set_count(len as u16);
+    COPY_DMA_CHANNEL.start();
+}
+
+/// Waits for a copy started by `copy_samples` to finish, which takes a few cycles a sample.
+pub fn wait_for_copy() {
+    while COPY_DMA_CHANNEL.remaining() != 0 {}
+    COPY_DMA_CHANNEL.stop();
+    COPY_DMA_CHANNEL.clear_complete();
+}
+
+/// Captures `samples` samples in one sweep, at the current timebase, and waits for them. For
+/// tests run outside the main loop's sweeps, with the gate off.
+pub fn capture_now(samples: usize) -> &'static [u16] {
+    wait_for_auxiliary_conversions();
+    let chaining = unsafe { CHAINING };
+    set_chaining(false);
+    begin_sweep(samples);
+    while get_transferred_sample_count() < samples {}
+    finish_sweep();
+    set_chaining(chaining);
+    &channel_1_data()[..samples]
 }
 
-/// Turns off DMA and prepares for the next sweep.
+/// Ends the current sweep, once its record is complete. Without chaining, this turns off DMA;
+/// with it, the rest of the sweep carries on to the interrupt, which starts the next one.
 pub fn finish_sweep() {
-    let dma1 = DMA1.get();
-    // - disable DMA
-    unsafe { (*dma1).ccr1.modify(|_, w| w.en().bits(0)); }
-    // - re-set transfer length
-    unsafe { (*dma1).cndtr1.write(|w| w.ndt().bits(160)); }
+    if unsafe { !CHAINING } {
+        abandon_sweep();
+    }
+}
+
+/// Turns off DMA, discarding the rest of the current sweep and any chained ones, e.g. when no
+/// trigger was found.
+pub fn abandon_sweep() {
+    cortex_m::interrupt::free(|cs| {
+        DMA_CHANNEL.stop();
+        let pool = unsafe { &mut POOL };
+        if pool.filling_current() {
+            let adc1 = ADC1.borrow(cs);
+            pool.tripped[pool.current] = adc1.isr.read().bits() & 1 << 7 != 0;
+            pool.done_at[pool.current] = millis();
+        }
+        pool.filling = None;
+        pool.ready = None;
+    });
 }
 
 /// Checks the AC OVR overrun flag, and clears it if set. Returns its value before it was cleared.
@@ -210,25 +788,188 @@ pub fn check_adc_ovr_flag() -> bool {
     ovr
 }
 
-/// Sets the timebase for sampling, to the specified number of samples per second.
-/// This sets the TIM15 update rate, and -FIX- should set the sample time as well, but doesn't yet.
-// -FIX- this works well out to 1 sample per second, but it might be cool to implement very long
-// sample intervals, e.g. one sample per minute or more.
-pub fn set_timebase(samples_per_second: u32) {
-    let arr;
-    let psc;
-    if samples_per_second > 1097 {
-        arr = 72_000_000 / samples_per_second - 1;
-        psc = 0;
+/// Sets the analog watchdog's limits on the signal input, as raw conversions, from the next
+/// sweep, which won't be a chained one. With neither, the watchdog is off.
+pub fn set_watchdog(low: Option<u16>, high: Option<u16>) {
+    unsafe {
+        WATCHDOG = (low, high);
+        WATCHDOG_CHANGED = true;
+    }
+}
+
+// stop conversions, which the watchdog settings require, and apply them
+fn apply_watchdog(adc1: &adc1::RegisterBlock) {
+    if adc1.cr.read().adstart().bits() != 0 {
+        adc1.cr.modify(|_, w| unsafe { w.adstp().bits(1) });
+        while adc1.cr.read().adstp().bits() != 0 {}
+    }
+    let (low, high) = unsafe { WATCHDOG };
+    let tr1 = (high.unwrap_or(0xfff) as u32) << 16 | low.unwrap_or(0) as u32;
+    adc1.tr1.write(|w| unsafe { w.bits(tr1) });
+    // AWD1CH, the signal input, with AWD1EN and AWD1SGL, watching that channel alone
+    let awd = if low.is_some() || high.is_some() {
+        (SIGNAL_INPUT.channel as u32) << 26 | 1 << 23 | 1 << 22
     } else {
-        arr = (72_000_000 / 2250) / samples_per_second - 1;
-        psc = 2249;
+        0
+    };
+    adc1.cfgr.modify(|r, w| unsafe { w.bits(r.bits() & !(0b11111 << 26 | 0b11 << 22) | awd) });
+    unsafe { WATCHDOG_CHANGED = false; }
+}
+
+/// Returns whether the analog watchdog saw a sample outside its limits during the last sweep.
+pub fn watchdog_tripped() -> bool {
+    cortex_m::interrupt::free(|cs| {
+        let pool = unsafe { &POOL };
+        if let Some((_, tripped)) = unsafe { SNAPSHOT_TAKEN } {
+            tripped // the current sweep's slot may now hold a chained sweep
+        } else if pool.filling_current() {
+            ADC1.borrow(cs).isr.read().bits() & 1 << 7 != 0
+        } else {
+            pool.tripped[pool.current] // the flag may now belong to a chained sweep
+        }
+    })
+}
+
+/// Checks the DMA1 channel 1 transfer error flag, and clears it if set. Returns its value before
+/// it was cleared.
+pub fn check_dma_error_flag() -> bool {
+    DMA_CHANNEL.take_error()
+}
+
+/// Starts conversion of the auxiliary channels, unless they are already being converted.
+pub fn start_auxiliary_conversions() {
+    cortex_m::interrupt::free(|cs| {
+        let adc1 = ADC1.borrow(cs);
+        if adc1.cr.read().jadstart().bits() == 0 {
+            adc1.cr.modify(|_, w| unsafe { w.jadstart().bits(1) });
+        }
+    });
+}
+
+/// Stores the auxiliary conversion results, if a set has completed since the last call.
+pub fn poll_auxiliary_conversions() {
+    cortex_m::interrupt::free(|cs| {
+        let adc1 = ADC1.borrow(cs);
+        if adc1.isr.read().jeos().bits() != 0 {
+            unsafe {
+                AUXILIARY_RAW[0] = adc1.jdr1.read().bits() as u16;
+                AUXILIARY_RAW[1] = adc1.jdr2.read().bits() as u16;
+                AUXILIARY_RAW[2] = adc1.jdr3.read().bits() as u16;
+            }
+            adc1.isr.write(|w| unsafe { w.jeos().bits(1).jeoc().bits(1) });
+        }
+    });
+}
+
+/// Waits for any auxiliary conversions in progress to finish, and stores their results.
+pub fn wait_for_auxiliary_conversions() {
+    let adc1 = ADC1.get();
+    while unsafe { (*adc1).cr.read().jadstart().bits() } != 0 {}
+    poll_auxiliary_conversions();
+}
+
+/// Selects DC_INPUTS[i] as the auxiliary DC input, from the next auxiliary conversions.
+pub fn set_dc_input(i: usize) {
+    // the injected sequence can only be changed while it isn't being converted
+    wait_for_auxiliary_conversions();
+    cortex_m::interrupt::free(|cs| {
+        let adc1 = ADC1.borrow(cs);
+        adc1.jsqr.modify(|_, w| unsafe { w.jsq3().bits(DC_INPUTS[i].channel) });
+    });
+    unsafe { DC_INPUT_INDEX = i; }
+}
+
+/// Returns the index into DC_INPUTS of the auxiliary DC input.
+pub fn dc_input() -> usize {
+    unsafe { DC_INPUT_INDEX }
+}
+
+/// Returns the latest auxiliary measurements.
+pub fn auxiliary() -> Auxiliary {
+    let raw = unsafe { AUXILIARY_RAW };
+    let ts_cal1 = unsafe { ptr::read_volatile(TS_CAL1 as *const u16) } as i32;
+    let ts_cal2 = unsafe { ptr::read_volatile(TS_CAL2 as *const u16) } as i32;
+    let vrefint_cal = unsafe { ptr::read_volatile(VREFINT_CAL as *const u16) } as u32;
+    // VREFINT is fixed, so its conversion gives the supply voltage
+    let supply_mv = if raw[1] > 0 { 3300 * vrefint_cal / raw[1] as u32 } else { 3300 };
+    // the temperature sensor was calibrated at 3.3V, so scale to that supply first
+    let temperature_raw = raw[0] as i32 * supply_mv as i32 / 3300;
+    Auxiliary {
+        temperature: 300 + (temperature_raw - ts_cal1) * 800 / (ts_cal2 - ts_cal1),
+        supply_mv: supply_mv,
+        input_mv: raw[2] as u32 * supply_mv / 4096,
     }
+}
+
+/// Re-runs the ADC's self calibration. This must only be called between sweeps.
+pub fn recalibrate() {
+    cancel_chain();
+    cortex_m::interrupt::free(|cs| {
+        let adc1 = ADC1.borrow(cs);
+        // stop conversions, and disable the ADC, as calibration requires
+        if adc1.cr.read().adstart().bits() != 0 {
+            adc1.cr.modify(|_, w| unsafe { w.adstp().bits(1) });
+            while adc1.cr.read().adstp().bits() != 0 {}
+        }
+        if adc1.cr.read().jadstart().bits() != 0 {
+            adc1.cr.modify(|_, w| unsafe { w.jadstp().bits(1) });
+            while adc1.cr.read().jadstp().bits() != 0 {}
+        }
+        adc1.cr.modify(|_, w| unsafe { w.addis().bits(1) });
+        while adc1.cr.read().aden().bits() != 0 {}
+        // calibrate, single-ended
+        adc1.cr.modify(|_, w| unsafe { w.adcaldif().bits(0) });
+        adc1.cr.modify(|_, w| unsafe { w.adcal().bits(1) });
+        while adc1.cr.read().adcal().bits() != 0 {}
+        // re-enable the ADC
+        adc1.cr.modify(|_, w| unsafe { w.aden().bits(1) });
+        while adc1.isr.read().adrdy().bits() == 0 {}
+    });
+}
+
+/// Sets whether sampling is gated, and by which level of the gate input. Returns false,
+/// leaving sampling ungated, if the board has no gate input.
+pub fn set_gate(gate: Gate) -> bool {
+    if GATE_INPUT.is_none() && gate != Gate::Off {
+        return false;
+    }
+    cancel_chain();
+    cortex_m::interrupt::free(|cs| {
+        let tim15 = TIM15.borrow(cs);
+        // CC1P inverts TI1FP1, so the counter runs while the input is low
+        let cc1p = if gate == Gate::Low { 1 << 1 } else { 0 };
+        tim15.ccer.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 1) | cc1p) });
+        // SMS = 101, gated mode, with TS = 101, TI1FP1; or SMS = 000, slave mode disabled
+        let smcr = if gate == Gate::Off { 0 } else { 0b101 << 4 | 0b101 };
+        tim15.smcr.modify(|r, w| unsafe { w.bits(r.bits() & !(0b111 << 4 | 0b111) | smcr) });
+    });
+    true
+}
+
+/// Sets the timebase for sampling, to as close to the specified number of samples per second as
+/// TIM15 can manage, and returns the rate achieved. This sets the TIM15 update rate, and -FIX-
+/// should set the sample time as well, but doesn't yet.
+// -FIX- this works well out to 1 sample per second, but it might be cool to implement very long
+// sample intervals, e.g. one sample per minute or more.
+pub fn set_timebase(samples_per_second: u32) -> timer::Rate {
+    let rate = timer::solve(clocks::apb2_timer_hz(), samples_per_second, timer::MAX_ARR_16);
+    cancel_chain();
     cortex_m::interrupt::free(|cs| {
         let tim15 = TIM15.borrow(cs);
-        tim15.arr.write(|w| unsafe { w.bits(arr) });
-        tim15.psc.write(|w| unsafe { w.psc().bits(psc) });
+        tim15.arr.write(|w| unsafe { w.bits(rate.arr) });
+        tim15.psc.write(|w| unsafe { w.psc().bits(rate.psc) });
         tim15.cnt.write(|w| unsafe { w.cnt().bits(0) });
         tim15.egr.write(|w| unsafe { w.ug().bits(1) }); // immediately update registers
+        // announce each sample, unless that would take too much of the CPU
+        let uie = if rate.hz() <= EVENT_MAX_SAMPLE_RATE { 1 } else { 0 };
+        tim15.dier.write(|w| unsafe { w.uie().bits(uie) });
     });
+    unsafe { SAMPLE_RATE = rate.hz(); }
+    rate
+}
+
+/// Returns the sample rate actually achieved, which may differ a little from the rate asked of
+/// `set_timebase`. Time measurements should use this rather than the nominal rate.
+pub fn sample_rate() -> u32 {
+    unsafe { SAMPLE_RATE }
 }
@@ -0,0 +1,126 @@
+// stm32f3-oscilloscope - src/clocks.rs
+// the clock tree frequencies, as read back from the RCC configuration
+
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// `update` works the frequencies out from the RCC registers, so it must be called again
+// whenever the clock configuration changes; set_sys_clock() does this. Until then, the
+// frequencies are those of the reset state, running from the 8MHz HSI.
+
+use stm32f30x::RCC;
+
+use board::HSE_HZ;
+
+const HSI_HZ: u32 = 8_000_000;
+
+static mut SYSCLK_HZ: u32 = HSI_HZ;
+static mut HCLK_HZ: u32 = HSI_HZ;
+static mut PCLK1_HZ: u32 = HSI_HZ;
+static mut PCLK2_HZ: u32 = HSI_HZ;
+static mut APB1_TIMER_HZ: u32 = HSI_HZ;
+static mut APB2_TIMER_HZ: u32 = HSI_HZ;
+static mut FROM_HSE: bool = false;
+
+// the divisions selected by the AHB and APB prescaler fields of RCC_CFGR
+fn ahb_divisor(hpre: u32) -> u32 {
+    match hpre {
+        0b1000 => 2,
+        0b1001 => 4,
+        0b1010 => 8,
+        0b1011 => 16,
+        0b1100 => 64,
+        0b1101 => 128,
+        0b1110 => 256,
+        0b1111 => 512,
+        _ => 1,
+    }
+}
+
+fn apb_divisor(ppre: u32) -> u32 {
+    match ppre {
+        0b100 => 2,
+        0b101 => 4,
+        0b110 => 8,
+        0b111 => 16,
+        _ => 1,
+    }
+}
+
+/// Reads the clock configuration, and records the frequencies it gives.
+pub fn update() {
+    let rcc = unsafe { &*RCC.get() };
+    let cfgr = rcc.cfgr.read().bits();
+    let cfgr2 = rcc.cfgr2.read().bits();
+    let sws = (cfgr >> 2) & 0b11;
+    let sysclk = match sws {
+        0b01 => HSE_HZ,
+        0b10 => {
+            let prediv = (cfgr2 & 0b1111) + 1;
+            let input = if cfgr & (1 << 16) != 0 {
+                HSE_HZ / prediv // HSE/PREDIV
+            } else if cfgr & (1 << 15) != 0 {
+                HSI_HZ / prediv // HSI/PREDIV, on the larger parts only
+            } else {
+                HSI_HZ / 2
+            };
+            let multiplier = ((cfgr >> 18) & 0b1111) + 2;
+            input * if multiplier > 16 { 16 } else { multiplier }
+        }
+        _ => HSI_HZ,
+    };
+    let hclk = sysclk / ahb_divisor((cfgr >> 4) & 0b1111);
+    let apb1_divisor = apb_divisor((cfgr >> 8) & 0b111);
+    let apb2_divisor = apb_divisor((cfgr >> 11) & 0b111);
+    unsafe {
+        FROM_HSE = sws == 0b01 || (sws == 0b10 && cfgr & (1 << 16) != 0);
+        SYSCLK_HZ = sysclk;
+        HCLK_HZ = hclk;
+        PCLK1_HZ = hclk / apb1_divisor;
+        PCLK2_HZ = hclk / apb2_divisor;
+        // the timers get double the APB clock, unless it is undivided
+        APB1_TIMER_HZ = if apb1_divisor == 1 { PCLK1_HZ } else { 2 * PCLK1_HZ };
+        APB2_TIMER_HZ = if apb2_divisor == 1 { PCLK2_HZ } else { 2 * PCLK2_HZ };
+    }
+}
+
+/// Returns whether the system clock comes from the HSE clock, rather than the less accurate
+/// HSI.
+pub fn from_hse() -> bool { unsafe { FROM_HSE } }
+
+pub fn sysclk_hz() -> u32 { unsafe { SYSCLK_HZ } }
+
+/// The AHB clock, which clocks the core, SysTick (divided by 8), and the ADCs.
+pub fn hclk_hz() -> u32 { unsafe { HCLK_HZ } }
+
+/// The APB1 clock, which clocks SPI2 and the DAC.
+pub fn pclk1_hz() -> u32 { unsafe { PCLK1_HZ } }
+
+#[allow(unused)]
+pub fn pclk2_hz() -> u32 { unsafe { PCLK2_HZ } }
+
+/// The clock to TIM2 to TIM7.
+pub fn apb1_timer_hz() -> u32 { unsafe { APB1_TIMER_HZ } }
+
+/// The clock to TIM1, TIM8, and TIM15 to TIM17.
+pub fn apb2_timer_hz() -> u32 { unsafe { APB2_TIMER_HZ } }
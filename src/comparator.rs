@@ -0,0 +1,126 @@
+// stm32f3-oscilloscope - src/comparator.rs
+// analog comparator on the input, COMP7, with its threshold set by DAC channel 1
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// COMP7's non-inverting input can be PC1, the signal input on both boards, so the comparator
+// sees the input just as the ADC does, and switches within tens of nanoseconds of the signal
+// crossing its threshold, whatever the sample rate. It is for things a sampled trigger can't
+// do, in hardware: the first user is the reciprocal frequency counter.
+//
+// The threshold, the inverting input, is DAC channel 1, so it can be set in steps of a
+// millivolt or so, rather than being fixed by resistors or VREFINT's few fractions. Both
+// DAC channels belong to the siggen, so while the comparator is on, the ramp on PA4 is stopped
+// and channel 1 holds the threshold instead, which can then be measured on PA4. The threshold
+// follows the trigger level: follow() converts the level, a raw conversion, to microvolts at
+// the pin through the calibration, then to a DAC value at the ADC reference in use, since the
+// DAC and the ADC share VDDA. It is written only when it changes, so it can be called every
+// main loop pass.
+//
+// The comparator has medium hysteresis, around 15mV, so a slow or noisy input doesn't make its
+// output chatter at the crossing.
+
+use cortex_m;
+use stm32f30x::{COMP, RCC};
+
+use calibration;
+use siggen;
+
+// COMP7_CSR fields
+const EN: u32 = 1 << 0;
+const INMSEL_DAC1_CH1: u32 = 0b100 << 4; // the inverting input
+const NONINSEL_PC1: u32 = 1 << 7; // the non-inverting input, rather than PA0
+const HYST_MEDIUM: u32 = 0b10 << 16;
+const OUT: u32 = 1 << 30;
+const OUTSEL_SHIFT: u32 = 10;
+
+static mut ENABLED: bool = false;
+static mut THRESHOLD: Option<u16> = None; // the DAC value last written
+
+/// Turns on the comparator, taking DAC channel 1 from the siggen for the threshold, which is
+/// set by the next follow(); or turns it off, giving the channel back.
+pub fn set_enabled(on: bool) {
+    if on == enabled() {
+        return;
+    }
+    siggen::set_ramp(!on);
+    cortex_m::interrupt::free(|cs| {
+        let rcc = RCC.borrow(cs);
+        let comp = COMP.borrow(cs);
+
+        if on {
+            rcc.apb2enr.modify(|_, w| w.syscfgen().enabled()); // the comparators are in SYSCFG
+            comp.comp7_csr.write(|w| unsafe {
+                w.bits(EN | INMSEL_DAC1_CH1 | NONINSEL_PC1 | HYST_MEDIUM)
+            });
+        } else {
+            comp.comp7_csr.write(|w| unsafe { w.bits(0) });
+        }
+    });
+    unsafe {
+        ENABLED = on;
+        THRESHOLD = None;
+    }
+}
+
+/// Returns whether the comparator is on.
+pub fn enabled() -> bool {
+    unsafe { ENABLED }
+}
+
+/// Routes the comparator's output to a timer input, `outsel` being the COMP7_OUTSEL value for
+/// it, or 0 for none.
+pub fn set_output(outsel: u32) {
+    cortex_m::interrupt::free(|cs| {
+        let comp = COMP.borrow(cs);
+        comp.comp7_csr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(0b1111 << OUTSEL_SHIFT) | outsel << OUTSEL_SHIFT)
+        });
+    });
+}
+
+/// Returns whether the input is above the threshold.
+pub fn output() -> bool {
+    cortex_m::interrupt::free(|cs| COMP.borrow(cs).comp7_csr.read().bits() & OUT != 0)
+}
+
+/// Sets the threshold to the trigger level `level`, a raw conversion, if the comparator is on.
+pub fn follow(level: u16) {
+    if !enabled() {
+        return;
+    }
+    let reference_uv = calibration::reference_mv() as u64 * 1000;
+    let value = calibration::microvolts(level) as u64 * 4096 / reference_uv;
+    let value = if value > 4095 { 4095 } else { value as u16 };
+    if unsafe { THRESHOLD } != Some(value) {
+        siggen::set_channel_1(value);
+        unsafe { THRESHOLD = Some(value); }
+    }
+}
+
+/// Returns the threshold, in microvolts at the pin, if the comparator is on.
+pub fn threshold_microvolts() -> Option<u32> {
+    unsafe { THRESHOLD }.map(|value| {
+        (value as u64 * calibration::reference_mv() as u64 * 1000 / 4096) as u32
+    })
+}
@@ -0,0 +1,114 @@
+// stm32f3-oscilloscope - src/console.rs
+// message console: a RAM log of timestamped messages, viewable in the trace area
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// Messages are logged whether or not the console is being displayed, into a ring buffer of the
+// most recent LINES lines, so the history is still there when the console is next shown. Each
+// line starts with the time since reset in seconds. Lines are drawn in the small proportional
+// font, to fit in as much as possible, and are clipped at the screen edge.
+
+use font::FONT_5X7;
+use screen;
+use st7735::*;
+
+use time::millis;
+
+const LINES: usize = 16;
+const COLUMNS: usize = 32; // about the most that fit across the screen
+const TIMESTAMP_COLUMNS: usize = 6; // "sssss "
+
+const TOP: u8 = 0;
+const LINE_HEIGHT: u8 = 8; // the font height, plus a pixel between lines
+
+static mut TEXT: [[u8; COLUMNS]; LINES] = [[b' '; COLUMNS]; LINES];
+static mut NEXT: usize = 0;  // the line to be written next
+static mut COUNT: usize = 0; // the number of lines written, up to LINES
+static mut CHANGED: bool = true; // lines have been logged since the console was last drawn
+
+/// Logs a message, with a timestamp.
+pub fn log(message: &[u8]) {
+    let seconds = millis() / 1000;
+    unsafe {
+        let line = &mut TEXT[NEXT];
+        *line = [b' '; COLUMNS];
+        // right-aligned seconds, wrapping after a bit more than a day
+        let mut n = seconds;
+        let mut i = TIMESTAMP_COLUMNS - 1;
+        while i > 0 {
+            i -= 1;
+            line[i] = b'0' + (n % 10) as u8;
+            n /= 10;
+            if n == 0 {
+                break;
+            }
+        }
+        let len = if message.len() > COLUMNS - TIMESTAMP_COLUMNS {
+            COLUMNS - TIMESTAMP_COLUMNS
+        } else {
+            message.len()
+        };
+        line[TIMESTAMP_COLUMNS..TIMESTAMP_COLUMNS + len].copy_from_slice(&message[..len]);
+        NEXT = (NEXT + 1) % LINES;
+        if COUNT < LINES {
+            COUNT += 1;
+        }
+        CHANGED = true;
+    }
+}
+
+/// Marks the console as needing to be redrawn, e.g. after the trace area has been cleared.
+pub fn invalidate() {
+    unsafe { CHANGED = true; }
+}
+
+/// Draws the most recent lines in the trace area, oldest first, if anything has changed since
+/// the last time.
+pub fn draw() {
+    unsafe {
+        if !CHANGED {
+            return;
+        }
+        CHANGED = false;
+        // as many lines as fit above the status line, 14 in landscape
+        let visible = screen::trace_height() as usize / LINE_HEIGHT as usize;
+        let visible = if visible < LINES { visible } else { LINES };
+        let shown = if COUNT < visible { COUNT } else { visible };
+        for row in 0..shown {
+            let line = (NEXT + LINES - shown + row) % LINES;
+            let color = if row + 1 == shown { St7735Color::White } else { St7735Color::Green };
+            let mut len = COLUMNS;
+            while len > 0 && TEXT[line][len - 1] == b' ' {
+                len -= 1;
+            }
+            let y = TOP + LINE_HEIGHT * row as u8;
+            let end = FONT_5X7.draw(&TEXT[line][..len], 0, y, color, St7735Color::Black);
+            // clear the gap below the text, and the rest of the line
+            st7735_fill_rect(0, (y + 7) as i16, end as i16, 1, St7735Color::Black as u16);
+            let width = screen::width();
+            if (end as i16) < width {
+                st7735_fill_rect(end as i16, y as i16, width - end as i16, LINE_HEIGHT as i16,
+                                 St7735Color::Black as u16);
+            }
+        }
+    }
+}
@@ -0,0 +1,327 @@
+// stm32f3-oscilloscope - src/counter.rs
+// reciprocal frequency counter: the comparator's output timed by TIM1
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The counter mode measures the input's frequency independently of the ADC: the comparator
+// (comparator.rs) squares up the input at the trigger level, and its output is routed inside
+// the chip to TIM1's input capture 2. TIM1 counts the timer clock, 72MHz, and every eighth
+// rising edge (the capture prescaler) captures the count; the capture interrupt extends it to
+// 64 bits with the count of overflows. Over each gate, GATE_MS unless set otherwise (the mains
+// monitor's are longer), the counter keeps the first and last capture times and the number of
+// periods between them, so the frequency is
+//     periods * clock / (last - first)
+// which is reciprocal counting: the resolution is one clock tick in the whole time measured,
+// around 14ns in a second, or eight digits, whatever the frequency, rather than one period in
+// the gate, as counting edges would give. Each gate begins at the last capture of the one
+// before, so no periods are missed between them.
+//
+// A slow signal, with fewer than two captures in a gate, stretches the gate until there are,
+// up to TIMEOUT_MS longer, past which there is no signal. A fast one costs an interrupt every
+// eight periods; past 1MHz or so the interrupts can't keep up, a capture is overwritten before
+// it is read, and the gate is discarded rather than miscounted.
+//
+// TIM1 is also the PWM analyzer's timer, so while the counter runs, it takes TIM1 over, and
+// gives it back, set up again for PWM input, when it stops. It also takes the comparator, and
+// with it the siggen's ramp output.
+
+use cortex_m;
+use stm32f30x::{Interrupt, TIM1};
+
+use clocks;
+use comparator;
+use measure;
+use priority;
+use pwm;
+use segment;
+use st7735::*;
+use time;
+
+/// How long each gate lasts by default, in milliseconds.
+pub const GATE_MS: u32 = 1000;
+// how much longer a gate may stretch waiting for a second capture before there is no signal
+const TIMEOUT_MS: u32 = 10_000;
+// the periods between captures, the capture prescaler
+const PERIODS_PER_CAPTURE: u64 = 8;
+// COMP7_OUTSEL for TIM1's input capture 2
+const OUTSEL_TIM1_IC2: u32 = 0b0111;
+
+const SR_UIF: u32 = 1 << 0;
+const SR_CC2OF: u32 = 1 << 10;
+
+/// One gate's reading.
+#[derive(Clone, Copy)]
+pub struct Reading {
+    pub frequency_uhz: u64, // microhertz
+    pub period_ps: u64,     // picoseconds
+    pub periods: u64,       // counted in the gate
+}
+
+static mut RUNNING: bool = false;
+static mut GATE: u32 = GATE_MS;
+// written by the interrupt handlers
+static mut OVERFLOWS: u64 = 0;
+static mut FIRST: Option<u64> = None; // the capture time the gate began at
+static mut LAST: u64 = 0; // the latest capture time
+static mut PERIODS: u64 = 0; // the periods from FIRST to LAST
+static mut OVERRUN: bool = false; // a capture was lost in this gate
+// read by the main loop
+static mut GATE_STARTED: u32 = 0;
+static mut READING: Option<Reading> = None;
+
+/// Starts the counter, taking TIM1 and the comparator; or with `on` false, stops it, giving
+/// them back. Doing either twice does nothing.
+pub fn set_running(on: bool) {
+    if on == unsafe { RUNNING } {
+        return;
+    }
+    unsafe { RUNNING = on; }
+    if !on {
+        cortex_m::interrupt::free(|cs| {
+            let tim1 = TIM1.borrow(cs);
+            let nvic = cortex_m::peripheral::NVIC.borrow(cs);
+
+            tim1.dier.write(|w| unsafe { w.bits(0) });
+            nvic.disable(Interrupt::Tim1Cc);
+            nvic.disable(Interrupt::Tim1UpTim16);
+        });
+        comparator::set_output(0);
+        comparator::set_enabled(false);
+        pwm::setup();
+        return;
+    }
+    comparator::set_enabled(true);
+    comparator::set_output(OUTSEL_TIM1_IC2);
+    cortex_m::interrupt::free(|cs| {
+        let tim1 = TIM1.borrow(cs);
+        let nvic = cortex_m::peripheral::NVIC.borrow(cs);
+
+        tim1.cr1.write(|w| unsafe { w.bits(0) });
+        tim1.smcr.write(|w| unsafe { w.bits(0) }); // free running
+        // IC2 is TI2, the comparator, captured every eighth edge
+        tim1.ccmr1_input.write(|w| unsafe { w.bits(0b11 << 10 | 0b01 << 8) }); // IC2PSC, CC2S
+        tim1.ccer.write(|w| unsafe { w.bits(1 << 4) }); // CC2E, rising edges
+        tim1.arr.write(|w| unsafe { w.bits(0xffff) });
+        tim1.psc.write(|w| unsafe { w.bits(0) });
+        tim1.egr.write(|w| unsafe { w.ug().bits(1) }); // immediately update registers
+        tim1.sr.write(|w| unsafe { w.bits(0) });
+        unsafe {
+            OVERFLOWS = 0;
+            FIRST = None;
+            PERIODS = 0;
+            OVERRUN = false;
+            READING = None;
+        }
+        tim1.dier.write(|w| unsafe { w.bits(1 << 2 | 1 << 0) }); // CC2IE, UIE
+        // the capture and overflow interrupts share a priority, so neither interrupts the other
+        // while it counts overflows
+        unsafe {
+            nvic.set_priority(Interrupt::Tim1Cc, priority::COUNTER);
+            nvic.set_priority(Interrupt::Tim1UpTim16, priority::COUNTER);
+        }
+        nvic.enable(Interrupt::Tim1Cc);
+        nvic.enable(Interrupt::Tim1UpTim16);
+        tim1.cr1.write(|w| unsafe { w.cen().bits(1) });
+    });
+    unsafe { GATE_STARTED = time::millis(); }
+}
+
+/// Sets the gate time, in milliseconds, from the next gate.
+pub fn set_gate_ms(ms: u32) {
+    unsafe { GATE = ms; }
+}
+
+/// Returns whether the counter is running.
+pub fn running() -> bool {
+    unsafe { RUNNING }
+}
+
+/// Counts an overflow of TIM1. Called from its update interrupt handler.
+pub fn overflow_interrupt() {
+    cortex_m::interrupt::free(|cs| {
+        let tim1 = TIM1.borrow(cs);
+        // the capture handler may have counted it already
+        if tim1.sr.read().bits() & SR_UIF != 0 {
+            tim1.sr.write(|w| unsafe { w.bits(!SR_UIF) });
+            unsafe { OVERFLOWS += 1; }
+        }
+    });
+}
+
+/// Takes a capture. Called from TIM1's capture interrupt handler.
+pub fn capture_interrupt() {
+    cortex_m::interrupt::free(|cs| {
+        let tim1 = TIM1.borrow(cs);
+        let sr = tim1.sr.read().bits();
+        let capture = tim1.ccr2.read().bits() as u64; // clears CC2IF
+        unsafe {
+            // an overflow not yet counted, before a capture early in the count, came first
+            if sr & SR_UIF != 0 && capture < 0x8000 {
+                tim1.sr.write(|w| w.bits(!SR_UIF));
+                OVERFLOWS += 1;
+            }
+            if sr & SR_CC2OF != 0 {
+                tim1.sr.write(|w| w.bits(!SR_CC2OF));
+                OVERRUN = true;
+            }
+            let at = OVERFLOWS << 16 | capture;
+            if FIRST.is_none() {
+                FIRST = Some(at);
+                PERIODS = 0;
+            } else {
+                PERIODS += PERIODS_PER_CAPTURE;
+            }
+            LAST = at;
+        }
+    });
+}
+
+/// Ends the gate if it is due, working out its reading, and starts the next. Returns whether
+/// there is a new reading, or the signal has just been lost.
+pub fn poll() -> bool {
+    let gate = unsafe { GATE };
+    if !running() || time::since(unsafe { GATE_STARTED }) < gate {
+        return false;
+    }
+    let (first, last, periods, overrun) = cortex_m::interrupt::free(|_| unsafe {
+        let gate = (FIRST, LAST, PERIODS, OVERRUN);
+        if PERIODS > 0 || OVERRUN {
+            // the next gate begins at this one's last capture
+            FIRST = Some(LAST);
+            PERIODS = 0;
+            OVERRUN = false;
+        }
+        gate
+    });
+    if periods == 0 && !overrun {
+        // too slow for two captures yet; stretch the gate, up to TIMEOUT_MS
+        if time::since(unsafe { GATE_STARTED }) < gate + TIMEOUT_MS {
+            return false;
+        }
+        // then there is no signal, and the next gate begins afresh at the next capture
+        cortex_m::interrupt::free(|_| unsafe { FIRST = None; });
+        unsafe { GATE_STARTED = time::millis(); }
+        let lost = reading().is_some();
+        unsafe { READING = None; }
+        return lost;
+    }
+    unsafe { GATE_STARTED = time::millis(); }
+    if overrun {
+        unsafe { READING = None; }
+        return true;
+    }
+    let ticks = last - first.unwrap_or(last);
+    if ticks == 0 {
+        return false;
+    }
+    let clock = clocks::apb2_timer_hz() as u64;
+    // microhertz, in two steps to stay within 64 bits
+    let millihertz = periods * clock * 1000;
+    let uhz = millihertz / ticks * 1000 + millihertz % ticks * 1000 / ticks;
+    let reading = Reading {
+        frequency_uhz: uhz,
+        period_ps: ticks * 1_000_000 / (periods * (clock / 1_000_000)),
+        periods: periods,
+    };
+    unsafe { READING = Some(reading); }
+    true
+}
+
+/// Returns the latest reading, or None if there is no signal.
+pub fn reading() -> Option<Reading> {
+    unsafe { READING }
+}
+
+const ROW_Y: i16 = 8;
+const ROW_PITCH: i16 = 32;
+const DIGITS_X: i16 = 16;
+const DIGITS_HEIGHT: i16 = 20;
+const DIGITS: usize = 8;
+
+// format `value`, in units of 1/scales[0] of units[0], to eight significant digits in the
+// largest unit that keeps four or fewer before the point, returning the unit
+fn format(value: u64, scales: [u64; 3], units: [&'static [u8]; 3], buf: &mut [u8; 9])
+          -> &'static [u8] {
+    let mut unit = 0;
+    while unit + 1 < units.len() && value >= scales[unit] * 10_000 {
+        unit += 1;
+    }
+    let scale = scales[unit];
+    let mut whole_digits = 1;
+    let mut whole = value / scale;
+    while whole >= 10 {
+        whole /= 10;
+        whole_digits += 1;
+    }
+    let decimals = if whole_digits > DIGITS { 0 } else { DIGITS - whole_digits };
+    let mut divisor = scale;
+    for _ in 0..decimals {
+        divisor /= 10;
+    }
+    measure::format_fixed(value / if divisor > 0 { divisor } else { 1 }, decimals, buf);
+    units[unit]
+}
+
+// draw one row: its label, digits, and unit
+fn draw_row(row: i16, label: &[u8], digits: &[u8], unit: &[u8]) {
+    let y = ROW_Y + row * ROW_PITCH;
+    let label_y = (y + DIGITS_HEIGHT - 12) as u8;
+    st7735_print(label, 0, label_y, St7735Color::Blue, St7735Color::Black);
+    let unit_x = segment::draw(digits, DIGITS_X, y, DIGITS_HEIGHT, St7735Color::Green,
+                               St7735Color::Black) + 2;
+    st7735_print(unit, unit_x as u8, label_y, St7735Color::Green, St7735Color::Black);
+}
+
+/// Draws the counter display: frequency, period, and the periods counted, with the
+/// comparator's threshold.
+pub fn draw() {
+    let mut buf = [b' '; 9];
+    match reading() {
+        Some(reading) => {
+            let unit = format(reading.frequency_uhz, [1_000_000, 1_000_000_000, 1_000_000_000_000],
+                              [b"Hz ", b"kHz", b"MHz"], &mut buf);
+            draw_row(0, b"F", &buf, unit);
+            let unit = format(reading.period_ps, [1_000_000, 1_000_000_000, 1_000_000_000_000],
+                              [b"us", b"ms", b"s "], &mut buf);
+            draw_row(1, b"P", &buf, unit);
+        }
+        None => {
+            draw_row(0, b"F", b"--------", b"   ");
+            draw_row(1, b"P", b"--------", b"   ");
+        }
+    }
+    let mut line = [b' '; 20];
+    line[..4].copy_from_slice(b"thr ");
+    if let Some(uv) = comparator::threshold_microvolts() {
+        measure::format_fixed(uv as u64 / 1000, 3, &mut line[4..10]);
+        line[10] = b'V';
+    }
+    let y = (ROW_Y + 2 * ROW_PITCH) as u8;
+    st7735_print(&line, 0, y, St7735Color::Blue, St7735Color::Black);
+    let mut line = [b' '; 20];
+    line[..2].copy_from_slice(b"n ");
+    if let Some(reading) = reading() {
+        measure::format_fixed(reading.periods, 0, &mut line[2..12]);
+    }
+    st7735_print(&line, 0, y + 12, St7735Color::Blue, St7735Color::Black);
+}
@@ -0,0 +1,214 @@
+// stm32f3-oscilloscope - src/cursors.rs
+// time cursors: two markers across the trace, reading the voltage at each and between them
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Each cursor is a dotted vertical line at a column of the screen, moved STEP columns at a
+// time, or off. Like the graticule dots, its pixels are repainted whenever part of a column
+// of the trace area is erased (see trace.rs), so the trace is always drawn over it.
+//
+// The readings come from the record rather than the screen, so they are finer than a pixel.
+// At the fastest timebases each sample spans `factor` columns, so a cursor can fall between
+// two samples; the voltage there is interpolated linearly between them, in microvolts at the
+// input (see input.rs), rather than taken from the nearer sample or from the screen row of the
+// interpolated trace. The time between the cursors is from the achieved sample rate, so it is
+// exact to a fraction of a sample. The readout is in the small font, below the measurements
+// row, with SI prefixes (see measure::format_si()).
+
+use calibration;
+use font::FONT_5X7;
+use input;
+use measure;
+use redraw;
+use screen;
+use st7735::*;
+
+/// The number of columns a cursor moves in a step.
+pub const STEP: usize = 4;
+
+const COLOR: St7735Color = St7735Color::Magenta;
+const DOT_PITCH: i16 = 2; // a dot on every other row
+
+// the readout, two lines of the small font below the measurements row
+const READOUT_Y: u8 = 13;
+const LINE_HEIGHT: u8 = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Cursor {
+    A,
+    B,
+}
+
+impl Cursor {
+    pub fn label(self) -> &'static [u8] {
+        match self {
+            Cursor::A => b"A",
+            Cursor::B => b"B",
+        }
+    }
+}
+
+static mut COLUMNS: [Option<usize>; 2] = [None; 2];
+static mut READOUT_END: [u8; 2] = [0; 2]; // where each line of the last readout ended
+
+/// Returns the column of `cursor`, or None if it is off.
+pub fn column(cursor: Cursor) -> Option<usize> {
+    unsafe { COLUMNS[cursor as usize] }
+}
+
+/// Moves `cursor` STEP columns right, or with `back`, left, turning it off past either edge
+/// of the screen, and on again at the other. Its old and new columns are marked for repainting.
+pub fn step(cursor: Cursor, back: bool) {
+    let last = (screen::width() as usize - 1) / STEP * STEP;
+    let old = column(cursor);
+    let new = match (old, back) {
+        (None, false) => Some(0),
+        (None, true) => Some(last),
+        (Some(x), false) if x + STEP <= last => Some(x + STEP),
+        (Some(x), true) if x >= STEP => Some(x - STEP),
+        _ => None,
+    };
+    unsafe { COLUMNS[cursor as usize] = new; }
+    for &x in old.iter().chain(new.iter()) {
+        redraw::invalidate_columns(x, 1);
+    }
+    if column(Cursor::A).is_none() && column(Cursor::B).is_none() {
+        clear_readout();
+    }
+}
+
+/// Repaints the dots of any cursor in column `x` between `top` and `bottom`, after erasing.
+pub fn repair(x: i16, top: i16, bottom: i16) {
+    let last_row = screen::trace_height() - 1;
+    let bottom = if bottom > last_row { last_row } else { bottom };
+    for &cursor in &[Cursor::A, Cursor::B] {
+        if column(cursor) != Some(x as usize) {
+            continue;
+        }
+        let mut y = top + (DOT_PITCH - top % DOT_PITCH) % DOT_PITCH;
+        while y <= bottom {
+            st7735_drawPixel(x, y, COLOR as u16);
+            y += DOT_PITCH;
+        }
+    }
+}
+
+/// Returns the voltage at column `x` of the screen showing `record`, where each sample spans
+/// `factor` columns, in microvolts at the input, interpolated linearly between samples. None
+/// if the record doesn't reach that far.
+pub fn microvolts_at(record: &[u16], x: usize, factor: usize) -> Option<i64> {
+    let n = x / factor;
+    let phase = (x % factor) as i64;
+    if n >= record.len() || (phase > 0 && n + 1 >= record.len()) {
+        return None;
+    }
+    let v0 = calibration::microvolts(record[n]) as i64;
+    let v = if phase == 0 {
+        v0
+    } else {
+        let v1 = calibration::microvolts(record[n + 1]) as i64;
+        v0 + (v1 - v0) * phase / factor as i64
+    };
+    Some(input::microvolts(v))
+}
+
+/// Returns the time from the left edge of the screen to column `x`, in nanoseconds, where
+/// each sample spans `factor` columns at `sample_rate`.
+pub fn nanoseconds_at(x: usize, factor: usize, sample_rate: u32) -> u64 {
+    x as u64 * 1_000_000_000 / (sample_rate as u64 * factor as u64)
+}
+
+/// Draws the readout for the screen showing `record`: the voltage at each cursor that is on,
+/// and with both on, the time and voltage between them.
+pub fn draw_readout(record: &[u16], factor: usize, sample_rate: u32) {
+    let (a, b) = (column(Cursor::A), column(Cursor::B));
+    if a.is_none() && b.is_none() {
+        return;
+    }
+    // "A 1.25V B -3.5V", then "dt 12.5us dV -4.75V"
+    let mut line = [b' '; 40];
+    let mut len = 0;
+    let mut volts = [None; 2];
+    for (i, &cursor) in [Cursor::A, Cursor::B].iter().enumerate() {
+        if let Some(x) = column(cursor) {
+            volts[i] = microvolts_at(record, x, factor);
+            len = append(&mut line, len, cursor.label());
+            len = append(&mut line, len, b" ");
+            len = append_si(&mut line, len, volts[i].map(|uv| uv * 1000), b"V");
+            len = append(&mut line, len, b" ");
+        }
+    }
+    draw_line(0, &line[..len - 1]); // without the last space
+    if let (Some(a), Some(b)) = (a, b) {
+        let (first, last) = if a < b { (a, b) } else { (b, a) };
+        let dt = nanoseconds_at(last - first, factor, sample_rate);
+        let mut len = append(&mut line, 0, b"dt ");
+        len = append_si(&mut line, len, Some(dt as i64), b"s");
+        len = append(&mut line, len, b" dV ");
+        let dv = match (volts[0], volts[1]) {
+            (Some(va), Some(vb)) => Some((vb - va) * 1000),
+            _ => None,
+        };
+        len = append_si(&mut line, len, dv, b"V");
+        draw_line(1, &line[..len]);
+    } else {
+        draw_line(1, b"");
+    }
+}
+
+// copy `text` into `line` at `len`, returning the new length
+fn append(line: &mut [u8; 40], len: usize, text: &[u8]) -> usize {
+    line[len..len + text.len()].copy_from_slice(text);
+    len + text.len()
+}
+
+// append `nano`, in nano-units, with an SI prefix and `unit`, or "--" if there is no value
+fn append_si(line: &mut [u8; 40], len: usize, nano: Option<i64>, unit: &[u8]) -> usize {
+    match nano {
+        Some(nano) => {
+            let mut text = [0u8; 12];
+            let n = measure::format_si(nano, unit, &mut text);
+            append(line, len, &text[..n])
+        }
+        None => append(line, len, b"--"),
+    }
+}
+
+// draw line `i` of the readout, clearing what's left of the last one
+fn draw_line(i: usize, text: &[u8]) {
+    let y = READOUT_Y + i as u8 * LINE_HEIGHT;
+    let end = FONT_5X7.draw(text, 0, y, COLOR, St7735Color::Black);
+    let last_end = unsafe { READOUT_END[i] };
+    if end < last_end {
+        st7735_fill_rect(end as i16, y as i16, (last_end - end) as i16, LINE_HEIGHT as i16,
+                         St7735Color::Black as u16);
+    }
+    unsafe { READOUT_END[i] = end; }
+}
+
+// erase the readout, repainting the columns under it
+fn clear_readout() {
+    let (first, second) = unsafe { (READOUT_END[0], READOUT_END[1]) };
+    redraw::invalidate_columns(0, if first > second { first } else { second } as usize);
+    unsafe { READOUT_END = [0; 2]; }
+}
@@ -0,0 +1,465 @@
+// stm32f3-oscilloscope - src/datalog.rs
+// data logger: periodic measurements, kept in a ring buffer in flash across power cycles
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// While logging, one measurement (see measure.rs) is appended to the log every interval, as
+// an entry in flash::LOG_PAGES pages of flash, so the log survives a reset or power cycle.
+// Logging resumes by itself after one, for recording standalone, until it is stopped. With an
+// external flash (see spiflash.rs), the log is instead kept in its sectors set aside for the
+// log, up to a megabyte (see records.rs), for logs 32 times as long.
+//
+// The pages are a ring buffer, written in turn, one entry after another. A page is erased just
+// before the first entry is written to it, losing the oldest entries, so each page is erased
+// once each time around the ring. Each entry has a check, so one left half written by a power
+// cut is skipped, and there is no index to wear out: the newest entry is found at startup by
+// scanning the log. Since the pages are written in turn, it is in the page whose first entry
+// is the newest, so only that page is scanned entry by entry.
+//
+// An entry is ENTRY_LENGTH half-words:
+//   0     the session, counting up each time logging starts or resumes; erased, 0xffff
+//   1, 2  the seconds since the session started
+//   3     the measurement, or STOP when logging was stopped
+//   4     the interval, in seconds
+//   5, 6  the value, signed, in thousandths of the measurement's unit, or NOT_MEASURED
+//   7     a check, making the entry's half-words sum to 0xffff
+// with 32-bit values low half first.
+//
+// A measurement is logged at the end of the first sweep after it is due, so with the trigger
+// in normal mode, the log has gaps where there were no trigger events.
+
+use core::i32;
+
+use console;
+use flash::{self, FlashError, LOG_PAGES, LOG_START, PAGE_SIZE};
+use measure::{self, Measurement, Results};
+use records;
+use serial;
+use spiflash::{self, SpiFlashError};
+use time;
+
+const ENTRY_LENGTH: usize = 8;
+const ENTRY_SIZE: u32 = ENTRY_LENGTH as u32 * 2; // bytes
+
+const ERASED: u16 = 0xffff;
+const STOP: u16 = 0xff;
+const NOT_MEASURED: u32 = 0x8000_0000; // the most negative value
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogError {
+    Flash(FlashError),       // in the on-chip flash
+    SpiFlash(SpiFlashError), // in the external flash
+}
+
+// where the log is kept: its pages, in the on-chip flash, or sectors, in the external flash
+#[derive(Clone, Copy)]
+struct Area {
+    start: u32,
+    end: u32,
+    page_size: u32,
+    external: bool,
+}
+
+/// One entry read from the log.
+#[derive(Clone, Copy)]
+pub struct Entry {
+    pub session: u16,
+    pub seconds: u32,
+    pub measurement: Option<Measurement>, // None for the end of a session
+    pub interval: u16,
+    pub value: Option<i32>,
+}
+
+#[derive(Clone, Copy)]
+struct Logging {
+    measurement: Measurement,
+    interval: u16, // seconds
+    session: u16,
+    seconds: u32,   // the session time of the last entry
+    last_at: u32,   // when it was due, in milliseconds since reset
+    first: bool,    // the first entry hasn't been written
+}
+
+static mut AREA: Area = Area {
+    start: LOG_START,
+    end: LOG_START + (LOG_PAGES * PAGE_SIZE) as u32,
+    page_size: PAGE_SIZE as u32,
+    external: false,
+};
+static mut LOGGING: Option<Logging> = None;
+static mut NEXT: u32 = LOG_START; // the address of the slot for the next entry
+static mut LAST_SESSION: Option<u16> = None; // the newest session in the log
+
+fn measurement_from(n: u16) -> Option<Measurement> {
+    let mut measurement = Measurement::Off;
+    loop {
+        if measurement as u16 == n {
+            return Some(measurement);
+        }
+        measurement = measurement.next();
+        if measurement == Measurement::Off {
+            return None;
+        }
+    }
+}
+
+fn area() -> Area {
+    unsafe { AREA }
+}
+
+// the number of entries the log has room for
+fn slots() -> usize {
+    let area = area();
+    ((area.end - area.start) / ENTRY_SIZE) as usize
+}
+
+// the half-words of the slot at `address`, all zeros if they can't be read
+fn read_slot(address: u32) -> [u16; ENTRY_LENGTH] {
+    let mut data = [0u16; ENTRY_LENGTH];
+    if area().external {
+        let mut bytes = [0u8; 2 * ENTRY_LENGTH];
+        if spiflash::read(address, &mut bytes).is_ok() {
+            for (i, half_word) in data.iter_mut().enumerate() {
+                *half_word = bytes[2 * i] as u16 | (bytes[2 * i + 1] as u16) << 8;
+            }
+        }
+    } else {
+        for (i, half_word) in data.iter_mut().enumerate() {
+            *half_word = flash::read_log(address + 2 * i as u32);
+        }
+    }
+    data
+}
+
+fn erased(address: u32) -> bool {
+    read_slot(address).iter().all(|&half_word| half_word == ERASED)
+}
+
+// read the entry at `address`, if there is a whole one there
+fn read(address: u32) -> Option<Entry> {
+    let data = read_slot(address);
+    let sum = data.iter().fold(0u16, |sum, &half_word| sum.wrapping_add(half_word));
+    if data[0] == ERASED || sum != 0xffff {
+        return None;
+    }
+    let value = data[5] as u32 | (data[6] as u32) << 16;
+    Some(Entry {
+        session: data[0],
+        seconds: data[1] as u32 | (data[2] as u32) << 16,
+        measurement: if data[3] == STOP { None } else { measurement_from(data[3]) },
+        interval: data[4],
+        value: if value == NOT_MEASURED { None } else { Some(value as i32) },
+    })
+}
+
+// the address of the slot after the one at `address`, around the ring
+fn after(address: u32) -> u32 {
+    let area = area();
+    if address + ENTRY_SIZE >= area.end { area.start } else { address + ENTRY_SIZE }
+}
+
+// the first entry in the page at `page`, and its address, if there is one; a page is written
+// from its start, so it has none if its first slot is erased
+fn first_entry(page: u32) -> Option<(u32, Entry)> {
+    let end = page + area().page_size;
+    let mut address = page;
+    while address < end && !erased(address) {
+        if let Some(entry) = read(address) {
+            return Some((address, entry));
+        }
+        address += ENTRY_SIZE;
+    }
+    None
+}
+
+// whether `entry` is newer than `newest`: a session's end has the time of its last entry, but
+// comes after it
+fn is_newer(entry: &Entry, newest: &Option<(u32, Entry)>) -> bool {
+    match *newest {
+        Some((_, ref n)) => {
+            let key = (entry.session, entry.seconds);
+            let newest_key = (n.session, n.seconds);
+            key > newest_key || key == newest_key && entry.measurement.is_none()
+        }
+        None => true,
+    }
+}
+
+/// Calls `f` with each entry in the log, oldest first.
+pub fn for_each<F: FnMut(&Entry)>(mut f: F) {
+    let area = area();
+    let page_slots = (area.page_size / ENTRY_SIZE) as usize;
+    // the slot for the next entry is the oldest
+    let mut address = unsafe { NEXT };
+    let mut remaining = slots();
+    while remaining > 0 {
+        if (address - area.start) % area.page_size == 0 && erased(address) {
+            // nothing has been written to the page since it was erased, so skip it, which
+            // saves reading most of a long log that is still filling up
+            remaining -= if remaining < page_slots { remaining } else { page_slots };
+            address += area.page_size;
+            if address >= area.end {
+                address = area.start;
+            }
+            continue;
+        }
+        if let Some(entry) = read(address) {
+            f(&entry);
+        }
+        address = after(address);
+        remaining -= 1;
+    }
+}
+
+/// Moves the log to the external flash, if there is one with room, then finds the newest
+/// entry in the log, and resumes logging if it wasn't stopped. Returns whether it was resumed.
+pub fn setup() -> bool {
+    if let Some((start, end)) = records::log_area() {
+        unsafe {
+            AREA = Area {
+                start: start,
+                end: end,
+                page_size: spiflash::SECTOR_SIZE,
+                external: true,
+            };
+            NEXT = start;
+        }
+    }
+    let area = area();
+    // the page with the newest first entry, then the newest entry in it
+    let mut newest: Option<(u32, Entry)> = None;
+    let mut page = area.start;
+    while page < area.end {
+        if let Some((address, entry)) = first_entry(page) {
+            if is_newer(&entry, &newest) {
+                newest = Some((address, entry));
+            }
+        }
+        page += area.page_size;
+    }
+    let (first, _) = match newest {
+        Some(newest) => newest,
+        None => return false,
+    };
+    let page = first - (first - area.start) % area.page_size;
+    let mut address = page;
+    while address < page + area.page_size {
+        if let Some(entry) = read(address) {
+            if is_newer(&entry, &newest) {
+                newest = Some((address, entry));
+            }
+        }
+        address += ENTRY_SIZE;
+    }
+    let (address, entry) = match newest {
+        Some(newest) => newest,
+        None => return false,
+    };
+    unsafe { LAST_SESSION = Some(entry.session); }
+    // the next slot is the first erased one after the newest entry, in the same page, or the
+    // start of the next page, skipping any left half written by a power cut
+    let mut next = after(address);
+    while next != area.start && (next - area.start) % area.page_size != 0 && !erased(next) {
+        next = after(next);
+    }
+    unsafe { NEXT = next; }
+    match entry.measurement {
+        Some(measurement) => start(measurement, entry.interval).is_ok(),
+        None => false,
+    }
+}
+
+/// Starts a new session, logging `measurement` every `interval` seconds, beginning now.
+pub fn start(measurement: Measurement, interval: u16) -> Result<(), LogError> {
+    stop()?;
+    let session = match unsafe { LAST_SESSION } {
+        Some(session) if session < ERASED - 1 => session + 1,
+        _ => 0,
+    };
+    unsafe {
+        LAST_SESSION = Some(session);
+        LOGGING = Some(Logging {
+            measurement: measurement,
+            interval: interval,
+            session: session,
+            seconds: 0,
+            last_at: time::millis(),
+            first: true,
+        });
+    }
+    Ok(())
+}
+
+/// Stops logging, marking the end of the session in the log.
+pub fn stop() -> Result<(), LogError> {
+    match unsafe { LOGGING.take() } {
+        Some(logging) => append(logging.session, logging.seconds, STOP, logging.interval,
+                                NOT_MEASURED),
+        None => Ok(()),
+    }
+}
+
+/// Stops logging and erases the log.
+pub fn erase() -> Result<(), LogError> {
+    unsafe { LOGGING = None; }
+    let area = area();
+    let mut address = area.start;
+    while address < area.end {
+        erase_page(address)?;
+        address += area.page_size;
+    }
+    unsafe {
+        NEXT = area.start;
+        LAST_SESSION = None;
+    }
+    Ok(())
+}
+
+/// The measurement being logged, and the interval, if logging.
+pub fn logging() -> Option<(Measurement, u16)> {
+    unsafe { LOGGING.map(|logging| (logging.measurement, logging.interval)) }
+}
+
+/// Returns whether an entry is due to be logged.
+pub fn due() -> bool {
+    match unsafe { LOGGING } {
+        Some(logging) => {
+            logging.first || time::since(logging.last_at) >= logging.interval as u32 * 1000
+        }
+        None => false,
+    }
+}
+
+/// Logs the measurement, if it's due, from a measured record. Returns whether an entry was
+/// logged. An error stops logging.
+pub fn log(results: Option<&Results>) -> bool {
+    if !due() {
+        return false;
+    }
+    let mut logging = match unsafe { LOGGING } {
+        Some(logging) => logging,
+        None => return false,
+    };
+    if logging.first {
+        logging.first = false;
+    } else {
+        // step the session time by whole intervals, so it doesn't drift, and intervals missed
+        // while there were no sweeps are skipped
+        let interval_ms = logging.interval as u32 * 1000;
+        let elapsed_ms = time::since(logging.last_at) / interval_ms * interval_ms;
+        logging.last_at = logging.last_at.wrapping_add(elapsed_ms);
+        logging.seconds += elapsed_ms / 1000;
+    }
+    let value = match results.and_then(|results| measure::value(logging.measurement, results)) {
+        Some(value) if value > i32::MAX as i64 => i32::MAX as u32,
+        Some(value) if value <= i32::MIN as i64 => (i32::MIN + 1) as u32,
+        Some(value) => value as i32 as u32,
+        None => NOT_MEASURED,
+    };
+    unsafe { LOGGING = Some(logging); }
+    if append(logging.session, logging.seconds, logging.measurement as u16, logging.interval,
+              value).is_err() {
+        unsafe { LOGGING = None; }
+        console::log(b"data log: flash error, stopped");
+        return false;
+    }
+    true
+}
+
+// write an entry into the next slot, first erasing its page if it isn't already
+fn append(session: u16, seconds: u32, measurement: u16, interval: u16, value: u32)
+          -> Result<(), LogError> {
+    let address = unsafe { NEXT };
+    let mut data = [session, seconds as u16, (seconds >> 16) as u16, measurement, interval,
+                    value as u16, (value >> 16) as u16, 0];
+    let sum = data.iter().fold(0u16, |sum, &half_word| sum.wrapping_add(half_word));
+    data[ENTRY_LENGTH - 1] = 0xffff - sum;
+    if !erased(address) {
+        let area = area();
+        erase_page(address - (address - area.start) % area.page_size)?;
+    }
+    if area().external {
+        let mut bytes = [0u8; 2 * ENTRY_LENGTH];
+        for (i, &half_word) in data.iter().enumerate() {
+            bytes[2 * i] = half_word as u8;
+            bytes[2 * i + 1] = (half_word >> 8) as u8;
+        }
+        spiflash::write(address, &bytes).map_err(LogError::SpiFlash)?;
+    } else {
+        flash::write_log(address, &data).map_err(LogError::Flash)?;
+    }
+    unsafe { NEXT = after(address); }
+    Ok(())
+}
+
+fn erase_page(address: u32) -> Result<(), LogError> {
+    if area().external {
+        spiflash::erase_sector(address).map_err(LogError::SpiFlash)
+    } else {
+        flash::erase_log_page(address).map_err(LogError::Flash)
+    }
+}
+
+/// Writes the logging state, and the number of entries in the log, to the serial port.
+pub fn report() {
+    match logging() {
+        Some((measurement, interval)) => {
+            serial::write(b"logging ");
+            serial::write(measurement.label());
+            serial::write(b" every ");
+            serial::write_number(interval as u32);
+            serial::write(b"s, ");
+        }
+        None => serial::write(b"not logging, "),
+    }
+    let mut entries = 0;
+    for_each(|entry| if entry.measurement.is_some() { entries += 1; });
+    serial::write_number(entries);
+    serial::write(b" entries logged, of at most ");
+    // a page is erased before each wrap around the ring
+    let area = area();
+    serial::write_number(slots() as u32 - area.page_size / ENTRY_SIZE);
+    let place: &[u8] = if area.external { b" in the external flash" } else { b"" };
+    serial::write(place);
+    serial::write(b"\r\n");
+}
+
+/// Writes the log to the serial port as CSV, oldest first, with a header line. The value is
+/// in the measurement's unit, as for an alarm, and is empty where it couldn't be measured.
+pub fn dump() {
+    serial::write(b"session,seconds,measurement,value\r\n");
+    for_each(|entry| {
+        if let Some(measurement) = entry.measurement {
+            serial::write_number(entry.session as u32);
+            serial::write_byte(b',');
+            serial::write_number(entry.seconds);
+            serial::write_byte(b',');
+            serial::write(measurement.label());
+            serial::write_byte(b',');
+            if let Some(value) = entry.value {
+                serial::write_signed_fixed(value as i64, 3);
+            }
+            serial::write(b"\r\n");
+        }
+    });
+}
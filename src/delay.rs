@@ -0,0 +1,85 @@
+// stm32f3-oscilloscope - src/delay.rs
+// delays: blocking, timed by the cycle counter, and non-blocking deadlines
+
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The blocking delays count core clock cycles on the DWT cycle counter, so they work with
+// interrupts disabled, and before SysTick is running. The counter wraps every minute or so at
+// 72MHz, so long delays are made of 1ms steps. Deadlines, for waiting without stalling the main
+// loop, are on the millisecond clock instead.
+
+use cortex_m::peripheral::{DCB, DWT};
+
+use clocks;
+use time::millis;
+
+const DEMCR_TRCENA: u32 = 1 << 24;
+const DWT_CTRL_CYCCNTENA: u32 = 1 << 0;
+
+/// Starts the cycle counter. This must be done before any delay.
+pub fn init() {
+    unsafe {
+        (*DCB.get()).demcr.modify(|r| r | DEMCR_TRCENA);
+        (*DWT.get()).cyccnt.write(0);
+        (*DWT.get()).ctrl.modify(|r| r | DWT_CTRL_CYCCNTENA);
+    }
+}
+
+/// Returns the cycle counter, which counts core clock cycles, wrapping.
+pub fn cycles() -> u32 {
+    unsafe { (*DWT.get()).cyccnt.read() }
+}
+
+/// Waits for at least `us` microseconds, up to 50 seconds.
+pub fn delay_us(us: u32) {
+    let start = cycles();
+    let wait = us * (clocks::hclk_hz() / 1_000_000);
+    while cycles().wrapping_sub(start) < wait {}
+}
+
+/// Waits for at least `ms` milliseconds. This is also called by the C LCD code.
+#[no_mangle]
+pub extern "C" fn delay_ms(ms: u32) {
+    for _ in 0..ms {
+        delay_us(1000);
+    }
+}
+
+/// A time to wait for, without blocking.
+#[derive(Clone, Copy)]
+pub struct Deadline {
+    at: u32, // in milliseconds since reset
+}
+
+impl Deadline {
+    /// Returns a deadline `ms` milliseconds from now, up to about 24 days.
+    pub fn after_ms(ms: u32) -> Deadline {
+        Deadline { at: millis().wrapping_add(ms) }
+    }
+
+    /// Returns whether the deadline has passed.
+    pub fn passed(&self) -> bool {
+        (millis().wrapping_sub(self.at) as i32) >= 0
+    }
+}
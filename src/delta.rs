@@ -0,0 +1,114 @@
+// stm32f3-oscilloscope - src/delta.rs
+// delta encoding of samples, for streaming them over a slow serial link
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Samples are encoded in frames of up to FRAME_SAMPLES. Each frame begins with a keyframe,
+// the first sample's absolute value, so a receiver can start at any frame, and an error can't
+// spread past the end of its frame. The rest of the frame is the difference from each sample
+// to the next, which for most signals is small, even with noise. Each item takes one or two
+// bytes, told apart by the top bits of the first:
+//
+//   0ddddddd             a difference of -64 to 63
+//   10dddddd dddddddd    a difference of -8192 to 8191, more than 12-bit samples need
+//   1100kkkk kkkkkkkk    a keyframe, the 12-bit absolute value
+//
+// So a frame of 12-bit samples is mostly one byte a sample, rather than the two raw samples
+// need, and never more than two. This module uses nothing of the hardware, so it can be built
+// and tested on the host, and decode_frame() is there for a receiver to follow.
+
+pub const FRAME_SAMPLES: usize = 64;
+pub const MAX_FRAME_BYTES: usize = 2 * FRAME_SAMPLES;
+
+const SHORT_TAG: u8 = 0x00; // 0ddddddd
+const LONG_TAG: u8 = 0x80;  // 10dddddd
+const KEY_TAG: u8 = 0xc0;   // 1100kkkk
+
+/// Encodes `samples`, at most FRAME_SAMPLES of them, as one frame in `out`, which must have
+/// room for MAX_FRAME_BYTES. Returns the length of the frame.
+pub fn encode_frame(samples: &[u16], out: &mut [u8]) -> usize {
+    debug_assert!(samples.len() <= FRAME_SAMPLES);
+    let mut len = 0;
+    let mut previous = 0;
+    for (i, &sample) in samples.iter().enumerate() {
+        let sample = sample & 0x0fff;
+        let delta = sample as i16 - previous as i16;
+        if i == 0 {
+            out[0] = KEY_TAG | (sample >> 8) as u8;
+            out[1] = sample as u8;
+            len = 2;
+        } else if delta >= -64 && delta < 64 {
+            out[len] = SHORT_TAG | (delta as u8 & 0x7f);
+            len += 1;
+        } else {
+            let bits = delta as u16 & 0x3fff;
+            out[len] = LONG_TAG | (bits >> 8) as u8;
+            out[len + 1] = bits as u8;
+            len += 2;
+        }
+        previous = sample;
+    }
+    len
+}
+
+/// Decodes a frame into `samples`, returning the number decoded, or None if the frame is
+/// malformed or has more samples than fit.
+#[allow(unused)]
+pub fn decode_frame(frame: &[u8], samples: &mut [u16]) -> Option<usize> {
+    let mut count = 0;
+    let mut i = 0;
+    let mut previous = 0i16;
+    while i < frame.len() {
+        if count == samples.len() {
+            return None;
+        }
+        let first = frame[i];
+        let value = if first & 0x80 == 0 {
+            // sign extend the 7-bit difference
+            i += 1;
+            previous + (((first << 1) as i8) >> 1) as i16
+        } else {
+            if i + 1 >= frame.len() {
+                return None;
+            }
+            let bits = (first as u16) << 8 | frame[i + 1] as u16;
+            i += 2;
+            if first & 0xc0 == LONG_TAG {
+                // sign extend the 14-bit difference
+                previous + (((bits << 2) as i16) >> 2)
+            } else if first & 0xf0 == KEY_TAG {
+                (bits & 0x0fff) as i16
+            } else {
+                return None;
+            }
+        };
+        // a frame starts with a keyframe, and stays within 12 bits
+        if (count == 0) != (first & 0xf0 == KEY_TAG) || value < 0 || value > 0x0fff {
+            return None;
+        }
+        samples[count] = value as u16;
+        previous = value;
+        count += 1;
+    }
+    Some(count)
+}
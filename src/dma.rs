@@ -0,0 +1,276 @@
+// stm32f3-oscilloscope - src/dma.rs
+// DMA channels, configured and controlled through a channel handle
+
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// As with GPIO pins, the device crate gives each DMA channel's registers their own types, so a
+// `Channel` instead reaches its registers by address. Each channel has a block of four
+// registers (CCR, CNDTR, CPAR, CMAR) at a fixed stride from the controller's base, and four bits
+// in each of the shared ISR and IFCR registers. Which channel serves which peripheral is fixed
+// by the hardware (see RM0316 tables 78 and 79), so the users of a channel name it as a constant.
+
+use core::ptr;
+
+use cortex_m;
+use stm32f30x::{DMA1, DMA2, RCC};
+use stm32f30x::interrupt::Interrupt;
+
+#[allow(unused)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Controller {
+    Dma1, // channels 1 to 7
+    Dma2, // channels 1 to 5
+}
+
+#[allow(unused)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    PeripheralToMemory = 0,
+    MemoryToPeripheral = 1,
+}
+
+#[allow(unused)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Priority {
+    Low = 0b00,
+    Medium = 0b01,
+    High = 0b10,
+    VeryHigh = 0b11,
+}
+
+/// The size of each transfer, at both the memory and the peripheral.
+#[allow(unused)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Size {
+    Bits8 = 0b00,
+    Bits16 = 0b01,
+    Bits32 = 0b10,
+}
+
+pub struct Config {
+    pub direction: Direction,
+    pub priority: Priority,
+    pub size: Size,
+    pub circular: bool,              // restart from the beginning after the last transfer
+    pub interrupt_on_complete: bool, // raise the channel's interrupt after the last transfer
+    pub interrupt_on_half: bool,     // and after half the transfers
+    pub memory_to_memory: bool,      // copy from the "peripheral" address, incrementing it too
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Channel {
+    pub controller: Controller,
+    pub number: u8,
+}
+
+// register offsets and CCR bits
+const ISR: u32 = 0x00;
+const IFCR: u32 = 0x04;
+const CCR: u32 = 0x08;
+const CNDTR: u32 = 0x0c;
+const CPAR: u32 = 0x10;
+const CMAR: u32 = 0x14;
+const CHANNEL_STRIDE: u32 = 0x14;
+
+const CCR_EN: u32 = 1 << 0;
+const CCR_TCIE: u32 = 1 << 1;
+const CCR_HTIE: u32 = 1 << 2;
+const CCR_DIR: u32 = 1 << 4;
+const CCR_CIRC: u32 = 1 << 5;
+const CCR_PINC: u32 = 1 << 6;
+const CCR_MINC: u32 = 1 << 7;
+const CCR_MEM2MEM: u32 = 1 << 14;
+
+// the per-channel bits in ISR and IFCR
+const FLAG_TC: u32 = 1 << 1;
+const FLAG_HT: u32 = 1 << 2;
+const FLAG_TE: u32 = 1 << 3;
+
+impl Channel {
+    fn base(self) -> u32 {
+        match self.controller {
+            Controller::Dma1 => DMA1.get() as u32,
+            Controller::Dma2 => DMA2.get() as u32,
+        }
+    }
+
+    // the address of one of this channel's registers
+    fn register(self, offset: u32) -> *mut u32 {
+        (self.base() + offset + CHANNEL_STRIDE * (self.number as u32 - 1)) as *mut u32
+    }
+
+    fn read(self, offset: u32) -> u32 {
+        unsafe { ptr::read_volatile(self.register(offset)) }
+    }
+
+    fn write(self, offset: u32, value: u32) {
+        unsafe { ptr::write_volatile(self.register(offset), value) }
+    }
+
+    // test or clear this channel's bit for `flag` in ISR or IFCR
+    fn flag_is_set(self, flag: u32) -> bool {
+        let isr = unsafe { ptr::read_volatile((self.base() + ISR) as *const u32) };
+        isr & flag << (4 * (self.number as u32 - 1)) != 0
+    }
+
+    fn clear_flag(self, flag: u32) {
+        let ifcr = (self.base() + IFCR) as *mut u32;
+        unsafe { ptr::write_volatile(ifcr, flag << (4 * (self.number as u32 - 1))); }
+    }
+
+    /// Enables the clock to the channel's controller.
+    pub fn enable_clock(self) {
+        cortex_m::interrupt::free(|cs| {
+            let rcc = RCC.borrow(cs);
+            match self.controller {
+                Controller::Dma1 => rcc.ahbenr.modify(|_, w| w.dmaen().enabled()), // 'dma1en'
+                Controller::Dma2 => rcc.ahbenr.modify(|_, w| w.dma2en().enabled()),
+            }
+        });
+    }
+
+    /// Configures the channel to transfer `count` items between the peripheral register at
+    /// address `peripheral` and the buffer at address `memory`, incrementing through the buffer.
+    /// The channel is left stopped.
+    pub fn configure(self, config: &Config, peripheral: u32, memory: u32, count: u16) {
+        let size = config.size as u32;
+        let mut ccr = (config.priority as u32) << 12 | size << 10 | size << 8 | CCR_MINC;
+        if config.direction == Direction::MemoryToPeripheral {
+            ccr |= CCR_DIR;
+        }
+        if config.circular {
+            ccr |= CCR_CIRC;
+        }
+        if config.interrupt_on_complete {
+            ccr |= CCR_TCIE;
+        }
+        if config.interrupt_on_half {
+            ccr |= CCR_HTIE;
+        }
+        if config.memory_to_memory {
+            ccr |= CCR_MEM2MEM | CCR_PINC;
+        }
+        cortex_m::interrupt::free(|_| {
+            self.write(CCR, 0);
+            self.write(CPAR, peripheral);
+            self.write(CMAR, memory);
+            self.write(CNDTR, count as u32);
+            self.write(CCR, ccr);
+        });
+    }
+
+    /// Sets the peripheral address the channel transfers to or from, or in memory-to-memory
+    /// mode, the source address, which takes effect when the channel is next started. The
+    /// channel must be stopped.
+    pub fn set_peripheral(self, peripheral: u32) {
+        self.write(CPAR, peripheral);
+    }
+
+    /// Sets the buffer address the channel transfers to or from, which takes effect when the
+    /// channel is next started. The channel must be stopped.
+    pub fn set_memory(self, memory: u32) {
+        self.write(CMAR, memory);
+    }
+
+    /// Sets the number of items to transfer when the channel is next started.
+    pub fn set_count(self, count: u16) {
+        self.write(CNDTR, count as u32);
+    }
+
+    pub fn start(self) {
+        cortex_m::interrupt::free(|_| {
+            let ccr = self.read(CCR);
+            self.write(CCR, ccr | CCR_EN);
+        });
+    }
+
+    pub fn stop(self) {
+        cortex_m::interrupt::free(|_| {
+            let ccr = self.read(CCR);
+            self.write(CCR, ccr & !CCR_EN);
+        });
+    }
+
+    /// Returns the number of items still to be transferred.
+    pub fn remaining(self) -> u16 {
+        self.read(CNDTR) as u16
+    }
+
+    /// Checks the transfer error flag, and clears it if set. Returns its value before it was
+    /// cleared.
+    pub fn take_error(self) -> bool {
+        let error = self.flag_is_set(FLAG_TE);
+        if error {
+            self.clear_flag(FLAG_TE);
+        }
+        error
+    }
+
+    /// Returns whether the transfer complete flag is set.
+    pub fn is_complete(self) -> bool {
+        self.flag_is_set(FLAG_TC)
+    }
+
+    /// Clears the transfer complete flag, as the channel's interrupt handler must.
+    pub fn clear_complete(self) {
+        self.clear_flag(FLAG_TC);
+    }
+
+    /// Checks the half transfer flag, and clears it if set. Returns its value before it was
+    /// cleared.
+    pub fn take_half_complete(self) -> bool {
+        let half = self.flag_is_set(FLAG_HT);
+        if half {
+            self.clear_flag(FLAG_HT);
+        }
+        half
+    }
+
+    /// Enables the channel's interrupt in the NVIC, at `priority`.
+    pub fn enable_interrupt(self, priority: u8) {
+        cortex_m::interrupt::free(|cs| {
+            let nvic = cortex_m::peripheral::NVIC.borrow(cs);
+            unsafe { nvic.set_priority(self.interrupt(), priority); }
+            nvic.enable(self.interrupt());
+        });
+    }
+
+    fn interrupt(self) -> Interrupt {
+        match (self.controller, self.number) {
+            (Controller::Dma1, 1) => Interrupt::Dma1Ch1,
+            (Controller::Dma1, 2) => Interrupt::Dma1Ch2,
+            (Controller::Dma1, 3) => Interrupt::Dma1Ch3,
+            (Controller::Dma1, 4) => Interrupt::Dma1Ch4,
+            (Controller::Dma1, 5) => Interrupt::Dma1Ch5,
+            (Controller::Dma1, 6) => Interrupt::Dma1Ch6,
+            (Controller::Dma1, 7) => Interrupt::Dma1Ch7,
+            (Controller::Dma2, 1) => Interrupt::Dma2Ch1,
+            (Controller::Dma2, 2) => Interrupt::Dma2Ch2,
+            (Controller::Dma2, 3) => Interrupt::Dma2Ch3,
+            (Controller::Dma2, 4) => Interrupt::Dma2Ch4,
+            (Controller::Dma2, _) => Interrupt::Dma2Ch5,
+            (Controller::Dma1, _) => panic!("no such DMA channel"),
+        }
+    }
+}
@@ -0,0 +1,232 @@
+// stm32f3-oscilloscope - src/eeprom.rs
+// an external I2C EEPROM, a 24C32 or larger, holding the calibration and settings
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+
+// With the i2c-eeprom feature, flash::store() and flash::read() keep the calibration and the
+// settings in a 24Cxx EEPROM rather than in the last page of the on-chip flash, so they survive
+// a firmware update that erases the whole chip, and the on-chip page is never erased for them.
+// The EEPROM holds the same half-words at the same offsets, low byte first, from its address 0.
+//
+// The bus is driven in software, on any two GPIO pins (see board.rs), at about 100kHz. Each
+// line is open drain, done by switching the pin between an output, driving low, and an input,
+// left to the pull-up: the EEPROM modules have 4.7k pull-ups, and the pins' own weak pull-ups
+// are turned on as well. The chip's address pins are expected to be low, giving it the bus
+// address 0x50, and it must be a 24C32 or larger, taking a two byte memory address.
+//
+// Writes are split at the chip's 32 byte pages. After each, the chip is busy for up to 5ms,
+// ignoring its address until it is done, so the next write polls for its acknowledge. See the
+// AT24C32 datasheet. The chip is probed once, when the pins are set up, so with none fitted,
+// reads give 0xffff, as erased, and writes fail, at once rather than each polling in vain.
+
+use board::{EEPROM_SCL_PIN, EEPROM_SDA_PIN};
+use delay::{self, Deadline};
+use flash::{FlashError, STORED_LENGTH};
+use gpio::{Mode, Pin, Pull};
+
+const ADDRESS: u8 = 0x50;
+const WRITE: u8 = 0;
+const READ: u8 = 1;
+const PAGE_SIZE: usize = 32;
+const WRITE_TIMEOUT_MS: u32 = 10; // the chip's 5ms, with a margin
+const HALF_BIT_US: u32 = 5;
+
+static mut READY: bool = false; // the pins are set up
+static mut PRESENT: bool = false; // and the chip acknowledged its address then
+
+/// Returns the half-word at `offset` half-words into the EEPROM, or 0xffff, as if erased, if
+/// it can't be read.
+pub fn read(offset: usize) -> u16 {
+    setup();
+    if unsafe { !PRESENT } {
+        return 0xffff;
+    }
+    let address = 2 * offset as u16;
+    let mut bytes = [0xff; 2];
+    if select(address, Deadline::after_ms(WRITE_TIMEOUT_MS)) {
+        start();
+        if write_byte(ADDRESS << 1 | READ) {
+            bytes[0] = read_byte(true);
+            bytes[1] = read_byte(false);
+        }
+    }
+    stop();
+    bytes[0] as u16 | (bytes[1] as u16) << 8
+}
+
+/// Writes `data` at `offset` half-words into the EEPROM.
+pub fn write(offset: usize, data: &[u16]) -> Result<(), FlashError> {
+    debug_assert!(data.len() <= STORED_LENGTH);
+    setup();
+    if unsafe { !PRESENT } {
+        return Err(FlashError::Eeprom);
+    }
+    let mut bytes = [0u8; 2 * STORED_LENGTH];
+    let bytes = &mut bytes[..2 * data.len()];
+    for (i, &half_word) in data.iter().enumerate() {
+        bytes[2 * i] = half_word as u8;
+        bytes[2 * i + 1] = (half_word >> 8) as u8;
+    }
+    let mut address = 2 * offset;
+    let mut rest = &bytes[..];
+    while !rest.is_empty() {
+        // up to the end of the page
+        let room = PAGE_SIZE - address % PAGE_SIZE;
+        let n = if rest.len() < room { rest.len() } else { room };
+        // waiting out the last write, if it is still going
+        if !select(address as u16, Deadline::after_ms(WRITE_TIMEOUT_MS)) {
+            stop();
+            return Err(FlashError::Eeprom);
+        }
+        let acknowledged = rest[..n].iter().all(|&byte| write_byte(byte));
+        stop();
+        if !acknowledged {
+            return Err(FlashError::Eeprom);
+        }
+        address += n;
+        rest = &rest[n..];
+    }
+    // and wait for the last page to be written, before it might be read back
+    let done = select(0, Deadline::after_ms(WRITE_TIMEOUT_MS));
+    stop();
+    if done { Ok(()) } else { Err(FlashError::Eeprom) }
+}
+
+// set up the pins, the first time, and free the bus, in case a reset left the chip part way
+// through sending a byte: clocking it until it lets go of SDA, then a stop; then see whether
+// the chip is there, allowing for a write the reset interrupted
+fn setup() {
+    if unsafe { READY } {
+        return;
+    }
+    for &pin in &[EEPROM_SCL_PIN, EEPROM_SDA_PIN] {
+        pin.enable_port_clock();
+        pin.set_low(); // driven low when it is an output
+        pin.set_pull(Pull::Up);
+        release(pin);
+    }
+    for _ in 0..9 {
+        if EEPROM_SDA_PIN.is_high() {
+            break;
+        }
+        pull_low(EEPROM_SCL_PIN);
+        delay::delay_us(HALF_BIT_US);
+        release(EEPROM_SCL_PIN);
+        delay::delay_us(HALF_BIT_US);
+    }
+    stop();
+    let present = select(0, Deadline::after_ms(WRITE_TIMEOUT_MS));
+    stop();
+    unsafe {
+        PRESENT = present;
+        READY = true;
+    }
+}
+
+// start a write of the memory address `address`, retrying until the chip acknowledges or the
+// deadline passes; the chip ignores its bus address while it is busy writing
+fn select(address: u16, deadline: Deadline) -> bool {
+    loop {
+        start();
+        if write_byte(ADDRESS << 1 | WRITE) {
+            return write_byte((address >> 8) as u8) && write_byte(address as u8);
+        }
+        stop();
+        if deadline.passed() {
+            return false;
+        }
+    }
+}
+
+fn release(pin: Pin) {
+    pin.configure(Mode::Input);
+}
+
+fn pull_low(pin: Pin) {
+    pin.configure(Mode::Output);
+}
+
+// SDA falling while SCL is high, from either an idle bus or the middle of a transfer (a
+// repeated start)
+fn start() {
+    release(EEPROM_SDA_PIN);
+    delay::delay_us(HALF_BIT_US);
+    release(EEPROM_SCL_PIN);
+    delay::delay_us(HALF_BIT_US);
+    pull_low(EEPROM_SDA_PIN);
+    delay::delay_us(HALF_BIT_US);
+    pull_low(EEPROM_SCL_PIN);
+}
+
+// SDA rising while SCL is high
+fn stop() {
+    pull_low(EEPROM_SDA_PIN);
+    delay::delay_us(HALF_BIT_US);
+    release(EEPROM_SCL_PIN);
+    delay::delay_us(HALF_BIT_US);
+    release(EEPROM_SDA_PIN);
+    delay::delay_us(HALF_BIT_US);
+}
+
+// clock one bit out, with SCL low on entry and exit
+fn write_bit(high: bool) {
+    if high {
+        release(EEPROM_SDA_PIN);
+    } else {
+        pull_low(EEPROM_SDA_PIN);
+    }
+    delay::delay_us(HALF_BIT_US);
+    release(EEPROM_SCL_PIN);
+    delay::delay_us(HALF_BIT_US);
+    pull_low(EEPROM_SCL_PIN);
+}
+
+// clock one bit in, with SCL low on entry and exit
+fn read_bit() -> bool {
+    release(EEPROM_SDA_PIN);
+    delay::delay_us(HALF_BIT_US);
+    release(EEPROM_SCL_PIN);
+    delay::delay_us(HALF_BIT_US);
+    let high = EEPROM_SDA_PIN.is_high();
+    pull_low(EEPROM_SCL_PIN);
+    high
+}
+
+// send a byte, most significant bit first, returning whether it was acknowledged
+fn write_byte(byte: u8) -> bool {
+    for bit in (0..8).rev() {
+        write_bit(byte & 1 << bit != 0);
+    }
+    !read_bit()
+}
+
+// receive a byte, acknowledging it if more are to follow
+fn read_byte(acknowledge: bool) -> u8 {
+    let mut byte = 0u8;
+    for _ in 0..8 {
+        byte = byte << 1 | read_bit() as u8;
+    }
+    write_bit(!acknowledge);
+    byte
+}
@@ -0,0 +1,85 @@
+// stm32f3-oscilloscope - src/envelope.rs
+// envelope display: the range of each screen column over many sweeps
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Each column keeps the highest and lowest screen rows its trace has reached since the last
+// reset, and is drawn as a blue band between them, with the latest sweep in white over it, so
+// amplitude modulation, jitter, and worst-case excursions show at a glance. The band only
+// grows until a reset, so each column is simply drawn again over itself.
+
+use st7735::*;
+
+use screen;
+
+const WIDTH: usize = screen::MAX_WIDTH;
+const BAND_COLOR: St7735Color = St7735Color::Blue;
+
+static mut TOP: [u8; WIDTH] = [255; WIDTH];    // 255 where nothing has been accumulated
+static mut BOTTOM: [u8; WIDTH] = [0; WIDTH];
+static mut LATEST: [u8; WIDTH] = [255; WIDTH]; // the latest sweep's row, 255 for none
+
+/// Clears the envelope, and the trace area.
+pub fn reset() {
+    unsafe {
+        TOP = [255; WIDTH];
+        BOTTOM = [0; WIDTH];
+        LATEST = [255; WIDTH];
+    }
+    screen::clear_trace_area();
+}
+
+/// Adds a sweep to the envelope, given as the screen row of each column, which is clipped to
+/// the trace area, or `None` where there is no value.
+pub fn accumulate(rows: &[Option<i16>]) {
+    let bottom_row = screen::trace_height() - 1;
+    for (x, &row) in rows.iter().enumerate().take(WIDTH) {
+        unsafe {
+            match row {
+                Some(y) => {
+                    let y = if y < 0 { 0 } else if y > bottom_row { bottom_row } else { y } as u8;
+                    if TOP[x] == 255 || y < TOP[x] { TOP[x] = y; }
+                    if y > BOTTOM[x] { BOTTOM[x] = y; }
+                    LATEST[x] = y;
+                }
+                None => LATEST[x] = 255,
+            }
+        }
+    }
+}
+
+/// Draws the envelope, and the latest sweep over it.
+pub fn draw() {
+    for x in 0..screen::width() as usize {
+        let (top, bottom, latest) = unsafe { (TOP[x], BOTTOM[x], LATEST[x]) };
+        if top == 255 {
+            continue;
+        }
+        // this also covers the previous sweep's pixel, which was within the band
+        st7735_drawFastVLine(x as i16, top as i16, (bottom - top + 1) as i16,
+                             BAND_COLOR as u16);
+        if latest != 255 {
+            st7735_drawPixel(x as i16, latest as i16, St7735Color::White as u16);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+// stm32f3-oscilloscope - src/export.rs
+// exporting records and screenshots over the serial port
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Both are sent in frames (see frame.rs). A dump is a DumpHeader frame, then DumpData frames
+// of up to DUMP_FRAME_SAMPLES raw samples each, numbered from zero. With the handshake, the
+// receiver answers each frame with ACK, or with NAK to have it sent again, as it also is if no
+// answer comes within ACK_TIMEOUT_MS; after TRIES tries, the dump is abandoned. A screenshot
+// is a ScreenshotHeader frame, then a ScreenshotRow frame for each row, read back from the
+// display, or from a screen stored in the external flash (see records.rs).
+
+use delay::Deadline;
+use frame::{self, FrameType};
+use serial;
+use st7735::*;
+
+const DUMP_FRAME_SAMPLES: usize = 128;
+
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const ACK_TIMEOUT_MS: u32 = 1000;
+const TRIES: u32 = 4;
+
+/// Sends `record`, captured at `sample_rate` samples per second. With `handshake`, each frame
+/// must be acknowledged; returns false if one never was.
+pub fn dump(record: &[u16], sample_rate: u32, handshake: bool) -> bool {
+    let (count, rate) = (le32(record.len() as u32), le32(sample_rate));
+    if !send(FrameType::DumpHeader, &[&count, &rate], handshake) {
+        return false;
+    }
+    for (sequence, chunk) in record.chunks(DUMP_FRAME_SAMPLES).enumerate() {
+        let mut samples = [0u8; 2 * DUMP_FRAME_SAMPLES];
+        for (i, &sample) in chunk.iter().enumerate() {
+            samples[2 * i..2 * i + 2].copy_from_slice(&le16(sample));
+        }
+        let sequence = le16(sequence as u16);
+        if !send(FrameType::DumpData, &[&sequence, &samples[..2 * chunk.len()]], handshake) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Sends the screen, as read back from the display. Returns false if the display can't be
+/// read.
+pub fn screenshot() -> bool {
+    let (width, height) = (st7735_get_width(), st7735_get_height());
+    let mut rgb = [0u8; 3 * 160];
+    if !st7735_read_row(0, 0, width, &mut rgb) {
+        return false;
+    }
+    send_screen(width, height, |y, rgb| { st7735_read_row(0, y, width, rgb); });
+    true
+}
+
+/// Sends a screen of `width` by `height` pixels, each row filled in by `read_row` with the red,
+/// green, and blue of each pixel.
+pub fn send_screen<F: FnMut(u8, &mut [u8])>(width: u8, height: u8, mut read_row: F) {
+    let mut rgb = [0u8; 3 * 160];
+    frame::send(FrameType::ScreenshotHeader, &[&le16(width as u16), &le16(height as u16)]);
+    for y in 0..height {
+        read_row(y, &mut rgb[..3 * width as usize]);
+        frame::send(FrameType::ScreenshotRow, &[&le16(y as u16), &rgb[..3 * width as usize]]);
+    }
+}
+
+// send a frame, and with `handshake`, wait for it to be acknowledged, sending it again as needed
+fn send(frame_type: FrameType, payload: &[&[u8]], handshake: bool) -> bool {
+    if !handshake {
+        frame::send(frame_type, payload);
+        return true;
+    }
+    for _ in 0..TRIES {
+        frame::send(frame_type, payload);
+        let deadline = Deadline::after_ms(ACK_TIMEOUT_MS);
+        loop {
+            match serial::read() {
+                Some(ACK) => return true,
+                Some(NAK) => break,
+                Some(_) => {} // e.g. the end of the line that asked for the dump
+                None if deadline.passed() => break,
+                None => {}
+            }
+        }
+    }
+    false
+}
+
+fn le16(n: u16) -> [u8; 2] {
+    [n as u8, (n >> 8) as u8]
+}
+
+fn le32(n: u32) -> [u8; 4] {
+    [n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8]
+}
@@ -0,0 +1,201 @@
+// stm32f3-oscilloscope - src/flash.rs
+// flash memory erase and programming, for storing calibration and settings
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// The last 2K page of flash is kept out of the program area by memory.x, and holds the stored
+// data. Flash is programmed a half-word at a time, and can only be erased a whole page at a
+// time, so the page is rewritten completely on each store. It holds more than one record (the
+// calibration, and the settings), so the first STORED_LENGTH half-words are read back and
+// rewritten along with the record being stored. See RM0316 section 4.2.
+//
+// The LOG_PAGES pages below it are also kept out of the program area, for the data log (see
+// datalog.rs), which writes them a page at a time, in turn. While a page is being erased, the
+// CPU stalls on any instruction fetch from flash, for up to 40ms.
+//
+// With the i2c-eeprom feature, store() and read() keep the records in an external EEPROM
+// instead (see eeprom.rs), and the storage page is left unused.
+
+use core::ptr;
+
+use cortex_m;
+use stm32f30x::FLASH;
+
+#[cfg(feature = "i2c-eeprom")]
+use eeprom;
+
+/// The address of the page reserved for stored data.
+pub const STORAGE_PAGE: u32 = 0x0803_f800;
+/// The size of the page, in bytes.
+pub const PAGE_SIZE: usize = 2048;
+/// The number of half-words at the start of the storage page that are kept when a record is
+/// stored.
+pub const STORED_LENGTH: usize = 64;
+/// The number of pages reserved for the data log.
+pub const LOG_PAGES: usize = 16;
+/// The address of the first of the data log's pages, which are just below the storage page.
+pub const LOG_START: u32 = STORAGE_PAGE - (LOG_PAGES * PAGE_SIZE) as u32;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xcdef_89ab;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FlashError {
+    Program,        // PGERR: the location was not erased
+    WriteProtected, // WRPRTERR
+    #[cfg(feature = "i2c-eeprom")]
+    Eeprom,         // the EEPROM didn't acknowledge
+}
+
+// wait for the current operation to finish, then check and clear the status flags
+fn wait() -> Result<(), FlashError> {
+    cortex_m::interrupt::free(|cs| {
+        let flash = FLASH.borrow(cs);
+        while flash.sr.read().bsy().bits() != 0 {}
+        let sr = flash.sr.read();
+        let result = if sr.pgerr().bits() != 0 {
+            Err(FlashError::Program)
+        } else if sr.wrprterr().bits() != 0 {
+            Err(FlashError::WriteProtected)
+        } else {
+            Ok(())
+        };
+        // the flags are cleared by writing ones to them
+        flash.sr.write(|w| unsafe { w.eop().bits(1).pgerr().bits(1).wrprterr().bits(1) });
+        result
+    })
+}
+
+fn unlock() {
+    cortex_m::interrupt::free(|cs| {
+        let flash = FLASH.borrow(cs);
+        if flash.cr.read().lock().bits() != 0 {
+            flash.keyr.write(|w| unsafe { w.bits(KEY1) });
+            flash.keyr.write(|w| unsafe { w.bits(KEY2) });
+        }
+    });
+}
+
+fn lock() {
+    cortex_m::interrupt::free(|cs| {
+        let flash = FLASH.borrow(cs);
+        flash.cr.modify(|_, w| unsafe { w.lock().bits(1) });
+    });
+}
+
+fn erase_page(address: u32) -> Result<(), FlashError> {
+    cortex_m::interrupt::free(|cs| {
+        let flash = FLASH.borrow(cs);
+        flash.cr.modify(|_, w| unsafe { w.per().bits(1) });
+        flash.ar.write(|w| unsafe { w.bits(address) });
+        flash.cr.modify(|_, w| unsafe { w.strt().bits(1) });
+    });
+    let result = wait();
+    cortex_m::interrupt::free(|cs| {
+        let flash = FLASH.borrow(cs);
+        flash.cr.modify(|_, w| unsafe { w.per().bits(0) });
+    });
+    result
+}
+
+fn program(address: u32, data: &[u16]) -> Result<(), FlashError> {
+    cortex_m::interrupt::free(|cs| {
+        let flash = FLASH.borrow(cs);
+        flash.cr.modify(|_, w| unsafe { w.pg().bits(1) });
+    });
+    let mut result = Ok(());
+    for (i, &half_word) in data.iter().enumerate() {
+        unsafe { ptr::write_volatile((address as usize + 2 * i) as *mut u16, half_word); }
+        result = wait();
+        if result.is_err() {
+            break;
+        }
+    }
+    cortex_m::interrupt::free(|cs| {
+        let flash = FLASH.borrow(cs);
+        flash.cr.modify(|_, w| unsafe { w.pg().bits(0) });
+    });
+    result
+}
+
+/// Erases the storage page and writes `data` at `offset` half-words into it, keeping the rest
+/// of the first STORED_LENGTH half-words.
+#[cfg(not(feature = "i2c-eeprom"))]
+pub fn store(offset: usize, data: &[u16]) -> Result<(), FlashError> {
+    debug_assert!(offset + data.len() <= STORED_LENGTH);
+    let mut page = [0u16; STORED_LENGTH];
+    for (i, half_word) in page.iter_mut().enumerate() {
+        *half_word = read(i);
+    }
+    page[offset..offset + data.len()].copy_from_slice(data);
+    unlock();
+    let result = erase_page(STORAGE_PAGE).and_then(|_| program(STORAGE_PAGE, &page));
+    lock();
+    result
+}
+
+/// Writes `data` at `offset` half-words into the EEPROM, leaving the rest as it is.
+#[cfg(feature = "i2c-eeprom")]
+pub fn store(offset: usize, data: &[u16]) -> Result<(), FlashError> {
+    debug_assert!(offset + data.len() <= STORED_LENGTH);
+    eeprom::write(offset, data)
+}
+
+/// Erases the data log's page at `address`.
+pub fn erase_log_page(address: u32) -> Result<(), FlashError> {
+    debug_assert!(address >= LOG_START && address < STORAGE_PAGE);
+    debug_assert!((address - LOG_START) as usize % PAGE_SIZE == 0);
+    unlock();
+    let result = erase_page(address);
+    lock();
+    result
+}
+
+/// Writes `data` to the data log's pages at `address`, which must have been erased.
+pub fn write_log(address: u32, data: &[u16]) -> Result<(), FlashError> {
+    debug_assert!(address >= LOG_START && address as usize + data.len() * 2 <=
+                  STORAGE_PAGE as usize);
+    unlock();
+    let result = program(address, data);
+    lock();
+    result
+}
+
+/// Returns the half-word at `address`, in the data log's pages.
+pub fn read_log(address: u32) -> u16 {
+    debug_assert!(address >= LOG_START && address < STORAGE_PAGE);
+    unsafe { ptr::read_volatile(address as usize as *const u16) }
+}
+
+/// Returns the half-word at `offset` half-words into the storage page.
+#[cfg(not(feature = "i2c-eeprom"))]
+pub fn read(offset: usize) -> u16 {
+    debug_assert!(offset * 2 < PAGE_SIZE);
+    unsafe { ptr::read_volatile((STORAGE_PAGE as usize + 2 * offset) as *const u16) }
+}
+
+/// Returns the half-word at `offset` half-words into the EEPROM.
+#[cfg(feature = "i2c-eeprom")]
+pub fn read(offset: usize) -> u16 {
+    debug_assert!(offset < STORED_LENGTH);
+    eeprom::read(offset)
+}
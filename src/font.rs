@@ -0,0 +1,301 @@
+// stm32f3-oscilloscope - src/font.rs
+// fonts: glyph metrics, and fixed or proportional text rendering in several sizes
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// Fonts are one-bit bitmaps, stored either a row at a time (like the Parallax 8x12 font, with
+// the leftmost pixel in the least significant bit) or a column at a time (like the classic 5x7
+// font, with the top pixel in the least significant bit), and drawn at any integer scale.
+//
+// In proportional fonts, each glyph's width is taken from the columns its bitmap actually
+// uses, so no separate width table is needed, and glyphs are separated by one blank column.
+
+use parallax_8x12_font;
+use st7735::*;
+
+#[derive(Clone, Copy)]
+pub enum Layout {
+    Rows,    // one byte per row, leftmost pixel in bit 0
+    Columns, // one byte per column, top pixel in bit 0
+}
+
+pub struct Font {
+    pub width: u8,  // glyph cell width, before scaling
+    pub height: u8, // glyph cell height, before scaling
+    pub first: u8,  // the first character in `glyphs`
+    pub count: u8,  // the number of characters in `glyphs`
+    pub layout: Layout,
+    pub glyphs: &'static [u8],
+    pub scale: u8,
+    pub proportional: bool,
+}
+
+/// The Parallax 8x12 font used for the status line.
+pub const FONT_8X12: Font = Font {
+    width: 8,
+    height: 12,
+    first: 0,
+    count: 128,
+    layout: Layout::Rows,
+    glyphs: &parallax_8x12_font::FONT_8X12,
+    scale: 1,
+    proportional: false,
+};
+
+/// The 8x12 font at double size, for large readouts.
+#[allow(unused)]
+pub const FONT_16X24: Font = Font { scale: 2, ..FONT_8X12 };
+
+/// A small proportional font, for dense text.
+pub const FONT_5X7: Font = Font {
+    width: 5,
+    height: 7,
+    first: 32,
+    count: 95,
+    layout: Layout::Columns,
+    glyphs: &GLYPHS_5X7,
+    scale: 1,
+    proportional: true,
+};
+
+impl Font {
+    // the bitmap bytes of a character, or of a space if the font doesn't include it
+    fn glyph(&self, c: u8) -> &'static [u8] {
+        let index = if c >= self.first && c - self.first < self.count {
+            (c - self.first) as usize
+        } else if b' ' >= self.first {
+            (b' ' - self.first) as usize
+        } else {
+            0
+        };
+        let size = match self.layout {
+            Layout::Rows => self.height as usize,
+            Layout::Columns => self.width as usize,
+        };
+        &self.glyphs[index * size..(index + 1) * size]
+    }
+
+    fn pixel(&self, glyph: &[u8], x: u8, y: u8) -> bool {
+        match self.layout {
+            Layout::Rows => glyph[y as usize] & (1 << x) != 0,
+            Layout::Columns => glyph[x as usize] & (1 << y) != 0,
+        }
+    }
+
+    // the first and last+1 columns of a glyph to draw
+    fn columns(&self, c: u8) -> (u8, u8) {
+        if !self.proportional {
+            return (0, self.width);
+        }
+        let glyph = self.glyph(c);
+        let mut first = self.width;
+        let mut last = 0;
+        for x in 0..self.width {
+            for y in 0..self.height {
+                if self.pixel(glyph, x, y) {
+                    if x < first { first = x; }
+                    last = x + 1;
+                    break;
+                }
+            }
+        }
+        if first >= last {
+            (0, (self.width + 1) / 2) // a blank glyph, like space
+        } else {
+            (first, last)
+        }
+    }
+
+    /// Returns the height of a line of text, in pixels.
+    pub fn line_height(&self) -> u8 {
+        self.height * self.scale
+    }
+
+    /// Returns the horizontal advance of a character, in pixels, including any spacing.
+    pub fn advance(&self, c: u8) -> u8 {
+        let (first, last) = self.columns(c);
+        let spacing = if self.proportional { 1 } else { 0 };
+        (last - first + spacing) * self.scale
+    }
+
+    /// Returns the width of a string, in pixels.
+    pub fn text_width(&self, text: &[u8]) -> u16 {
+        text.iter().map(|&c| self.advance(c) as u16).sum()
+    }
+
+    /// Renders a string into `columns`, one byte per pixel column with the top pixel in bit 0,
+    /// for a font laid out in columns at its normal size, clipping at the end of `columns`.
+    /// Returns the number of columns used.
+    pub fn render_columns(&self, text: &[u8], columns: &mut [u8]) -> usize {
+        debug_assert!(self.height <= 8 && self.scale == 1);
+        let mut x = 0;
+        for &c in text {
+            let advance = self.advance(c) as usize;
+            if x + advance > columns.len() {
+                break;
+            }
+            let glyph = self.glyph(c);
+            let (first, _) = self.columns(c);
+            for gx in 0..advance {
+                let gx = first as usize + gx;
+                columns[x] = match self.layout {
+                    Layout::Columns if gx < self.width as usize => glyph[gx],
+                    _ => 0, // the spacing column
+                };
+                x += 1;
+            }
+        }
+        x
+    }
+
+    /// Draws a string with its top left corner at (`x`, `y`), clipping at the right edge of the
+    /// screen, and returns the x coordinate following it.
+    pub fn draw(&self, text: &[u8], x: u8, y: u8, fg: St7735Color, bg: St7735Color) -> u8 {
+        let screen_width = st7735_get_width() as u16;
+        if y as u16 + self.line_height() as u16 > st7735_get_height() as u16 {
+            return x;
+        }
+        let mut x = x;
+        for &c in text {
+            let advance = self.advance(c);
+            if x as u16 + advance as u16 > screen_width {
+                break;
+            }
+            let glyph = self.glyph(c);
+            let (first, _) = self.columns(c);
+            let columns = advance / self.scale; // including any spacing column
+            st7735_setAddrWindow(x, y, x + advance - 1, y + self.line_height() - 1);
+            for gy in 0..self.height {
+                for _ in 0..self.scale {
+                    for gx in 0..columns {
+                        let set = first + gx < self.width && self.pixel(glyph, first + gx, gy);
+                        let color = if set { fg } else { bg };
+                        for _ in 0..self.scale {
+                            st7735_send_color(color as u16);
+                        }
+                    }
+                }
+            }
+            x += advance;
+        }
+        x
+    }
+}
+
+// The classic 5x7 font, characters 32..126, in columns.
+const GLYPHS_5X7: [u8; 95 * 5] = [
+    0x00, 0x00, 0x00, 0x00, 0x00,  // space
+    0x00, 0x00, 0x5f, 0x00, 0x00,  // !
+    0x00, 0x07, 0x00, 0x07, 0x00,  // "
+    0x14, 0x7f, 0x14, 0x7f, 0x14,  // #
+    0x24, 0x2a, 0x7f, 0x2a, 0x12,  // $
+    0x23, 0x13, 0x08, 0x64, 0x62,  // %
+    0x36, 0x49, 0x55, 0x22, 0x50,  // &
+    0x00, 0x05, 0x03, 0x00, 0x00,  // '
+    0x00, 0x1c, 0x22, 0x41, 0x00,  // (
+    0x00, 0x41, 0x22, 0x1c, 0x00,  // )
+    0x08, 0x2a, 0x1c, 0x2a, 0x08,  // *
+    0x08, 0x08, 0x3e, 0x08, 0x08,  // +
+    0x00, 0x50, 0x30, 0x00, 0x00,  // ,
+    0x08, 0x08, 0x08, 0x08, 0x08,  // -
+    0x00, 0x60, 0x60, 0x00, 0x00,  // .
+    0x20, 0x10, 0x08, 0x04, 0x02,  // /
+    0x3e, 0x51, 0x49, 0x45, 0x3e,  // 0
+    0x00, 0x42, 0x7f, 0x40, 0x00,  // 1
+    0x42, 0x61, 0x51, 0x49, 0x46,  // 2
+    0x21, 0x41, 0x45, 0x4b, 0x31,  // 3
+    0x18, 0x14, 0x12, 0x7f, 0x10,  // 4
+    0x27, 0x45, 0x45, 0x45, 0x39,  // 5
+    0x3c, 0x4a, 0x49, 0x49, 0x30,  // 6
+    0x01, 0x71, 0x09, 0x05, 0x03,  // 7
+    0x36, 0x49, 0x49, 0x49, 0x36,  // 8
+    0x06, 0x49, 0x49, 0x29, 0x1e,  // 9
+    0x00, 0x36, 0x36, 0x00, 0x00,  // :
+    0x00, 0x56, 0x36, 0x00, 0x00,  // ;
+    0x08, 0x14, 0x22, 0x41, 0x00,  // <
+    0x14, 0x14, 0x14, 0x14, 0x14,  // =
+    0x00, 0x41, 0x22, 0x14, 0x08,  // >
+    0x02, 0x01, 0x51, 0x09, 0x06,  // ?
+    0x32, 0x49, 0x79, 0x41, 0x3e,  // @
+    0x7e, 0x11, 0x11, 0x11, 0x7e,  // A
+    0x7f, 0x49, 0x49, 0x49, 0x36,  // B
+    0x3e, 0x41, 0x41, 0x41, 0x22,  // C
+    0x7f, 0x41, 0x41, 0x22, 0x1c,  // D
+    0x7f, 0x49, 0x49, 0x49, 0x41,  // E
+    0x7f, 0x09, 0x09, 0x01, 0x01,  // F
+    0x3e, 0x41, 0x41, 0x51, 0x32,  // G
+    0x7f, 0x08, 0x08, 0x08, 0x7f,  // H
+    0x00, 0x41, 0x7f, 0x41, 0x00,  // I
+    0x20, 0x40, 0x41, 0x3f, 0x01,  // J
+    0x7f, 0x08, 0x14, 0x22, 0x41,  // K
+    0x7f, 0x40, 0x40, 0x40, 0x40,  // L
+    0x7f, 0x02, 0x04, 0x02, 0x7f,  // M
+    0x7f, 0x04, 0x08, 0x10, 0x7f,  // N
+    0x3e, 0x41, 0x41, 0x41, 0x3e,  // O
+    0x7f, 0x09, 0x09, 0x09, 0x06,  // P
+    0x3e, 0x41, 0x51, 0x21, 0x5e,  // Q
+    0x7f, 0x09, 0x19, 0x29, 0x46,  // R
+    0x46, 0x49, 0x49, 0x49, 0x31,  // S
+    0x01, 0x01, 0x7f, 0x01, 0x01,  // T
+    0x3f, 0x40, 0x40, 0x40, 0x3f,  // U
+    0x1f, 0x20, 0x40, 0x20, 0x1f,  // V
+    0x7f, 0x20, 0x18, 0x20, 0x7f,  // W
+    0x63, 0x14, 0x08, 0x14, 0x63,  // X
+    0x03, 0x04, 0x78, 0x04, 0x03,  // Y
+    0x61, 0x51, 0x49, 0x45, 0x43,  // Z
+    0x00, 0x7f, 0x41, 0x41, 0x00,  // [
+    0x02, 0x04, 0x08, 0x10, 0x20,  // backslash
+    0x00, 0x41, 0x41, 0x7f, 0x00,  // ]
+    0x04, 0x02, 0x01, 0x02, 0x04,  // ^
+    0x40, 0x40, 0x40, 0x40, 0x40,  // _
+    0x00, 0x01, 0x02, 0x04, 0x00,  // `
+    0x20, 0x54, 0x54, 0x54, 0x78,  // a
+    0x7f, 0x48, 0x44, 0x44, 0x38,  // b
+    0x38, 0x44, 0x44, 0x44, 0x20,  // c
+    0x38, 0x44, 0x44, 0x48, 0x7f,  // d
+    0x38, 0x54, 0x54, 0x54, 0x18,  // e
+    0x08, 0x7e, 0x09, 0x01, 0x02,  // f
+    0x08, 0x14, 0x54, 0x54, 0x3c,  // g
+    0x7f, 0x08, 0x04, 0x04, 0x78,  // h
+    0x00, 0x44, 0x7d, 0x40, 0x00,  // i
+    0x20, 0x40, 0x44, 0x3d, 0x00,  // j
+    0x00, 0x7f, 0x10, 0x28, 0x44,  // k
+    0x00, 0x41, 0x7f, 0x40, 0x00,  // l
+    0x7c, 0x04, 0x18, 0x04, 0x78,  // m
+    0x7c, 0x08, 0x04, 0x04, 0x78,  // n
+    0x38, 0x44, 0x44, 0x44, 0x38,  // o
+    0x7c, 0x14, 0x14, 0x14, 0x08,  // p
+    0x08, 0x14, 0x14, 0x18, 0x7c,  // q
+    0x7c, 0x08, 0x04, 0x04, 0x08,  // r
+    0x48, 0x54, 0x54, 0x54, 0x20,  // s
+    0x04, 0x3f, 0x44, 0x40, 0x20,  // t
+    0x3c, 0x40, 0x40, 0x20, 0x7c,  // u
+    0x1c, 0x20, 0x40, 0x20, 0x1c,  // v
+    0x3c, 0x40, 0x30, 0x40, 0x3c,  // w
+    0x44, 0x28, 0x10, 0x28, 0x44,  // x
+    0x0c, 0x50, 0x50, 0x50, 0x3c,  // y
+    0x44, 0x64, 0x54, 0x4c, 0x44,  // z
+    0x00, 0x08, 0x36, 0x41, 0x00,  // {
+    0x00, 0x00, 0x7f, 0x00, 0x00,  // |
+    0x00, 0x41, 0x36, 0x08, 0x00,  // }
+    0x08, 0x04, 0x08, 0x10, 0x08,  // ~
+];
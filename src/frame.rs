@@ -0,0 +1,77 @@
+// stm32f3-oscilloscope - src/frame.rs
+// framing for data sent over the serial port: sync word, type, length, and CRC
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Everything the shell exports (record dumps, streaming, and screenshots) is sent in frames:
+//
+//   0xa5 0x5a, type, length (2 bytes), payload (`length` bytes), CRC (2 bytes)
+//
+// with the two-byte fields little-endian. The CRC is CRC-16/CCITT-FALSE (polynomial 0x1021,
+// initial value 0xffff) over the type, length, and payload. A receiver finds the start of a
+// frame by the sync word, and checks the CRC before trusting anything else in it, so a byte
+// dropped or garbled by a flaky USB-serial adapter costs just the frame it was in, which for
+// a dump can be sent again (see export.rs). Like delta.rs, this uses none of the hardware
+// except through serial::write().
+
+use serial;
+
+const SYNC: [u8; 2] = [0xa5, 0x5a];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FrameType {
+    DumpHeader = 0x01,        // sample count (4 bytes), samples per second (4 bytes)
+    DumpData = 0x02,          // sequence number (2 bytes), then raw samples (2 bytes each)
+    StreamRecordStart = 0x10, // a delta-encoded frame, the first of a record
+    Stream = 0x11,            // a delta-encoded frame, continuing the record
+    ScreenshotHeader = 0x20,  // width (2 bytes), height (2 bytes)
+    ScreenshotRow = 0x21,     // row number (2 bytes), then red, green, and blue for each pixel
+}
+
+/// Sends a frame, with the payload given in parts, which are sent one after the other.
+pub fn send(frame_type: FrameType, payload: &[&[u8]]) {
+    let length = payload.iter().fold(0, |sum, part| sum + part.len()) as u16;
+    let header = [frame_type as u8, length as u8, (length >> 8) as u8];
+    let mut crc = crc16(0xffff, &header);
+    for part in payload {
+        crc = crc16(crc, part);
+    }
+    serial::write(&SYNC);
+    serial::write(&header);
+    for part in payload {
+        serial::write(part);
+    }
+    serial::write(&[crc as u8, (crc >> 8) as u8]);
+}
+
+/// Continues a CRC-16/CCITT-FALSE over `data`. Start with 0xffff.
+pub fn crc16(crc: u16, data: &[u8]) -> u16 {
+    let mut crc = crc;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { crc << 1 ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
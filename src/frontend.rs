@@ -0,0 +1,167 @@
+// stm32f3-oscilloscope - src/frontend.rs
+// range select outputs for an external input attenuator, with the front-end feature
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// An input attenuator board, relays or an analog multiplexer ahead of the ADC, has a few
+// ranges, each selected by a code on board::FRONT_END_PINS, bit 0 on the first pin. Each range
+// is an input mapping (see input.rs), the input voltages at which the ADC sees 0V and 3.3V.
+// RANGES describes a board with three ranges a decade apart, ±25V, ±2.5V, and ±0.25V; edit it
+// to match another board.
+//
+// The ranges extend the vertical gains (screen.rs): the vertical setting steps through each
+// gain of the least sensitive range, then each of the next, and so on, and so does
+// auto-ranging, so the range switches when the gain would run off either end. With ranges a
+// decade apart and 10x the top gain, a range's top gain shows the same span as the next one's
+// 1x, so the steps carry on smoothly, the more sensitive range resolving the signal finer. The
+// vertical setting is labelled with the overall magnification, e.g. 45x for 4.5x on the second
+// range.
+//
+// Switching ranges changes the input mapping, so the screen's center, which is kept at the
+// ADC, is converted to keep its voltage at the input; take_switched() tells the main loop to do
+// the same for the trigger level, with convert_level().
+//
+// Without the front-end feature, there are no ranges, the pins are left alone, and the mapping
+// is whatever the input command sets.
+
+use board::FRONT_END_PINS;
+use calibration;
+use gpio::Mode;
+use input;
+use measure;
+use screen;
+
+/// One of the attenuator's ranges.
+pub struct Range {
+    pub low_mv: i32,  // the input voltage at 0V on the ADC
+    pub high_mv: i32, // and at 3.3V
+    pub code: u8,     // the code on the range select pins
+}
+
+/// The attenuator's ranges, least sensitive first.
+#[cfg(feature = "front-end")]
+pub const RANGES: [Range; 3] = [
+    Range { low_mv: -25_000, high_mv: 25_000, code: 0b00 },
+    Range { low_mv: -2_500, high_mv: 2_500, code: 0b01 },
+    Range { low_mv: -250, high_mv: 250, code: 0b10 },
+];
+#[cfg(not(feature = "front-end"))]
+pub const RANGES: [Range; 0] = [];
+
+static mut RANGE: usize = 0;
+static mut SWITCHED_FROM: Option<(i32, i32)> = None; // the mapping before a switch
+
+/// Sets up the range select pins, and selects the least sensitive range, the safest for an
+/// unknown signal.
+pub fn setup() {
+    if RANGES.is_empty() {
+        return;
+    }
+    for pin in &FRONT_END_PINS {
+        pin.enable_port_clock();
+        pin.set_low();
+        pin.configure(Mode::Output);
+    }
+    select(0);
+}
+
+// drive range `range`'s code, and map the input to it
+fn select(range: usize) {
+    for (bit, pin) in FRONT_END_PINS.iter().enumerate() {
+        if RANGES[range].code & 1 << bit != 0 {
+            pin.set_high();
+        } else {
+            pin.set_low();
+        }
+    }
+    unsafe {
+        if SWITCHED_FROM.is_none() {
+            SWITCHED_FROM = Some(input::mapping());
+        }
+        RANGE = range;
+    }
+    input::set(RANGES[range].low_mv, RANGES[range].high_mv);
+}
+
+/// Returns the number of vertical settings: each gain, on each range.
+pub fn positions() -> usize {
+    let ranges = if RANGES.is_empty() { 1 } else { RANGES.len() };
+    ranges * screen::GAINS.len()
+}
+
+/// Returns the vertical setting, as an index from the least sensitive.
+pub fn position() -> usize {
+    unsafe { RANGE } * screen::GAINS.len() + screen::gain_index()
+}
+
+/// Sets the vertical setting, switching ranges if need be, and centering the screen as near
+/// `center` microvolts at the ADC, on the range before the switch, as it can.
+pub fn set_position(position: usize, center: u32) {
+    let range = position / screen::GAINS.len();
+    let center = if range != unsafe { RANGE } {
+        let at_input = input::microvolts(center as i64);
+        select(range);
+        input::adc_microvolts(at_input)
+    } else {
+        center
+    };
+    screen::set_vertical(position % screen::GAINS.len(), center);
+}
+
+/// Returns the input mapping before the range switched, if it has since the last call.
+pub fn take_switched() -> Option<(i32, i32)> {
+    unsafe {
+        let from = SWITCHED_FROM;
+        SWITCHED_FROM = None;
+        from
+    }
+}
+
+/// Returns the raw conversion for the same input voltage as `level` was under the mapping
+/// `from`, under the mapping in use.
+pub fn convert_level(level: u16, from: (i32, i32)) -> u16 {
+    let (low, high) = from;
+    let adc_uv = calibration::microvolts(level) as i64;
+    let at_input = low as i64 * 1000 + adc_uv * (high - low) as i64 / input::ADC_SPAN_MV as i64;
+    calibration::raw_conversion(input::adc_microvolts(at_input))
+}
+
+/// Formats the overall magnification, e.g. "4.5x" or "450x", returning its length. `text`
+/// needs room for seven characters.
+pub fn format_gain(text: &mut [u8]) -> usize {
+    let mut tenths = screen::GAINS[screen::gain_index()].tenths as u64;
+    for _ in 0..unsafe { RANGE } {
+        tenths *= 10;
+    }
+    let mut buf = [b' '; 6];
+    if tenths % 10 == 0 {
+        measure::format_fixed(tenths / 10, 0, &mut buf);
+    } else {
+        measure::format_fixed(tenths, 1, &mut buf);
+    }
+    let start = buf.iter().position(|&c| c != b' ').unwrap_or(0);
+    let len = buf.len() - start;
+    text[..len].copy_from_slice(&buf[start..]);
+    text[len] = b'x';
+    len + 1
+}
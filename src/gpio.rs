@@ -0,0 +1,153 @@
+// stm32f3-oscilloscope - src/gpio.rs
+// GPIO pins chosen by the board wiring tables, rather than named in code
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The device crate gives each GPIO pin its own register field methods, which is fine when the
+// pins are fixed, but means code must be edited to move a signal to another pin. A `Pin` is
+// instead a port and pin number, as listed in board.rs, and is manipulated through the raw
+// register bits. Port A's register block is a different type in the device crate, differing
+// only in its reset values, so it is accessed through the port B type like the others.
+
+use cortex_m;
+use stm32f30x::{GPIOA, GPIOB, GPIOC, GPIOD, GPIOE, GPIOF, RCC};
+use stm32f30x::gpiob;
+
+#[allow(unused)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Port {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+}
+
+#[allow(unused)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Input = 0b00,
+    Output = 0b01,
+    Alternate = 0b10,
+    Analog = 0b11,
+}
+
+#[allow(unused)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pull {
+    None = 0b00,
+    Up = 0b01,
+    Down = 0b10,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Pin {
+    pub port: Port,
+    pub number: u8,
+}
+
+impl Pin {
+    fn gpio(self) -> &'static gpiob::RegisterBlock {
+        unsafe {
+            match self.port {
+                Port::A => &*(GPIOA.get() as *const gpiob::RegisterBlock),
+                Port::B => &*GPIOB.get(),
+                Port::C => &*GPIOC.get(),
+                Port::D => &*GPIOD.get(),
+                Port::E => &*GPIOE.get(),
+                Port::F => &*GPIOF.get(),
+            }
+        }
+    }
+
+    /// Enables the clock to the pin's port.
+    pub fn enable_port_clock(self) {
+        cortex_m::interrupt::free(|cs| {
+            let rcc = RCC.borrow(cs);
+            match self.port {
+                Port::A => rcc.ahbenr.modify(|_, w| w.iopaen().enabled()),
+                Port::B => rcc.ahbenr.modify(|_, w| w.iopben().enabled()),
+                Port::C => rcc.ahbenr.modify(|_, w| w.iopcen().enabled()),
+                Port::D => rcc.ahbenr.modify(|_, w| w.iopden().enabled()),
+                Port::E => rcc.ahbenr.modify(|_, w| w.iopeen().enabled()),
+                Port::F => rcc.ahbenr.modify(|_, w| w.iopfen().enabled()),
+            }
+        });
+    }
+
+    /// Sets the pin's mode. Outputs are push-pull, at the fastest slew rate.
+    pub fn configure(self, mode: Mode) {
+        let n = self.number as u32;
+        cortex_m::interrupt::free(|_| {
+            let gpio = self.gpio();
+            gpio.otyper.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << n)) }); // push-pull
+            gpio.ospeedr.modify(|r, w| unsafe { w.bits(r.bits() | 0b11 << (2 * n)) }); // fast
+            gpio.moder.modify(|r, w| unsafe {
+                w.bits(r.bits() & !(0b11 << (2 * n)) | (mode as u32) << (2 * n))
+            });
+        });
+    }
+
+    /// Sets the pin's pull-up or pull-down.
+    pub fn set_pull(self, pull: Pull) {
+        let n = self.number as u32;
+        cortex_m::interrupt::free(|_| {
+            self.gpio().pupdr.modify(|r, w| unsafe {
+                w.bits(r.bits() & !(0b11 << (2 * n)) | (pull as u32) << (2 * n))
+            });
+        });
+    }
+
+    /// Selects alternate function `af` for the pin, for when it is in alternate mode.
+    pub fn set_alternate_function(self, af: u8) {
+        let shift = 4 * (self.number as u32 % 8);
+        let bits = |r: u32| r & !(0b1111 << shift) | (af as u32) << shift;
+        cortex_m::interrupt::free(|_| {
+            let gpio = self.gpio();
+            if self.number < 8 {
+                gpio.afrl.modify(|r, w| unsafe { w.bits(bits(r.bits())) });
+            } else {
+                gpio.afrh.modify(|r, w| unsafe { w.bits(bits(r.bits())) });
+            }
+        });
+    }
+
+    pub fn set_high(self) {
+        self.gpio().bsrr.write(|w| unsafe { w.bits(1 << self.number) });
+    }
+
+    pub fn set_low(self) {
+        self.gpio().brr.write(|w| unsafe { w.bits(1 << self.number) });
+    }
+
+    /// Returns whether the pin's input is high.
+    pub fn is_high(self) -> bool {
+        self.gpio().idr.read().bits() & (1 << self.number) != 0
+    }
+
+    /// Returns whether the pin's output is set high.
+    pub fn is_set_high(self) -> bool {
+        self.gpio().odr.read().bits() & (1 << self.number) != 0
+    }
+}
@@ -0,0 +1,301 @@
+// stm32f3-oscilloscope - src/harmonics.rs
+// total harmonic distortion and the harmonics' levels, from an FFT of the record
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The harmonics display takes the first FFT_LENGTH samples of each record, less their mean,
+// through a Hann window and a fixed-point radix-2 FFT, and finds the fundamental as the
+// largest bin above the lowest few (which the window smears DC into). Its frequency comes
+// from the fundamental's bin and its neighbours, interpolated to a 256th of a bin with the
+// estimator exact for the Hann window's main lobe, so that even the tenth harmonic is looked
+// for in the right bins. Each component's power is summed over its bin and two either side,
+// which covers the window's main lobe wherever the frequency falls between bins.
+//
+// The fundamental's RMS amplitude comes from its power: with the Hann window, a sine of RMS
+// amplitude A codes puts 3A^2N^2/16 into the lobe on each side, N being FFT_LENGTH. It is
+// shown in the selected units (see measure.rs), so in dBm, for instance, the harmonics' levels
+// in dBc give their absolute levels too.
+//
+// The total harmonic distortion is the RMS sum of harmonics 2 to MAX_HARMONIC, those below
+// half the sample rate, relative to the fundamental, shown as a percentage in large digits
+// and in dB, with a bar for each harmonic's level in dB below the fundamental (dBc) from 0 at
+// the top to -100dB at the bottom. A 12-bit ADC's quantization noise floor is around -74dB
+// over the whole band, but much lower per bin, so harmonics down to around -90dBc can be seen,
+// although the THD includes the noise in each harmonic's bins. The fundamental needs at least
+// MIN_FUNDAMENTAL_BIN cycles in the FFT, so the timebase should show around 5 to 20 cycles
+// across FFT_LENGTH samples; the record length needs to be 1k or more.
+//
+// The FFT keeps full precision through its stages: the 12-bit samples grow by at most a bit a
+// stage, to 21 bits after nine, and the twiddle factor products are taken in 64 bits.
+
+use calibration;
+use font::FONT_5X7;
+use input;
+use measure;
+use screen;
+use segment;
+use st7735::*;
+
+/// The samples transformed, a power of two.
+pub const FFT_LENGTH: usize = 512;
+const LOG2_LENGTH: usize = 9;
+// the fundamental must be at least this many bins up, clear of DC and its window
+const MIN_FUNDAMENTAL_BIN: usize = 5;
+/// The highest harmonic measured.
+pub const MAX_HARMONIC: usize = 10;
+// bins either side of a component's center summed into its power
+const LOBE: usize = 2;
+
+const DIGITS_Y: i16 = 2;
+const DIGITS_HEIGHT: i16 = 20;
+const LINE_Y: u8 = 26; // the fundamental frequency and THD in dB
+const LEVELS_Y: u8 = 36; // each harmonic's level
+const BARS_TOP: i16 = 46;
+const BAR_PITCH: i16 = 17;
+const BAR_WIDTH: i16 = 13;
+const LABEL_HEIGHT: i16 = 9; // the font height, plus a pixel either side
+const FLOOR_TENTHS: i32 = -1000; // the bottom of the bars, -100dBc
+
+// sin(2 pi k / FFT_LENGTH) for the first quarter wave, scaled by 32768
+const QUARTER_SINE: [i32; FFT_LENGTH / 4 + 1] = [
+    0, 402, 804, 1206, 1608, 2009, 2411, 2811, 3212, 3612, 4011, 4410,
+    4808, 5205, 5602, 5998, 6393, 6787, 7180, 7571, 7962, 8351, 8740, 9127,
+    9512, 9896, 10279, 10660, 11039, 11417, 11793, 12167, 12540, 12910, 13279, 13646,
+    14010, 14373, 14733, 15091, 15447, 15800, 16151, 16500, 16846, 17190, 17531, 17869,
+    18205, 18538, 18868, 19195, 19520, 19841, 20160, 20475, 20788, 21097, 21403, 21706,
+    22006, 22302, 22595, 22884, 23170, 23453, 23732, 24008, 24279, 24548, 24812, 25073,
+    25330, 25583, 25833, 26078, 26320, 26557, 26791, 27020, 27246, 27467, 27684, 27897,
+    28106, 28311, 28511, 28707, 28899, 29086, 29269, 29448, 29622, 29792, 29957, 30118,
+    30274, 30425, 30572, 30715, 30853, 30986, 31114, 31238, 31357, 31471, 31581, 31686,
+    31786, 31881, 31972, 32058, 32138, 32214, 32286, 32352, 32413, 32470, 32522, 32568,
+    32610, 32647, 32679, 32706, 32729, 32746, 32758, 32766, 32768,
+];
+
+/// The outcome of a record's analysis.
+pub struct Analysis {
+    pub fundamental_mhz: u64,
+    pub fundamental_uv: u64, // RMS, at the input
+    pub thd_millipercent: u32, // thousandths of a percent
+    pub thd_tenths_db: i32,    // tenths of a dB, relative to the fundamental
+    // each harmonic's level in tenths of a dBc, from the second; None above half the sample
+    // rate
+    pub levels: [Option<i32>; MAX_HARMONIC - 1],
+}
+
+static mut REAL: [i32; FFT_LENGTH] = [0; FFT_LENGTH];
+static mut IMAGINARY: [i32; FFT_LENGTH] = [0; FFT_LENGTH];
+
+// sin(2 pi k / FFT_LENGTH), scaled by 32768
+fn sine(k: usize) -> i32 {
+    let quarter = FFT_LENGTH / 4;
+    let k = k % FFT_LENGTH;
+    let i = k % quarter;
+    match k / quarter {
+        0 => QUARTER_SINE[i],
+        1 => QUARTER_SINE[quarter - i],
+        2 => -QUARTER_SINE[i],
+        _ => -QUARTER_SINE[quarter - i],
+    }
+}
+
+fn cosine(k: usize) -> i32 {
+    sine(k + FFT_LENGTH / 4)
+}
+
+// transform REAL and IMAGINARY in place
+fn fft(real: &mut [i32; FFT_LENGTH], imaginary: &mut [i32; FFT_LENGTH]) {
+    // bit-reversed order
+    for i in 0..FFT_LENGTH {
+        let mut j = 0;
+        for bit in 0..LOG2_LENGTH {
+            j |= ((i >> bit) & 1) << (LOG2_LENGTH - 1 - bit);
+        }
+        if j > i {
+            real.swap(i, j);
+            imaginary.swap(i, j);
+        }
+    }
+    let mut length = 2;
+    while length <= FFT_LENGTH {
+        let half = length / 2;
+        let stride = FFT_LENGTH / length;
+        let mut start = 0;
+        while start < FFT_LENGTH {
+            for j in 0..half {
+                let wr = cosine(j * stride) as i64;
+                let wi = -sine(j * stride) as i64;
+                let (a, b) = (start + j, start + j + half);
+                let (br, bi) = (real[b] as i64, imaginary[b] as i64);
+                let tr = ((br * wr - bi * wi) >> 15) as i32;
+                let ti = ((br * wi + bi * wr) >> 15) as i32;
+                real[b] = real[a] - tr;
+                imaginary[b] = imaginary[a] - ti;
+                real[a] += tr;
+                imaginary[a] += ti;
+            }
+            start += length;
+        }
+        length *= 2;
+    }
+}
+
+/// Returns ten times log10(`power` / `reference`), in tenths of a dB; both must be nonzero.
+pub fn tenths_db(power: u64, reference: u64) -> i32 {
+    // log10(2) is 0.30103, and log2_q16 gives 65536ths
+    let difference = (measure::log2_q16(power) - measure::log2_q16(reference)) as i64;
+    (difference * 30103 / (65536 * 1000)) as i32
+}
+
+/// Analyzes the first FFT_LENGTH samples of `data`, captured at `sample_rate`, or returns a
+/// message saying why it couldn't.
+pub fn analyze(data: &[u16], sample_rate: u32) -> Result<Analysis, &'static [u8]> {
+    if data.len() < FFT_LENGTH {
+        return Err(b"set record 1k or more");
+    }
+    let data = &data[..FFT_LENGTH];
+    let mean = (data.iter().map(|&s| s as u32).sum::<u32>() / FFT_LENGTH as u32) as i32;
+    let (real, imaginary) = unsafe { (&mut REAL, &mut IMAGINARY) };
+    for (k, &sample) in data.iter().enumerate() {
+        let window = (32768 - cosine(k)) / 2;
+        real[k] = (sample as i32 - mean) * window >> 15;
+        imaginary[k] = 0;
+    }
+    fft(real, imaginary);
+    let power = |k: usize| -> u64 {
+        let (r, i) = (real[k] as i64, imaginary[k] as i64);
+        (r * r + i * i) as u64
+    };
+    // the power around bin `center`, in 256ths of a bin, or None if that's too near half the
+    // sample rate
+    let lobe_power = |center: usize| -> Option<u64> {
+        let bin = (center + 128) / 256;
+        if bin + LOBE >= FFT_LENGTH / 2 {
+            return None;
+        }
+        Some((bin - LOBE..bin + LOBE + 1).map(|k| power(k)).sum())
+    };
+
+    let mut peak = MIN_FUNDAMENTAL_BIN - 1;
+    // leaving room for the lobe either side, however the peak is interpolated
+    for k in peak..FFT_LENGTH / 2 - LOBE - 1 {
+        if power(k) > power(peak) {
+            peak = k;
+        }
+    }
+    if power(peak) == 0 {
+        return Err(b"no signal");
+    }
+    if peak < MIN_FUNDAMENTAL_BIN {
+        return Err(b"too few cycles");
+    }
+    // interpolate between the bins, from their amplitudes
+    let amplitude = |k: usize| measure::isqrt(power(k)) as i64;
+    let (before, at, after) = (amplitude(peak - 1), amplitude(peak), amplitude(peak + 1));
+    let offset = 2 * 256 * (after - before) / (before + 2 * at + after);
+    let center = (peak as i64 * 256 + offset) as usize;
+    let fundamental_mhz = center as u64 * sample_rate as u64 * 1000 / (256 * FFT_LENGTH) as u64;
+
+    let fundamental = lobe_power(center).unwrap_or(0);
+    let mut levels = [None; MAX_HARMONIC - 1];
+    let mut harmonics = 0;
+    for h in 2..MAX_HARMONIC + 1 {
+        if let Some(p) = lobe_power(center * h) {
+            harmonics += p;
+            levels[h - 2] = Some(if p == 0 { FLOOR_TENTHS } else { tenths_db(p, fundamental) });
+        }
+    }
+    // the RMS in 256ths of a code, then in microvolts at the ADC, and at the input
+    let rms = measure::isqrt(fundamental * 16 / 3 * 65536) / FFT_LENGTH as u64;
+    let adc_uv = rms * calibration::nanovolts_per_lsb(0) as u64 / 256 / 1000;
+    let fundamental_uv = input::scale(adc_uv as i64).abs() as u64;
+    let thd_millipercent = measure::isqrt(harmonics) * 100_000 / measure::isqrt(fundamental);
+    let thd_tenths_db =
+        if harmonics == 0 { FLOOR_TENTHS } else { tenths_db(harmonics, fundamental) };
+    Ok(Analysis {
+        fundamental_mhz: fundamental_mhz,
+        fundamental_uv: fundamental_uv,
+        thd_millipercent: thd_millipercent as u32,
+        thd_tenths_db: thd_tenths_db,
+        levels: levels,
+    })
+}
+
+// format `tenths` of a dB, rounded to whole dB and limited to -99, in `buf`
+fn format_db(tenths: i32, buf: &mut [u8]) {
+    let db = if tenths < -990 { -99 } else { (tenths - 5) / 10 };
+    measure::format_signed_fixed(db as i64, 0, buf);
+}
+
+/// Draws the harmonics display: the THD, the fundamental's frequency, and a bar for each
+/// harmonic's level.
+pub fn draw(analysis: Result<Analysis, &'static [u8]>) {
+    screen::clear_trace_area();
+    let analysis = match analysis {
+        Ok(analysis) => analysis,
+        Err(message) => {
+            FONT_5X7.draw(message, 0, LINE_Y, St7735Color::White, St7735Color::Black);
+            return;
+        }
+    };
+    let mut digits = [b' '; 7];
+    measure::format_fixed(analysis.thd_millipercent as u64, 3, &mut digits);
+    let x = segment::draw(&digits, 0, DIGITS_Y, DIGITS_HEIGHT, St7735Color::Green,
+                          St7735Color::Black);
+    st7735_print(b"% THD", (x + 2) as u8, (DIGITS_Y + DIGITS_HEIGHT - 12) as u8,
+                 St7735Color::Green, St7735Color::Black);
+
+    // the fundamental, and the THD in dB, e.g. "1.43kHz 1.06V -58.2dB"
+    let mut line = [b' '; 30];
+    let mut len = measure::format_si(analysis.fundamental_mhz as i64 * 1_000_000, b"Hz",
+                                     &mut line);
+    len += 1;
+    len += measure::format_amplitude(analysis.fundamental_uv, &mut line[len..]);
+    len += 1;
+    let mut db = [b' '; 6];
+    measure::format_signed_fixed(analysis.thd_tenths_db as i64, 1, &mut db);
+    let first = db.iter().position(|&c| c != b' ').unwrap_or(0);
+    line[len..len + 6 - first].copy_from_slice(&db[first..]);
+    len += 6 - first;
+    line[len..len + 2].copy_from_slice(b"dB");
+    FONT_5X7.draw(&line[..len + 2], 0, LINE_Y, St7735Color::White, St7735Color::Black);
+
+    // the bars, with each level above and the harmonic's number below
+    let label_y = screen::trace_height() - LABEL_HEIGHT + 1;
+    let height = label_y - 1 - BARS_TOP;
+    for (i, level) in analysis.levels.iter().enumerate() {
+        let x = i as i16 * BAR_PITCH;
+        let mut number = [b' '; 2];
+        measure::format_fixed(i as u64 + 2, 0, &mut number);
+        FONT_5X7.draw(&number, (x + 1) as u8, label_y as u8, St7735Color::White,
+                      St7735Color::Black);
+        if let Some(tenths) = *level {
+            let mut text = [b' '; 3];
+            format_db(tenths, &mut text);
+            FONT_5X7.draw(&text, x as u8, LEVELS_Y, St7735Color::White, St7735Color::Black);
+            let above_floor = if tenths < FLOOR_TENTHS { 0 } else { tenths - FLOOR_TENTHS };
+            let bar = (above_floor as i64 * height as i64 / -FLOOR_TENTHS as i64) as i16;
+            st7735_fill_rect(x + 1, BARS_TOP + height - bar, BAR_WIDTH, bar,
+                             St7735Color::Green as u16);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+// stm32f3-oscilloscope - src/help.rs
+// on-device help screens, describing what the buttons do
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// Help is entered by holding buttons 3 and 4 together for HOLD_MS. The pages shown depend on
+// the mode the scope was in. Each page is at most nine lines of twenty characters, the first
+// line being its title. In portrait, where the screen is 16 characters wide, the ends of the
+// longer lines are cut off.
+
+use screen;
+use st7735::*;
+
+/// How long buttons 3 and 4 must be held together to enter help, in milliseconds.
+pub const HOLD_MS: u32 = 1000;
+
+const TOP: u8 = 0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum HelpContext {
+    Normal,
+    Zoomed,
+    Search,   // zoomed, with a search selected
+    Log,      // the data log display
+    Envelope, // the envelope display
+    Noise,    // the noise display
+    Rc,       // the RC display
+}
+
+type Page = &'static [&'static [u8]];
+
+const PARAMETERS_PAGE: Page = &[
+    b"parameters (3, 4)",
+    b"siggen freq, probe",
+    b"  comp, test, interp",
+    b"trig mode, slope,",
+    b"  coupling, level,",
+    b"  delay, auto, gate",
+    b"record len, tb",
+    b"  track/fine, search",
+    b"display, rotation",
+];
+
+const MORE_PARAMETERS_PAGE: Page = &[
+    b"parameters, cont.",
+    b"vertical, history,",
+    b"  segments,",
+    b"trace, show, color,",
+    b"  style, math,",
+    b"  cutoff, cursor A,",
+    b"  cursor B, measure",
+    b"buzzer",
+    b"cal (4 to start)",
+];
+
+const NORMAL_PAGES: [Page; 3] = [
+    &[
+        b"buttons",
+        b"1: timebase",
+        b"2: zoom",
+        b"3: select parameter",
+        b"4: adjust parameter",
+        b"hold 1/3/4: go back",
+        b"hold 3+4: help",
+    ],
+    PARAMETERS_PAGE,
+    MORE_PARAMETERS_PAGE,
+];
+
+const ZOOMED_PAGES: [Page; 3] = [
+    &[
+        b"buttons - zoomed",
+        b"1: move window",
+        b"2: magnify, or off",
+        b"3: select parameter",
+        b"4: adjust parameter",
+        b"strip at the top",
+        b"shows whole record,",
+        b"green bar the window",
+    ],
+    PARAMETERS_PAGE,
+    MORE_PARAMETERS_PAGE,
+];
+
+const SEARCH_PAGES: [Page; 3] = [
+    &[
+        b"buttons - search",
+        b"1: previous hit",
+        b"2: magnify, or off",
+        b"3: select parameter",
+        b"4: next hit",
+        b"select search off",
+        b"to adjust the other",
+        b"parameters",
+    ],
+    PARAMETERS_PAGE,
+    MORE_PARAMETERS_PAGE,
+];
+
+const LOG_PAGES: [Page; 3] = [
+    &[
+        b"buttons - log",
+        b"1: older entries",
+        b"2: newer entries",
+        b"3: select parameter",
+        b"4: adjust parameter",
+        b"plots the last",
+        b"measurement logged;",
+        b"blue: a new session",
+    ],
+    PARAMETERS_PAGE,
+    MORE_PARAMETERS_PAGE,
+];
+
+const ENVELOPE_PAGES: [Page; 3] = [
+    &[
+        b"buttons - envelope",
+        b"1: timebase",
+        b"2: reset envelope",
+        b"3: select parameter",
+        b"4: adjust parameter",
+        b"blue: the range of",
+        b"each column so far;",
+        b"any setting change",
+        b"also resets",
+    ],
+    PARAMETERS_PAGE,
+    MORE_PARAMETERS_PAGE,
+];
+
+const NOISE_PAGES: [Page; 3] = [
+    &[
+        b"buttons - noise",
+        b"1: timebase",
+        b"2: start again",
+        b"3: select parameter",
+        b"4: adjust parameter",
+        b"blue: with the input",
+        b"grounded, the noise",
+        b"of all the sweeps;",
+        b"settings restart it",
+    ],
+    PARAMETERS_PAGE,
+    MORE_PARAMETERS_PAGE,
+];
+
+const RC_PAGES: [Page; 3] = [
+    &[
+        b"rc - wiring",
+        b"PB4 (probe comp) to",
+        b"R, R to the input,",
+        b"C from the input to",
+        b"ground; set R with",
+        b"the rc command;",
+        b"set the timebase to",
+        b"about 5 time",
+        b"constants a record",
+    ],
+    PARAMETERS_PAGE,
+    MORE_PARAMETERS_PAGE,
+];
+
+fn pages(context: HelpContext) -> &'static [Page] {
+    match context {
+        HelpContext::Normal => &NORMAL_PAGES,
+        HelpContext::Zoomed => &ZOOMED_PAGES,
+        HelpContext::Search => &SEARCH_PAGES,
+        HelpContext::Log => &LOG_PAGES,
+        HelpContext::Envelope => &ENVELOPE_PAGES,
+        HelpContext::Noise => &NOISE_PAGES,
+        HelpContext::Rc => &RC_PAGES,
+    }
+}
+
+/// Returns the page following `page`, or `None` after the last page.
+pub fn next_page(context: HelpContext, page: usize) -> Option<usize> {
+    if page + 1 < pages(context).len() { Some(page + 1) } else { None }
+}
+
+/// Draws a help page in the trace area.
+pub fn draw(context: HelpContext, page: usize) {
+    // the trace area, stopping above the status line
+    screen::clear_trace_area();
+    for (i, line) in pages(context)[page].iter().enumerate() {
+        let color = if i == 0 { St7735Color::White } else { St7735Color::Green };
+        st7735_print(line, 0, TOP + 12 * i as u8, color, St7735Color::Black);
+    }
+}
@@ -0,0 +1,115 @@
+// stm32f3-oscilloscope - src/histogram.rs
+// histogram of sample values, for one record or accumulated over many
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// Each screen column is one bin, as many as the screen is wide when the histogram is reset. The
+// range of ADC codes covered by the bins is set from the first record after a reset, with some
+// margin either side, and at one code per bin where the signal allows, so the histogram of a
+// quiet input shows the individual ADC codes (useful for code density checks). Values outside
+// the range are counted in the end bins.
+
+use screen;
+use st7735::*;
+
+const TOP: i16 = 0;
+
+static mut COUNTS: [u32; screen::MAX_WIDTH] = [0; screen::MAX_WIDTH];
+static mut BINS: usize = screen::MAX_WIDTH;
+static mut FIRST_CODE: u16 = 0;
+static mut CODES_PER_BIN: u16 = 1;
+static mut RANGE_SET: bool = false;
+
+/// Clears the accumulated counts, and causes the range to be set again from the next record.
+pub fn reset() {
+    unsafe {
+        COUNTS = [0; screen::MAX_WIDTH];
+        BINS = screen::width() as usize;
+        RANGE_SET = false;
+    }
+}
+
+// choose the bin range to cover `data`, with a quarter of its span as margin either side
+fn set_range(data: &[u16]) {
+    if data.is_empty() {
+        return;
+    }
+    let mut min = 4095;
+    let mut max = 0;
+    for &sample in data {
+        if sample < min { min = sample; }
+        if sample > max { max = sample; }
+    }
+    let margin = (max - min) / 4 + 1;
+    let low = min.saturating_sub(margin);
+    let high = if max as u32 + margin as u32 > 4095 { 4095 } else { max + margin };
+    let bins = unsafe { BINS };
+    let codes_per_bin = ((high - low) as usize + bins) / bins; // round up
+    let span = codes_per_bin * bins;
+    // center the range on the data, without going below code zero
+    let center = (low as usize + high as usize) / 2;
+    let first = if center > span / 2 { center - span / 2 } else { 0 };
+    unsafe {
+        FIRST_CODE = first as u16;
+        CODES_PER_BIN = codes_per_bin as u16;
+        RANGE_SET = true;
+    }
+}
+
+/// Adds the samples in `data` to the histogram.
+pub fn accumulate(data: &[u16]) {
+    if unsafe { !RANGE_SET } {
+        set_range(data);
+    }
+    let counts = unsafe { &mut COUNTS };
+    let first = unsafe { FIRST_CODE };
+    let codes_per_bin = unsafe { CODES_PER_BIN };
+    let bins = unsafe { BINS };
+    for &sample in data {
+        let bin = if sample <= first {
+            0
+        } else {
+            let bin = ((sample - first) / codes_per_bin) as usize;
+            if bin >= bins { bins - 1 } else { bin }
+        };
+        counts[bin] = counts[bin].saturating_add(1);
+    }
+}
+
+/// Draws the histogram as a bar for each bin, scaled so the largest bin fills the height.
+pub fn draw() {
+    let counts = unsafe { &COUNTS[..BINS] };
+    let height = screen::trace_height(); // stop above the status line
+    let mut max = 1;
+    for &count in counts.iter() {
+        if count > max { max = count; }
+    }
+    for (x, &count) in counts.iter().enumerate() {
+        let bar = (count as u64 * height as u64 / max as u64) as i16;
+        if bar < height {
+            st7735_drawFastVLine(x as i16, TOP, height - bar, St7735Color::Black as u16);
+        }
+        if bar > 0 {
+            st7735_drawFastVLine(x as i16, TOP + height - bar, bar, St7735Color::Blue as u16);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+// stm32f3-oscilloscope - src/history.rs
+// sweep history: the most recent displayed sweeps, kept for review
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Each completed trace sweep's displayed screen is saved into a ring buffer, so that the
+// sweeps can be stopped and the last few stepped back through, e.g. to see what a glitch that
+// flashed past looked like. Only the 160 raw CH1 values of each screen are saved, not the
+// record behind it.
+//
+// The screens live in the core-coupled RAM, which is otherwise unused, and which holds SWEEPS
+// screens (see the .ccmram section in memory.x). It isn't initialized at reset, but nothing is
+// read from it until it has been written.
+
+const WIDTH: usize = 160;
+/// The number of sweeps kept: as many as fit in the 8K of core-coupled RAM.
+pub const SWEEPS: usize = 25;
+
+const NONE: u16 = 0xffff; // an undrawn column, which no 12-bit sample can be
+
+#[link_section = ".ccmram"]
+static mut SCREENS: [[u16; WIDTH]; SWEEPS] = [[NONE; WIDTH]; SWEEPS];
+static mut NEXT: usize = 0;  // the screen to be written next
+static mut COUNT: usize = 0; // the number of screens written, up to SWEEPS
+
+/// Saves a completed sweep's screen as the most recent.
+pub fn record(values: &[Option<u16>; WIDTH]) {
+    unsafe {
+        for (saved, value) in SCREENS[NEXT].iter_mut().zip(values.iter()) {
+            *saved = value.unwrap_or(NONE);
+        }
+        NEXT = (NEXT + 1) % SWEEPS;
+        if COUNT < SWEEPS {
+            COUNT += 1;
+        }
+    }
+}
+
+/// Returns the number of sweeps saved.
+pub fn count() -> usize {
+    unsafe { COUNT }
+}
+
+/// Copies the screen of the sweep `age` sweeps before the most recent (0 being the most
+/// recent) into `values`. Returns false, leaving `values` alone, if there is no such sweep.
+pub fn get(age: usize, values: &mut [Option<u16>; WIDTH]) -> bool {
+    unsafe {
+        if age >= COUNT {
+            return false;
+        }
+        let screen = &SCREENS[(NEXT + SWEEPS - 1 - age) % SWEEPS];
+        for (value, &saved) in values.iter_mut().zip(screen.iter()) {
+            *value = if saved == NONE { None } else { Some(saved) };
+        }
+    }
+    true
+}
@@ -0,0 +1,151 @@
+// stm32f3-oscilloscope - src/host/golden.rs
+// rendering regression tests on the host: fixed captures, compared with reference images
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// The host build's counterpart to tools/golden_screens.py. Each case renders a test pattern or
+// one of the captures in tools/golden/host/captures/ with some display settings, and the image
+// must match the reference in tools/golden/host/<case>.png byte for byte: with no analog path,
+// a render is exactly the same every time, so there's no tolerance. A case that fails has its
+// image written next to the reference, with a .new.png suffix, for comparing. With --update,
+// the references are recorded instead, to be checked by eye and committed after a change that
+// is meant to alter the rendering.
+//
+// The captures cover both of replay.rs's formats: sine-10k.bin is a dump, as the board sends
+// it, of a 10kHz sine at 500kS/s, and clipped.txt is text, of a triangle overdriven past both
+// ends of the ADC's range.
+//
+// Each case is rendered by running this binary again, so none sees what another left in the
+// modules' statics.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+struct Case {
+    name: &'static str,
+    capture: Option<&'static str>, // in tools/golden/host/captures/, or a test pattern
+    args: &'static [&'static str],
+}
+
+const CASES: [Case; 8] = [
+    Case { name: "sine-pattern", capture: None, args: &["--pattern", "sine", "--vectors"] },
+    Case {
+        name: "stairs-portrait",
+        capture: None,
+        args: &["--pattern", "stairs", "--orientation", "90", "--vectors"],
+    },
+    Case {
+        name: "impulses-dots",
+        capture: None,
+        args: &["--pattern", "impulses", "--factor", "2", "--interpolation", "dots"],
+    },
+    Case {
+        name: "jitter-falling",
+        capture: None,
+        args: &["--pattern", "jitter", "--trigger", "normal", "--slope", "falling", "--vectors"],
+    },
+    Case { name: "dump-sinc-x4", capture: Some("sine-10k.bin"), args: &["--factor", "4"] },
+    Case {
+        name: "dump-linear-delay",
+        capture: Some("sine-10k.bin"),
+        args: &["--interpolation", "linear", "--level", "2500", "--delay", "25", "--vectors"],
+    },
+    Case {
+        name: "text-clipped",
+        capture: Some("clipped.txt"),
+        args: &["--rate", "100000", "--vectors"],
+    },
+    Case {
+        name: "text-flipped",
+        capture: Some("clipped.txt"),
+        args: &["--rate", "100000", "--orientation", "180", "--slope", "falling"],
+    },
+];
+
+/// Runs the cases named in `args`, or all of them, after an optional --update. Returns an
+/// error if any failed.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let update = args.first().map_or(false, |arg| arg == "--update");
+    let names = if update { &args[1..] } else { args };
+    for name in names {
+        if !CASES.iter().any(|case| case.name == name) {
+            return Err(format!("no case {}", name));
+        }
+    }
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tools/golden/host");
+    let mut failed = 0;
+    let mut count = 0;
+    let chosen = |case: &&Case| names.is_empty() || names.iter().any(|name| name == case.name);
+    for case in CASES.iter().filter(chosen) {
+        count += 1;
+        let image = render(case, &golden)?;
+        let reference = golden.join(format!("{}.png", case.name));
+        let new = golden.join(format!("{}.new.png", case.name));
+        if update {
+            write(&reference, &image)?;
+            println!("{}: recorded", case.name);
+        } else if read(&reference).as_ref() == Some(&image) {
+            let _ = fs::remove_file(&new);
+            println!("{}: ok", case.name);
+        } else {
+            write(&new, &image)?;
+            println!("{}: FAILED, see {}", case.name, new.display());
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} cases failed", failed, count));
+    }
+    Ok(())
+}
+
+// the case's image, rendered by another run of this binary
+fn render(case: &Case, golden: &Path) -> Result<Vec<u8>, String> {
+    let output = env::temp_dir().join(format!("host-golden-{}-{}.png", process::id(),
+                                              case.name));
+    let exe = env::current_exe().map_err(|e| e.to_string())?;
+    let mut command = Command::new(exe);
+    command.args(case.args);
+    if let Some(capture) = case.capture {
+        command.arg(golden.join("captures").join(capture));
+    }
+    let status = command.arg(&output).status().map_err(|e| e.to_string())?;
+    let image = read(&output);
+    let _ = fs::remove_file(&output);
+    match image {
+        Some(image) if status.success() => Ok(image),
+        _ => Err(format!("{}: the render failed", case.name)),
+    }
+}
+
+fn read(path: &PathBuf) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)).ok().map(|_| bytes)
+}
+
+fn write(path: &PathBuf, bytes: &[u8]) -> Result<(), String> {
+    File::create(path).and_then(|mut file| file.write_all(bytes))
+                      .map_err(|e| format!("{}: {}", path.display(), e))
+}
@@ -0,0 +1,288 @@
+// stm32f3-oscilloscope - src/host/main.rs
+// the host build: the firmware's drawing and measurement, run on the desktop
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// The host build is a second crate root over the same modules as the firmware, for developing
+// and regression-testing the rendering, trigger, and measurements without flashing the board
+// (see docs/host-build.rst). The modules that use no peripherals are included from src/ as
+// they are; those that do are replaced by the stand-ins in src/sim/, which keep the same
+// functions over plain statics, and st7735.rs draws into a frame buffer with the `host`
+// feature. The sweeps come from a capture replayed from a file, or a test pattern, through
+// the AcquisitionSource seam in source.rs, and the screen is saved as a PNG file.
+//
+// Build and run it for the host's target, since .cargo/config builds for the board:
+//
+//   cargo run --features host --bin host --target x86_64-unknown-linux-gnu -- [options]
+
+#![allow(dead_code)] // the shared modules have much that the host build doesn't call
+
+// the shared modules use core:: paths, as the firmware is no_std
+extern crate core;
+
+#[path = "../axis.rs"]
+mod axis;
+#[path = "../calibration.rs"]
+mod calibration;
+#[path = "../cursors.rs"]
+mod cursors;
+#[path = "../delta.rs"]
+mod delta;
+#[path = "../export.rs"]
+mod export;
+#[path = "../font.rs"]
+mod font;
+#[path = "../frame.rs"]
+mod frame;
+#[path = "../icon.rs"]
+mod icon;
+#[path = "../input.rs"]
+mod input;
+#[path = "../interp.rs"]
+mod interp;
+#[path = "../keys.rs"]
+mod keys;
+#[path = "../measure.rs"]
+mod measure;
+#[path = "../parallax_8x12_font.rs"]
+mod parallax_8x12_font;
+#[path = "../phase.rs"]
+mod phase;
+#[path = "../plotter.rs"]
+mod plotter;
+#[path = "../records.rs"]
+mod records;
+#[path = "../redraw.rs"]
+mod redraw;
+#[path = "../screen.rs"]
+mod screen;
+#[path = "../segment.rs"]
+mod segment;
+#[path = "../shell.rs"]
+mod shell;
+#[path = "../source.rs"]
+mod source;
+#[path = "../st7735.rs"]
+mod st7735;
+#[path = "../status.rs"]
+mod status;
+#[path = "../telemetry.rs"]
+mod telemetry;
+#[path = "../testpattern.rs"]
+mod testpattern;
+#[path = "../trace.rs"]
+mod trace;
+#[path = "../trigger.rs"]
+mod trigger;
+#[path = "../trigrate.rs"]
+mod trigrate;
+
+// stand-ins for the modules that use the peripherals
+#[path = "../sim/capture.rs"]
+mod capture;
+#[path = "../sim/delay.rs"]
+mod delay;
+#[path = "../sim/flash.rs"]
+mod flash;
+#[path = "../sim/serial.rs"]
+mod serial;
+#[path = "../sim/spiflash.rs"]
+mod spiflash;
+#[path = "../sim/time.rs"]
+mod time;
+
+#[path = "../qemu/checks.rs"]
+mod checks;
+mod golden;
+mod png;
+mod render;
+mod replay;
+
+use std::env;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+use interp::Interpolation;
+use render::View;
+use screen::Orientation;
+use st7735::St7735Type;
+use testpattern::Pattern;
+use trace::TraceId;
+use trigger::{TriggerConfig, TriggerCoupling, TriggerMode, TriggerSlope};
+
+// the ST7735 module variant, selected by cargo feature, as for the firmware
+#[cfg(feature = "greentab")]
+const LCD_TYPE: St7735Type = St7735Type::GreenTab;
+#[cfg(feature = "blacktab")]
+const LCD_TYPE: St7735Type = St7735Type::BlackTab;
+#[cfg(feature = "greentab-128x128")]
+const LCD_TYPE: St7735Type = St7735Type::GreenTab128;
+#[cfg(not(any(feature = "greentab", feature = "blacktab", feature = "greentab-128x128")))]
+const LCD_TYPE: St7735Type = St7735Type::RedTab;
+
+const USAGE: &'static str = "\
+usage: host [options] (CAPTURE | --pattern NAME) OUTPUT.png
+       host golden [--update] [case ...]
+       host check
+
+Draws one sweep of CAPTURE (a dump from the board, or one raw sample per line) or of a test
+pattern, as the board would, and saves the screen as a PNG file. `host golden` renders the
+rendering regression tests' cases, and compares them with the references in tools/golden/host.
+`host check` runs the checks the QEMU build runs (see src/qemu/checks.rs), natively.
+
+options:
+  --pattern NAME        sine, stairs, impulses, or jitter
+  --rate HZ             the sample rate, unless CAPTURE is a dump (default 1000000)
+  --factor N            pixels per sample, for the interpolated timebases (default 1)
+  --interpolation MODE  dots, linear, or sin(x)/x (default sin(x)/x)
+  --vectors             join CH1's values with lines, rather than plotting dots
+  --orientation DEG     0, 90, 180, or 270 (default 0)
+  --trigger MODE        off, auto, or normal (default auto)
+  --slope SLOPE         rising, falling, or either (default rising)
+  --level N             the trigger level, as a raw 12-bit sample (default 2048)
+  --delay N             samples from the trigger to the screen's left edge (default 0)
+";
+
+fn main() {
+    if let Err(message) = run(env::args().skip(1).collect()) {
+        eprintln!("host: {}", message);
+        process::exit(1);
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    if args.first().map_or(false, |arg| arg == "golden") {
+        return golden::run(&args[1..]);
+    }
+    if args.first().map_or(false, |arg| arg == "check") {
+        let mut stdout = io::stdout();
+        let passed = checks::run(|bytes| { let _ = stdout.write_all(bytes); });
+        return if passed { Ok(()) } else { Err("checks failed".to_string()) };
+    }
+    let mut view = View {
+        lcd_type: LCD_TYPE,
+        orientation: Orientation::Landscape,
+        factor: 1,
+        interpolation: Interpolation::Sinc,
+        trigger: TriggerConfig {
+            mode: TriggerMode::Auto,
+            slope: TriggerSlope::Rising,
+            coupling: TriggerCoupling::DC,
+            level: 2048, // mid-screen
+            delay: 0,
+            auto_timeout_ms: 0,
+        },
+    };
+    let mut rate = 1_000_000;
+    let mut paths = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            paths.push(arg);
+            continue;
+        }
+        if arg == "--help" {
+            print!("{}", USAGE);
+            return Ok(());
+        }
+        if arg == "--vectors" {
+            let mut styles = trace::DEFAULT_STYLES;
+            styles[TraceId::Ch1 as usize].vectors = true;
+            trace::set_styles(styles);
+            continue;
+        }
+        let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+        match &arg[..] {
+            "--pattern" => {
+                let pattern = choose(Pattern::Sine, Pattern::next, Pattern::label, &value);
+                testpattern::set_pattern(pattern.ok_or_else(|| bad(&arg, &value))?);
+            }
+            "--rate" => rate = number(&arg, &value)?,
+            "--factor" => view.factor = number(&arg, &value)?,
+            "--interpolation" => {
+                view.interpolation = choose(Interpolation::None, Interpolation::next,
+                                            Interpolation::label, &value)
+                    .ok_or_else(|| bad(&arg, &value))?;
+            }
+            "--orientation" => {
+                view.orientation = choose(Orientation::Landscape, Orientation::next,
+                                          Orientation::label, &value)
+                    .ok_or_else(|| bad(&arg, &value))?;
+            }
+            "--trigger" => {
+                view.trigger.mode = choose(TriggerMode::Off, TriggerMode::next,
+                                           TriggerMode::label, &value)
+                    .ok_or_else(|| bad(&arg, &value))?;
+            }
+            "--slope" => {
+                view.trigger.slope = choose(TriggerSlope::Rising, TriggerSlope::next,
+                                            TriggerSlope::label, &value)
+                    .ok_or_else(|| bad(&arg, &value))?;
+            }
+            "--level" => view.trigger.level = number(&arg, &value)?,
+            "--delay" => view.trigger.delay = number(&arg, &value)?,
+            _ => return Err(format!("unknown option {}\n\n{}", arg, USAGE)),
+        }
+    }
+    let pattern = testpattern::pattern() != Pattern::Off;
+    let output = match (pattern, paths.len()) {
+        (true, 1) => &paths[0],
+        (false, 2) => &paths[1],
+        _ => return Err(USAGE.to_string()),
+    };
+    if rate == 0 || view.factor == 0 || view.trigger.level > 4095 ||
+       view.trigger.delay > trigger::MAX_DELAY {
+        return Err("a setting is out of range".to_string());
+    }
+    capture::set_sample_rate(rate);
+    if !pattern {
+        replay::load(Path::new(&paths[0]))?; // a dump sets its own rate
+    }
+    if !render::render(&view) {
+        eprintln!("host: no trigger event found");
+    }
+    png::save_screen(Path::new(output)).map_err(|e| format!("{}: {}", output, e))
+}
+
+// the setting of `T` labelled `label`, going through them in order from `first`
+fn choose<T: Copy + PartialEq>(first: T, next: fn(T) -> T, label: fn(T) -> &'static [u8],
+                               wanted: &str) -> Option<T> {
+    let mut choice = first;
+    loop {
+        if label(choice) == wanted.as_bytes() {
+            return Some(choice);
+        }
+        choice = next(choice);
+        if choice == first {
+            return None;
+        }
+    }
+}
+
+fn number<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| bad(arg, value))
+}
+
+fn bad(arg: &str, value: &str) -> String {
+    format!("{} {}: not one of the choices, or out of range", arg, value)
+}
@@ -0,0 +1,119 @@
+// stm32f3-oscilloscope - src/host/png.rs
+// the host build's screen, saved as a PNG file
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// The screen is read back a row at a time with st7735_read_row(), as a screenshot is on the
+// board (see export.rs), so the image is what the display would show, at its 18-bit color
+// depth. The file is written without compression, in deflate's stored blocks, which keeps this
+// short and needs no crates; a 160x128 screen is about 60kB. The same screen always gives the
+// same bytes, so images can be compared byte for byte.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use st7735::*;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+const STORED_BLOCK_MAX: usize = 65535;
+
+/// Returns the screen as a PNG image.
+pub fn encode_screen() -> Vec<u8> {
+    let (width, height) = (st7735_get_width(), st7735_get_height());
+    // each row starts with its filter type, 0 for none
+    let mut pixels = Vec::with_capacity((1 + 3 * width as usize) * height as usize);
+    let mut rgb = [0u8; 3 * 160];
+    for y in 0..height {
+        st7735_read_row(0, y, width, &mut rgb[..3 * width as usize]);
+        pixels.push(0);
+        // widen the 6 bits to 8, so full scale is 255
+        pixels.extend(rgb[..3 * width as usize].iter().map(|&c| c | c >> 6));
+    }
+    let mut header = Vec::new();
+    header.extend_from_slice(&be32(width as u32));
+    header.extend_from_slice(&be32(height as u32));
+    header.extend_from_slice(&[8, 2, 0, 0, 0]); // 8-bit RGB, deflate, no filtering, no interlace
+    let mut png = SIGNATURE.to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib_stored(&pixels));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Writes the screen to `path` as a PNG file.
+pub fn save_screen(path: &Path) -> io::Result<()> {
+    File::create(path)?.write_all(&encode_screen())
+}
+
+// append a chunk: length, type, data, and the CRC of the type and data
+fn chunk(png: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&be32(data.len() as u32));
+    png.extend_from_slice(chunk_type);
+    png.extend_from_slice(data);
+    let crc = crc32(crc32(0xffffffff, chunk_type), data) ^ 0xffffffff;
+    png.extend_from_slice(&be32(crc));
+}
+
+// a zlib stream of `data` in stored blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut z = vec![0x78, 0x01]; // deflate, 32kB window, no dictionary
+    let mut blocks = data.chunks(STORED_BLOCK_MAX).peekable();
+    if blocks.peek().is_none() {
+        z.extend_from_slice(&[1, 0, 0, 0xff, 0xff]); // an empty final block
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let length = block.len() as u16;
+        z.push(if last { 1 } else { 0 });
+        z.extend_from_slice(&[length as u8, (length >> 8) as u8]);
+        z.extend_from_slice(&[!length as u8, (!length >> 8) as u8]);
+        z.extend_from_slice(block);
+    }
+    z.extend_from_slice(&be32(adler32(data)));
+    z
+}
+
+// continue a CRC-32 (as for zip and PNG, reflected, polynomial 0xedb88320) over `data`
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = crc;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { crc >> 1 ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+fn be32(n: u32) -> [u8; 4] {
+    [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]
+}
@@ -0,0 +1,126 @@
+// stm32f3-oscilloscope - src/host/render.rs
+// the host build's screen: one sweep, drawn as the board draws it
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// A render takes a single sweep from the active source (the replayed capture, or a test
+// pattern), and draws it through the same modules the main loop does, in the same order: the
+// graticule and axis labels, the CH1 trace column by column through interp.rs and TracePlot,
+// then the measurement readout and the status line once the record is complete. Only CH1 is
+// drawn; the reference, math, and CH2 traces need state that main.rs keeps for itself.
+//
+// Each render starts the display again from st7735_initR(), but the modules' statics (the
+// readout slots, the trace styles, the status queue) carry on, so a process should render just
+// one screen when the result must not depend on what was drawn before.
+
+use axis::{self, sample_to_y};
+use capture;
+use interp::{self, Interpolation};
+use measure;
+use redraw;
+use screen::{self, Orientation};
+use source;
+use st7735::*;
+use status;
+use testpattern::{self, Pattern};
+use time;
+use trace::{self, TraceId, TracePlot};
+use trigger::{TriggerConfig, TriggerMode, TriggerSearch};
+
+/// The samples in each record, as the shortest of the board's record lengths.
+pub const RECORD_LENGTH: usize = 160;
+
+/// How a sweep is shown.
+pub struct View {
+    pub lcd_type: St7735Type,
+    pub orientation: Orientation,
+    pub factor: usize, // pixels per sample
+    pub interpolation: Interpolation,
+    pub trigger: TriggerConfig,
+}
+
+/// Takes a sweep and draws it, with the source's name on the status line. Returns false if,
+/// in normal mode, no trigger event was found, leaving the screen with just the graticule, as
+/// the board would.
+pub fn render(view: &View) -> bool {
+    st7735_setup();
+    st7735_initR(view.lcd_type);
+    screen::set_orientation(view.orientation);
+    st7735_fillScreen(St7735Color::Black as u16);
+    trace::paint_graticule();
+    axis::set_time_labels(capture::sample_rate(), view.factor, view.trigger.delay);
+    axis::set_voltage_labels();
+    redraw::invalidate_trace_area();
+    status::clear();
+    match testpattern::pattern() {
+        Pattern::Off => status::print(b"replay", 0),
+        pattern => status::print(pattern.label(), 0),
+    }
+
+    // the clock stands still until it's moved on, so let the sweep take its time
+    let source = source::active();
+    let sweep_length = view.trigger.sweep_length(RECORD_LENGTH);
+    source.start(sweep_length);
+    while source.samples_ready() < sweep_length {
+        time::advance(1);
+    }
+    let ready = source.samples_ready();
+    let data = source.data();
+    let search_length = view.trigger.search_length();
+    let end = if ready < search_length { ready } else { search_length };
+    let trigger_point = match TriggerSearch::new().scan(data, end, &view.trigger) {
+        Some(t) => Some(t),
+        None if view.trigger.mode == TriggerMode::Normal => None,
+        None => Some(0), // off, or auto timed out: free run
+    };
+    let start = match trigger_point {
+        Some(t) => t + view.trigger.delay,
+        None => {
+            source.stop();
+            status::flush();
+            return false;
+        }
+    };
+
+    let width = screen::width() as usize;
+    let style = trace::style(TraceId::Ch1);
+    let mut plot = TracePlot::new();
+    for x in 0..width {
+        let value = interp::value_at(&data[start..start + width], x, view.factor,
+                                     view.interpolation);
+        let y = value.map(sample_to_y);
+        plot.erase_column(x);
+        if redraw::take_column(x) {
+            trace::clear_column(x);
+        }
+        plot.plot(x, y, style);
+    }
+
+    let record = &data[start..start + RECORD_LENGTH];
+    status::set_clipping(measure::count_clipped(record) > 0);
+    if let Some(ref results) = measure::measure(record, capture::sample_rate()) {
+        measure::draw(results);
+    }
+    status::flush();
+    source.finish();
+    true
+}
@@ -0,0 +1,183 @@
+// stm32f3-oscilloscope - src/host/replay.rs
+// the host build's input: a capture replayed from a file
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// A recording is read whole into RECORDING, and every sweep replays it from its start into the
+// capture buffer, repeating it if the sweep is longer, so each sweep sees the same samples and
+// a render is the same every time. Two kinds of file are read:
+//
+//   a dump     the bytes the board sends for the shell's `dump` command (see export.rs): a
+//              DumpHeader frame, with the sample count and rate, then the DumpData frames, each
+//              checked against its CRC and sequence number
+//   text       one raw 12-bit sample per line, in decimal; blank lines, and lines starting
+//              with '#', are skipped
+//
+// A dump sets the sample rate the sweeps are taken to have been captured at; for text, it is
+// whatever the harness sets (see capture::set_sample_rate()).
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use capture::{self, CAPTURE_CHANNEL_1};
+use frame::{self, FrameType};
+use source::AcquisitionSource;
+
+const SYNC: [u8; 2] = [0xa5, 0x5a];
+
+static mut RECORDING: [u16; capture::BUFFER_LENGTH] = [0; capture::BUFFER_LENGTH];
+static mut LENGTH: usize = 0; // the recording's samples
+static mut SWEEP_LENGTH: usize = 0;
+
+/// Reads a recording from `path`, as a dump or as text, returning a description of what was
+/// wrong with it if it can't be read.
+pub fn load(path: &Path) -> Result<(), String> {
+    let mut bytes = Vec::new();
+    File::open(path).and_then(|mut file| file.read_to_end(&mut bytes))
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let samples = if bytes.starts_with(&SYNC) {
+        read_dump(&bytes)
+    } else {
+        read_text(&bytes)
+    }.map_err(|e| format!("{}: {}", path.display(), e))?;
+    if samples.is_empty() {
+        return Err(format!("{}: no samples", path.display()));
+    }
+    let length = if samples.len() < capture::BUFFER_LENGTH {
+        samples.len()
+    } else {
+        capture::BUFFER_LENGTH
+    };
+    unsafe {
+        RECORDING[..length].copy_from_slice(&samples[..length]);
+        LENGTH = length;
+    }
+    Ok(())
+}
+
+// the samples of a dump, setting the sample rate from its header
+fn read_dump(bytes: &[u8]) -> Result<Vec<u16>, String> {
+    let mut samples = Vec::new();
+    let mut expected: Option<usize> = None;
+    let mut sequence = 0;
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let (frame_type, payload, next) = read_frame(rest)?;
+        rest = next;
+        match frame_type {
+            t if t == FrameType::DumpHeader as u8 && payload.len() == 8 => {
+                expected = Some(le32(&payload[0..4]) as usize);
+                capture::set_sample_rate(le32(&payload[4..8]));
+            }
+            t if t == FrameType::DumpData as u8 && payload.len() >= 2 => {
+                if expected.is_none() {
+                    return Err("data before the dump header".to_string());
+                }
+                if le16(&payload[0..2]) != sequence {
+                    return Err(format!("frame {} missing", sequence));
+                }
+                sequence = sequence.wrapping_add(1);
+                for pair in payload[2..].chunks(2) {
+                    samples.push(le16(pair));
+                }
+            }
+            _ => {} // e.g. the stream or a screenshot, sent around the dump
+        }
+    }
+    match expected {
+        None => Err("no dump header".to_string()),
+        Some(count) if count != samples.len() => {
+            Err(format!("{} samples, but the header says {}", samples.len(), count))
+        }
+        Some(_) => Ok(samples),
+    }
+}
+
+// the type and payload of the frame at the start of `bytes`, and the bytes after it
+fn read_frame(bytes: &[u8]) -> Result<(u8, &[u8], &[u8]), String> {
+    if bytes.len() < 7 || !bytes.starts_with(&SYNC) {
+        return Err("not a frame".to_string());
+    }
+    let length = le16(&bytes[3..5]) as usize;
+    let end = 5 + length + 2;
+    if bytes.len() < end {
+        return Err("frame cut short".to_string());
+    }
+    let crc = frame::crc16(0xffff, &bytes[2..5 + length]);
+    if le16(&bytes[5 + length..end]) != crc {
+        return Err("frame CRC mismatch".to_string());
+    }
+    Ok((bytes[2], &bytes[5..5 + length], &bytes[end..]))
+}
+
+// one sample per line
+fn read_text(bytes: &[u8]) -> Result<Vec<u16>, String> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut samples = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.parse::<u16>() {
+            Ok(sample) if sample < 4096 => samples.push(sample),
+            _ => return Err(format!("line {}: not a 12-bit sample", i + 1)),
+        }
+    }
+    Ok(samples)
+}
+
+fn le16(bytes: &[u8]) -> u16 {
+    bytes[0] as u16 | (bytes[1] as u16) << 8
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    le16(&bytes[0..2]) as u32 | (le16(&bytes[2..4]) as u32) << 16
+}
+
+/// The recording, written into the capture buffer.
+pub struct Replay;
+
+impl AcquisitionSource for Replay {
+    fn start(&self, samples: usize) {
+        unsafe {
+            if LENGTH > 0 {
+                for (i, sample) in CAPTURE_CHANNEL_1[..samples].iter_mut().enumerate() {
+                    *sample = RECORDING[i % LENGTH];
+                }
+            }
+            SWEEP_LENGTH = if LENGTH > 0 { samples } else { 0 };
+        }
+    }
+
+    fn stop(&self) {}
+
+    // the whole recording is there at once
+    fn samples_ready(&self) -> usize {
+        unsafe { SWEEP_LENGTH }
+    }
+
+    fn data(&self) -> &'static [u16] {
+        unsafe { &CAPTURE_CHANNEL_1 }
+    }
+}
@@ -0,0 +1,79 @@
+// stm32f3-oscilloscope - src/icon.rs
+// small icons for the status line and menus
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Icons are eight pixels wide and twelve high, the size of a character in the 8x12 font, so
+// they can be drawn in line with text.
+
+use st7735::*;
+use trigger::TriggerSlope;
+
+/// The width of an icon, in pixels.
+pub const WIDTH: u8 = 8;
+
+#[allow(unused)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Icon {
+    Play,
+    Stop,
+    TriggerRising,
+    TriggerFalling,
+    TriggerEither,
+}
+
+// one byte per row, least significant bit on the left
+const PLAY: [u8; 12] = [0x00, 0x04, 0x0c, 0x1c, 0x3c, 0x7c, 0x7c, 0x3c, 0x1c, 0x0c, 0x04, 0x00];
+const STOP: [u8; 12] = [0x00, 0x00, 0x7e, 0x7e, 0x7e, 0x7e, 0x7e, 0x7e, 0x7e, 0x7e, 0x00, 0x00];
+const TRIGGER_RISING: [u8; 12] =
+    [0x00, 0xf0, 0x10, 0x38, 0x7c, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f, 0x00];
+const TRIGGER_FALLING: [u8; 12] =
+    [0x00, 0x1f, 0x10, 0x10, 0x10, 0x10, 0x10, 0x7c, 0x38, 0x10, 0xf0, 0x00];
+const TRIGGER_EITHER: [u8; 12] =
+    [0x00, 0x3c, 0x24, 0x24, 0x24, 0x24, 0x24, 0x24, 0x24, 0x24, 0xe7, 0x00];
+
+impl Icon {
+    /// Returns the icon for a trigger slope.
+    pub fn for_slope(slope: TriggerSlope) -> Icon {
+        match slope {
+            TriggerSlope::Rising => Icon::TriggerRising,
+            TriggerSlope::Falling => Icon::TriggerFalling,
+            TriggerSlope::Either => Icon::TriggerEither,
+        }
+    }
+
+    fn rows(self) -> &'static [u8] {
+        match self {
+            Icon::Play => &PLAY,
+            Icon::Stop => &STOP,
+            Icon::TriggerRising => &TRIGGER_RISING,
+            Icon::TriggerFalling => &TRIGGER_FALLING,
+            Icon::TriggerEither => &TRIGGER_EITHER,
+        }
+    }
+
+    /// Draws the icon with its top left corner at (`x`, `y`).
+    pub fn draw(self, x: u8, y: u8, fg: St7735Color, bg: St7735Color) {
+        st7735_draw_bitmap(self.rows(), x, y, fg, bg);
+    }
+}
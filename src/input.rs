@@ -0,0 +1,92 @@
+// stm32f3-oscilloscope - src/input.rs
+// the input mapping, from the voltage at the ADC to the voltage at an external front end's input
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// With an external divider or bias network in front of the ADC input, e.g. to bring a ±5V
+// signal into the ADC's 0 to 3.3V, the voltage the ADC sees isn't the one being measured. The
+// mapping is the straight line through two points: the actual input voltages when the ADC sees
+// 0V and when it sees ADC_SPAN_MV. It defaults to the identity, 0V and 3.3V.
+//
+// Measurements, the meter and voltmeter, the voltage labels, the trigger level, alarms, and
+// the data log are all in actual voltages, which may be negative. The trace is drawn as before,
+// since the mapping only changes what its rows stand for, and the supply and auxiliary DC input
+// measurements, which don't come through the front end, are not mapped.
+
+pub const ADC_SPAN_MV: i32 = 3300;
+// the limits of a mapped voltage, so it can be stored in a half-word
+pub const LIMIT_MV: i32 = 32_767;
+
+static mut LOW_MV: i32 = 0;            // the actual voltage at 0V on the ADC
+static mut HIGH_MV: i32 = ADC_SPAN_MV; // and at ADC_SPAN_MV
+
+/// Returns whether a mapping can be set: `low_mv` is below `high_mv`, and both are within
+/// ±LIMIT_MV.
+pub fn valid(low_mv: i32, high_mv: i32) -> bool {
+    low_mv < high_mv && low_mv >= -LIMIT_MV && high_mv <= LIMIT_MV
+}
+
+/// Sets the actual voltages, in millivolts, when the ADC sees 0V and ADC_SPAN_MV. Returns false,
+/// leaving the mapping as it was, unless the mapping is valid().
+pub fn set(low_mv: i32, high_mv: i32) -> bool {
+    if !valid(low_mv, high_mv) {
+        return false;
+    }
+    unsafe {
+        LOW_MV = low_mv;
+        HIGH_MV = high_mv;
+    }
+    true
+}
+
+/// Returns the actual voltages, in millivolts, when the ADC sees 0V and ADC_SPAN_MV.
+pub fn mapping() -> (i32, i32) {
+    unsafe { (LOW_MV, HIGH_MV) }
+}
+
+/// Returns whether the mapping is the identity, with no external front end.
+pub fn is_identity() -> bool {
+    mapping() == (0, ADC_SPAN_MV)
+}
+
+/// Returns the actual voltage for `adc_uv` microvolts at the ADC, in microvolts.
+pub fn microvolts(adc_uv: i64) -> i64 {
+    let (low, _) = mapping();
+    low as i64 * 1000 + scale(adc_uv)
+}
+
+/// Returns the actual size of a difference of `adc_uv` microvolts at the ADC, e.g. a
+/// peak-to-peak or AC voltage, in microvolts.
+pub fn scale(adc_uv: i64) -> i64 {
+    let (low, high) = mapping();
+    adc_uv * (high - low) as i64 / ADC_SPAN_MV as i64
+}
+
+/// Returns the voltage at the ADC for an actual voltage of `uv` microvolts, clamped to the
+/// ADC's range, in microvolts.
+pub fn adc_microvolts(uv: i64) -> u32 {
+    let (low, high) = mapping();
+    let adc_uv = (uv - low as i64 * 1000) * ADC_SPAN_MV as i64 / (high - low) as i64;
+    let top = ADC_SPAN_MV as i64 * 1000;
+    (if adc_uv < 0 { 0 } else if adc_uv > top { top } else { adc_uv }) as u32
+}
@@ -0,0 +1,110 @@
+// stm32f3-oscilloscope - src/integrity.rs
+// firmware integrity check: a CRC32 of the flash image, checked at startup
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The image is the vector table, code, and read-only data, followed by the initial values of
+// the .data section, from the start of flash up to the end of .data's load address, as given
+// by the linker symbols cortex-m-rt defines. Its CRC can't be known until after linking, so
+// tools/firmware_crc.py writes it into the word at _firmware_crc, the end of the FLASH region
+// in memory.x, after the build. An image flashed without that step has the erased value
+// there, and isn't checked.
+//
+// The CRC is the common CRC-32 of zlib and Ethernet (reflected polynomial 0xedb88320), so the
+// script can use Python's zlib.crc32(). Computed a bit at a time, it takes about 50ms for a
+// 50K image.
+
+use core::slice;
+
+use st7735::*;
+
+extern "C" {
+    static _sidata: u32; // the load address of .data's initial values
+    static _sdata: u32;
+    static _edata: u32;
+    static _firmware_crc: u32; // defined in memory.x
+}
+
+const FLASH_START: usize = 0x0800_0000;
+const ERASED: u32 = 0xffff_ffff;
+
+pub enum Integrity {
+    Ok,
+    Unrecorded, // no CRC was written after the build
+    Mismatch { expected: u32, actual: u32 },
+}
+
+/// Computes the image's CRC, and compares it with the recorded one.
+pub fn check() -> Integrity {
+    let expected = unsafe { ::core::ptr::read_volatile(&_firmware_crc) };
+    if expected == ERASED {
+        return Integrity::Unrecorded;
+    }
+    let image = unsafe {
+        let data_length = &_edata as *const u32 as usize - &_sdata as *const u32 as usize;
+        let end = &_sidata as *const u32 as usize + data_length;
+        slice::from_raw_parts(FLASH_START as *const u8, end - FLASH_START)
+    };
+    let actual = crc32(image);
+    if actual == expected {
+        Integrity::Ok
+    } else {
+        Integrity::Mismatch { expected: expected, actual: actual }
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { crc >> 1 ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Fills the screen with a warning that the image is damaged, with the two CRCs.
+pub fn show_warning(expected: u32, actual: u32) {
+    let (fg, bg) = (St7735Color::White, St7735Color::Black);
+    st7735_fillScreen(bg as u16);
+    st7735_print(b"FIRMWARE DAMAGED", 0, 4, St7735Color::Red, bg);
+    st7735_print(b"The flash image", 0, 24, fg, bg);
+    st7735_print(b"doesn't match its", 0, 36, fg, bg);
+    st7735_print(b"CRC: flash it again.", 0, 48, fg, bg);
+    st7735_print(b"want", 0, 68, St7735Color::Green, bg);
+    st7735_print(&hex(expected), 40, 68, St7735Color::Green, bg);
+    st7735_print(b"got", 0, 80, St7735Color::Red, bg);
+    st7735_print(&hex(actual), 40, 80, St7735Color::Red, bg);
+    st7735_print(b"Any button goes on,", 0, 100, fg, bg);
+    st7735_print(b"at your own risk.", 0, 112, fg, bg);
+}
+
+fn hex(n: u32) -> [u8; 8] {
+    let mut digits = [0; 8];
+    for i in 0..8 {
+        let nibble = (n >> (28 - 4 * i)) as u8 & 0xf;
+        digits[i] = if nibble < 10 { b'0' + nibble } else { b'a' + nibble - 10 };
+    }
+    digits
+}
@@ -0,0 +1,123 @@
+// stm32f3-oscilloscope - src/interp.rs
+// display interpolation between samples, for timebases faster than the ADC can sample
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// At the fastest timebases, each sample spans several pixels horizontally (the 'factor'). The
+// functions here generate the display values for the pixels in between the real samples.
+// The sin(x)/x interpolation uses a Lanczos (a = 3) windowed sinc kernel, truncated to six
+// taps, with the coefficients precomputed for each pixel phase in 2.14 fixed point.
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    None = 0,   // plot only the real samples
+    Linear = 1, // straight lines between samples
+    Sinc = 2,   // sin(x)/x reconstruction
+}
+
+impl Interpolation {
+    pub fn next(self) -> Interpolation {
+        match self {
+            Interpolation::None => Interpolation::Linear,
+            Interpolation::Linear => Interpolation::Sinc,
+            Interpolation::Sinc => Interpolation::None,
+        }
+    }
+
+    pub fn label(self) -> &'static [u8] {
+        match self {
+            Interpolation::None => b"dots",
+            Interpolation::Linear => b"linear",
+            Interpolation::Sinc => b"sin(x)/x",
+        }
+    }
+}
+
+// the kernel taps cover samples n-2 through n+3, for a pixel between samples n and n+1
+const SINC_TAPS_BEFORE: usize = 2;
+const SINC_TAPS_AFTER: usize = 3;
+
+const SINC_KERNEL_X2: [[i32; 6]; 1] = [
+    [401, -2226, 10017, 10017, -2226, 401],
+];
+
+const SINC_KERNEL_X5: [[i32; 6]; 4] = [
+    [450, -1937, 15248,  3406,  -861,   78],
+    [486, -2416, 12104,  7774, -1850,  286],
+    [286, -1850,  7775, 12103, -2416,  486],
+    [ 78,  -861,  3406, 15248, -1937,  450],
+];
+
+/// Returns the number of samples (out of `len`) that must have been captured before pixel `x`
+/// can be plotted.
+pub fn samples_needed(x: usize, factor: usize, mode: Interpolation, len: usize) -> usize {
+    let n = x / factor;
+    let needed = if factor == 1 || x % factor == 0 {
+        n + 1
+    } else {
+        match mode {
+            Interpolation::None => n + 1,
+            Interpolation::Linear => n + 2,
+            Interpolation::Sinc => n + SINC_TAPS_AFTER + 1,
+        }
+    };
+    if needed > len { len } else { needed }
+}
+
+/// Returns the display value for pixel `x`, where each sample in `data` spans `factor` pixels,
+/// or `None` if nothing should be plotted at that pixel.
+pub fn value_at(data: &[u16], x: usize, factor: usize, mode: Interpolation) -> Option<u16> {
+    let n = x / factor;
+    if n >= data.len() {
+        return None;
+    }
+    let phase = x % factor;
+    if factor == 1 || phase == 0 {
+        return Some(data[n]);
+    }
+    let last = data.len() - 1;
+    match mode {
+        Interpolation::None => None,
+        Interpolation::Linear => {
+            let s0 = data[n] as i32;
+            let s1 = data[if n < last { n + 1 } else { last }] as i32;
+            Some((s0 + (s1 - s0) * phase as i32 / factor as i32) as u16)
+        }
+        Interpolation::Sinc => {
+            let kernel = match factor {
+                2 => &SINC_KERNEL_X2[phase - 1],
+                5 => &SINC_KERNEL_X5[phase - 1],
+                _ => return value_at(data, x, factor, Interpolation::Linear),
+            };
+            let mut acc = 0i32;
+            for (tap, coefficient) in kernel.iter().enumerate() {
+                // clamp at the ends of the record
+                let i = (n + tap) as isize - SINC_TAPS_BEFORE as isize;
+                let i = if i < 0 { 0 } else if i as usize > last { last } else { i as usize };
+                acc += data[i] as i32 * coefficient;
+            }
+            // round, then clamp the overshoot to the 12-bit ADC range
+            let value = (acc + (1 << 13)) >> 14;
+            Some(if value < 0 { 0 } else if value > 4095 { 4095 } else { value as u16 })
+        }
+    }
+}
@@ -0,0 +1,99 @@
+// stm32f3-oscilloscope - src/keys.rs
+// keyboard control: single keystrokes from a terminal as button actions
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+
+// The keys command turns the serial shell into a remote keypad: rather than collecting a line,
+// shell::poll() hands each byte to decode(), which returns a Key as soon as one is complete,
+// with no echo and no return needed. The arrow keys arrive from the terminal as escape
+// sequences, ESC [ A to ESC [ D, or ESC O A to ESC O D in a terminal's application cursor
+// mode, so decode() keeps the sequence's progress between bytes. Unknown bytes, and unknown
+// sequences, are ignored. 'q' leaves key mode, and the shell then writes its prompt again.
+
+/// A keystroke, as the action it stands for.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Key {
+    Up,    // raise the trigger level
+    Down,  // lower it
+    Left,  // a slower timebase
+    Right, // a faster one
+    RunStop,
+    Single, // arm for the next triggered sweep, then stop
+    Quit,   // leave key mode
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Sequence {
+    None,
+    Escape, // ESC
+    Cursor, // ESC [ or ESC O
+}
+
+static mut ACTIVE: bool = false;
+static mut SEQUENCE: Sequence = Sequence::None;
+
+/// Starts taking keystrokes, rather than lines.
+pub fn start() {
+    unsafe {
+        ACTIVE = true;
+        SEQUENCE = Sequence::None;
+    }
+}
+
+/// Returns whether keystrokes are being taken.
+pub fn active() -> bool {
+    unsafe { ACTIVE }
+}
+
+/// Takes the next byte typed, returning the key it completes, if any. Quit also ends key mode.
+pub fn decode(byte: u8) -> Option<Key> {
+    unsafe {
+        let sequence = SEQUENCE;
+        SEQUENCE = Sequence::None;
+        let key = match (sequence, byte) {
+            (Sequence::None, 0x1b) | (Sequence::Escape, 0x1b) => {
+                SEQUENCE = Sequence::Escape;
+                None
+            }
+            (Sequence::Escape, b'[') | (Sequence::Escape, b'O') => {
+                SEQUENCE = Sequence::Cursor;
+                None
+            }
+            (Sequence::Cursor, b'A') => Some(Key::Up),
+            (Sequence::Cursor, b'B') => Some(Key::Down),
+            (Sequence::Cursor, b'C') => Some(Key::Right),
+            (Sequence::Cursor, b'D') => Some(Key::Left),
+            (Sequence::None, b' ') => Some(Key::RunStop),
+            (Sequence::None, b's') | (Sequence::None, b'S') => Some(Key::Single),
+            (Sequence::None, b'q') | (Sequence::None, b'Q') | (Sequence::None, 0x03) => {
+                Some(Key::Quit)
+            }
+            _ => None,
+        };
+        if key == Some(Key::Quit) {
+            ACTIVE = false;
+        }
+        key
+    }
+}
@@ -1,6 +1,5 @@
 // led.rs
-// BSP for STM32F3 Discovery LEDs
-// Assumes that GPIOE has alread been powered up.
+// status LEDs, on the pins given in board.rs
 
 // Copyright © 2017 Sean Bolton
 //
@@ -23,70 +22,55 @@
 // OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
 // WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-// extern crate stm32f30x;
-
-use stm32f30x::GPIOE;
+use board::LED_PINS;
+use gpio::{Mode, Pin};
 
+// named after the STM32F3 Discovery's LEDs; on boards with fewer, some do nothing
 #[derive(Clone, Copy)]
 #[allow(unused)]
 pub enum Led {
-//  Northwest  North  NE      East   SE    South  SW      West
-//  blue       red    orange  green  blue  red    orange  green
-    LD4 = 8,   LD3,   LD5,    LD7,   LD9,  LD10,  LD8,    LD6,
+//  North  Northwest  NE      West   East   SW      SE    South
+//  red    blue       orange  green  green  orange  blue  red
+    LD3,   LD4,       LD5,    LD6,   LD7,   LD8,    LD9,  LD10,
+}
+
+fn led_pin(led: Led) -> Option<Pin> {
+    LED_PINS[led as usize]
 }
 
 pub fn led_init(led: Led) {
-    let pin = led as u8;
-    if pin >= 8 && pin <= 15 {
-        let gpioe = GPIOE.get();
-        // set pin for output
-        let mask = !(0b11 << (pin * 2));
-        let mode = 0b01 << (pin * 2);
-        unsafe { (*gpioe).moder.modify(|r, w| w.bits((r.bits() & mask) | mode)); }
+    if let Some(pin) = led_pin(led) {
+        pin.enable_port_clock();
+        pin.configure(Mode::Output);
     }
 }
 
 #[allow(unused)]
 pub fn led_set(led: Led, state: bool) {
-    let pin = led as u8;
-    if pin >= 8 && pin <= 15 {
-        let gpioe = GPIOE.get();
-        if state {
-            unsafe { (*gpioe).bsrr.write(|w| w.bits(1 << pin)); }
-        } else {
-            unsafe { (*gpioe).bsrr.write(|w| w.bits(1 << (pin + 16))); }
-        }
+    if state {
+        led_on(led);
+    } else {
+        led_off(led);
     }
 }
 
 #[allow(unused)]
 pub fn led_on(led: Led) {
-    let pin = led as u8;
-    if pin >= 8 && pin <= 15 {
-        let gpioe = GPIOE.get();
-        unsafe { (*gpioe).bsrr.write(|w| w.bits(1 << pin)); }
+    if let Some(pin) = led_pin(led) {
+        pin.set_high();
     }
 }
 
 #[allow(unused)]
 pub fn led_off(led: Led) {
-    let pin = led as u8;
-    if pin >= 8 && pin <= 15 {
-        let gpioe = GPIOE.get();
-        unsafe { (*gpioe).bsrr.write(|w| w.bits(1 << (pin + 16))); }
+    if let Some(pin) = led_pin(led) {
+        pin.set_low();
     }
 }
 
 #[allow(unused)]
 pub fn led_toggle(led: Led) {
-    let pin = led as u8;
-    if pin >= 8 && pin <= 15 {
-        let gpioe = GPIOE.get();
-        let new_state = ( unsafe { (*gpioe).odr.read().bits() } & (1 << pin)) == 0;
-        if new_state {
-            unsafe { (*gpioe).bsrr.write(|w| w.bits(1 << pin)); }
-        } else {
-            unsafe { (*gpioe).bsrr.write(|w| w.bits(1 << (pin + 16))); }
-        }
+    if let Some(pin) = led_pin(led) {
+        led_set(led, !pin.is_set_high());
     }
 }
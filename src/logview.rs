@@ -0,0 +1,217 @@
+// stm32f3-oscilloscope - src/logview.rs
+// data log browser: the logged history plotted in the trace area, panned with the buttons
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// One column is plotted for each entry of the measurement in the newest entry of the log (see
+// datalog.rs), the newest at the right, scaled to fit the smallest and largest values shown
+// between the label lines at the top and bottom. The plot breaks where a value couldn't be
+// measured, and a blue line marks the start of each session, since the times of different
+// sessions aren't related. Panning moves the window half a screen at a time.
+
+use datalog::{self, Entry};
+use font::FONT_5X7;
+use measure::{self, Measurement};
+use screen::{self, MAX_WIDTH};
+use st7735::*;
+
+const LABEL_HEIGHT: i16 = 9; // the font height, plus a pixel either side
+
+static mut MEASUREMENT: Option<Measurement> = None; // the measurement plotted
+static mut END: usize = 0; // the number of entries after the last one shown
+
+// the number of entries of the measurement in the log
+fn count(measurement: Measurement) -> usize {
+    let mut count = 0;
+    datalog::for_each(|entry| if entry.measurement == Some(measurement) { count += 1; });
+    count
+}
+
+fn columns() -> usize {
+    let width = screen::width() as usize;
+    if width > MAX_WIDTH { MAX_WIDTH } else { width }
+}
+
+/// Shows the newest entries, of the measurement most recently logged.
+pub fn enter() {
+    let mut newest = None;
+    datalog::for_each(|entry| if entry.measurement.is_some() { newest = entry.measurement; });
+    unsafe {
+        MEASUREMENT = newest;
+        END = 0;
+    }
+    draw();
+}
+
+/// Moves the window half a screen towards older entries, or towards newer ones.
+pub fn pan(older: bool) {
+    let measurement = match unsafe { MEASUREMENT } {
+        Some(measurement) => measurement,
+        None => return,
+    };
+    let step = columns() / 2;
+    let last = count(measurement).saturating_sub(columns());
+    unsafe {
+        END = if older {
+            if END + step > last { last } else { END + step }
+        } else {
+            END.saturating_sub(step)
+        };
+    }
+    draw();
+}
+
+// draw a value in thousandths, without leading spaces or trailing zeros, returning the x
+// coordinate following it
+fn draw_value(value: i32, x: u8, y: u8) -> u8 {
+    let mut buf = [b' '; 12];
+    measure::format_signed_fixed(value as i64, 3, &mut buf);
+    let start = buf.iter().position(|&c| c != b' ').unwrap_or(0);
+    let mut end = buf.len();
+    while buf[end - 1] == b'0' {
+        end -= 1;
+    }
+    if buf[end - 1] == b'.' {
+        end -= 1;
+    }
+    FONT_5X7.draw(&buf[start..end], x, y, St7735Color::White, St7735Color::Black)
+}
+
+// draw a session and its time, e.g. "s3 12h34m"
+fn draw_time(session: u16, seconds: u32, x: u8, y: u8) -> u8 {
+    let mut buf = [0u8; 20];
+    let mut len = 0;
+    {
+        let mut push = |n: u32, suffix: u8| {
+            let start = len;
+            let mut n = n;
+            loop {
+                buf[len] = b'0' + (n % 10) as u8;
+                len += 1;
+                n /= 10;
+                if n == 0 {
+                    break;
+                }
+            }
+            buf[start..len].reverse();
+            buf[len] = suffix;
+            len += 1;
+        };
+        push(session as u32, b' ');
+        if seconds >= 3600 {
+            push(seconds / 3600, b'h');
+        }
+        push(seconds / 60 % 60, b'm');
+        push(seconds % 60, b's');
+    }
+    let x = FONT_5X7.draw(b"s", x, y, St7735Color::Blue, St7735Color::Black);
+    FONT_5X7.draw(&buf[..len], x, y, St7735Color::Blue, St7735Color::Black)
+}
+
+/// Draws the window of the log in the trace area.
+pub fn draw() {
+    screen::clear_trace_area();
+    let bottom_label_y = (screen::trace_height() - LABEL_HEIGHT + 1) as u8;
+    let measurement = match unsafe { MEASUREMENT } {
+        Some(measurement) => measurement,
+        None => {
+            FONT_5X7.draw(b"nothing logged", 0, 1, St7735Color::White, St7735Color::Black);
+            return;
+        }
+    };
+    let columns = columns();
+    let total = count(measurement);
+    let end = total.saturating_sub(unsafe { END });
+    let start = end.saturating_sub(columns);
+
+    // gather the window's entries, right-aligned when there are too few to fill the screen
+    let mut values: [Option<i32>; MAX_WIDTH] = [None; MAX_WIDTH];
+    let mut session_starts = [false; MAX_WIDTH];
+    let mut last: Option<Entry> = None;
+    let first_column = columns - (end - start);
+    let mut i = 0;
+    let mut previous_session: Option<u16> = None;
+    datalog::for_each(|entry| {
+        if entry.measurement != Some(measurement) {
+            return;
+        }
+        if i >= start && i < end {
+            let x = first_column + i - start;
+            values[x] = entry.value;
+            session_starts[x] = previous_session.map_or(false, |s| s != entry.session);
+            last = Some(*entry);
+        }
+        previous_session = Some(entry.session);
+        i += 1;
+    });
+
+    // scale the values between the labels
+    let (mut low, mut high) = (i32::max_value(), i32::min_value());
+    for &value in values[..columns].iter().filter_map(|v| v.as_ref()) {
+        if value < low { low = value; }
+        if value > high { high = value; }
+    }
+    let top = LABEL_HEIGHT;
+    let height = screen::trace_height() - 2 * LABEL_HEIGHT;
+    let to_y = |value: i32| -> i16 {
+        if high == low {
+            top + height / 2
+        } else {
+            top + height - 1 - ((value as i64 - low as i64) * (height - 1) as i64 /
+                                (high as i64 - low as i64)) as i16
+        }
+    };
+    let mut previous_y: Option<i16> = None;
+    for x in 0..columns {
+        if session_starts[x] {
+            st7735_drawFastVLine(x as i16, top, height, St7735Color::Blue as u16);
+            previous_y = None;
+        }
+        match values[x] {
+            Some(value) => {
+                let y = to_y(value);
+                match previous_y {
+                    Some(py) => st7735_draw_line(x as i16 - 1, py, x as i16, y,
+                                                 St7735Color::Green as u16),
+                    None => st7735_drawPixel(x as i16, y, St7735Color::Green as u16),
+                }
+                previous_y = Some(y);
+            }
+            None => previous_y = None,
+        }
+    }
+
+    // the labels: the measurement and the largest value at the top, and the smallest value and
+    // the time of the newest entry shown at the bottom
+    let x = FONT_5X7.draw(measurement.label(), 0, 1, St7735Color::Blue, St7735Color::Black);
+    if high >= low {
+        let x = FONT_5X7.draw(b" max ", x, 1, St7735Color::Blue, St7735Color::Black);
+        draw_value(high, x, 1);
+        let x = FONT_5X7.draw(b"min ", 0, bottom_label_y, St7735Color::Blue,
+                              St7735Color::Black);
+        let x = draw_value(low, x, bottom_label_y);
+        if let Some(entry) = last {
+            draw_time(entry.session, entry.seconds, x + 8, bottom_label_y);
+        }
+    }
+}
@@ -26,6 +26,7 @@
 
 // For a summary of the peripherals used, see docs/peripherals.rst
 
+#![feature(asm)]
 #![feature(core_intrinsics)]
 #![feature(used)]
 #![no_std]
@@ -34,37 +35,126 @@ extern crate cortex_m;
 extern crate cortex_m_rt;
 extern crate stm32f30x;
 
+mod alarm;
+mod autorange;
+mod axis;
+mod board;
+mod buzzer;
+mod bootloader;
+mod calibration;
 mod capture;
+mod clocks;
+mod comparator;
+mod console;
+mod counter;
+mod cursors;
+mod datalog;
+mod delay;
+mod delta;
+mod dma;
+#[cfg(feature = "i2c-eeprom")]
+mod eeprom;
+mod envelope;
+mod export;
+mod flash;
+mod font;
+mod frame;
+mod frontend;
+mod gpio;
+mod harmonics;
+mod help;
+mod histogram;
+mod history;
+mod icon;
+mod input;
+mod integrity;
+mod interp;
+mod keys;
 mod led;
+mod math;
+mod logview;
+mod mains;
+mod measure;
+mod monitor;
+mod noise;
 mod parallax_8x12_font;
+mod phase;
+mod plotter;
+mod priority;
+mod probe;
+mod rc;
+mod records;
+mod profile;
+mod pwm;
+mod redraw;
+mod screen;
+mod search;
+mod segment;
+mod segments;
+#[cfg(feature = "self-check")]
+mod selfcheck;
+mod selftest;
+mod serial;
+mod settings;
+mod shell;
 mod siggen;
+mod source;
+mod spiflash;
+mod splash;
 mod st7735;
+mod stack;
+mod status;
+mod stream;
 mod sysclk;
+mod telemetry;
+mod testpattern;
+mod time;
+mod timer;
+mod trace;
+mod trigger;
+mod trigger_out;
+mod trigrate;
+mod voltmeter;
+mod wizard;
+mod zoom;
 
 use core::intrinsics::{volatile_load, volatile_store};
 use cortex_m::exception;
 use cortex_m::peripheral::{SCB, SYST, SystClkSource};
-use stm32f30x::{DMA1, GPIOD, RCC, interrupt};
+use stm32f30x::{RCC, interrupt};
 
+use delay::{Deadline, delay_ms};
+use interp::Interpolation;
+use keys::Key;
+use settings::{DisplayMode, Settings, BUTTONS};
+use trigger::{TriggerConfig, TriggerCoupling, TriggerMode, TriggerSearch, TriggerSlope};
+use trigger::{AUTO_TIMEOUTS, TRIGGER_DELAYS};
 use led::*;
 use led::Led::*;
+use measure::Units;
+use search::SearchKind;
+use axis::sample_to_y;
+use segments::Segmented;
+use records::RecordError;
+use shell::{AlarmCommand, Command, LogCommand, Record, Setting, StoreCommand, VrefCommand};
+use help::HelpContext;
+use icon::Icon;
+use integrity::Integrity;
+use redraw::Region;
+use screen::Orientation;
 use siggen::*;
+use testpattern::Pattern;
+use spiflash::SpiFlashError;
+use time::millis;
 use st7735::*;
 use sysclk::set_sys_clock;
+use trace::{TraceId, TracePlot, TraceStyle};
 
 // ======== required declarations for Rust and C linkage ========
 
 // the C functions we call from Rust
 extern "C" {
     fn _st7735_initR(lcd_type: u8);
-    fn _st7735_drawFastHLine(x: i16, y: i16, h: i16, color: u16);
-    fn _st7735_drawFastVLine(x: i16, y: i16, h: i16, color: u16);
-    fn _st7735_drawPixel(x: i16, y: i16, color: u16);
-    fn _st7735_fillScreen(color: u16);
-    fn _st7735_setAddrWindow(x0: u8, y0: u8, x1: u8, y1: u8);
-    fn _st7735_setRotation(rotation: u8);
-    fn _st7735_get_height() -> u8;
-    fn _st7735_get_width() -> u8;
 }
 
 // the Rust functions in submodules that we call from C
@@ -73,30 +163,74 @@ pub use st7735::{
     st7735_send_data,
     lcd_cs0, lcd_cs1,
     lcd_rst0, lcd_rst1,
-    st7735_fill_rect,
 };
 
 // ======== global (cough) state ========
 
-// constants and state for the LCD breakout board pushbuttons
-const BUTTONS: usize = 4;
-const BUTTON_PIN: [usize; BUTTONS] = [ 12, 13, 14, 15 ];
+// state for the LCD breakout board pushbuttons
 static mut BUTTON_CHANGED: [bool; BUTTONS] = [ false, false, false, false];
 static mut BUTTON_STATE: [bool; BUTTONS] = [ false, false, false, false];
 static mut BUTTON_DEBOUNCE: [u32; BUTTONS] = [ 0, 0, 0, 0 ];
+// the physical button for each of the functions of buttons 1 to 4, as in the settings; the
+// state above is by physical button, and the functions below by function
+static mut BUTTON_MAP: [usize; BUTTONS] = [0, 1, 2, 3];
 
 fn button_get_changed(i: usize) -> bool {
-    unsafe { volatile_load(&BUTTON_CHANGED[i]) }
+    unsafe { volatile_load(&BUTTON_CHANGED[BUTTON_MAP[i]]) }
 }
 fn button_reset_changed(i: usize) {
-    unsafe { volatile_store(&mut BUTTON_CHANGED[i], false); }
+    unsafe { volatile_store(&mut BUTTON_CHANGED[BUTTON_MAP[i]], false); }
 }
 fn button_get_state(i: usize) -> bool {
-    unsafe { volatile_load(&BUTTON_STATE[i]) }
+    unsafe { volatile_load(&BUTTON_STATE[BUTTON_MAP[i]]) }
+}
+
+// a press of a button which steps through settings, and the backward steps made while it has
+// been held
+#[derive(Clone, Copy)]
+struct Press {
+    since: u32,
+    repeats: u32,
+}
+
+// Buttons 1, 3, and 4 step forwards when released after a short press, and backwards once held
+// for LONG_PRESS_MS, repeating every REPEAT_MS until released. Returns Some(back) for a step.
+fn button_step(i: usize, press: &mut Option<Press>) -> Option<bool> {
+    if button_get_changed(i) {
+        button_reset_changed(i);
+        let short = match *press { Some(p) => p.repeats == 0, None => false };
+        if button_get_state(i) {
+            *press = Some(Press { since: millis(), repeats: 0 });
+            return None;
+        }
+        *press = None;
+        return if short { Some(false) } else { None };
+    }
+    if press.is_some() && !button_get_state(i) {
+        // the release was taken by something else, e.g. help
+        *press = None;
+    }
+    if let Some(ref mut p) = *press {
+        if time::since(p.since) >= LONG_PRESS_MS + p.repeats * REPEAT_MS {
+            p.repeats += 1;
+            return Some(true);
+        }
+    }
+    None
 }
 
 // ======== constants ========
 
+// the ST7735 module variant, selected by cargo feature
+#[cfg(feature = "greentab")]
+const LCD_TYPE: St7735Type = St7735Type::GreenTab;
+#[cfg(feature = "blacktab")]
+const LCD_TYPE: St7735Type = St7735Type::BlackTab;
+#[cfg(feature = "greentab-128x128")]
+const LCD_TYPE: St7735Type = St7735Type::GreenTab128;
+#[cfg(not(any(feature = "greentab", feature = "blacktab", feature = "greentab-128x128")))]
+const LCD_TYPE: St7735Type = St7735Type::RedTab;
+
 // signal generator frequencies
 struct SiggenFreq {
     frequency: u32,
@@ -118,46 +252,219 @@ const SIGGEN_FREQUENCIES: [SiggenFreq; 9] = [
 // timebase intervals
 struct TimebaseInterval {
     sample_rate: u32, // samples per second
+    factor: usize,    // pixels per sample, >1 when interpolating past the maximum sample rate
+    label: &'static [u8],
+}
+
+const TIMEBASE_INTERVALS: [TimebaseInterval; 19] = [
+    TimebaseInterval { sample_rate:       1, factor: 1, label: b"32s" },
+    TimebaseInterval { sample_rate:      32, factor: 1, label: b"1s" },
+    TimebaseInterval { sample_rate:      64, factor: 1, label: b".5s" },
+    TimebaseInterval { sample_rate:     160, factor: 1, label: b".2s" },
+    TimebaseInterval { sample_rate:     320, factor: 1, label: b".1s" },
+    TimebaseInterval { sample_rate:     640, factor: 1, label: b"50ms" },
+    TimebaseInterval { sample_rate:    1600, factor: 1, label: b"20ms" },
+    TimebaseInterval { sample_rate:    3200, factor: 1, label: b"10ms" },
+    TimebaseInterval { sample_rate:    6400, factor: 1, label: b"5ms" },
+    TimebaseInterval { sample_rate:   16000, factor: 1, label: b"2ms" },
+    TimebaseInterval { sample_rate:   32000, factor: 1, label: b"1ms" },
+    TimebaseInterval { sample_rate:   64000, factor: 1, label: b".5ms" },
+    TimebaseInterval { sample_rate:  160000, factor: 1, label: b".2ms" },
+    TimebaseInterval { sample_rate:  320000, factor: 1, label: b".1ms" },
+    TimebaseInterval { sample_rate:  640000, factor: 1, label: b"~50us" }, // 50.222µs/div
+    TimebaseInterval { sample_rate: 1600000, factor: 1, label: b"20us" },
+    TimebaseInterval { sample_rate: 3130434, factor: 1, label: b"~10us" }, // 10.222µs/div
+    TimebaseInterval { sample_rate: 3130434, factor: 2, label: b"~5us" },  // 5.111µs/div
+    TimebaseInterval { sample_rate: 3130434, factor: 5, label: b"~2us" },  // 2.044µs/div
+];
+
+// record lengths, the number of samples captured after the trigger point (plus any delay)
+struct RecordLength {
+    samples: usize,
     label: &'static [u8],
 }
 
-const TIMEBASE_INTERVALS: [TimebaseInterval; 17] = [
-    TimebaseInterval { sample_rate:       1, label: b"32s" },
-    TimebaseInterval { sample_rate:      32, label: b"1s" },
-    TimebaseInterval { sample_rate:      64, label: b".5s" },
-    TimebaseInterval { sample_rate:     160, label: b".2s" },
-    TimebaseInterval { sample_rate:     320, label: b".1s" },
-    TimebaseInterval { sample_rate:     640, label: b"50ms" },
-    TimebaseInterval { sample_rate:    1600, label: b"20ms" },
-    TimebaseInterval { sample_rate:    3200, label: b"10ms" },
-    TimebaseInterval { sample_rate:    6400, label: b"5ms" },
-    TimebaseInterval { sample_rate:   16000, label: b"2ms" },
-    TimebaseInterval { sample_rate:   32000, label: b"1ms" },
-    TimebaseInterval { sample_rate:   64000, label: b".5ms" },
-    TimebaseInterval { sample_rate:  160000, label: b".2ms" },
-    TimebaseInterval { sample_rate:  320000, label: b".1ms" },
-    TimebaseInterval { sample_rate:  640000, label: b"~50us" }, // 49.777µs/div
-    TimebaseInterval { sample_rate: 1600000, label: b"20us" },
-    TimebaseInterval { sample_rate: 3130434, label: b"~10us" }, // 10.222µs/div
+const RECORD_LENGTHS: [RecordLength; 4] = [
+    RecordLength { samples: 160, label: b"160" },
+    RecordLength { samples: 1024, label: b"1k" },
+    RecordLength { samples: 4096, label: b"4k" },
+    RecordLength { samples: capture::MAX_RECORD_LENGTH, label: b"max" },
 ];
 
+// how often to measure the auxiliary channels (temperature, supply, and DC input), in
+// milliseconds
+const AUXILIARY_INTERVAL_MS: u32 = 250;
+
+// how often to check the temperature for drift since the last ADC calibration, in milliseconds
+const TEMPERATURE_CHECK_MS: u32 = 10_000;
+
+// how often to check the stack's high-water mark, in milliseconds
+const STACK_CHECK_MS: u32 = 1_000;
+
+// how long a shell capture waits for a trigger event, beyond the time for the sweep itself, in
+// milliseconds
+const SINGLE_TRIGGER_WAIT_MS: u32 = 10_000;
+
+// with timebase tracking, the fewest signal periods across the screen before a slower
+// timebase is chosen, in thousandths
+const TRACK_MIN_PERIODS: u64 = 2000;
+
+// each fine timebase step lengthens the time/div by this much of the selected timebase's
+const FINE_STEP_PERCENT: u32 = 10;
+
+// how often the PWM analyzer display is updated
+const PWM_UPDATE_MS: u32 = 250;
+
+// the color of the adjusted parameter's label on the status line, set apart from its value
+const PARAMETER_COLOR: St7735Color = St7735Color::Yellow;
+
+// the trigger level, as adjusted by the buttons, steps across the input range (see input.rs)
+const TRIGGER_LEVEL_STEP_MV: i32 = 100;
+
+// how long a button is held before it steps backwards, and how often the step repeats
+const LONG_PRESS_MS: u32 = 600;
+const REPEAT_MS: u32 = 250;
+
+// parameters adjustable with button 4, selected with button 3
+#[derive(Clone, Copy, PartialEq)]
+enum Parameter {
+    Display,
+    Rotation,
+    Vertical,
+    SiggenFreq,
+    ProbeComp,
+    TestPattern,
+    Interpolation,
+    TriggerMode,
+    TriggerSlope,
+    TriggerCoupling,
+    TriggerLevel,
+    TriggerDelay,
+    AutoTimeout,
+    Gate,
+    RecordLength,
+    TimebaseTrack,
+    TimebaseFine,
+    Search,
+    History,
+    Segments,
+    Trace,
+    TraceShow,
+    TraceColor,
+    TraceStyle,
+    Math,
+    MathCutoff,
+    CursorA,
+    CursorB,
+    MeasureSlot,
+    Measurement,
+    Buzzer,
+    Calibrate,
+}
+
+impl Parameter {
+    fn next(self) -> Parameter {
+        match self {
+            Parameter::SiggenFreq => Parameter::ProbeComp,
+            Parameter::ProbeComp => Parameter::TestPattern,
+            Parameter::TestPattern => Parameter::Interpolation,
+            Parameter::Interpolation => Parameter::TriggerMode,
+            Parameter::TriggerMode => Parameter::TriggerSlope,
+            Parameter::TriggerSlope => Parameter::TriggerCoupling,
+            Parameter::TriggerCoupling => Parameter::TriggerLevel,
+            Parameter::TriggerLevel => Parameter::TriggerDelay,
+            Parameter::TriggerDelay => Parameter::AutoTimeout,
+            Parameter::AutoTimeout => Parameter::Gate,
+            Parameter::Gate => Parameter::RecordLength,
+            Parameter::RecordLength => Parameter::TimebaseTrack,
+            Parameter::TimebaseTrack => Parameter::TimebaseFine,
+            Parameter::TimebaseFine => Parameter::Search,
+            Parameter::Search => Parameter::Display,
+            Parameter::Display => Parameter::Rotation,
+            Parameter::Rotation => Parameter::Vertical,
+            Parameter::Vertical => Parameter::History,
+            Parameter::History => Parameter::Segments,
+            Parameter::Segments => Parameter::Trace,
+            Parameter::Trace => Parameter::TraceShow,
+            Parameter::TraceShow => Parameter::TraceColor,
+            Parameter::TraceColor => Parameter::TraceStyle,
+            Parameter::TraceStyle => Parameter::Math,
+            Parameter::Math => Parameter::MathCutoff,
+            Parameter::MathCutoff => Parameter::CursorA,
+            Parameter::CursorA => Parameter::CursorB,
+            Parameter::CursorB => Parameter::MeasureSlot,
+            Parameter::MeasureSlot => Parameter::Measurement,
+            Parameter::Measurement => Parameter::Buzzer,
+            Parameter::Buzzer => Parameter::Calibrate,
+            Parameter::Calibrate => Parameter::SiggenFreq,
+        }
+    }
+
+    fn label(self) -> &'static [u8] {
+        match self {
+            Parameter::SiggenFreq => b"siggen freq:",
+            Parameter::ProbeComp => b"probe comp:",
+            Parameter::TestPattern => b"test:",
+            Parameter::Interpolation => b"interp:",
+            Parameter::TriggerMode => b"trig mode:",
+            Parameter::TriggerSlope => b"trig slope:",
+            Parameter::TriggerCoupling => b"trig cpl:",
+            Parameter::TriggerLevel => b"trig level:",
+            Parameter::TriggerDelay => b"trig delay:",
+            Parameter::AutoTimeout => b"auto wait:",
+            Parameter::Gate => b"gate:",
+            Parameter::RecordLength => b"record:",
+            Parameter::TimebaseTrack => b"tb track:",
+            Parameter::TimebaseFine => b"tb fine:",
+            Parameter::Search => b"search:",
+            Parameter::Display => b"display:",
+            Parameter::Rotation => b"rotation:",
+            Parameter::Vertical => b"vertical:",
+            Parameter::History => b"history:",
+            Parameter::Segments => b"segments:",
+            Parameter::Trace => b"trace:",
+            Parameter::TraceShow => b"show:",
+            Parameter::TraceColor => b"color:",
+            Parameter::TraceStyle => b"style:",
+            Parameter::Math => b"math:",
+            Parameter::MathCutoff => b"cutoff:",
+            Parameter::CursorA => b"cursor A:",
+            Parameter::CursorB => b"cursor B:",
+            Parameter::MeasureSlot => b"meas slot:",
+            Parameter::Measurement => b"measure:",
+            Parameter::Buzzer => b"buzzer:",
+            Parameter::Calibrate => b"cal:",
+        }
+    }
+}
+
 // ======== main ========
 
 #[inline(never)]
 fn main() {
     // set system clock to 72MHz
     set_sys_clock();
+    delay::init();
+    // paint the stack's unused room, to find its high-water mark later
+    stack::paint();
+
+    // set up LCD breakout board pushbuttons, before SysTick starts reading them
+    for pin in &board::BUTTON_PINS {
+        pin.enable_port_clock();
+        pin.configure(gpio::Mode::Input);
+        pin.set_pull(gpio::Pull::Up);
+    }
+    // buttons 1 and 4 held at power-up: start the bootloader, for a firmware update
+    delay::delay_us(10); // for the pull-ups
+    if !board::BUTTON_PINS[0].is_high() && !board::BUTTON_PINS[3].is_high() {
+        bootloader::enter();
+    }
 
     cortex_m::interrupt::free(|cs| {
         // borrow peripherals
         let rcc = RCC.borrow(cs);
         let syst = SYST.borrow(cs);
         let scb = SCB.borrow(cs);
-        let gpiod = GPIOD.borrow(cs);
-
-        // power on GPIOD and GPIOE
-        rcc.ahbenr.modify(|_, w| w.iopden().enabled()
-                                  .iopeen().enabled());
 
         // initialize LEDs
         led_init(LD3);
@@ -165,9 +472,9 @@ fn main() {
         led_init(LD5);
 
         // enable Cortex-M SysTick counter
-        syst.set_reload(9000); // set to update every 9000 clocks, or every 1ms
+        syst.set_reload(systick_reload()); // update every 1ms
         // - set SysTick exception (interrupt) priority to lowest possible
-        unsafe { scb.shpr[11].write(0xf0); } // write to PRI_15 field of SHPR3
+        unsafe { scb.shpr[11].write(priority::SYSTICK); } // write to PRI_15 field of SHPR3
         syst.clear_current();
         // SVD has incorrect identifiers here, so the API is nonsensical:
         // 'Core' means AHB, 'External' means AHB/8
@@ -175,49 +482,82 @@ fn main() {
         syst.enable_interrupt();
         syst.enable_counter();
 
-        // set up LCD breakout board pushbuttons
-        // - GPIOD powered on above
-        gpiod.moder.modify(|_, w| w.moder12().input()
-                                   .moder13().input()
-                                   .moder14().input()
-                                   .moder15().input());
-        unsafe {
-            gpiod.pupdr.modify(|_, w| w.pupdr12().bits(0b01) // pull up
-                                       .pupdr13().bits(0b01)
-                                       .pupdr14().bits(0b01)
-                                       .pupdr15().bits(0b01));
+        // note a reset by either watchdog, then clear the reset flags
+        if rcc.csr.read().iwdgrstf().bits() != 0 || rcc.csr.read().wwdgrstf().bits() != 0 {
+            console::log(b"watchdog reset");
         }
+        rcc.csr.modify(|_, w| unsafe { w.rmvf().bits(1) });
     });
+    console::log(board::BOARD_NAME);
 
     // LCD setup
     st7735_setup();
     delay_ms(50);
-    st7735_initR(St7735Type::RedTab as u8);
-    st7735_setRotation(3); // landscape
+    st7735_initR(LCD_TYPE);
+    let spi_hz = st7735_probe_spi_clock(LCD_TYPE, board::LCD_SPI_MAX_HZ);
+    screen::set_orientation(Orientation::Landscape);
+
+    // check the flash image is whole, since a partly flashed one can fail in confusing ways
+    match integrity::check() {
+        Integrity::Ok => console::log(b"firmware CRC ok"),
+        Integrity::Unrecorded => console::log(b"no firmware CRC"),
+        Integrity::Mismatch { expected, actual } => {
+            console::log(b"firmware CRC mismatch");
+            integrity::show_warning(expected, actual);
+            wait_for_button();
+        }
+    }
+    splash::show();
     st7735_fillScreen(St7735Color::Black as u16);
-    st7735_print(b"stm-scope", 0, 0, St7735Color::Green, St7735Color::Black);
-    //st7735_print(env!("CARGO_PKG_VERSION").as_ref(),
-    //             10 * 8, 0, St7735Color::Green, St7735Color::Black);
+
+    match spi_hz {
+        Some(hz) => {
+            let mut message = [b' '; 26];
+            message[..15].copy_from_slice(b"LCD ready, SPI ");
+            let end = 15 + decimal_digits(hz / 1000) as usize;
+            measure::format_fixed((hz / 1000) as u64, 0, &mut message[15..end]);
+            message[end..end + 4].copy_from_slice(b" kHz");
+            console::log(&message[..end + 4]);
+        }
+        None => console::log(b"LCD ready"),
+    }
 
     // signal generator (DAC, DMA, TIM, GPIO output) setup
     siggen_setup();
+    console::log(b"siggen ready");
+    probe::setup();
+    trigger_out::setup();
+    alarm::setup();
+    buzzer::setup();
+    pwm::setup();
 
     // capture (ADC, DMA, TIM, GPIO input) setup
     capture::setup();
+    console::log(b"capture ready");
+    if calibration::load() {
+        console::log(b"calibration loaded");
+    }
+    if spiflash::setup().is_some() {
+        console::log(b"external flash found");
+    }
+    if datalog::setup() {
+        console::log(b"data logging resumed");
+    }
+
+    // command shell on the serial port
+    serial::init();
+    console::log(b"serial ready");
+    run_self_checks();
+    // the temperature at the last ADC calibration, and when the temperature is next checked
+    let mut adc_calibration_temperature = capture::auxiliary().temperature;
+    let mut temperature_check = Deadline::after_ms(TEMPERATURE_CHECK_MS);
+    let mut stack_check = Deadline::after_ms(STACK_CHECK_MS);
+    let mut auxiliary_started_at = millis(); // when the auxiliary conversions were last started
 
     // turn on LD4 (northwest, blue) to show we've gotten this far
     led_on(LD4);
 
-    // paint graticule
-    let mut x = 32;
-    while x <= 128 {
-        let mut y = 32;
-        while y <= 96 {
-            st7735_drawPixel(x, 127 - y, St7735Color::Red as u16);
-            y += 32;
-        }
-        x += 32;
-    }
+    trace::paint_graticule();
 
     // ======== main loop ========
 
@@ -228,71 +568,597 @@ fn main() {
     };
     let mut state = SweepState::Before;
 
-    let mut siggen_freq_index = 6; // 1kHz
-    set_siggen_freq_from_index(siggen_freq_index);
-    let mut timebase_index = TIMEBASE_INTERVALS.len() / 2; // -FIX- something in the middle
-    set_capture_timebase_from_index(timebase_index);
-    let mut previous_y = [255u8; 160];
+    let mut settings = Settings {
+        timebase_index: TIMEBASE_INTERVALS.len() / 2, // -FIX- something in the middle
+        timebase_fine: 0,
+        timebase_track: false,
+        record_length_index: 0,
+        interpolation: Interpolation::Sinc,
+        display_mode: DisplayMode::Trace,
+        trigger: TriggerConfig {
+            mode: TriggerMode::Auto,
+            slope: TriggerSlope::Rising,
+            coupling: TriggerCoupling::DC,
+            level: 2048, // mid-screen
+            delay: 0,
+            auto_timeout_ms: 0,
+        },
+        trigger_delay_index: 0,
+        auto_timeout_index: 0,
+        gate: capture::Gate::Off,
+        siggen_freq_index: 6, // 1kHz
+        siggen_hz: SIGGEN_FREQUENCIES[6].frequency,
+        input_low_mv: 0,
+        input_high_mv: input::ADC_SPAN_MV,
+        reference_mv: Some(calibration::NOMINAL_REFERENCE_MV),
+        orientation: Orientation::Landscape,
+        frame_rate_index: 0,
+        units: Units::Volts,
+        impedance_index: 2, // 600 ohms, as for audio
+        button_map: [0, 1, 2, 3],
+        trace_styles: trace::DEFAULT_STYLES,
+        measurements: measure::DEFAULT_SLOTS,
+    };
+    let startup = match settings::load() {
+        Some(loaded) if settings_in_range(&loaded) => {
+            console::log(b"settings loaded");
+            loaded
+        }
+        _ => settings,
+    };
+    update_settings(&mut settings, startup, true, false, false);
+    frontend::setup(); // after the settings' input mapping, which its range replaces
+    if !clocks::from_hse() {
+        report_hsi_clock();
+    }
+    let mut zoom_index: Option<usize> = None; // index into zoom::MAGNIFICATIONS, when zoomed
+    let mut zoom_position = 0;
+    let mut untriggered_samples = 0u32; // samples searched without a trigger event, in auto mode
+    // the trigger settings and record length in effect for the current sweep
+    let mut sweep_trigger = settings.trigger;
+    let mut sweep_record_length = RECORD_LENGTHS[settings.record_length_index].samples;
+    let mut trigger_search = TriggerSearch::new();
+    let mut trigger_point: Option<usize> = None;
+    let mut search_kind = SearchKind::Off;
+    let mut search_hit: Option<usize> = None; // the hit the zoom window was last moved to
+    // the location of the last completed record in the capture buffer
+    let mut record_start = 0;
+    let mut record_end = 0;
+    let mut parameter = Parameter::SiggenFreq; // the parameter adjusted by button 4
+    let mut selected_trace = TraceId::Ch1; // the trace whose style is adjusted by button 4
+    let mut ch1_plot = TracePlot::new();
+    let mut reference_plot = TracePlot::new();
+    // the displayed screen, raw
+    let mut ch1_values: [Option<u16>; screen::MAX_WIDTH] = [None; screen::MAX_WIDTH];
+    let mut ch2_plot = TracePlot::new();
+    // ADC2's samples of the sine
+    let mut ch2_values: [Option<u16>; screen::MAX_WIDTH] = [None; screen::MAX_WIDTH];
+    let mut reference: [Option<u16>; screen::MAX_WIDTH] = [None; screen::MAX_WIDTH];
+    let mut math_plot = TracePlot::new();
+    // see math.rs for the units
+    let mut math_values: [Option<i64>; screen::MAX_WIDTH] = [None; screen::MAX_WIDTH];
+    let mut measure_slot = 0; // the readout slot whose measurement is chosen by button 4
+    let mut help: Option<(HelpContext, usize)> = None; // the help page shown, if any
+    let mut help_hold_since: Option<u32> = None; // when buttons 3 and 4 were both pressed
+    // a shell command that must wait until between sweeps: a dump of the last record, or the
+    // self-test
+    let mut deferred: Option<Command> = None;
+    // a shell capture is waiting for a record, until this deadline
+    let mut single: Option<Deadline> = None;
+    // the PWM analyzer's readings from the last record, and when its display is next updated
+    let mut record_pwm: Option<pwm::Reading> = None;
+    let mut pwm_update = Deadline::after_ms(PWM_UPDATE_MS);
+    let mut remote = false; // under control of the serial shell, until a button is pressed
+    let mut local_lockout = false; // the pushbuttons are ignored, until the shell's local command
+    let mut presses: [Option<Press>; BUTTONS] = [None; BUTTONS]; // buttons 1, 3, and 4
+    let mut reviewing: Option<usize> = None; // the age of the history sweep shown, if stopped
+    let mut running = true; // sweeping, unless stopped from the keyboard
+    let mut single_sweep = false; // stop after the next triggered sweep
+    let mut segmented = Segmented::Off;
+    let mut triggered_at: Option<u32> = None; // when this sweep triggered, unless it free-ran
+    // the settings the envelope, or the noise readings, are accumulated with
+    let mut accumulated_settings = settings;
+    let mut time_axis = (0, 0, 0); // the sample rate, factor, and delay the labels are for
+    // the screen height, vertical gain, and center the voltage labels are for
+    let mut voltage_axis = (0, 0, 0, (0, 0), 0);
     let mut x_out = 0;
 
     loop {
+        // a new record length takes effect at the next sweep; keep the zoom window within it
+        let record_length = RECORD_LENGTHS[settings.record_length_index].samples;
+        if let Some(i) = zoom_index {
+            zoom_position = zoom::clamp_position(zoom_position, zoom::MAGNIFICATIONS[i],
+                                                 record_length);
+        }
+        if sysclk::take_hse_lost() {
+            // the timers now run slower, so set their rates again
+            set_siggen_from_settings(&settings);
+            set_capture_timebase_from_index(settings.timebase_index, settings.timebase_fine);
+            probe::set_rate();
+            trigger_out::set_rate();
+            report_hsi_clock();
+        }
+        capture::poll_auxiliary_conversions();
+        alarm::poll();
+        buzzer::poll();
+        comparator::follow(settings.trigger.level); // the comparator's threshold, if it's on
+        if help.is_none() {
+            monitor::poll(); // the DC monitor, if it's on
+        }
+        let auxiliary_due = time::since(auxiliary_started_at) >= AUXILIARY_INTERVAL_MS;
+        // when the front end switches ranges, keep the trigger level's voltage at the input,
+        // and the settings' mapping in step
+        if let Some(from) = frontend::take_switched() {
+            let mut new = settings;
+            new.trigger.level = frontend::convert_level(settings.trigger.level, from);
+            let (low, high) = input::mapping();
+            new.input_low_mv = low;
+            new.input_high_mv = high;
+            change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+        }
+        // label the time axis again whenever the timebase or trigger delay changes
+        let axis = (capture::sample_rate(), TIMEBASE_INTERVALS[settings.timebase_index].factor,
+                    settings.trigger.delay);
+        if axis != time_axis {
+            time_axis = axis;
+            axis::set_time_labels(axis.0, axis.1, axis.2);
+            redraw::invalidate_trace_area();
+        }
+        // and the voltage axis whenever the vertical scale changes, or the screen is rotated
+        // or the input mapping or ADC reference
+        let axis = (screen::height(), screen::gain_index(), screen::center_microvolts(),
+                    input::mapping(), calibration::reference_mv());
+        if axis != voltage_axis {
+            voltage_axis = axis;
+            axis::set_voltage_labels();
+            redraw::invalidate_trace_area();
+            if settings.display_mode == DisplayMode::Envelope {
+                envelope::reset(); // its rows are at the old scale
+            } else if settings.display_mode == DisplayMode::Noise {
+                noise::reset(); // its readings are at the old scale
+            }
+        }
+        // repaint a few of any trace columns that were drawn over, and the status line
+        if settings.display_mode == DisplayMode::Trace && zoom_index.is_none() && help.is_none() {
+            for _ in 0..redraw::COLUMNS_PER_PASS {
+                match redraw::next_column() {
+                    Some(x) => repaint_column(x, &mut ch1_plot, &ch1_values, &mut ch2_plot,
+                                              &ch2_values, &mut reference_plot, &reference,
+                                              &mut math_plot, &math_values),
+                    None => break,
+                }
+            }
+        }
+        if settings.display_mode == DisplayMode::Pwm && help.is_none() && pwm_update.passed() {
+            pwm_update = Deadline::after_ms(PWM_UPDATE_MS);
+            pwm::draw(pwm::read().or(record_pwm));
+        }
+        // the counter and the mains monitor are drawn at the end of each gate
+        if counter::poll() {
+            if settings.display_mode == DisplayMode::Mains {
+                mains::add(counter::reading());
+            }
+            match settings.display_mode {
+                _ if help.is_some() => {}
+                DisplayMode::Counter => counter::draw(),
+                DisplayMode::Mains => mains::draw(),
+                _ => {}
+            }
+        }
+        if redraw::take(Region::Status) {
+            status::clear();
+            status::print_color(parameter.label(), 0, PARAMETER_COLOR);
+        }
+        // draw status line changes, unless a fast sweep is being plotted
+        let fast_sweep = match state {
+            SweepState::During => capture::sample_rate() >
+                                  capture::AUXILIARY_MAX_SAMPLE_RATE,
+            _ => false,
+        };
+        if !fast_sweep {
+            let start = profile::start();
+            status::flush();
+            profile::end(profile::Scope::StatusFlush, start);
+        }
+
         match state {
             SweepState::Before => {
-                // begin the next sweep of 160 samples
-                capture::begin_sweep();
-                // turn on LD3 at the beginning of the capture sweep
-                led_on(LD3);
-                state = SweepState::During;
-                x_out = 0;
+                // the record is only complete and untouched between sweeps
+                match deferred.take() {
+                    Some(Command::Dump(handshake)) => {
+                        let record = source::active().record(record_start, record_end);
+                        let sample_rate = capture::sample_rate();
+                        if !export::dump(record, sample_rate, handshake) {
+                            console::log(b"dump not acknowledged");
+                            shell::reply(b"dump abandoned, not acknowledged");
+                        }
+                        shell::done();
+                    }
+                    Some(Command::Single) => {
+                        // this sweep, or a later one if it isn't triggered, is dumped
+                        let sample_rate = capture::sample_rate();
+                        let sweep_ms = settings.trigger.sweep_length(record_length) as u32 * 1000 /
+                                       sample_rate;
+                        single = Some(Deadline::after_ms(sweep_ms + SINGLE_TRIGGER_WAIT_MS));
+                    }
+                    Some(Command::SelfTest) => {
+                        let mut frequencies = [0; 9];
+                        for (i, f) in SIGGEN_FREQUENCIES.iter().enumerate() {
+                            frequencies[i] = f.frequency;
+                        }
+                        capture::set_gate(capture::Gate::Off);
+                        let passed = selftest::run(&frequencies);
+                        capture::set_gate(settings.gate);
+                        set_siggen_from_settings(&settings);
+                        set_capture_timebase_from_index(settings.timebase_index,
+                                                        settings.timebase_fine);
+                        status::clear();
+                        if passed {
+                            console::log(b"self-test passed");
+                            status::print(b"self-test: pass", 0);
+                        } else {
+                            console::log(b"self-test FAILED");
+                            status::print_color(b"self-test: FAIL", 0, St7735Color::Red);
+                        }
+                        shell::done();
+                    }
+                    _ => {}
+                }
+                // don't start the sweep until the auxiliary conversions are out of the way
+                capture::wait_for_auxiliary_conversions();
+                // follow the supply with the ADC reference, if it is set to
+                if calibration::track_supply(capture::auxiliary().supply_mv) {
+                    screen::set_vertical(screen::gain_index(), screen::center_microvolts());
+                }
+                // between sweeps, occasionally check for temperature drift since the last ADC
+                // calibration, and recalibrate if needed
+                if temperature_check.passed() {
+                    temperature_check = Deadline::after_ms(TEMPERATURE_CHECK_MS);
+                    let temperature = capture::auxiliary().temperature;
+                    let drift = temperature - adc_calibration_temperature;
+                    if drift > capture::RECALIBRATION_DRIFT ||
+                       drift < -capture::RECALIBRATION_DRIFT {
+                        capture::recalibrate();
+                        adc_calibration_temperature = temperature;
+                        console::log(b"ADC recalibrated");
+                    }
+                }
+                if stack_check.passed() {
+                    stack_check = Deadline::after_ms(STACK_CHECK_MS);
+                    if stack::check() {
+                        report_stack_warning();
+                    }
+                }
+                // begin the next sweep, unless stopped from the keyboard, or to review the sweep
+                // history or the segments, or waiting under the frame rate cap: trigger search
+                // window, trigger delay, and record
+                if running && reviewing.is_none() && !segmented.stopped() &&
+                   redraw::start_frame() {
+                    sweep_trigger = settings.trigger;
+                    if wizard::measuring() {
+                        sweep_trigger.mode = TriggerMode::Off; // a grounded input won't trigger
+                    }
+                    sweep_record_length = if segmented == Segmented::Armed {
+                        segments::LENGTH // leaving the rest of the buffer for the segments
+                    } else {
+                        record_length
+                    };
+                    if settings.display_mode == DisplayMode::Rc {
+                        // stepping the probe compensation output, triggering on its edges
+                        sweep_trigger = rc::prepare(sweep_trigger, capture::sample_rate(),
+                                                    sweep_record_length);
+                    }
+                    // chain sweeps, except while keeping segments, which use the rest of the
+                    // buffer, or showing CH2, whose buffer holds only the one record
+                    capture::set_chaining(segmented == Segmented::Off &&
+                                          !trace::style(TraceId::Ch2).visible);
+                    source::active().start(sweep_trigger.sweep_length(sweep_record_length));
+                    stream::begin_record();
+                    // turn on LD3 at the beginning of the capture sweep
+                    led_on(LD3);
+                    state = SweepState::During;
+                    trigger_search = TriggerSearch::new();
+                    trigger_point = if sweep_trigger.mode == TriggerMode::Off {
+                        Some(0)
+                    } else {
+                        None
+                    };
+                    triggered_at = trigger_point.map(|_| millis());
+                    x_out = 0;
+                }
             }
             SweepState::During => {
                 // Plot data as it becomes available via DMA from ADC1
                 // - read the number of samples transfered by DMA controller
-                let x_in = capture::get_transferred_sample_count();
-                if x_in > x_out {
-                    // erase old plot
-                    let x = x_out as i16;
-                    let y = previous_y[x_out] as i16;
-                    if y < 255 {
-                        if x % 32 == 0 && y % 32 == 0 {
-                            st7735_drawPixel(x, y, St7735Color::Green as u16);
+                let x_in = source::active().samples_ready();
+                let data = source::active().data();
+                // - at slow sample rates, measure the auxiliary channels during the sweep
+                if auxiliary_due &&
+                   capture::sample_rate() <= capture::AUXILIARY_MAX_SAMPLE_RATE {
+                    capture::start_auxiliary_conversions();
+                    auxiliary_started_at = millis();
+                }
+                // - look for the trigger event in the samples so far
+                if trigger_point.is_none() {
+                    let search_length = sweep_trigger.search_length();
+                    let end = if x_in < search_length { x_in } else { search_length };
+                    let scan_start = profile::start();
+                    trigger_point = trigger_search.scan(data, end, &sweep_trigger);
+                    profile::end(profile::Scope::TriggerScan, scan_start);
+                    if let Some(t) = trigger_point {
+                        untriggered_samples = 0;
+                        trigger_out::pulse();
+                        // when the trigger sample was taken, not when it was found
+                        triggered_at = Some(capture::sample_time_ms(t));
+                    } else if x_in >= search_length {
+                        untriggered_samples += search_length as u32;
+                        let sample_rate = capture::sample_rate();
+                        if sweep_trigger.mode == TriggerMode::Auto &&
+                           untriggered_samples >= sweep_trigger.auto_timeout_samples(sample_rate) {
+                            // auto mode timed out: free run
+                            untriggered_samples = 0;
+                            trigger_point = Some(0);
                         } else {
-                            st7735_drawPixel(x, y, St7735Color::Black as u16);
-                        }
-                    }
-                    // plot new value
-                    let raw_conversion = capture::channel_1_data()[x_out];
-                    let microvolts_per_lsb = 806u32; // 3.3v / 2^12 bits * 10^6
-                    let microvolts = raw_conversion as u32 * microvolts_per_lsb;
-                    // Note that the 3.3v * 10^6 just cancels out in these calculations; we could
-                    // just right shift by 5 bits. But later we'll want the vertical gain
-                    // represented in terms of voltage, so build it in now.
-                    let microvolts_per_y = 25_781u32; // 3.3v * 10^6 / 128 pixels
-                    let y = 127 - (microvolts / microvolts_per_y) as i16;
-                    if y < 0 { // (can't yet happen)
-                        st7735_drawPixel(x, 0, St7735Color::Red as u16);
-                        previous_y[x_out] = 0;
-                    } else if y > 127 {
-                        st7735_drawPixel(x, 127, St7735Color::Red as u16);
-                        previous_y[x_out] = 127;
-                    } else {
-                        st7735_drawPixel(x, y, St7735Color::White as u16);
-                        previous_y[x_out] = y as u8;
+                            // no trigger event, so discard this record and try again
+                            source::active().stop();
+                            state = SweepState::Before;
+                        }
+                    }
+                }
+                if let Some(t) = trigger_point {
+                    // the displayed window begins after the trigger delay
+                    let start = t + sweep_trigger.delay;
+                    let factor = TIMEBASE_INTERVALS[settings.timebase_index].factor;
+                    // the zoomed and histogram displays are drawn from the completed record,
+                    // otherwise plot the first screen of the record as it arrives
+                    let plotting = zoom_index.is_none() &&
+                                   settings.display_mode == DisplayMode::Trace && help.is_none();
+                    let width = screen::width() as usize;
+                    if plotting && x_out < width &&
+                       x_in >= start + interp::samples_needed(x_out, factor,
+                                                              settings.interpolation, width) {
+                        let render_start = profile::start();
+                        // the new CH1 value, interpolated if each sample spans more than one
+                        // pixel, and the math trace's
+                        let sample_rate = capture::sample_rate();
+                        math_values[x_out] = math::value_at(&data[start..start + width], x_out,
+                                                            factor, sample_rate);
+                        ch1_values[x_out] = interp::value_at(&data[start..start + width], x_out,
+                                                             factor, settings.interpolation);
+                        let ch2_style = trace::style(TraceId::Ch2);
+                        ch2_values[x_out] = if ch2_style.visible &&
+                                               start + width <= capture::LOOPBACK_LENGTH {
+                            let ch2_data = &capture::channel_2_data()[start..start + width];
+                            interp::value_at(ch2_data, x_out, factor, settings.interpolation)
+                        } else {
+                            None
+                        };
+                        let ch2_y = ch2_values[x_out].map(sample_to_y);
+                        let reference_y = reference[x_out].map(sample_to_y);
+                        let math_y = math_values[x_out].map(math::value_to_y);
+                        let ch1_y = ch1_values[x_out].map(sample_to_y);
+                        let reference_style = trace::style(TraceId::Reference);
+                        let math_style = trace::style(TraceId::Math);
+                        let ch1_style = trace::style(TraceId::Ch1);
+                        let drawn_over = redraw::take_column(x_out);
+                        if !drawn_over &&
+                           reference_plot.unchanged(x_out, reference_y, reference_style) &&
+                           math_plot.unchanged(x_out, math_y, math_style) &&
+                           ch2_plot.unchanged(x_out, ch2_y, ch2_style) &&
+                           ch1_plot.unchanged(x_out, ch1_y, ch1_style) {
+                            // the column is already just as it would be drawn
+                            reference_plot.keep(reference_y);
+                            math_plot.keep(math_y);
+                            ch2_plot.keep(ch2_y);
+                            ch1_plot.keep(ch1_y);
+                        } else {
+                            // erase old plots, then redraw the reference, math trace, and CH2
+                            // under CH1
+                            ch1_plot.erase_column(x_out);
+                            ch2_plot.erase_column(x_out);
+                            reference_plot.erase_column(x_out);
+                            math_plot.erase_column(x_out);
+                            if drawn_over {
+                                trace::clear_column(x_out);
+                            }
+                            reference_plot.plot(x_out, reference_y, reference_style);
+                            math_plot.plot(x_out, math_y, math_style);
+                            ch2_plot.plot(x_out, ch2_y, ch2_style);
+                            ch1_plot.plot(x_out, ch1_y, ch1_style);
+                        }
+                        profile::end(profile::Scope::Render, render_start);
+                        x_out += 1;
+                    }
+                    // stream the record as it arrives
+                    if x_in > start {
+                        let available = x_in - start;
+                        let available = if available < sweep_record_length {
+                            available
+                        } else {
+                            sweep_record_length
+                        };
+                        stream::send(&data[start..start + sweep_record_length], available, false);
                     }
-                    // end of sweep?
-                    x_out += 1;
-                    if x_out >= 160 {
+                    // end of sweep? the record may extend well past the displayed window
+                    if (!plotting || x_out >= screen::width() as usize) &&
+                       x_in >= start + sweep_record_length {
                         state = SweepState::After;
                     }
                 }
             }
             SweepState::After => {
                 // Sweep is finished (both capture and display)
-                // - disable DMA and prepare for next sweep
-                capture::finish_sweep();
+                // - disable DMA and prepare for next sweep, unless it is already chained
+                source::active().finish();
+                if auxiliary_due {
+                    // a fast chained sweep would be disturbed by the conversions, so drop it
+                    if capture::sample_rate() > capture::AUXILIARY_MAX_SAMPLE_RATE {
+                        capture::cancel_chain();
+                    }
+                    capture::start_auxiliary_conversions();
+                    auxiliary_started_at = millis();
+                }
                 if capture::check_adc_ovr_flag() {
-                    #[cfg(debug_assertions)]
-                    st7735_print(b"OVR set", 0, 104, St7735Color::Green, St7735Color::Black);
+                    console::log(b"ADC overrun");
+                }
+                if capture::check_dma_error_flag() {
+                    console::log(b"DMA transfer error");
+                }
+                if let Some(t) = trigger_point {
+                    record_start = t + sweep_trigger.delay;
+                    record_end = record_start + sweep_record_length;
+                }
+                // copy the record out, so a chained sweep can start in its place meanwhile
+                source::active().snapshot(record_start, record_end);
+                let record = source::active().record(record_start, record_end);
+                let sample_rate = capture::sample_rate();
+                stream::send(record, record.len(), true);
+                plotter::send(record);
+                status::set_clipping(measure::count_clipped(record) > 0);
+                if autorange::enabled() {
+                    // the samples on the screen, fewer when each spans more than one pixel
+                    let factor = TIMEBASE_INTERVALS[settings.timebase_index].factor;
+                    let shown = (screen::width() as usize + factor - 1) / factor;
+                    let shown = if record.len() < shown { record.len() } else { shown };
+                    if autorange::adjust(&record[..shown]) && parameter == Parameter::Vertical {
+                        print_vertical();
+                    }
+                }
+                if single.is_some() && triggered_at.is_some() {
+                    single = None;
+                    export::dump(record, sample_rate, false);
+                    shell::done();
+                }
+                if single_sweep && triggered_at.is_some() {
+                    single_sweep = false;
+                    running = false;
+                    print_run_state(running);
+                    shell::reply(b"stopped");
+                }
+                trigrate::count(record, sample_rate, &sweep_trigger);
+                if trace::style(TraceId::Ch2).visible && record_start < capture::LOOPBACK_LENGTH {
+                    let end = if record_end < capture::LOOPBACK_LENGTH {
+                        record_end
+                    } else {
+                        capture::LOOPBACK_LENGTH
+                    };
+                    phase::measure(record, &capture::channel_2_data()[record_start..end]);
+                } else {
+                    phase::clear();
+                }
+                // measure the record once, for whatever needs it
+                let measured = match (settings.display_mode, zoom_index) {
+                    (DisplayMode::Trace, None) | (DisplayMode::Meter, _) |
+                    (DisplayMode::Pwm, _) => true,
+                    _ => {
+                        telemetry::due() || alarm::enabled() || datalog::due() ||
+                            settings.timebase_track
+                    }
+                };
+                let results = if measured { measure::measure(record, sample_rate) } else { None };
+                if settings.timebase_track {
+                    let mhz = results.as_ref().and_then(|results| results.frequency_millihz());
+                    let index = settings.timebase_index;
+                    if let Some(i) = mhz.and_then(|mhz| tracked_timebase(index, mhz)) {
+                        let mut new = settings;
+                        new.timebase_index = i;
+                        new.timebase_fine = 0;
+                        change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    }
+                }
+                if telemetry::due() {
+                    telemetry::send(results.as_ref());
+                }
+                alarm::check(results.as_ref());
+                let logged = datalog::log(results.as_ref());
+                wizard::add_record(record);
+                match (settings.display_mode, zoom_index) {
+                    _ if help.is_some() => {} // the help screen covers the trace area
+                    (DisplayMode::Trace, Some(i)) => {
+                        zoom::draw(record, zoom_position, zoom::MAGNIFICATIONS[i],
+                                   settings.interpolation);
+                    }
+                    (DisplayMode::Trace, None) => {
+                        history::record(&ch1_values);
+                        if let Some(ref results) = results {
+                            measure::draw(results);
+                        }
+                        math::finish_sweep();
+                        math::draw_readout(trace::style(TraceId::Math).color);
+                        cursors::draw_readout(record,
+                                              TIMEBASE_INTERVALS[settings.timebase_index].factor,
+                                              sample_rate);
+                    }
+                    (DisplayMode::Histogram, _) => {
+                        histogram::reset();
+                        histogram::accumulate(record);
+                        histogram::draw();
+                    }
+                    (DisplayMode::HistogramAccumulate, _) => {
+                        histogram::accumulate(record);
+                        histogram::draw();
+                    }
+                    (DisplayMode::Meter, _) => {
+                        if let Some(ref results) = results {
+                            measure::draw_meter(results);
+                        }
+                    }
+                    (DisplayMode::Volts, _) => voltmeter::update(record),
+                    (DisplayMode::Harmonics, _) => {
+                        harmonics::draw(harmonics::analyze(record, sample_rate));
+                    }
+                    (DisplayMode::Pwm, _) => {
+                        // drawn at PWM_UPDATE_MS intervals, from the timer if it has a signal
+                        record_pwm = results.as_ref().and_then(pwm::from_results);
+                    }
+                    (DisplayMode::Log, _) => {
+                        if logged {
+                            logview::draw();
+                        }
+                    }
+                    (DisplayMode::Console, _) => console::draw(),
+                    (DisplayMode::Rc, _) => rc::draw(rc::measure(record, sample_rate)),
+                    (DisplayMode::Noise, _) => {
+                        // any change of settings starts again
+                        if settings != accumulated_settings {
+                            accumulated_settings = settings;
+                            noise::reset();
+                        }
+                        noise::update(record);
+                    }
+                    (DisplayMode::Envelope, _) => {
+                        // any change of settings starts a new envelope
+                        if settings != accumulated_settings {
+                            accumulated_settings = settings;
+                            envelope::reset();
+                        }
+                        let factor = TIMEBASE_INTERVALS[settings.timebase_index].factor;
+                        let width = screen::width() as usize;
+                        let mut rows = [None; screen::MAX_WIDTH];
+                        for (x, row) in rows[..width].iter_mut().enumerate() {
+                            *row = interp::value_at(record, x, factor, settings.interpolation)
+                                   .map(sample_to_y);
+                        }
+                        envelope::accumulate(&rows[..width]);
+                        envelope::draw();
+                    }
+                    // drawn at the end of each gate, not each sweep
+                    (DisplayMode::Counter, _) | (DisplayMode::Mains, _) => {}
+                }
+                // keep the displayed window as a segment, and stop when there's no room for more
+                if let (Segmented::Armed, Some(at)) = (segmented, triggered_at) {
+                    if !segments::keep(record_start, at) {
+                        segmented = Segmented::Viewing(0);
+                        show_segment(0, TIMEBASE_INTERVALS[settings.timebase_index].factor,
+                                     settings.interpolation, &mut ch1_values);
+                        math_values = [None; screen::MAX_WIDTH];
+                        print_segmented(segmented);
+                    } else if parameter == Parameter::Segments {
+                        print_segmented(segmented);
+                    }
+                }
+                if alarm::latched() && help.is_none() {
+                    alarm::draw_banner();
                 }
                 // toggle LD5 at the end of each display sweep
                 led_toggle(LD5);
@@ -300,111 +1166,1771 @@ fn main() {
             }
         }
 
-        // button 1 (left): change timebase
-        if button_get_changed(0) {
-            button_reset_changed(0);
-            if button_get_state(0) {
-                timebase_index = (timebase_index + 1) % TIMEBASE_INTERVALS.len();
-                set_capture_timebase_from_index(timebase_index);
+        // under remote control, pressing any button returns to local control, unless the
+        // buttons are locked out, when they are ignored
+        if remote {
+            for i in 0..BUTTONS {
+                if button_get_changed(i) && local_lockout {
+                    button_reset_changed(i);
+                } else if button_get_changed(i) && button_get_state(i) {
+                    remote = false;
+                    status::set_remote(false, false);
+                }
             }
         }
-        // button 4 (right): change signal generator frequency
-        if button_get_changed(3) {
-            button_reset_changed(3);
-            if button_get_state(3) {
-                siggen_freq_index = (siggen_freq_index + 1) % SIGGEN_FREQUENCIES.len();
-                set_siggen_freq_from_index(siggen_freq_index);
+
+        for i in 0..BUTTONS {
+            if button_get_changed(i) && button_get_state(i) {
+                buzzer::click();
             }
         }
-    }
-}
-
-fn set_siggen_freq_from_index(i: usize) {
-    let f = &SIGGEN_FREQUENCIES[i];
-    siggen_set_freq(f.frequency);
-    clear_status_line();
-    st7735_print(b"siggen freq:", 0, 116, St7735Color::Green, St7735Color::Black);
-    st7735_print(f.label, 104, 116, St7735Color::Green, St7735Color::Black);
-}
 
-fn set_capture_timebase_from_index(i: usize) {
-    let t = &TIMEBASE_INTERVALS[i];
-    capture::set_timebase(t.sample_rate);
-    clear_status_line();
-    st7735_print(t.label, 0, 116, St7735Color::Green, St7735Color::Black);
-    st7735_print(b"/div", 8 * t.label.len() as u8, 116, St7735Color::Green, St7735Color::Black);
-}
-
-fn clear_status_line() {
-    st7735_fill_rect(0, 116, 160, 12, St7735Color::Black as u16);
-}
-
-// ======== exception handlers, including SysTick ========
-
-#[allow(dead_code)]
-#[used]
-#[link_section = ".rodata.exceptions"]
-static EXCEPTIONS: exception::Handlers = exception::Handlers {
-    // override the default SysTick handler
-    sys_tick: systick_handler,
-    ..exception::DEFAULT_HANDLERS
-};
-
-static mut TIMING_DELAY: u32 = 0;
+        // while an alarm is latched, the next button press acknowledges it, and does nothing
+        // else
+        if alarm::latched() {
+            for i in 0..BUTTONS {
+                if button_get_changed(i) {
+                    button_reset_changed(i);
+                    if button_get_state(i) {
+                        acknowledge_alarm(settings.display_mode, zoom_index.is_some());
+                    }
+                }
+            }
+        }
 
-extern "C" fn systick_handler(_: exception::SysTick) {
-    unsafe {
-        // decrement delay
-        if TIMING_DELAY != 0 {
-            TIMING_DELAY -= 1;
+        // buttons 3 and 4 held together: show help for the current mode
+        if let Some(since) = help_hold_since {
+            if !button_get_state(2) || !button_get_state(3) {
+                help_hold_since = None;
+            } else if time::since(since) >= help::HOLD_MS {
+                help_hold_since = None;
+                let context = match zoom_index {
+                    _ if settings.display_mode == DisplayMode::Log => HelpContext::Log,
+                    _ if settings.display_mode == DisplayMode::Envelope => HelpContext::Envelope,
+                    _ if settings.display_mode == DisplayMode::Noise => HelpContext::Noise,
+                    _ if settings.display_mode == DisplayMode::Rc => HelpContext::Rc,
+                    Some(_) if search_kind != SearchKind::Off => HelpContext::Search,
+                    Some(_) => HelpContext::Zoomed,
+                    None => HelpContext::Normal,
+                };
+                help = Some((context, 0));
+                help::draw(context, 0);
+            }
+        }
+        // while help is shown, button 4 shows the next page, and the others leave help
+        let mut leave_help = false;
+        if help.is_some() {
+            for i in 0..BUTTONS {
+                if button_get_changed(i) {
+                    button_reset_changed(i);
+                    let pressed = button_get_state(i);
+                    if i == 3 && pressed {
+                        let (context, page) = help.unwrap();
+                        match help::next_page(context, page) {
+                            Some(page) => {
+                                help = Some((context, page));
+                                help::draw(context, page);
+                            }
+                            None => leave_help = true,
+                        }
+                    } else if pressed {
+                        leave_help = true;
+                    }
+                }
+            }
+        }
+        if leave_help {
+            help = None;
+            match (settings.display_mode, zoom_index) {
+                (DisplayMode::Trace, Some(_)) => zoom::enter(),
+                (DisplayMode::Trace, None) => redraw::invalidate_trace_area(),
+                (DisplayMode::Console, _) => {
+                    screen::clear_trace_area();
+                    console::invalidate();
+                }
+                (DisplayMode::Log, _) => logview::draw(),
+                _ => {
+                    // the other modes are redrawn as they update
+                    screen::clear_trace_area();
+                }
+            }
+            redraw::invalidate(Region::Status);
         }
 
-        // read the buttons, with debounce
-        for i in 0..BUTTONS {
-            if BUTTON_DEBOUNCE[i] > 0 {
-                BUTTON_DEBOUNCE[i] -= 1;
-            } else {
-                let gpiod = GPIOD.get();
-                // buttons are short-to-ground-with-pull-up, so invert the logic
-                let state = ((*gpiod).idr.read().bits() & (1 << BUTTON_PIN[i])) == 0;
-                if state {
-                    if BUTTON_STATE[i] == false {
-                        BUTTON_STATE[i] = true;
-                        BUTTON_CHANGED[i] = true;
-                        BUTTON_DEBOUNCE[i] = 100;
+        // button 1 (left): change timebase, stepping back to slower ones when held, or when
+        // zoomed, move the zoom window, or when searching, move it to the previous hit, or in the
+        // log display, pan to older entries; held, these repeat
+        if let Some(back) = button_step(0, &mut presses[0]) {
+            match zoom_index {
+                _ if settings.display_mode == DisplayMode::Log => logview::pan(true),
+                Some(i) if search_kind != SearchKind::Off => {
+                    let record = source::active().record(record_start, record_end);
+                    let before = match search_hit { Some(h) => h, None => record.len() };
+                    search_hit = search::find_previous(record, before, search_kind,
+                                                       settings.trigger.level);
+                    zoom_position = show_search_hit(record, search_hit, search_kind,
+                                                    settings.trigger.level,
+                                                    zoom::MAGNIFICATIONS[i], zoom_position);
+                }
+                Some(i) => {
+                    zoom_position = zoom::next_position(zoom_position, zoom::MAGNIFICATIONS[i],
+                                                        record_length);
+                }
+                None => {
+                    let mut new = settings;
+                    new.timebase_index = step_index(settings.timebase_index,
+                                                    TIMEBASE_INTERVALS.len(), back);
+                    new.timebase_fine = 0;
+                    new.timebase_track = false; // the timebase is chosen by hand again
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                }
+            }
+        }
+        // button 2: change zoom magnification, or turn zoom off, or in the log display, pan to
+        // newer entries, or in the envelope display, reset the envelope, or in the noise display,
+        // start again
+        if button_get_changed(1) {
+            button_reset_changed(1);
+            if button_get_state(1) && settings.display_mode == DisplayMode::Log {
+                logview::pan(false);
+            } else if button_get_state(1) && settings.display_mode == DisplayMode::Envelope {
+                envelope::reset();
+            } else if button_get_state(1) && settings.display_mode == DisplayMode::Noise {
+                noise::reset();
+            } else if button_get_state(1) {
+                zoom_index = match zoom_index {
+                    None => {
+                        zoom::enter();
+                        Some(0)
+                    }
+                    Some(i) if i + 1 < zoom::MAGNIFICATIONS.len() => Some(i + 1),
+                    Some(_) => {
+                        redraw::invalidate_trace_area();
+                        None
                     }
+                };
+                status::clear();
+                if let Some(i) = zoom_index {
+                    // keep the window within the record at the new magnification
+                    zoom_position = zoom::clamp_position(zoom_position, zoom::MAGNIFICATIONS[i],
+                                                         record_length);
+                    status::print(b"zoom:", 0);
+                    print_number(zoom::MAGNIFICATIONS[i] as u32, 48);
+                    status::print(b"x", 48 + 8 * decimal_digits(zoom::MAGNIFICATIONS[i] as u32));
                 } else {
-                    if BUTTON_STATE[i] == true {
-                        BUTTON_STATE[i] = false;
-                        BUTTON_CHANGED[i] = true;
-                        BUTTON_DEBOUNCE[i] = 100;
+                    status::print(b"zoom: off", 0);
+                }
+            }
+        }
+        // buttons 3 and 4 pressed together: the start of a hold for help, so neither steps
+        if button_get_changed(3) && button_get_state(3) && button_get_state(2) {
+            button_reset_changed(3);
+            presses[2] = None;
+            presses[3] = None;
+            help_hold_since = Some(millis());
+        }
+        // button 3: select the parameter adjusted by button 4, or held, the previous ones
+        if let Some(back) = button_step(2, &mut presses[2]) {
+            parameter = step(parameter, Parameter::next, back);
+            wizard::cancel();
+            status::clear();
+            status::print_color(parameter.label(), 0, PARAMETER_COLOR);
+        }
+        // button 4 (right): adjust the selected parameter, or held, step it backwards, or when
+        // zoomed and searching, move the zoom window to the next hit
+        if let Some(back) = button_step(3, &mut presses[3]) {
+            match (zoom_index, parameter) {
+                (Some(i), _) if search_kind != SearchKind::Off => {
+                    let record = source::active().record(record_start, record_end);
+                    let from = match search_hit { Some(h) => h + 1, None => 0 };
+                    search_hit = search::find_next(record, from, search_kind,
+                                                   settings.trigger.level);
+                    zoom_position = show_search_hit(record, search_hit, search_kind,
+                                                    settings.trigger.level,
+                                                    zoom::MAGNIFICATIONS[i], zoom_position);
+                }
+                (_, Parameter::SiggenFreq) => {
+                    let mut new = settings;
+                    new.siggen_freq_index = step_index(settings.siggen_freq_index,
+                                                       SIGGEN_FREQUENCIES.len(), back);
+                    new.siggen_hz = SIGGEN_FREQUENCIES[new.siggen_freq_index].frequency;
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                }
+                (_, Parameter::ProbeComp) => {
+                    probe::set_enabled(!probe::enabled());
+                    print_parameter(parameter, probe::label());
+                }
+                (_, Parameter::Interpolation) => {
+                    let mut new = settings;
+                    new.interpolation = step(settings.interpolation, Interpolation::next, back);
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    print_parameter(parameter, settings.interpolation.label());
+                }
+                (_, Parameter::TriggerMode) => {
+                    let mut new = settings;
+                    new.trigger.mode = step(settings.trigger.mode, TriggerMode::next, back);
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    print_parameter(parameter, settings.trigger.mode.label());
+                }
+                (_, Parameter::TriggerSlope) => {
+                    let mut new = settings;
+                    new.trigger.slope = step(settings.trigger.slope, TriggerSlope::next, back);
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    print_trigger_slope(settings.trigger.slope);
+                }
+                (_, Parameter::TriggerCoupling) => {
+                    let mut new = settings;
+                    new.trigger.coupling = step(settings.trigger.coupling, TriggerCoupling::next,
+                                                back);
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    print_parameter(parameter, settings.trigger.coupling.label());
+                }
+                (_, Parameter::TriggerLevel) => {
+                    // in TRIGGER_LEVEL_STEP_MV steps, from the nearest step to the level now
+                    let (lowest, steps) = trigger_level_steps();
+                    let above = trigger_level_millivolts(settings.trigger.level) - lowest;
+                    let now = if above < 0 {
+                        0
+                    } else {
+                        ((above + TRIGGER_LEVEL_STEP_MV / 2) / TRIGGER_LEVEL_STEP_MV) as usize
+                    };
+                    let i = step_index(if now < steps { now } else { steps - 1 }, steps, back);
+                    let millivolts = lowest + i as i32 * TRIGGER_LEVEL_STEP_MV;
+                    let mut new = settings;
+                    new.trigger.level = trigger_level_conversion(millivolts);
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    print_trigger_level(millivolts);
+                }
+                (_, Parameter::TriggerDelay) => {
+                    let mut new = settings;
+                    new.trigger_delay_index = step_index(settings.trigger_delay_index,
+                                                         TRIGGER_DELAYS.len(), back);
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    print_parameter(parameter, TRIGGER_DELAYS[settings.trigger_delay_index].label);
+                }
+                (_, Parameter::AutoTimeout) => {
+                    let mut new = settings;
+                    new.auto_timeout_index = step_index(settings.auto_timeout_index,
+                                                        AUTO_TIMEOUTS.len(), back);
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    print_parameter(parameter, AUTO_TIMEOUTS[settings.auto_timeout_index].label);
+                }
+                (_, Parameter::Gate) => {
+                    let mut new = settings;
+                    new.gate = step(settings.gate, capture::Gate::next, back);
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    if settings.gate == new.gate {
+                        print_parameter(parameter, settings.gate.label());
+                    } else {
+                        print_parameter(parameter, b"no gate pin");
+                    }
+                }
+                (_, Parameter::TimebaseTrack) => {
+                    let mut new = settings;
+                    new.timebase_track = !settings.timebase_track;
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    let label: &[u8] = if settings.timebase_track { b"on" } else { b"off" };
+                    print_parameter(parameter, label);
+                }
+                (_, Parameter::TimebaseFine) => {
+                    let i = settings.timebase_index;
+                    if next_fine_step(i, settings.timebase_fine).is_some() {
+                        let next = |f| next_fine_step(i, f).unwrap_or(0);
+                        let mut new = settings;
+                        new.timebase_fine = step(settings.timebase_fine, next, back);
+                        new.timebase_track = false;
+                        change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    } else {
+                        print_parameter(parameter, b"not here");
+                    }
+                }
+                (_, Parameter::RecordLength) => {
+                    let mut new = settings;
+                    new.record_length_index = step_index(settings.record_length_index,
+                                                         RECORD_LENGTHS.len(), back);
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    print_parameter(parameter, RECORD_LENGTHS[settings.record_length_index].label);
+                }
+                (_, Parameter::Display) => {
+                    let mut new = settings;
+                    new.display_mode = step(settings.display_mode, DisplayMode::next, back);
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    print_parameter(parameter, settings.display_mode.label());
+                }
+                (_, Parameter::Rotation) => {
+                    let mut new = settings;
+                    new.orientation = step(settings.orientation, screen::Orientation::next, back);
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    print_parameter(parameter, settings.orientation.label());
+                }
+                (_, Parameter::Search) => {
+                    search_kind = step(search_kind, SearchKind::next, back);
+                    search_hit = None;
+                    print_parameter(parameter, search_kind.label());
+                }
+                (_, Parameter::Vertical) => {
+                    // each of the gains, on each of the front end's ranges, then auto-ranging
+                    let positions = frontend::positions();
+                    let position = if autorange::enabled() {
+                        positions
+                    } else {
+                        frontend::position()
+                    };
+                    let position = step_index(position, positions + 1, back);
+                    autorange::set_enabled(position == positions);
+                    if position < positions {
+                        frontend::set_position(position, screen::center_microvolts());
+                    }
+                    print_vertical();
+                }
+                (_, Parameter::History) => {
+                    // stepping into the history, to older sweeps, stops the sweeps, and
+                    // stepping out of it again, to "live", starts them
+                    let count = history::count();
+                    let older = |r: Option<usize>| match r {
+                        None if count > 0 => Some(0),
+                        Some(age) if age + 1 < count => Some(age + 1),
+                        _ => None,
+                    };
+                    reviewing = step(reviewing, older, back);
+                    if let Some(age) = reviewing {
+                        if let SweepState::During = state {
+                            source::active().stop();
+                            led_off(LD3);
+                            state = SweepState::Before;
+                        }
+                        history::get(age, &mut ch1_values);
+                        math_values = [None; screen::MAX_WIDTH]; // not kept
+                        redraw::invalidate_trace_area();
+                    }
+                    print_history(reviewing, count);
+                }
+                (_, Parameter::Segments) => {
+                    // arming discards the last segments, as does turning segments off, since
+                    // the sweeps then overwrite them
+                    segmented = step(segmented, Segmented::next, back);
+                    match segmented {
+                        Segmented::Off | Segmented::Armed => segments::clear(),
+                        Segmented::Viewing(i) => {
+                            if let SweepState::During = state {
+                                source::active().stop();
+                                led_off(LD3);
+                                state = SweepState::Before;
+                            }
+                            show_segment(i, TIMEBASE_INTERVALS[settings.timebase_index].factor,
+                                         settings.interpolation, &mut ch1_values);
+                            math_values = [None; screen::MAX_WIDTH]; // not kept
+                        }
+                    }
+                    print_segmented(segmented);
+                }
+                (_, Parameter::Trace) => {
+                    selected_trace = step(selected_trace, TraceId::next, back);
+                    print_parameter(parameter, selected_trace.label());
+                }
+                (_, Parameter::TraceShow) => {
+                    let mut style = settings.trace_styles[selected_trace as usize];
+                    style.visible = !style.visible;
+                    if selected_trace == TraceId::Reference && style.visible {
+                        // showing the reference stores the current screen as the reference
+                        reference = ch1_values;
+                    }
+                    let mut new = settings;
+                    new.trace_styles[selected_trace as usize] = style;
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    print_parameter(parameter, if style.visible { b"on" } else { b"off" });
+                }
+                (_, Parameter::TraceColor) => {
+                    let style = settings.trace_styles[selected_trace as usize].step_color(back);
+                    let mut new = settings;
+                    new.trace_styles[selected_trace as usize] = style;
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    print_parameter(parameter, style.color_label());
+                }
+                (_, Parameter::TraceStyle) => {
+                    let mut style = settings.trace_styles[selected_trace as usize];
+                    style.vectors = !style.vectors;
+                    let mut new = settings;
+                    new.trace_styles[selected_trace as usize] = style;
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    print_parameter(parameter, style.style_label());
+                }
+                (_, Parameter::Math) => {
+                    math::set_op(step(math::op(), math::MathOp::next, back));
+                    redraw::invalidate_trace_area();
+                    print_parameter(parameter, math::op().label());
+                }
+                (_, Parameter::MathCutoff) => {
+                    math::step_cutoff(back);
+                    redraw::invalidate_trace_area();
+                    print_parameter(parameter, math::cutoff_label());
+                }
+                (_, Parameter::CursorA) | (_, Parameter::CursorB) => {
+                    let cursor = if parameter == Parameter::CursorA {
+                        cursors::Cursor::A
+                    } else {
+                        cursors::Cursor::B
+                    };
+                    cursors::step(cursor, back);
+                    print_cursor(parameter, cursor,
+                                 TIMEBASE_INTERVALS[settings.timebase_index].factor);
+                }
+                (_, Parameter::TestPattern) => {
+                    // the sweep under way is the old source's
+                    if let SweepState::During = state {
+                        source::active().stop();
+                        led_off(LD3);
+                        state = SweepState::Before;
+                    }
+                    let pattern = step(testpattern::pattern(), Pattern::next, back);
+                    testpattern::set_pattern(pattern);
+                    print_parameter(parameter, pattern.label());
+                }
+                (_, Parameter::Buzzer) => {
+                    buzzer::set_mode(step(buzzer::mode(), buzzer::BuzzerMode::next, back));
+                    print_parameter(parameter, buzzer::mode().label());
+                }
+                (_, Parameter::MeasureSlot) => {
+                    measure_slot = step_index(measure_slot, measure::SLOTS, back);
+                    let label = [b'1' + measure_slot as u8];
+                    print_parameter(parameter, &label);
+                }
+                (_, Parameter::Calibrate) if back => {
+                    // an action, not a setting, so nothing to step back through
+                }
+                (_, Parameter::Calibrate) => {
+                    // OK for the wizard's step, which may set the reference to the supply
+                    if wizard::press() {
+                        let mut new = settings;
+                        new.reference_mv = calibration::reference();
+                        change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
                     }
                 }
+                (_, Parameter::Measurement) => {
+                    let mut new = settings;
+                    new.measurements[measure_slot] = step(settings.measurements[measure_slot],
+                                                          measure::Measurement::next, back);
+                    change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+                    print_parameter(parameter, settings.measurements[measure_slot].label());
+                }
             }
         }
-    }
-}
-
-#[no_mangle]
-pub extern "C" fn delay_ms(ms: u32) {
-    unsafe {
-        volatile_store(&mut TIMING_DELAY, ms);
-        while volatile_load(&TIMING_DELAY) != 0 {}
-    }
-}
 
-#[allow(dead_code)]
-#[used]
+        // commands typed at the serial shell
+        if serial::take_rx_lost() {
+            console::log(b"serial input lost");
+        }
+        if let Some(deadline) = single {
+            if deadline.passed() {
+                single = None;
+                shell::reply(b"no trigger, capture abandoned");
+                shell::done();
+            }
+        }
+        if let Some(command) = shell::poll() {
+            remote = true;
+            let mut ok = true;
+            // the settings as the command changes them, applied once it has, and a report of
+            // the result to make then
+            let mut new = settings;
+            let mut report: Option<fn()> = None;
+            match command {
+                Command::Set(Setting::Timebase, value) => {
+                    match TIMEBASE_INTERVALS.iter().position(|t| value.matches(t.label)) {
+                        Some(i) => {
+                            new.timebase_index = i;
+                            new.timebase_fine = 0;
+                            new.timebase_track = false;
+                        }
+                        None if value.matches(b"track") => {
+                            new.timebase_track = true;
+                            print_parameter(Parameter::TimebaseTrack, b"on");
+                        }
+                        None => ok = false,
+                    }
+                }
+                Command::Set(Setting::RecordLength, value) => {
+                    match RECORD_LENGTHS.iter().position(|r| value.matches(r.label)) {
+                        Some(i) => {
+                            new.record_length_index = i;
+                            print_parameter(Parameter::RecordLength, RECORD_LENGTHS[i].label);
+                        }
+                        None => ok = false,
+                    }
+                }
+                Command::Set(Setting::Interpolation, value) => {
+                    match find_value(settings.interpolation, Interpolation::next,
+                                     Interpolation::label, &value) {
+                        Some(i) => {
+                            new.interpolation = i;
+                            print_parameter(Parameter::Interpolation, i.label());
+                        }
+                        None => ok = false,
+                    }
+                }
+                Command::Set(Setting::Display, value) => {
+                    match find_value(settings.display_mode, DisplayMode::next, DisplayMode::label,
+                                     &value) {
+                        Some(mode) => {
+                            new.display_mode = mode;
+                            print_parameter(Parameter::Display, mode.label());
+                        }
+                        None => ok = false,
+                    }
+                }
+                Command::Set(Setting::TriggerMode, value) => {
+                    match find_value(settings.trigger.mode, TriggerMode::next, TriggerMode::label,
+                                     &value) {
+                        Some(mode) => {
+                            new.trigger.mode = mode;
+                            print_parameter(Parameter::TriggerMode, mode.label());
+                        }
+                        None => ok = false,
+                    }
+                }
+                Command::Set(Setting::TriggerSlope, value) => {
+                    match find_value(settings.trigger.slope, TriggerSlope::next,
+                                     TriggerSlope::label, &value) {
+                        Some(slope) => {
+                            new.trigger.slope = slope;
+                            print_trigger_slope(slope);
+                        }
+                        None => ok = false,
+                    }
+                }
+                Command::Set(Setting::TriggerCoupling, value) => {
+                    match find_value(settings.trigger.coupling, TriggerCoupling::next,
+                                     TriggerCoupling::label, &value) {
+                        Some(coupling) => {
+                            new.trigger.coupling = coupling;
+                            print_parameter(Parameter::TriggerCoupling, coupling.label());
+                        }
+                        None => ok = false,
+                    }
+                }
+                Command::Set(Setting::TriggerDelay, value) => {
+                    match TRIGGER_DELAYS.iter().position(|d| value.matches(d.label)) {
+                        Some(i) => {
+                            new.trigger_delay_index = i;
+                            print_parameter(Parameter::TriggerDelay, TRIGGER_DELAYS[i].label);
+                        }
+                        None => ok = false,
+                    }
+                }
+                Command::Set(Setting::AutoTimeout, value) => {
+                    match AUTO_TIMEOUTS.iter().position(|t| value.matches(t.label)) {
+                        Some(i) => {
+                            new.auto_timeout_index = i;
+                            print_parameter(Parameter::AutoTimeout, AUTO_TIMEOUTS[i].label);
+                        }
+                        None => ok = false,
+                    }
+                }
+                Command::Set(Setting::Gate, value) => {
+                    match find_value(settings.gate, capture::Gate::next, capture::Gate::label,
+                                     &value) {
+                        Some(g) if g == capture::Gate::Off || board::GATE_INPUT.is_some() => {
+                            new.gate = g;
+                            print_parameter(Parameter::Gate, g.label());
+                        }
+                        Some(_) => shell::reply(b"this board has no gate input"),
+                        None => ok = false,
+                    }
+                }
+                Command::Set(Setting::FrameRate, value) => {
+                    match redraw::FRAME_RATES.iter().position(|f| value.matches(f.label)) {
+                        Some(i) => {
+                            new.frame_rate_index = i;
+                            status::clear();
+                            status::print(b"fps:", 0);
+                            status::print(redraw::FRAME_RATES[i].label, 8 * 5);
+                        }
+                        None => ok = false,
+                    }
+                }
+                Command::Set(Setting::Buttons, value) => {
+                    match parse_button_map(&value) {
+                        Some(map) => {
+                            new.button_map = map;
+                            status::clear();
+                            status::print(b"buttons:", 0);
+                            status::print(value.as_bytes(), 8 * 9);
+                        }
+                        None => ok = false,
+                    }
+                }
+                Command::Set(Setting::Units, value) => {
+                    match find_value(Units::Volts, Units::next, Units::label, &value) {
+                        Some(units) => {
+                            new.units = units;
+                            status::clear();
+                            status::print(b"units:", 0);
+                            status::print(units.label(), 8 * 7);
+                        }
+                        None => ok = false,
+                    }
+                }
+                Command::Set(Setting::Impedance, value) => {
+                    let impedances = &measure::IMPEDANCES;
+                    match impedances.iter().position(|&(_, label)| value.matches(label)) {
+                        Some(i) => {
+                            new.impedance_index = i;
+                            status::clear();
+                            status::print(b"ohms:", 0);
+                            status::print(impedances[i].1, 8 * 6);
+                        }
+                        None => ok = false,
+                    }
+                }
+                Command::TriggerLevel(millivolts) => {
+                    new.trigger.level = trigger_level_conversion(millivolts);
+                    print_trigger_level(millivolts);
+                }
+                Command::Input(Some((low, high))) => {
+                    if input::valid(low, high) {
+                        new.input_low_mv = low;
+                        new.input_high_mv = high;
+                        report = Some(report_input);
+                    } else {
+                        shell::reply(b"the volts should be within 32.767V of 0");
+                    }
+                }
+                Command::Input(None) => report_input(),
+                Command::Vref(VrefCommand::Set(reference_mv)) => {
+                    if reference_mv.map_or(true, calibration::reference_in_range) {
+                        new.reference_mv = reference_mv;
+                        report = Some(report_reference);
+                    } else {
+                        shell::reply(b"the reference should be 2V to 3.6V");
+                    }
+                }
+                Command::Vref(VrefCommand::Show) => report_reference(),
+                Command::Siggen(hz) => {
+                    if hz > SIGGEN_FREQUENCIES[SIGGEN_FREQUENCIES.len() - 1].frequency {
+                        shell::reply(b"10kHz at most");
+                    } else {
+                        new.siggen_hz = hz;
+                    }
+                }
+                Command::Dump(handshake) => deferred = Some(Command::Dump(handshake)),
+                Command::Capture(capture) => {
+                    let valid = capture.settings.iter().all(|s| match *s {
+                        Some((setting, ref value)) => is_valid(setting, value),
+                        None => true,
+                    });
+                    if valid {
+                        shell::start_capture(capture);
+                    } else {
+                        ok = false;
+                    }
+                }
+                Command::Single => deferred = Some(Command::Single),
+                Command::SelfTest => deferred = Some(Command::SelfTest),
+                Command::Screenshot => {
+                    status::flush(); // so the screen is up to date
+                    if !export::screenshot() {
+                        shell::reply(b"the display can't be read with software SPI");
+                    }
+                }
+                Command::Store(StoreCommand::List) => records::report(),
+                Command::Store(StoreCommand::Store(Record::Reference, slot)) => {
+                    let values = if trace::style(TraceId::Reference).visible {
+                        &reference
+                    } else {
+                        &ch1_values
+                    };
+                    if let Err(error) = records::store_reference(slot, values) {
+                        report_record_error(error);
+                    }
+                }
+                Command::Store(StoreCommand::Store(Record::Screen, slot)) => {
+                    status::flush(); // so the screen is up to date
+                    if let Err(error) = records::store_screen(slot) {
+                        report_record_error(error);
+                    }
+                }
+                Command::Store(StoreCommand::Recall(Record::Reference, slot)) => {
+                    match records::recall_reference(slot) {
+                        Ok(values) => {
+                            reference = values;
+                            new.trace_styles[TraceId::Reference as usize].visible = true;
+                        }
+                        Err(error) => report_record_error(error),
+                    }
+                }
+                Command::Store(StoreCommand::Recall(Record::Screen, slot)) => {
+                    if let Err(error) = records::send_screen(slot) {
+                        report_record_error(error);
+                    }
+                }
+                Command::Stream(on) => stream::set_enabled(on),
+                Command::Alarm(AlarmCommand::List) => alarm::report(),
+                Command::Alarm(AlarmCommand::Acknowledge) => {
+                    if alarm::latched() {
+                        acknowledge_alarm(settings.display_mode, zoom_index.is_some());
+                    }
+                }
+                Command::Alarm(AlarmCommand::ClearAll) => alarm::clear_all(),
+                Command::Alarm(AlarmCommand::Set(name, low, high)) => {
+                    match find_value(measure::Measurement::Frequency, measure::Measurement::next,
+                                     measure::Measurement::label, &name) {
+                        Some(measurement) if measurement != measure::Measurement::Off => {
+                            if !alarm::set(measurement, low.map(|l| l as i64),
+                                           high.map(|h| h as i64)) {
+                                shell::reply(b"too many alarms");
+                            }
+                        }
+                        _ => ok = false,
+                    }
+                }
+                Command::Telemetry(format) => telemetry::set_format(format),
+                Command::Profile(false) => {
+                    profile::report();
+                    stack::report();
+                }
+                Command::Profile(true) => profile::reset(),
+                Command::Loopback(on) => new.trace_styles[TraceId::Ch2 as usize].visible = on,
+                Command::Trace(name, color_name, visible, vectors) => {
+                    let id = find_value(TraceId::Ch1, TraceId::next, TraceId::label, &name);
+                    let color = trace::COLORS.iter().cloned()
+                                             .find(|&c| color_name.matches(trace::color_label(c)));
+                    match (id, color) {
+                        (Some(id), Some(color)) => {
+                            let style = TraceStyle { color: color, visible: visible,
+                                                     vectors: vectors };
+                            new.trace_styles[id as usize] = style;
+                        }
+                        _ => ok = false,
+                    }
+                }
+                Command::Rc(Some(ohms)) => rc::set_series_ohms(ohms),
+                Command::Rc(None) => {
+                    let mut line = [b' '; 16];
+                    let len = measure::format_si(rc::series_ohms() as i64 * 1_000_000_000,
+                                                 b"ohm", &mut line);
+                    shell::reply(&line[..len]);
+                }
+                Command::Monitor(Some(value)) => {
+                    if value.matches(b"off") {
+                        monitor::set(None);
+                        redraw::invalidate_trace_area();
+                    } else {
+                        let inputs = &board::DC_INPUTS;
+                        match inputs.iter().position(|input| value.matches(input.label)) {
+                            Some(i) => monitor::set(Some(i)),
+                            None => report_dc_inputs(),
+                        }
+                    }
+                }
+                Command::Monitor(None) => report_monitor(),
+                Command::Keys => {
+                    keys::start();
+                    shell::reply(b"keys: arrows, space to run or stop, s for single, q to quit");
+                }
+                Command::Key(Key::Up) => nudge_trigger_level(&mut new, true),
+                Command::Key(Key::Down) => nudge_trigger_level(&mut new, false),
+                Command::Key(Key::Right) => nudge_timebase(&mut new, true),
+                Command::Key(Key::Left) => nudge_timebase(&mut new, false),
+                Command::Key(Key::RunStop) => {
+                    running = !running;
+                    single_sweep = false;
+                    // a sweep still waiting for its trigger is dropped, a triggered one finishes
+                    if let SweepState::During = state {
+                        if !running && trigger_point.is_none() {
+                            source::active().stop();
+                            led_off(LD3);
+                            state = SweepState::Before;
+                        }
+                    }
+                    print_run_state(running);
+                    shell::reply(if running { b"running" } else { b"stopped" });
+                }
+                Command::Key(Key::Single) => {
+                    running = true;
+                    single_sweep = true;
+                    print_run_state(running);
+                    shell::reply(b"single: waiting for a trigger");
+                }
+                Command::Key(Key::Quit) => shell::reply(b"keys off"),
+                Command::Latency(Some(on)) => priority::set_measuring(on),
+                Command::Latency(None) => report_latency(),
+                Command::Plot(channels) => plotter::set_channels(channels),
+                Command::Log(LogCommand::Status) => datalog::report(),
+                Command::Log(LogCommand::Dump) => datalog::dump(),
+                Command::Log(LogCommand::Stop) => {
+                    if datalog::stop().is_err() {
+                        shell::reply(b"flash error");
+                    }
+                }
+                Command::Log(LogCommand::Erase) => {
+                    if datalog::erase().is_err() {
+                        shell::reply(b"flash error");
+                    }
+                }
+                Command::Log(LogCommand::Start(name, interval)) => {
+                    match find_value(measure::Measurement::Frequency, measure::Measurement::next,
+                                     measure::Measurement::label, &name) {
+                        Some(measurement) if measurement != measure::Measurement::Off => {
+                            if datalog::start(measurement, interval).is_err() {
+                                shell::reply(b"flash error");
+                            }
+                        }
+                        _ => ok = false,
+                    }
+                }
+                Command::Settings(false) => report_settings(&settings),
+                Command::Settings(true) => {
+                    match settings::store(&settings) {
+                        Ok(()) => shell::reply(b"settings saved"),
+                        Err(_) => {
+                            console::log(b"settings not saved");
+                            shell::reply(b"flash error, settings not saved");
+                        }
+                    }
+                }
+                Command::Lock => local_lockout = true,
+                Command::Local => {
+                    remote = false;
+                    local_lockout = false;
+                }
+                Command::Bootloader => {
+                    shell::reply(b"starting the bootloader");
+                    serial::flush();
+                    st7735_fillScreen(St7735Color::Black as u16);
+                    st7735_print(b"Bootloader", 0, 0, St7735Color::White, St7735Color::Black);
+                    st7735_print(b"Reset to leave.", 0, 16, St7735Color::Green,
+                                 St7735Color::Black);
+                    bootloader::enter();
+                }
+            }
+            change_settings(&mut settings, new, zoom_index.is_some(), help.is_some());
+            if let Some(report) = report {
+                report();
+            }
+            status::set_remote(remote, local_lockout);
+            if !ok {
+                shell::reply(b"no such value, try help");
+            }
+            if deferred.is_none() {
+                shell::done();
+            }
+        }
+    }
+}
+
+// the value, of those reached from `start` by `next`, whose label is `value`
+fn find_value<T: Copy + PartialEq>(start: T, next: fn(T) -> T, label: fn(T) -> &'static [u8],
+                                   value: &shell::Value) -> Option<T> {
+    let mut t = start;
+    loop {
+        if value.matches(label(t)) {
+            return Some(t);
+        }
+        t = next(t);
+        if t == start {
+            return None;
+        }
+    }
+}
+
+// whether `value` is one of `setting`'s values, so a capture's settings can all be checked
+// before any of them is applied
+fn is_valid(setting: Setting, value: &shell::Value) -> bool {
+    match setting {
+        Setting::Timebase => TIMEBASE_INTERVALS.iter().any(|t| value.matches(t.label)),
+        Setting::RecordLength => RECORD_LENGTHS.iter().any(|r| value.matches(r.label)),
+        Setting::Interpolation => {
+            find_value(Interpolation::Sinc, Interpolation::next, Interpolation::label, value)
+                .is_some()
+        }
+        Setting::Display => {
+            find_value(DisplayMode::Trace, DisplayMode::next, DisplayMode::label, value).is_some()
+        }
+        Setting::Gate => {
+            match find_value(capture::Gate::Off, capture::Gate::next, capture::Gate::label,
+                             value) {
+                Some(gate) => gate == capture::Gate::Off || board::GATE_INPUT.is_some(),
+                None => false,
+            }
+        }
+        Setting::TriggerMode => {
+            find_value(TriggerMode::Off, TriggerMode::next, TriggerMode::label, value).is_some()
+        }
+        Setting::TriggerSlope => {
+            find_value(TriggerSlope::Rising, TriggerSlope::next, TriggerSlope::label, value)
+                .is_some()
+        }
+        Setting::TriggerCoupling => {
+            find_value(TriggerCoupling::DC, TriggerCoupling::next, TriggerCoupling::label, value)
+                .is_some()
+        }
+        Setting::TriggerDelay => TRIGGER_DELAYS.iter().any(|d| value.matches(d.label)),
+        Setting::AutoTimeout => AUTO_TIMEOUTS.iter().any(|t| value.matches(t.label)),
+        Setting::FrameRate => redraw::FRAME_RATES.iter().any(|f| value.matches(f.label)),
+        Setting::Buttons => parse_button_map(value).is_some(),
+        Setting::Units => find_value(Units::Volts, Units::next, Units::label, value).is_some(),
+        Setting::Impedance => measure::IMPEDANCES.iter().any(|&(_, label)| value.matches(label)),
+    }
+}
+
+// the button map, typed as the physical buttons doing the jobs of buttons 1 to 4 in turn
+fn parse_button_map(value: &shell::Value) -> Option<[usize; BUTTONS]> {
+    let bytes = value.as_bytes();
+    if bytes.len() != BUTTONS {
+        return None;
+    }
+    let mut map = [0; BUTTONS];
+    for (i, &c) in bytes.iter().enumerate() {
+        match c {
+            b'1'...b'4' if !bytes[..i].contains(&c) => map[i] = (c - b'1') as usize,
+            _ => return None,
+        }
+    }
+    Some(map)
+}
+
+// reply with the DC monitor's pin and voltage, or that it's off
+fn report_monitor() {
+    if !monitor::enabled() {
+        shell::reply(b"monitor off");
+        return;
+    }
+    let mut line = [b' '; 20];
+    line[..8].copy_from_slice(b"monitor ");
+    let label = board::DC_INPUTS[capture::dc_input()].label;
+    line[8..8 + label.len()].copy_from_slice(label);
+    let len = 8 + label.len() + 1;
+    measure::format_fixed(capture::auxiliary().input_mv as u64, 3, &mut line[len..len + 5]);
+    line[len + 5] = b'V';
+    shell::reply(&line[..len + 6]);
+}
+
+// reply with the pins the DC input can be on
+fn report_dc_inputs() {
+    let mut line = [b' '; 40];
+    line[..6].copy_from_slice(b"pins: ");
+    let mut len = 6;
+    for (i, input) in board::DC_INPUTS.iter().enumerate() {
+        if i > 0 {
+            line[len..len + 2].copy_from_slice(b", ");
+            len += 2;
+        }
+        line[len..len + input.label.len()].copy_from_slice(input.label);
+        len += input.label.len();
+    }
+    shell::reply(&line[..len]);
+}
+
+// acknowledge the latched alarm, and redraw what its banner covered
+fn acknowledge_alarm(mode: DisplayMode, zoomed: bool) {
+    alarm::acknowledge();
+    measure::clear();
+    enter_display_mode(mode, zoomed);
+}
+
+// make `new` the settings, applying to the hardware and the display whatever differs from
+// `settings`; `zoomed` and `help_shown` say what the trace area is showing
+fn change_settings(settings: &mut Settings, new: Settings, zoomed: bool, help_shown: bool) {
+    update_settings(settings, new, false, zoomed, help_shown);
+}
+
+// make `new` the settings, as change_settings(), or with `all`, as at startup, applying every
+// one of them. A setting the hardware refuses (a gate on a board without a gate input, or an
+// input mapping or ADC reference out of range, as loaded from a corrupt record) is left as it
+// was. The trigger delay and auto timeout follow their indexes, the delay scaled down by the
+// timebase's interpolation factor, so that it is still in divisions.
+fn update_settings(settings: &mut Settings, mut new: Settings, all: bool, zoomed: bool,
+                   help_shown: bool) {
+    let old = *settings;
+    let factor = TIMEBASE_INTERVALS[new.timebase_index].factor;
+    new.trigger.delay = (TRIGGER_DELAYS[new.trigger_delay_index].samples + factor / 2) / factor;
+    new.trigger.auto_timeout_ms = AUTO_TIMEOUTS[new.auto_timeout_index].ms;
+    if all || new.siggen_hz != old.siggen_hz || new.siggen_freq_index != old.siggen_freq_index {
+        set_siggen_from_settings(&new);
+    }
+    if all || new.timebase_index != old.timebase_index || new.timebase_fine != old.timebase_fine {
+        set_capture_timebase_from_index(new.timebase_index, new.timebase_fine);
+    }
+    if (all || new.gate != old.gate) && !capture::set_gate(new.gate) {
+        new.gate = old.gate;
+        capture::set_gate(new.gate);
+    }
+    let mapping = (new.input_low_mv, new.input_high_mv);
+    if (all || mapping != (old.input_low_mv, old.input_high_mv)) &&
+       !input::set(mapping.0, mapping.1) {
+        new.input_low_mv = old.input_low_mv;
+        new.input_high_mv = old.input_high_mv;
+        input::set(new.input_low_mv, new.input_high_mv);
+    }
+    if all || new.reference_mv != old.reference_mv {
+        if !calibration::set_reference(new.reference_mv) {
+            new.reference_mv = old.reference_mv;
+            calibration::set_reference(new.reference_mv);
+        } else if new.reference_mv.is_none() && !all {
+            // at startup the supply isn't measured yet, and is followed once it is
+            calibration::track_supply(capture::auxiliary().supply_mv);
+        }
+        screen::set_vertical(screen::gain_index(), screen::center_microvolts());
+    }
+    if all || new.frame_rate_index != old.frame_rate_index {
+        redraw::set_frame_rate(new.frame_rate_index);
+    }
+    if all || new.units != old.units || new.impedance_index != old.impedance_index {
+        measure::set_units(new.units);
+        measure::set_impedance_index(new.impedance_index);
+        measure::clear();
+    }
+    if all || new.button_map != old.button_map {
+        unsafe { BUTTON_MAP = new.button_map; }
+    }
+    if all || new.trace_styles != old.trace_styles {
+        trace::set_styles(new.trace_styles);
+        redraw::invalidate_trace_area(); // show the new styles right away
+    }
+    for (i, &measurement) in new.measurements.iter().enumerate() {
+        if all || measurement != old.measurements[i] {
+            measure::set_slot(i, measurement);
+        }
+    }
+    // a new orientation starts the screen again, as does a new display mode
+    let rotated = new.orientation != screen::orientation();
+    if rotated {
+        screen::set_orientation(new.orientation);
+        st7735_fillScreen(St7735Color::Black as u16);
+        trace::paint_graticule();
+    }
+    if (all || rotated || new.display_mode != old.display_mode) && !help_shown {
+        enter_display_mode(new.display_mode, zoomed);
+    }
+    *settings = new;
+}
+
+// whether the indexes into this file's tables, in settings loaded from flash, are in range,
+// and the values that go straight to the hardware: the trigger level, for the comparator and
+// its DAC threshold, the trigger delay, the input mapping, and the ADC reference
+fn settings_in_range(settings: &Settings) -> bool {
+    let top = SIGGEN_FREQUENCIES[SIGGEN_FREQUENCIES.len() - 1].frequency;
+    let i = settings.timebase_index;
+    let fine = settings.timebase_fine;
+    let delay = settings.trigger_delay_index;
+    i < TIMEBASE_INTERVALS.len() &&
+        (fine == 0 || next_fine_step(i, fine - 1) == Some(fine)) &&
+        settings.record_length_index < RECORD_LENGTHS.len() &&
+        settings.siggen_freq_index < SIGGEN_FREQUENCIES.len() &&
+        settings.siggen_hz > 0 && settings.siggen_hz <= top &&
+        settings.trigger.level <= 4095 &&
+        delay < TRIGGER_DELAYS.len() && settings.trigger.delay == TRIGGER_DELAYS[delay].samples &&
+        input::valid(settings.input_low_mv, settings.input_high_mv) &&
+        settings.reference_mv.map_or(true, calibration::reference_in_range)
+}
+
+// list the settings as the shell commands that would make them
+fn report_settings(settings: &Settings) {
+    let timebase: &[u8] = if settings.timebase_track {
+        b"track"
+    } else {
+        TIMEBASE_INTERVALS[settings.timebase_index].label
+    };
+    reply_setting(b"set timebase ", timebase);
+    if settings.timebase_fine != 0 {
+        shell::reply(b"(and fine timebase steps, which have no command)");
+    }
+    reply_setting(b"set record ", RECORD_LENGTHS[settings.record_length_index].label);
+    reply_setting(b"set interp ", settings.interpolation.label());
+    reply_setting(b"set display ", settings.display_mode.label());
+    reply_setting(b"set gate ", settings.gate.label());
+    reply_setting(b"trig mode ", settings.trigger.mode.label());
+    reply_setting(b"trig slope ", settings.trigger.slope.label());
+    reply_setting(b"trig coupling ", settings.trigger.coupling.label());
+    reply_setting(b"trig delay ", TRIGGER_DELAYS[settings.trigger_delay_index].label);
+    reply_setting(b"trig auto ", AUTO_TIMEOUTS[settings.auto_timeout_index].label);
+    let mut volts = [b' '; 8];
+    reply_setting(b"trig level ",
+                  format_volts(trigger_level_millivolts(settings.trigger.level), &mut volts));
+    let (mut low, mut high) = ([b' '; 8], [b' '; 8]);
+    let low = format_volts(settings.input_low_mv, &mut low);
+    let high = format_volts(settings.input_high_mv, &mut high);
+    let mut mapping = [b' '; 17];
+    mapping[..low.len()].copy_from_slice(low);
+    mapping[low.len() + 1..low.len() + 1 + high.len()].copy_from_slice(high);
+    reply_setting(b"input ", &mapping[..low.len() + 1 + high.len()]);
+    match settings.reference_mv {
+        Some(mv) => reply_setting(b"vref ", format_volts(mv as i32, &mut volts)),
+        None => reply_setting(b"vref ", b"auto"),
+    }
+    let mut hz = [b'0'; 10];
+    let digits = decimal_digits(settings.siggen_hz) as usize;
+    let mut n = settings.siggen_hz;
+    for i in (0..digits).rev() {
+        hz[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+    }
+    reply_setting(b"siggen ", &hz[..digits]);
+    reply_setting(b"set units ", settings.units.label());
+    reply_setting(b"set ohms ", measure::IMPEDANCES[settings.impedance_index].1);
+    reply_setting(b"set fps ", redraw::FRAME_RATES[settings.frame_rate_index].label);
+    let mut buttons = [0u8; BUTTONS];
+    for (i, &button) in settings.button_map.iter().enumerate() {
+        buttons[i] = b'1' + button as u8;
+    }
+    reply_setting(b"set buttons ", &buttons);
+    let mut id = TraceId::Ch1;
+    for _ in 0..trace::TRACES {
+        let style = settings.trace_styles[id as usize];
+        let shown: &[u8] = if style.visible { b"on" } else { b"off" };
+        let mut line = [b' '; 26];
+        let mut len = 0;
+        for part in &[id.label(), style.color_label(), shown, style.style_label()] {
+            line[len..len + part.len()].copy_from_slice(part);
+            len += part.len() + 1;
+        }
+        reply_setting(b"trace ", &line[..len - 1]);
+        id = id.next();
+    }
+    let mut slots = [b' '; 40];
+    let mut len = 0;
+    for measurement in &settings.measurements {
+        let label = measurement.label();
+        slots[len..len + label.len()].copy_from_slice(label);
+        len += label.len() + 2;
+        slots[len - 2] = b',';
+    }
+    serial::write(b"(and readout slots ");
+    serial::write(&slots[..len - 2]);
+    shell::reply(b", which have no command)");
+    if settings.orientation != Orientation::Landscape {
+        serial::write(b"(and a rotation of ");
+        serial::write(settings.orientation.label());
+        shell::reply(b", which has no command)");
+    }
+}
+
+// reply with a shell command, `command` then `value`, without any '~' marking it approximate
+fn reply_setting(command: &[u8], value: &[u8]) {
+    let value = if value.first() == Some(&b'~') { &value[1..] } else { value };
+    let mut line = [0u8; 40];
+    let len = command.len() + value.len();
+    line[..command.len()].copy_from_slice(command);
+    line[command.len()..len].copy_from_slice(value);
+    shell::reply(&line[..len]);
+}
+
+// clear the trace area for a newly selected display mode, or have it redrawn
+fn enter_display_mode(mode: DisplayMode, zoomed: bool) {
+    // the counter runs only while it is shown, since it takes TIM1 from the PWM analyzer
+    let mains = mode == DisplayMode::Mains;
+    counter::set_gate_ms(if mains { mains::GATE_MS } else { counter::GATE_MS });
+    counter::set_running(mode == DisplayMode::Counter || mains);
+    mains::set_active(mains);
+    rc::set_active(mode == DisplayMode::Rc);
+    if mode != DisplayMode::Trace {
+        screen::clear_trace_area();
+    }
+    match mode {
+        DisplayMode::Trace => {
+            if zoomed {
+                zoom::enter();
+            } else {
+                redraw::invalidate_trace_area();
+            }
+        }
+        DisplayMode::Meter | DisplayMode::Pwm | DisplayMode::Harmonics => {}
+        DisplayMode::Volts => voltmeter::reset(),
+        DisplayMode::Console => console::invalidate(),
+        DisplayMode::Log => logview::enter(),
+        DisplayMode::Envelope => envelope::reset(),
+        DisplayMode::Noise => noise::reset(),
+        DisplayMode::Rc => rc::enter(),
+        DisplayMode::Counter => counter::draw(),
+        DisplayMode::Mains => mains::draw(),
+        _ => histogram::reset(),
+    }
+}
+
+fn print_trigger_level(millivolts: i32) {
+    let label = Parameter::TriggerLevel.label();
+    let mut x = 8 * label.len() as u8;
+    status::clear();
+    status::print_color(label, 0, PARAMETER_COLOR);
+    if millivolts < 0 {
+        status::print(b"-", x);
+        x += 8;
+    }
+    let magnitude = (millivolts as i64).abs() as u32;
+    print_number(magnitude, x);
+    status::print(b"mV", x + 8 * decimal_digits(magnitude));
+}
+
+// the trigger level steps across the input range, rounded inwards: the lowest, in millivolts,
+// and the number of steps
+fn trigger_level_steps() -> (i32, usize) {
+    let (low, high) = input::mapping();
+    let step = TRIGGER_LEVEL_STEP_MV;
+    let first = if low < 0 { -(-low / step) } else { (low + step - 1) / step };
+    let last = if high < 0 { -((-high + step - 1) / step) } else { high / step };
+    (first * step, if last < first { 1 } else { (last - first + 1) as usize })
+}
+
+// the trigger level, a conversion, as millivolts at the input
+fn trigger_level_millivolts(level: u16) -> i32 {
+    (input::microvolts(calibration::microvolts(level) as i64) / 1000) as i32
+}
+
+// the conversion for a trigger level of `millivolts` at the input, within the ADC's range
+fn trigger_level_conversion(millivolts: i32) -> u16 {
+    calibration::raw_conversion(input::adc_microvolts(millivolts as i64 * 1000))
+}
+
+// format millivolts as volts, e.g. "-1.650", returning the text without leading spaces
+fn format_volts(millivolts: i32, text: &mut [u8; 8]) -> &[u8] {
+    measure::format_signed_fixed(millivolts as i64, 3, &mut text[..]);
+    let start = text.iter().position(|&c| c != b' ').unwrap_or(0);
+    &text[start..]
+}
+
+// reply with the ADC reference, and where it comes from
+fn report_reference() {
+    let mut text = [b' '; 8];
+    serial::write(b"ADC reference ");
+    serial::write(format_volts(calibration::reference_mv() as i32, &mut text));
+    match calibration::reference() {
+        Some(_) => shell::reply(b"V"),
+        None => shell::reply(b"V, following the supply measured through VREFINT"),
+    }
+}
+
+// reply with the capture interrupt's worst latency since measuring started, and log it
+fn report_latency() {
+    if !priority::measuring() {
+        shell::reply(b"not measuring: latency on starts");
+        return;
+    }
+    let mut line = *b"latency          ";
+    let len = 8 + measure::format_si(priority::worst_latency_ns() as i64, b"s", &mut line[8..]);
+    serial::write(b"capture interrupt ");
+    shell::reply(&line[..len]);
+    console::log(&line[..len]);
+}
+
+// reply with the input mapping
+fn report_input() {
+    let (low, high) = input::mapping();
+    let mut text = [b' '; 8];
+    serial::write(b"0V on the ADC is ");
+    serial::write(format_volts(low, &mut text));
+    serial::write(b"V at the input, 3.3V is ");
+    serial::write(format_volts(high, &mut text));
+    shell::reply(b"V");
+}
+
+// show the vertical gain, and whether it is auto-ranged
+fn print_vertical() {
+    let label = Parameter::Vertical.label();
+    let x = 8 * label.len() as u8;
+    status::clear();
+    status::print_color(label, 0, PARAMETER_COLOR);
+    let x = if autorange::enabled() {
+        status::print(b"auto", x);
+        x + 40
+    } else {
+        x
+    };
+    let mut gain = [0u8; 7];
+    let len = frontend::format_gain(&mut gain);
+    status::print(&gain[..len], x);
+}
+
+// reply with why a record couldn't be stored or recalled
+fn report_record_error(error: RecordError) {
+    let message: &[u8] = match error {
+        RecordError::SpiFlash(SpiFlashError::Absent) => b"no external flash",
+        RecordError::SpiFlash(SpiFlashError::Timeout) => b"external flash error",
+        RecordError::Empty => b"nothing is stored there",
+        RecordError::Corrupt => b"the stored record is damaged",
+        RecordError::Unreadable => b"the display can't be read with software SPI",
+    };
+    shell::reply(message);
+}
+
+// show whether the sweeps are running, or stopped from the keyboard
+fn print_run_state(running: bool) {
+    let label: &[u8] = if running { b"run" } else { b"stop" };
+    status::clear();
+    status::print(label, 0);
+}
+
+// step the trigger level in `settings` up or down from the nearest TRIGGER_LEVEL_STEP_MV step,
+// stopping at the ends of the input range rather than wrapping around as button 4 does
+fn nudge_trigger_level(settings: &mut Settings, up: bool) {
+    let (lowest, steps) = trigger_level_steps();
+    let above = trigger_level_millivolts(settings.trigger.level) - lowest;
+    let now = if above < 0 {
+        0
+    } else {
+        ((above + TRIGGER_LEVEL_STEP_MV / 2) / TRIGGER_LEVEL_STEP_MV) as usize
+    };
+    let now = if now < steps { now } else { steps - 1 };
+    let i = match up {
+        true if now + 1 < steps => now + 1,
+        false if now > 0 => now - 1,
+        _ => now,
+    };
+    let millivolts = lowest + i as i32 * TRIGGER_LEVEL_STEP_MV;
+    settings.trigger.level = trigger_level_conversion(millivolts);
+    print_trigger_level(millivolts);
+    let mut text = [b' '; 8];
+    serial::write(b"trig level ");
+    serial::write(format_volts(millivolts, &mut text));
+    shell::reply(b"V");
+}
+
+// step `settings` to the next faster or slower timebase, stopping at the ends
+fn nudge_timebase(settings: &mut Settings, faster: bool) {
+    let i = settings.timebase_index;
+    let i = match faster {
+        true if i + 1 < TIMEBASE_INTERVALS.len() => i + 1,
+        false if i > 0 => i - 1,
+        _ => i,
+    };
+    settings.timebase_index = i;
+    settings.timebase_fine = 0;
+    settings.timebase_track = false;
+    serial::write(b"timebase ");
+    serial::write(TIMEBASE_INTERVALS[i].label);
+    shell::reply(b"/div");
+}
+
+// show the sweep being reviewed, counting back from the most recent, or "live"
+fn print_history(reviewing: Option<usize>, count: usize) {
+    let label = Parameter::History.label();
+    let x = 8 * label.len() as u8;
+    match reviewing {
+        Some(age) => {
+            let n = age as u32 + 1;
+            status::clear();
+            status::print_color(label, 0, PARAMETER_COLOR);
+            status::print(b"-", x);
+            print_number(n, x + 8);
+            let x = x + 8 + 8 * decimal_digits(n);
+            status::print(b"/", x);
+            print_number(count as u32, x + 8);
+        }
+        None => print_parameter(Parameter::History, b"live"),
+    }
+}
+
+// show the number of segments kept so far, or the time of the segment being viewed, from the
+// first segment's trigger
+fn print_segmented(segmented: Segmented) {
+    let label = Parameter::Segments.label();
+    let x = 8 * label.len() as u8;
+    status::clear();
+    status::print_color(label, 0, PARAMETER_COLOR);
+    match segmented {
+        Segmented::Off => status::print(b"off", x),
+        Segmented::Armed => {
+            let n = segments::count() as u32;
+            status::print(b"armed", x);
+            print_number(n, x + 48);
+            let x = x + 48 + 8 * decimal_digits(n);
+            status::print(b"/", x);
+            print_number(segments::SEGMENTS as u32, x + 8);
+        }
+        Segmented::Viewing(i) => {
+            let n = i as u32 + 1;
+            print_number(n, x);
+            let x = x + 8 * decimal_digits(n);
+            let ms = segments::offset_ms(i);
+            let ms = if ms > 9_999_000 { 9_999_000 } else { ms }; // fits in the status line
+            let mut text = [0u8; 8];
+            let len = if ms == 0 {
+                text[..2].copy_from_slice(b"0s");
+                2
+            } else {
+                format_duration(ms as u64 * 1_000_000, &mut text)
+            };
+            status::print(b"+", x + 8);
+            status::print(&text[..len], x + 16);
+        }
+    }
+}
+
+// load segment `i` into the displayed screen, and have it repainted
+fn show_segment(i: usize, factor: usize, interpolation: Interpolation,
+                ch1_values: &mut [Option<u16>; screen::MAX_WIDTH]) {
+    let samples = segments::samples(i);
+    for (x, value) in ch1_values.iter_mut().enumerate() {
+        *value = interp::value_at(samples, x, factor, interpolation);
+    }
+    redraw::invalidate_trace_area();
+}
+
+// show a cursor's time from the left edge of the screen, or off
+fn print_cursor(parameter: Parameter, cursor: cursors::Cursor, factor: usize) {
+    match cursors::column(cursor) {
+        Some(x) => {
+            let mut text = [0u8; 8];
+            let ns = cursors::nanoseconds_at(x, factor, capture::sample_rate());
+            let len = axis::format_axis_time(ns, &mut text);
+            print_parameter(parameter, &text[..len]);
+        }
+        None => print_parameter(parameter, b"off"),
+    }
+}
+
+fn print_trigger_slope(slope: TriggerSlope) {
+    let label = slope.label();
+    print_parameter(Parameter::TriggerSlope, label);
+    let x = 8 * (Parameter::TriggerSlope.label().len() + label.len() + 1) as u8;
+    status::icon(Icon::for_slope(slope), x);
+}
+
+// Center the zoom window on a search hit, and show which hit it is on the status line. Returns
+// the new zoom window position.
+fn show_search_hit(record: &[u16], hit: Option<usize>, kind: SearchKind, level: u16,
+                   magnification: usize, position: usize) -> usize {
+    status::clear();
+    match hit {
+        Some(h) => {
+            let (number, total) = search::hit_number(record, h, kind, level);
+            status::print(b"hit", 0);
+            print_number(number as u32, 32);
+            let x = 32 + 8 * decimal_digits(number as u32);
+            status::print(b"/", x);
+            print_number(total as u32, x + 8);
+            let half_width = zoom::window_width(magnification) / 2;
+            let start = if h > half_width { h - half_width } else { 0 };
+            zoom::clamp_position(start, magnification, record.len())
+        }
+        None => {
+            status::print(b"no more hits", 0);
+            position
+        }
+    }
+}
+
+// repaint trace column `x` from the stored values, after it has been drawn over
+fn repaint_column(x: usize, ch1_plot: &mut TracePlot, ch1_values: &[Option<u16>],
+                  ch2_plot: &mut TracePlot, ch2_values: &[Option<u16>],
+                  reference_plot: &mut TracePlot, reference: &[Option<u16>],
+                  math_plot: &mut TracePlot, math_values: &[Option<i64>]) {
+    ch1_plot.erase_column(x);
+    ch2_plot.erase_column(x);
+    reference_plot.erase_column(x);
+    math_plot.erase_column(x);
+    trace::clear_column(x);
+    let y = |values: &[Option<u16>], x: usize| values[x].map(sample_to_y);
+    let previous = |values: &[Option<u16>]| if x > 0 { y(values, x - 1) } else { None };
+    reference_plot.replot(x, previous(reference), y(reference, x),
+                          trace::style(TraceId::Reference));
+    let math_previous = if x > 0 { math_values[x - 1].map(math::value_to_y) } else { None };
+    math_plot.replot(x, math_previous, math_values[x].map(math::value_to_y),
+                     trace::style(TraceId::Math));
+    ch2_plot.replot(x, previous(ch2_values), y(ch2_values, x), trace::style(TraceId::Ch2));
+    ch1_plot.replot(x, previous(ch1_values), y(ch1_values, x), trace::style(TraceId::Ch1));
+}
+
+// set the signal generator to any frequency, showing it on the status line, with a '~' if it is
+// approximate
+fn set_siggen_freq(hz: u32) {
+    let rate = siggen_set_freq(hz);
+    let exact = rate.is_exact(hz * siggen::TABLE_LENGTH as u32);
+    print_parameter(Parameter::SiggenFreq, if exact { b"" } else { b"~" });
+    let x = 8 * (Parameter::SiggenFreq.label().len() + if exact { 0 } else { 1 }) as u8;
+    print_number(hz, x);
+    status::print(b"Hz", x + 8 * decimal_digits(hz));
+}
+
+// set the signal generator to the settings' frequency, labelled as in the table if it's the
+// table's frequency
+fn set_siggen_from_settings(settings: &Settings) {
+    if settings.siggen_hz == SIGGEN_FREQUENCIES[settings.siggen_freq_index].frequency {
+        set_siggen_freq_from_index(settings.siggen_freq_index);
+    } else {
+        set_siggen_freq(settings.siggen_hz);
+    }
+}
+
+fn set_siggen_freq_from_index(i: usize) {
+    let f = &SIGGEN_FREQUENCIES[i];
+    let rate = siggen_set_freq(f.frequency);
+    if rate.is_exact(f.frequency * siggen::TABLE_LENGTH as u32) {
+        print_parameter(Parameter::SiggenFreq, f.label);
+    } else {
+        // mark the frequency as approximate
+        print_parameter(Parameter::SiggenFreq, b"~");
+        status::print(f.label, 8 * (Parameter::SiggenFreq.label().len() + 1) as u8);
+    }
+}
+
+// show a parameter and its new value on the status line
+fn print_parameter(parameter: Parameter, value: &[u8]) {
+    let label = parameter.label();
+    status::clear();
+    status::print_color(label, 0, PARAMETER_COLOR);
+    status::print(value, 8 * label.len() as u8);
+}
+
+// set the timebase `i`, lengthened by `fine` fine steps
+fn set_capture_timebase_from_index(i: usize, fine: u32) {
+    let t = &TIMEBASE_INTERVALS[i];
+    let rate = capture::set_timebase(fine_sample_rate(i, fine));
+    status::clear();
+    if fine == 0 && rate.is_exact(t.sample_rate) {
+        status::print(t.label, 0);
+        status::print(b"/div", 8 * t.label.len() as u8);
+        return;
+    }
+    // show the time per division and the sample rate actually achieved, the rate only if it
+    // fits
+    let mut text = [b' '; 8];
+    let len = format_duration(rate.nanoseconds(32) / t.factor as u64, &mut text);
+    status::print(&text[..len], 0);
+    status::print(b"/div", 8 * len as u8);
+    let x = 8 * (len as u8 + 5);
+    let digits = decimal_digits(rate.hz());
+    if x as i16 + 8 * (digits as i16 + 2) <= screen::width() {
+        print_number(rate.hz(), x);
+        status::print(b"/s", x + 8 * digits);
+    }
+}
+
+// the sample rate of timebase `i`, lengthened by `fine` fine steps
+fn fine_sample_rate(i: usize, fine: u32) -> u32 {
+    let rate = TIMEBASE_INTERVALS[i].sample_rate as u64;
+    (rate * 100 / (100 + FINE_STEP_PERCENT * fine) as u64) as u32
+}
+
+// Returns the fine step following `fine` at timebase `i`, back to 0 once the time/div would
+// reach the next slower timebase's, or None if the timebase can't be adjusted: the slowest one
+// has no slower one to approach, and those beyond the ADC's maximum sample rate only magnify.
+fn next_fine_step(i: usize, fine: u32) -> Option<u32> {
+    if i == 0 || TIMEBASE_INTERVALS[i].factor != 1 {
+        None
+    } else if fine_sample_rate(i, fine + 1) > TIMEBASE_INTERVALS[i - 1].sample_rate {
+        Some(fine + 1)
+    } else {
+        Some(0)
+    }
+}
+
+// format a duration of `ns` nanoseconds to four significant figures, with a unit, e.g.
+// "49.78us", returning the length
+fn format_duration(ns: u64, text: &mut [u8; 8]) -> usize {
+    let (scale, unit): (u64, &[u8]) = match ns {
+        0...999 => (1, b"ns"),
+        1_000...999_999 => (1_000, b"us"),
+        1_000_000...999_999_999 => (1_000_000, b"ms"),
+        _ => (1_000_000_000, b"s"),
+    };
+    // thousandths of the unit, rounded to four figures
+    let thousandths = (ns * 1000 + scale / 2) / scale;
+    let (value, decimals) = match thousandths {
+        0...9_999 => (thousandths, 3),
+        10_000...99_999 => ((thousandths + 5) / 10, 2),
+        _ => ((thousandths + 50) / 100, 1),
+    };
+    let len = decimal_digits(value as u32) as usize + 1;
+    measure::format_fixed(value, decimals, &mut text[..len]);
+    text[len..len + unit.len()].copy_from_slice(unit);
+    len + unit.len()
+}
+
+// With timebase tracking, returns the timebase to step to from timebase `i`, so that about two
+// periods of a signal of `mhz` millihertz fill the screen: the next slower timebase if there
+// are fewer than TRACK_MIN_PERIODS, or the next faster one if that would still show as many.
+// The timebases are about 2 to 2.5 times apart, so this settles with 2 to 5 periods showing.
+fn tracked_timebase(i: usize, mhz: u64) -> Option<usize> {
+    // periods across the screen, in thousandths
+    let width = screen::width() as u64;
+    let periods = |t: &TimebaseInterval| mhz * width / (t.sample_rate as u64 * t.factor as u64);
+    if periods(&TIMEBASE_INTERVALS[i]) < TRACK_MIN_PERIODS {
+        if i > 0 { Some(i - 1) } else { None }
+    } else if i + 1 < TIMEBASE_INTERVALS.len() &&
+              periods(&TIMEBASE_INTERVALS[i + 1]) >= TRACK_MIN_PERIODS {
+        Some(i + 1)
+    } else {
+        None
+    }
+}
+
+// the value after `value` in the cycle stepped through by `next`, or with `back`, the one before
+fn step<T: Copy + PartialEq, F: Fn(T) -> T>(value: T, next: F, back: bool) -> T {
+    if !back {
+        return next(value);
+    }
+    let mut previous = value;
+    while next(previous) != value {
+        previous = next(previous);
+    }
+    previous
+}
+
+// the index after `i`, or with `back`, the one before, cycling through `len` indexes
+fn step_index(i: usize, len: usize, back: bool) -> usize {
+    if back { (i + len - 1) % len } else { (i + 1) % len }
+}
+
+fn decimal_digits(n: u32) -> u8 {
+    let mut digits = 1;
+    let mut n = n / 10;
+    while n > 0 {
+        digits += 1;
+        n /= 10;
+    }
+    digits
+}
+
+// wait for any button to be pressed, and released
+fn wait_for_button() {
+    for i in 0..BUTTONS {
+        button_reset_changed(i);
+    }
+    while !(0..BUTTONS).any(|i| button_get_changed(i) && !button_get_state(i)) {}
+    for i in 0..BUTTONS {
+        button_reset_changed(i);
+    }
+}
+
+// note that the system clock is from the HSI, which is only accurate to about 1%
+fn report_hsi_clock() {
+    console::log(b"no HSE clock, using HSI");
+    status::clear();
+    status::print_color(b"clock: HSI (no HSE)", 0, St7735Color::Red);
+}
+
+// run the on-target self-checks, in a build with the "self-check" feature
+#[cfg(feature = "self-check")]
+fn run_self_checks() {
+    selfcheck::run();
+}
+#[cfg(not(feature = "self-check"))]
+fn run_self_checks() {}
+
+// warn that the stack has come close to running into .bss
+fn report_stack_warning() {
+    console::log(b"stack nearly full");
+    status::clear();
+    status::print_color(b"stack nearly full", 0, St7735Color::Red);
+}
+
+// print an unsigned decimal number on the status line
+fn print_number(n: u32, x: u8) {
+    let mut buf = [0u8; 10];
+    let digits = decimal_digits(n) as usize;
+    let mut n = n;
+    for i in (0..digits).rev() {
+        buf[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+    }
+    status::print(&buf[..digits], x);
+}
+
+// ======== exception handlers, including SysTick ========
+
+#[allow(dead_code)]
+#[used]
+#[link_section = ".rodata.exceptions"]
+static EXCEPTIONS: exception::Handlers = exception::Handlers {
+    // override the default NMI and SysTick handlers
+    nmi: nmi_handler,
+    sys_tick: systick_handler,
+    ..exception::DEFAULT_HANDLERS
+};
+
+// the clock security system raises an NMI when the HSE clock fails
+extern "C" fn nmi_handler(_: exception::Nmi) {
+    if sysclk::hse_failure() {
+        // keep SysTick at 1ms on the new clock
+        cortex_m::interrupt::free(|cs| SYST.borrow(cs).set_reload(systick_reload()));
+    }
+}
+
+// SysTick counts at HCLK/8: 9000 clocks per millisecond at 72MHz
+fn systick_reload() -> u32 {
+    clocks::hclk_hz() / 8 / 1000
+}
+
+extern "C" fn systick_handler(_: exception::SysTick) {
+    unsafe {
+        time::tick();
+
+        // read the buttons, with debounce
+        for i in 0..BUTTONS {
+            if BUTTON_DEBOUNCE[i] > 0 {
+                BUTTON_DEBOUNCE[i] -= 1;
+            } else {
+                // buttons are short-to-ground-with-pull-up, so invert the logic
+                let state = !board::BUTTON_PINS[i].is_high();
+                if state {
+                    if BUTTON_STATE[i] == false {
+                        BUTTON_STATE[i] = true;
+                        BUTTON_CHANGED[i] = true;
+                        BUTTON_DEBOUNCE[i] = 100;
+                    }
+                } else {
+                    if BUTTON_STATE[i] == true {
+                        BUTTON_STATE[i] = false;
+                        BUTTON_CHANGED[i] = true;
+                        BUTTON_DEBOUNCE[i] = 100;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[used]
 #[link_section = ".rodata.interrupts"]
 static INTERRUPTS: interrupt::Handlers = interrupt::Handlers {
     Dma1Ch1: dma1ch1_interrupt_handler,
+    Tim1BrkTim15: tim15_interrupt_handler,
+    // the serial port is one or the other, depending on the board
+    Usart1Exti25: usart1_interrupt_handler,
+    Usart2Exti26: usart2_interrupt_handler,
+    Tim1TrgComTim17: tim17_interrupt_handler,
+    Tim1UpTim16: tim1_up_interrupt_handler,
+    Tim1Cc: tim1_cc_interrupt_handler,
     ..interrupt::DEFAULT_HANDLERS
 };
 
 extern "C" fn dma1ch1_interrupt_handler(_ctxt: interrupt::Dma1Ch1) {
-    // turn off LD3 at the end of the capture sweep
-    led_off(LD3);
-    // clear the DMA1 channel 1 transfer complete interrupt flag TCIF
-    let dma1 = DMA1.get();
-    unsafe { (*dma1).ifcr.write(|w| w.ctcif1().bits(1)); }
+    let start = profile::start();
+    // announce the samples captured, and at the end of the sweep, start the next if chaining
+    if capture::dma_interrupt() {
+        // turn off LD3 at the end of the capture sweep
+        led_off(LD3);
+    }
+    profile::end(profile::Scope::CaptureInterrupt, start);
+}
+
+extern "C" fn tim15_interrupt_handler(_ctxt: interrupt::Tim1BrkTim15) {
+    let start = profile::start();
+    // a conversion was triggered: announce the sample before it
+    capture::timer_interrupt();
+    profile::end(profile::Scope::CaptureInterrupt, start);
+}
+
+extern "C" fn usart1_interrupt_handler(_ctxt: interrupt::Usart1Exti25) {
+    serial::interrupt();
+}
+
+extern "C" fn usart2_interrupt_handler(_ctxt: interrupt::Usart2Exti26) {
+    serial::interrupt();
+}
+
+extern "C" fn tim17_interrupt_handler(_ctxt: interrupt::Tim1TrgComTim17) {
+    // the end of a trigger output pulse
+    trigger_out::interrupt();
+}
+
+extern "C" fn tim1_up_interrupt_handler(_ctxt: interrupt::Tim1UpTim16) {
+    // TIM1 overflowed, while the counter runs
+    counter::overflow_interrupt();
+}
+
+extern "C" fn tim1_cc_interrupt_handler(_ctxt: interrupt::Tim1Cc) {
+    // the counter's input was captured
+    counter::capture_interrupt();
 }
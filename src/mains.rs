@@ -0,0 +1,198 @@
+// stm32f3-oscilloscope - src/mains.rs
+// mains frequency monitor: long reciprocal counter gates, and a trend of the frequency
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The mains monitor display runs the reciprocal counter (counter.rs) with GATE_MS gates, so
+// each reading averages 500 periods of 50Hz mains, or 600 of 60Hz, to a resolution of around
+// a microhertz, and plots each against the time, one column a gate, the newest at the right:
+// 160 columns of 10s is almost 27 minutes. The signal needs to be stepped down to a few volts
+// first, with a small transformer, not connected directly!
+//
+// The nominal frequency, 50Hz or 60Hz, is whichever the reading is nearer, and the trend is
+// plotted as the deviation from it, scaled by the smallest of SPANS_MHZ that fits all the
+// deviations shown, labelled at the top and bottom. Gates with no reading leave gaps.
+
+use counter::Reading;
+use font::FONT_5X7;
+use measure;
+use screen::{self, MAX_WIDTH};
+use segment;
+use st7735::*;
+
+/// The gate time, in milliseconds.
+pub const GATE_MS: u32 = 10_000;
+
+// the deviations at the top and bottom of the plot, in millihertz
+const SPANS_MHZ: [i32; 8] = [20, 50, 100, 200, 500, 1000, 2000, 5000];
+const DIGITS_Y: i16 = 2;
+const DIGITS_HEIGHT: i16 = 20;
+const RANGE_Y: u8 = 26; // the minimum and maximum
+const PLOT_TOP: i16 = 36;
+const LABEL_HEIGHT: i16 = 9; // the font height, plus a pixel either side
+
+// the deviation of each gate from the nominal frequency, in microhertz, the newest at NEXT - 1
+static mut TREND: [Option<i32>; MAX_WIDTH] = [None; MAX_WIDTH];
+static mut NEXT: usize = 0;
+static mut LATEST: Option<Reading> = None;
+// the lowest and highest frequencies since the display was entered, in microhertz
+static mut LOWEST: u64 = 0;
+static mut HIGHEST: u64 = 0;
+static mut ACTIVE: bool = false;
+
+/// Starts a new trend when the display is entered from another, or with `active` false, notes
+/// that it has been left.
+pub fn set_active(active: bool) {
+    if active && !unsafe { ACTIVE } {
+        reset();
+    }
+    unsafe { ACTIVE = active; }
+}
+
+// clear the trend
+fn reset() {
+    unsafe {
+        TREND = [None; MAX_WIDTH];
+        NEXT = 0;
+        LATEST = None;
+        LOWEST = u64::max_value();
+        HIGHEST = 0;
+    }
+}
+
+// the nominal frequency nearest `uhz` microhertz, in microhertz
+fn nominal(uhz: u64) -> u64 {
+    if uhz < 55_000_000 { 50_000_000 } else { 60_000_000 }
+}
+
+/// Adds a gate's reading, or None for a gate with no signal, to the trend.
+pub fn add(reading: Option<Reading>) {
+    let deviation = reading.map(|r| {
+        let deviation = r.frequency_uhz as i64 - nominal(r.frequency_uhz) as i64;
+        let limit = i32::max_value() as i64;
+        (if deviation > limit { limit } else if deviation < -limit { -limit } else { deviation })
+            as i32
+    });
+    unsafe {
+        TREND[NEXT] = deviation;
+        NEXT = (NEXT + 1) % MAX_WIDTH;
+        LATEST = reading;
+        if let Some(r) = reading {
+            if r.frequency_uhz < LOWEST { LOWEST = r.frequency_uhz; }
+            if r.frequency_uhz > HIGHEST { HIGHEST = r.frequency_uhz; }
+        }
+    }
+}
+
+// format `uhz` microhertz to eight digits, e.g. "50.001234", in `buf`, nine characters
+fn format_hz(uhz: u64, buf: &mut [u8]) {
+    let mut value = uhz;
+    let mut decimals = 6;
+    while decimals > 0 && value >= 100_000_000 {
+        value /= 10;
+        decimals -= 1;
+    }
+    measure::format_fixed(value, decimals, buf);
+}
+
+// the deviation trend's columns, oldest first, right-aligned to the screen width
+fn column(x: usize, columns: usize) -> Option<i32> {
+    let age = columns - 1 - x; // gates before the newest
+    unsafe { TREND[(NEXT + MAX_WIDTH - 1 - age) % MAX_WIDTH] }
+}
+
+/// Draws the mains monitor display: the latest frequency, the range seen, and the trend.
+pub fn draw() {
+    screen::clear_trace_area();
+    let mut buf = [b'-'; 9];
+    let latest = unsafe { LATEST };
+    if let Some(reading) = latest {
+        format_hz(reading.frequency_uhz, &mut buf);
+    }
+    let x = segment::draw(&buf, 0, DIGITS_Y, DIGITS_HEIGHT, St7735Color::Green,
+                          St7735Color::Black);
+    st7735_print(b"Hz", (x + 2) as u8, (DIGITS_Y + DIGITS_HEIGHT - 12) as u8,
+                 St7735Color::Green, St7735Color::Black);
+    if unsafe { HIGHEST } > 0 {
+        let mut line = [b' '; 24];
+        format_hz(unsafe { LOWEST }, &mut line[..9]);
+        line[9..13].copy_from_slice(b" to ");
+        format_hz(unsafe { HIGHEST }, &mut line[13..22]);
+        line[22..].copy_from_slice(b"Hz");
+        FONT_5X7.draw(&line, 0, RANGE_Y, St7735Color::White, St7735Color::Black);
+    }
+
+    // the smallest span that fits every deviation shown
+    let columns = {
+        let width = screen::width() as usize;
+        if width > MAX_WIDTH { MAX_WIDTH } else { width }
+    };
+    let mut largest = 0i64;
+    for x in 0..columns {
+        if let Some(deviation) = column(x, columns) {
+            let magnitude = (deviation as i64).abs();
+            if magnitude > largest { largest = magnitude; }
+        }
+    }
+    let span_mhz = SPANS_MHZ.iter().cloned().find(|&s| s as i64 * 1000 >= largest)
+                            .unwrap_or(SPANS_MHZ[SPANS_MHZ.len() - 1]);
+    let bottom_label_y = screen::trace_height() - LABEL_HEIGHT + 1;
+    let top = PLOT_TOP + LABEL_HEIGHT;
+    let height = bottom_label_y - 1 - top;
+    let middle = top + height / 2;
+    let span_uhz = span_mhz as i64 * 1000;
+    let to_y = |deviation: i32| -> i16 {
+        let d = deviation as i64;
+        let d = if d > span_uhz { span_uhz } else if d < -span_uhz { -span_uhz } else { d };
+        middle - (d * (height / 2) as i64 / span_uhz) as i16
+    };
+
+    // the nominal line, the labels, and the trend
+    st7735_drawFastHLine(0, middle, columns as i16, St7735Color::Blue as u16);
+    let mut label = [b' '; 9];
+    measure::format_fixed(span_mhz as u64, 3, &mut label[1..7]);
+    label[7..].copy_from_slice(b"Hz");
+    // the sign goes just before the digits
+    let sign = label[1..].iter().position(|&c| c != b' ').unwrap_or(0);
+    label[sign] = b'+';
+    FONT_5X7.draw(&label[sign..], 0, (PLOT_TOP + 1) as u8, St7735Color::White,
+                  St7735Color::Black);
+    label[sign] = b'-';
+    FONT_5X7.draw(&label[sign..], 0, bottom_label_y as u8, St7735Color::White,
+                  St7735Color::Black);
+    let mut previous_y: Option<i16> = None;
+    for x in 0..columns {
+        match column(x, columns) {
+            Some(deviation) => {
+                let y = to_y(deviation);
+                match previous_y {
+                    Some(py) => st7735_draw_line(x as i16 - 1, py, x as i16, y,
+                                                 St7735Color::Green as u16),
+                    None => st7735_drawPixel(x as i16, y, St7735Color::Green as u16),
+                }
+                previous_y = Some(y);
+            }
+            None => previous_y = None,
+        }
+    }
+}
@@ -0,0 +1,315 @@
+// stm32f3-oscilloscope - src/math.rs
+// math traces: the derivative, integral, or a filtered copy of CH1, from the capture buffer
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The math trace is computed column by column as CH1 is plotted, from the samples themselves
+// rather than the plotted values, using the calibrated voltage of each:
+//   dV/dt, the difference between each sample and the one before, times the sample rate,
+//     in microvolts per second, with 0 two divisions up from the bottom of the screen
+//   the integral, the sum of the samples from the start of the screen, each times the sample
+//     interval, in picovolt-seconds, with 0 at the bottom of the screen
+//   a low-pass filter, in microvolts, on the same scale as CH1
+//   a high-pass filter, CH1 less its low-pass, in microvolts, on the same scale as CH1 but
+//     with 0 at mid-screen, as with AC coupling
+// The filters are single-pole IIR filters, y += a * (x - y), with a cutoff frequency chosen as
+// a fraction of the sample rate from CUTOFFS, starting from the first sample on the screen.
+//
+// For dV/dt and the integral, the vertical scale is chosen from a 1-2-5 sequence at the end of
+// each sweep, to fit the largest value of that sweep, and shown in the readout at the bottom
+// left of the trace area. It changes to a larger scale as soon as the trace doesn't fit, but
+// to a smaller one only when the trace would fit two steps smaller, so it doesn't flicker
+// between two.
+
+use calibration;
+use font::FONT_5X7;
+use screen;
+use st7735::*;
+
+const DIVISION: i64 = 32; // pixels, as the graticule
+const SCALES: usize = 3 * 17; // up to 5 * 10^16 units per division
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MathOp {
+    Off,
+    Derivative,
+    Integral,
+    LowPass,
+    HighPass,
+}
+
+impl MathOp {
+    pub fn next(self) -> MathOp {
+        match self {
+            MathOp::Off => MathOp::Derivative,
+            MathOp::Derivative => MathOp::Integral,
+            MathOp::Integral => MathOp::LowPass,
+            MathOp::LowPass => MathOp::HighPass,
+            MathOp::HighPass => MathOp::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static [u8] {
+        match self {
+            MathOp::Off => b"off",
+            MathOp::Derivative => b"dV/dt",
+            MathOp::Integral => b"integral",
+            MathOp::LowPass => b"low pass",
+            MathOp::HighPass => b"high pass",
+        }
+    }
+
+    // the screen row of zero, from the bottom, and the height available above it for the
+    // largest value, in pixels
+    fn zero_and_range(self) -> (i16, i64) {
+        match self {
+            MathOp::Integral => (0, 96),
+            _ => (64, 48), // and about as much below
+        }
+    }
+
+    // the unit of the values, and the power of ten of the base unit it is
+    fn unit(self) -> (&'static [u8], i32) {
+        match self {
+            MathOp::Integral => (b"Vs", -12),
+            _ => (b"V/s", -6),
+        }
+    }
+}
+
+const MANTISSAS: [i64; 3] = [1, 2, 5];
+
+struct Cutoff {
+    label: &'static [u8], // as a fraction of the sample rate
+    a: i64,               // 1 - e^(-2π fc/fs), times 2^16
+}
+
+const CUTOFFS: [Cutoff; 6] = [
+    Cutoff { label: b"fs/4", a: 51912 },
+    Cutoff { label: b"fs/10", a: 30573 },
+    Cutoff { label: b"fs/20", a: 17668 },
+    Cutoff { label: b"fs/50", a: 7739 },
+    Cutoff { label: b"fs/100", a: 3991 },
+    Cutoff { label: b"fs/200", a: 2027 },
+];
+
+// the high-pass filter's 0, mid-screen
+const HIGH_PASS_ZERO_MICROVOLTS: i64 = 1_650_000;
+
+static mut OP: MathOp = MathOp::Off;
+static mut SCALE: usize = 0; // an index into the 1-2-5 sequence, 0 being one unit per division
+static mut CUTOFF: usize = 1;
+static mut INTEGRAL: i64 = 0;   // the sum, or the filter's state (times 2^16), to NEXT_SAMPLE
+static mut NEXT_SAMPLE: usize = 0; // the next sample to add to the sum, or to filter
+static mut PEAK: i64 = 0;       // the largest magnitude this sweep
+
+pub fn op() -> MathOp {
+    unsafe { OP }
+}
+
+pub fn set_op(op: MathOp) {
+    unsafe {
+        OP = op;
+        SCALE = 0;
+        INTEGRAL = 0;
+        NEXT_SAMPLE = 0;
+    }
+}
+
+/// Selects the next filter cutoff frequency, or with `back`, the previous one.
+pub fn step_cutoff(back: bool) {
+    unsafe {
+        let step = if back { CUTOFFS.len() - 1 } else { 1 };
+        CUTOFF = (CUTOFF + step) % CUTOFFS.len();
+        NEXT_SAMPLE = 0;
+    }
+}
+
+pub fn cutoff_label() -> &'static [u8] {
+    CUTOFFS[unsafe { CUTOFF }].label
+}
+
+// the value of one division at `scale`, in units
+fn division(scale: usize) -> i64 {
+    let mut value = MANTISSAS[scale % MANTISSAS.len()];
+    for _ in 0..scale / MANTISSAS.len() {
+        value *= 10;
+    }
+    value
+}
+
+/// Returns the math trace's value for pixel `x` of the screen starting at `window`, where each
+/// sample spans `factor` pixels, or `None` if there is none.
+pub fn value_at(window: &[u16], x: usize, factor: usize, sample_rate: u32) -> Option<i64> {
+    let n = x / factor;
+    if n >= window.len() {
+        return None;
+    }
+    if x == 0 {
+        unsafe {
+            INTEGRAL = 0;
+            NEXT_SAMPLE = 0;
+            PEAK = 0;
+        }
+    }
+    let value = match op() {
+        MathOp::Off => return None,
+        MathOp::Derivative => {
+            if n == 0 {
+                return None;
+            }
+            let dv = calibration::microvolts(window[n]) as i64 -
+                     calibration::microvolts(window[n - 1]) as i64;
+            dv * sample_rate as i64
+        }
+        MathOp::Integral => unsafe {
+            while NEXT_SAMPLE <= n {
+                let microvolts = calibration::microvolts(window[NEXT_SAMPLE]) as i64;
+                INTEGRAL += microvolts * 1_000_000 / sample_rate as i64;
+                NEXT_SAMPLE += 1;
+            }
+            INTEGRAL
+        },
+        MathOp::LowPass | MathOp::HighPass => unsafe {
+            let a = CUTOFFS[CUTOFF].a;
+            while NEXT_SAMPLE <= n {
+                let microvolts = calibration::microvolts(window[NEXT_SAMPLE]) as i64;
+                if NEXT_SAMPLE == 0 {
+                    INTEGRAL = microvolts << 16;
+                } else {
+                    INTEGRAL += a * (microvolts - (INTEGRAL >> 16));
+                }
+                NEXT_SAMPLE += 1;
+            }
+            let low_pass = INTEGRAL >> 16;
+            if op() == MathOp::LowPass {
+                low_pass
+            } else {
+                calibration::microvolts(window[n]) as i64 - low_pass
+            }
+        },
+    };
+    unsafe {
+        let magnitude = if value < 0 { -value } else { value };
+        if magnitude > PEAK {
+            PEAK = magnitude;
+        }
+    }
+    Some(value)
+}
+
+/// Returns the screen row for a math value, at the current scale. The row may be off-screen.
+pub fn value_to_y(value: i64) -> i16 {
+    let microvolts = match op() {
+        MathOp::LowPass => value,
+        MathOp::HighPass => value + HIGH_PASS_ZERO_MICROVOLTS,
+        _ => return scaled_value_to_y(value),
+    };
+    if microvolts < 0 {
+        screen::microvolts_to_y(0) + 1 // below the bottom of the screen
+    } else {
+        screen::microvolts_to_y(microvolts as u32)
+    }
+}
+
+// the screen row for dV/dt or the integral, at the current scale
+fn scaled_value_to_y(value: i64) -> i16 {
+    let (zero, _) = op().zero_and_range();
+    let pixels = value * DIVISION / division(unsafe { SCALE });
+    let pixels = if pixels > 1000 { 1000 } else if pixels < -1000 { -1000 } else { pixels };
+    screen::from_bottom(zero) - pixels as i16
+}
+
+/// Chooses the scale for the next sweep, from the largest value of the one just plotted.
+pub fn finish_sweep() {
+    if op() != MathOp::Derivative && op() != MathOp::Integral {
+        return;
+    }
+    let (_, range) = op().zero_and_range();
+    let fits = |scale: usize| unsafe { PEAK * DIVISION <= division(scale) * range };
+    unsafe {
+        while !fits(SCALE) && SCALE + 1 < SCALES {
+            SCALE += 1;
+        }
+        while SCALE >= 2 && fits(SCALE - 2) {
+            SCALE -= 1;
+        }
+    }
+}
+
+/// Draws the scale in the math trace's color at the bottom left of the trace area, e.g.
+/// "dV/dt 5kV/s/div", or the filter and its cutoff, e.g. "low pass fs/10".
+pub fn draw_readout(color: St7735Color) {
+    let op = op();
+    let y = (screen::trace_height() - 8) as u8;
+    match op {
+        MathOp::Off => return,
+        MathOp::LowPass | MathOp::HighPass => {
+            let x = FONT_5X7.draw(op.label(), 0, y, color, St7735Color::Black);
+            let x = FONT_5X7.draw(b" ", x, y, color, St7735Color::Black);
+            FONT_5X7.draw(cutoff_label(), x, y, color, St7735Color::Black);
+            return;
+        }
+        _ => {}
+    }
+    let scale = unsafe { SCALE };
+    let (unit, unit_power) = op.unit();
+    // the division in the base unit is MANTISSAS[..] * 10^power
+    let power = unit_power + (scale / MANTISSAS.len()) as i32;
+    let prefixes: [(i32, u8); 7] =
+        [(-12, b'p'), (-9, b'n'), (-6, b'u'), (-3, b'm'), (0, b' '), (3, b'k'), (6, b'M')];
+    let mut prefix = prefixes[0];
+    for &p in prefixes.iter() {
+        if p.0 <= power {
+            prefix = p;
+        }
+    }
+    let mut number = MANTISSAS[scale % MANTISSAS.len()];
+    for _ in prefix.0..power {
+        number *= 10;
+    }
+    let mut text = [b' '; 24];
+    let mut len = 0;
+    for &c in op.label().iter().chain(b" ") {
+        text[len] = c;
+        len += 1;
+    }
+    let digits = if number >= 100 { 3 } else if number >= 10 { 2 } else { 1 };
+    for i in 0..digits {
+        let mut n = number;
+        for _ in 0..digits - 1 - i {
+            n /= 10;
+        }
+        text[len] = b'0' + (n % 10) as u8;
+        len += 1;
+    }
+    if prefix.1 != b' ' {
+        text[len] = prefix.1;
+        len += 1;
+    }
+    for &c in unit.iter().chain(b"/div ") {
+        text[len] = c;
+        len += 1;
+    }
+    FONT_5X7.draw(&text[..len], 0, y, color, St7735Color::Black);
+}
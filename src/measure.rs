@@ -0,0 +1,755 @@
+// stm32f3-oscilloscope - src/measure.rs
+// automatic measurements of the captured record, shown in selectable readout slots
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// The readout row across the top of the trace area is divided into four slots of five
+// characters, each showing one measurement (or nothing) as a one-letter label followed by a
+// four-character value. Voltages are in volts, frequencies in hertz with a k or M suffix. The
+// supply, DC input, and temperature come from the ADC's auxiliary channels rather than the
+// record. The record's voltages are the actual input voltages, through the input mapping (see
+// input.rs), so they may be negative; the RMS is then that of the mapped signal, found from its
+// mean and standard deviation.
+//
+// A sample is clipped if the ADC gave its lowest or highest conversion, when the input may
+// have been beyond the ADC's range, so a flat top or bottom may be the ADC saturating rather
+// than the signal.
+//
+// Frequency and duty cycle are measured between the first and last rising crossings of the
+// midpoint between the minimum and maximum, so they need at least two cycles in the record.
+//
+// The trigger rate comes from trigrate.rs, counted over many records rather than measured in
+// this one.
+//
+// The phase of CH2 relative to CH1 comes from phase.rs, measured only while CH2 is sampled; it
+// reads "f<>f" if the two channels' frequencies don't match.
+//
+// RMS amplitudes, here and in the harmonics display and the telemetry, are shown in the
+// selected Units: volts, with an SI prefix where there is room for one, millivolts, dBV
+// (relative to 1V RMS), or dBm (relative to 1mW into the selected reference impedance, so 0dBm
+// is 0.775V into 600 ohms, or 0.224V into 50 ohms). value(), which alarms and the data log
+// use, stays in millivolts whatever the units, so their limits and stored values keep their
+// meaning.
+//
+// The meter display shows the mean voltage and the frequency in large seven-segment digits,
+// filling the trace area, as a voltmeter and frequency counter.
+
+use calibration;
+use capture;
+use input;
+use phase;
+use phase::Phase;
+use segment;
+use screen;
+use trigrate;
+use st7735::*;
+
+pub const SLOTS: usize = 4;
+
+const ROW_Y: u8 = 0;
+const SLOT_WIDTH: u8 = 40; // pixels, a letter and four characters
+
+// the meter display: the volts digits, then the frequency digits below them
+const VOLTS_X: i16 = 8;
+const VOLTS_Y: i16 = 8;
+const VOLTS_HEIGHT: i16 = 44;
+const FREQUENCY_X: i16 = 8;
+const FREQUENCY_Y: i16 = 72;
+const FREQUENCY_HEIGHT: i16 = 28;
+
+// hysteresis around the midpoint for frequency and duty cycle crossings
+const HYSTERESIS: u16 = 32;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Measurement {
+    Off = 0,
+    Frequency = 1,
+    PeakToPeak = 2,
+    Rms = 3,
+    Mean = 4,
+    Duty = 5,
+    Min = 6,
+    Max = 7,
+    Supply = 8,       // the ADC supply and reference voltage, VDDA
+    AuxInput = 9,     // the auxiliary DC input
+    Temperature = 10, // the chip temperature, in °C
+    Clipped = 11,     // the percentage of samples at the ends of the ADC's range
+    TriggerRate = 12, // the trigger events a second
+    Phase = 13,       // CH2's phase relative to CH1, in degrees
+}
+
+impl Measurement {
+    pub fn next(self) -> Measurement {
+        match self {
+            Measurement::Off => Measurement::Frequency,
+            Measurement::Frequency => Measurement::PeakToPeak,
+            Measurement::PeakToPeak => Measurement::Rms,
+            Measurement::Rms => Measurement::Mean,
+            Measurement::Mean => Measurement::Duty,
+            Measurement::Duty => Measurement::Min,
+            Measurement::Min => Measurement::Max,
+            Measurement::Max => Measurement::Supply,
+            Measurement::Supply => Measurement::AuxInput,
+            Measurement::AuxInput => Measurement::Temperature,
+            Measurement::Temperature => Measurement::Clipped,
+            Measurement::Clipped => Measurement::TriggerRate,
+            Measurement::TriggerRate => Measurement::Phase,
+            Measurement::Phase => Measurement::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static [u8] {
+        match self {
+            Measurement::Off => b"off",
+            Measurement::Frequency => b"freq",
+            Measurement::PeakToPeak => b"Vpp",
+            Measurement::Rms => b"RMS",
+            Measurement::Mean => b"mean",
+            Measurement::Duty => b"duty",
+            Measurement::Min => b"min",
+            Measurement::Max => b"max",
+            Measurement::Supply => b"supply",
+            Measurement::AuxInput => b"DC in",
+            Measurement::Temperature => b"temp",
+            Measurement::Clipped => b"clip",
+            Measurement::TriggerRate => b"trig/s",
+            Measurement::Phase => b"phase",
+        }
+    }
+
+    // the label shown in the readout row
+    fn letter(self) -> u8 {
+        match self {
+            Measurement::Off => b' ',
+            Measurement::Frequency => b'F',
+            Measurement::PeakToPeak => b'P',
+            Measurement::Rms => b'R',
+            Measurement::Mean => b'A',
+            Measurement::Duty => b'D',
+            Measurement::Min => b'L',
+            Measurement::Max => b'H',
+            Measurement::Supply => b'S',
+            Measurement::AuxInput => b'X',
+            Measurement::Temperature => b'T',
+            Measurement::Clipped => b'C',
+            Measurement::TriggerRate => b'E', // events
+            Measurement::Phase => b'Q', // quadrature, as P is peak to peak
+        }
+    }
+}
+
+/// The units RMS amplitudes are shown in.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Units {
+    Volts = 0,
+    Millivolts = 1,
+    Dbv = 2, // dB relative to 1V
+    Dbm = 3, // dB relative to 1mW into the reference impedance
+}
+
+impl Units {
+    pub fn next(self) -> Units {
+        match self {
+            Units::Volts => Units::Millivolts,
+            Units::Millivolts => Units::Dbv,
+            Units::Dbv => Units::Dbm,
+            Units::Dbm => Units::Volts,
+        }
+    }
+
+    pub fn label(self) -> &'static [u8] {
+        match self {
+            Units::Volts => b"V",
+            Units::Millivolts => b"mV",
+            Units::Dbv => b"dBV",
+            Units::Dbm => b"dBm",
+        }
+    }
+
+    /// The name of the RMS field in telemetry.
+    pub fn field_name(self) -> &'static [u8] {
+        match self {
+            Units::Volts => b"rms_v",
+            Units::Millivolts => b"rms_mv",
+            Units::Dbv => b"rms_dbv",
+            Units::Dbm => b"rms_dbm",
+        }
+    }
+}
+
+/// The reference impedances for dBm, in ohms, and their labels.
+pub const IMPEDANCES: [(u64, &'static [u8]); 3] = [(50, b"50"), (75, b"75"), (600, b"600")];
+
+static mut UNITS: Units = Units::Volts;
+static mut IMPEDANCE_INDEX: usize = 2; // 600 ohms, as for audio
+
+pub fn units() -> Units {
+    unsafe { UNITS }
+}
+
+pub fn set_units(units: Units) {
+    unsafe { UNITS = units; }
+}
+
+/// The index into IMPEDANCES of the reference impedance.
+pub fn impedance_index() -> usize {
+    unsafe { IMPEDANCE_INDEX }
+}
+
+pub fn set_impedance_index(i: usize) {
+    unsafe { IMPEDANCE_INDEX = i; }
+}
+
+/// Returns an RMS amplitude of `rms_uv` microvolts in thousandths of the selected units:
+/// millivolts, microvolts, or thousandths of a dB. None for zero in dB.
+pub fn amplitude(rms_uv: u64) -> Option<i64> {
+    // 20log10(x) is 20 * 0.30103 * log2(x), and log2_q16() gives 65536ths
+    let millidb = |uv: u64| (log2_q16(uv) - log2_q16(1_000_000)) as i64 * 60206 / 655_360;
+    match unsafe { UNITS } {
+        Units::Volts => Some((rms_uv / 1000) as i64),
+        Units::Millivolts => Some(rms_uv as i64),
+        _ if rms_uv == 0 => None,
+        Units::Dbv => Some(millidb(rms_uv)),
+        Units::Dbm => {
+            // less 10log10(R / 1000 ohms), as P = V^2 / R
+            let ohms = IMPEDANCES[unsafe { IMPEDANCE_INDEX }].0;
+            let relative = (log2_q16(ohms) - log2_q16(1000)) as i64 * 30103 / 655_360;
+            Some(millidb(rms_uv) - relative)
+        }
+    }
+}
+
+/// Formats an RMS amplitude of `rms_uv` microvolts in the selected units, with the unit, e.g.
+/// "1.06V", "1061.2mV", or "-12.3dBm", returning the length. `text` needs room for nine
+/// characters.
+pub fn format_amplitude(rms_uv: u64, text: &mut [u8]) -> usize {
+    let units = units();
+    if units == Units::Volts {
+        return format_si(rms_uv as i64 * 1000, b"V", text);
+    }
+    let value = match amplitude(rms_uv) {
+        Some(value) => value,
+        None => {
+            text[..2].copy_from_slice(b"--");
+            text[2..2 + units.label().len()].copy_from_slice(units.label());
+            return 2 + units.label().len();
+        }
+    };
+    // to one decimal place
+    let half = if value < 0 { -50 } else { 50 };
+    let mut buf = [b' '; 7];
+    format_signed_fixed((value + half) / 100, 1, &mut buf);
+    let first = buf.iter().position(|&c| c != b' ').unwrap_or(0);
+    let len = buf.len() - first;
+    text[..len].copy_from_slice(&buf[first..]);
+    text[len..len + units.label().len()].copy_from_slice(units.label());
+    len + units.label().len()
+}
+
+// format an RMS amplitude of `rms_uv` microvolts in the selected units, without the unit, in
+// a readout slot
+fn format_amplitude_slot(rms_uv: u64, buf: &mut [u8; 4]) {
+    let value = match amplitude(rms_uv) {
+        Some(value) => value,
+        None => return,
+    };
+    match units() {
+        Units::Volts => format_millivolts(value as i32, buf),
+        Units::Millivolts => {
+            let mv = (value + 500) / 1000;
+            if mv > 9999 {
+                buf.copy_from_slice(b"9999");
+            } else {
+                format_fixed(mv as u64, 0, &mut buf[..]);
+            }
+        }
+        Units::Dbv | Units::Dbm => {
+            // to one decimal place where it fits, otherwise to whole dB
+            let half = if value < 0 { -50 } else { 50 };
+            let tenths = (value + half) / 100;
+            if tenths > -100 && tenths < 1000 {
+                format_signed_fixed(tenths, 1, &mut buf[..]);
+            } else {
+                let whole = (tenths + if tenths < 0 { -5 } else { 5 }) / 10;
+                let whole = if whole < -999 { -999 } else if whole > 9999 { 9999 } else { whole };
+                format_signed_fixed(whole, 0, &mut buf[..]);
+            }
+        }
+    }
+}
+
+/// The measurements in the readout's slots at startup, before any stored settings are loaded.
+pub const DEFAULT_SLOTS: [Measurement; SLOTS] = [
+    Measurement::Frequency,
+    Measurement::PeakToPeak,
+    Measurement::Off,
+    Measurement::Off,
+];
+
+// the measurements shown, as in the settings
+static mut SLOT_MEASUREMENTS: [Measurement; SLOTS] = DEFAULT_SLOTS;
+
+pub fn slot(i: usize) -> Measurement {
+    unsafe { SLOT_MEASUREMENTS[i] }
+}
+
+/// Sets the measurement shown in slot `i`, and clears the readout row so an emptied slot
+/// disappears.
+pub fn set_slot(i: usize, measurement: Measurement) {
+    unsafe { SLOT_MEASUREMENTS[i] = measurement; }
+    clear();
+}
+
+// the number of slots that fit across the screen, four in landscape, three in portrait, where
+// the rest go on a second row
+fn slots_per_row() -> usize {
+    let fit = screen::width() as usize / SLOT_WIDTH as usize;
+    if fit < SLOTS { fit } else { SLOTS }
+}
+
+/// Clears the readout row, or rows.
+pub fn clear() {
+    let per_row = slots_per_row();
+    let rows = (SLOTS + per_row - 1) / per_row;
+    st7735_fill_rect(0, ROW_Y as i16, SLOT_WIDTH as i16 * per_row as i16, 12 * rows as i16,
+                     St7735Color::Black as u16);
+}
+
+/// The results of measuring one record, with voltages in millivolts at the input.
+pub struct Results {
+    min: i32,
+    max: i32,
+    mean: i32,
+    rms_uv: u64,
+    frequency_mhz: Option<u64>, // millihertz
+    duty_percent: Option<u32>,
+    clipped_permille: u32, // tenths of a percent of the samples
+}
+
+impl Results {
+    /// The frequency in millihertz, if there were at least two cycles.
+    pub fn frequency_millihz(&self) -> Option<u64> {
+        self.frequency_mhz
+    }
+
+    pub fn peak_to_peak_millivolts(&self) -> u32 {
+        (self.max - self.min) as u32
+    }
+
+    pub fn rms_millivolts(&self) -> u32 {
+        (self.rms_uv / 1000) as u32
+    }
+
+    pub fn rms_microvolts(&self) -> u64 {
+        self.rms_uv
+    }
+
+    pub fn mean_millivolts(&self) -> i32 {
+        self.mean
+    }
+}
+
+/// Returns a measurement's value in thousandths of the unit it is shown in: millivolts,
+/// millihertz, thousandths of a percent, or thousandths of a degree. None if it couldn't be
+/// measured, or is off.
+pub fn value(measurement: Measurement, results: &Results) -> Option<i64> {
+    let auxiliary = capture::auxiliary();
+    match measurement {
+        Measurement::Off => None,
+        Measurement::Frequency => results.frequency_mhz.map(|mhz| mhz as i64),
+        Measurement::PeakToPeak => Some((results.max - results.min) as i64),
+        Measurement::Rms => Some((results.rms_uv / 1000) as i64),
+        Measurement::Mean => Some(results.mean as i64),
+        Measurement::Duty => results.duty_percent.map(|percent| percent as i64 * 1000),
+        Measurement::Min => Some(results.min as i64),
+        Measurement::Max => Some(results.max as i64),
+        Measurement::Supply => Some(auxiliary.supply_mv as i64),
+        Measurement::AuxInput => Some(auxiliary.input_mv as i64),
+        Measurement::Temperature => {
+            let tenths = if auxiliary.temperature < 0 { 0 } else { auxiliary.temperature };
+            Some(tenths as i64 * 100)
+        }
+        Measurement::Clipped => Some(results.clipped_permille as i64 * 100),
+        Measurement::TriggerRate => trigrate::millihz().map(|mhz| mhz as i64),
+        Measurement::Phase => match phase::phase() {
+            Some(Phase::Degrees(millidegrees)) => Some(millidegrees as i64),
+            _ => None,
+        },
+    }
+}
+
+/// Returns whether a raw conversion is at either end of the ADC's range.
+pub fn is_clipped(raw_conversion: u16) -> bool {
+    raw_conversion == 0 || raw_conversion >= 4095
+}
+
+/// Returns the number of clipped samples in `data`.
+pub fn count_clipped(data: &[u16]) -> usize {
+    data.iter().filter(|&&sample| is_clipped(sample)).count()
+}
+
+// convert an offset-corrected conversion, or a fraction of one in 1/len steps, to microvolts
+// at the ADC
+fn raw_to_microvolts(raw: u64, len: u64) -> i64 {
+    (raw * calibration::nanovolts_per_lsb(0) as u64 / len / 1000) as i64
+}
+
+// convert an offset-corrected conversion to millivolts at the input
+fn raw_to_millivolts(raw: u16) -> i32 {
+    (input::microvolts(raw_to_microvolts(raw as u64, 1)) / 1000) as i32
+}
+
+/// Returns the integer square root of `n`, rounded down.
+pub fn isqrt(n: u64) -> u64 {
+    // Newton's method, starting from above the root
+    if n < 2 {
+        return n;
+    }
+    let mut x = n;
+    let mut y = (x + 1) / 2;
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+    x
+}
+
+/// Returns the base 2 logarithm of `n`, which must be nonzero, in 65536ths.
+pub fn log2_q16(n: u64) -> i32 {
+    let whole = 63 - n.leading_zeros() as i32;
+    // normalize to between 1 and 2, with 30 fraction bits, then square it for each bit of the
+    // logarithm's fraction, halving it when it reaches 2
+    let mut x = if whole > 30 { n >> (whole - 30) } else { n << (30 - whole) };
+    let mut fraction = 0;
+    for bit in (0..16).rev() {
+        x = (x * x) >> 30;
+        if x >= 2 << 30 {
+            x >>= 1;
+            fraction |= 1 << bit;
+        }
+    }
+    (whole << 16) | fraction
+}
+
+/// Measures a record captured at `sample_rate`.
+pub fn measure(data: &[u16], sample_rate: u32) -> Option<Results> {
+    if data.is_empty() {
+        return None;
+    }
+    let mut min = 4095;
+    let mut max = 0;
+    let mut sum = 0u64;
+    let mut sum_of_squares = 0u64;
+    let mut clipped = 0u64;
+    for &sample in data {
+        if is_clipped(sample) {
+            clipped += 1;
+        }
+        let sample = calibration::correct(sample);
+        if sample < min { min = sample; }
+        if sample > max { max = sample; }
+        sum += sample as u64;
+        sum_of_squares += sample as u64 * sample as u64;
+    }
+    let len = data.len() as u64;
+
+    // find the first and last rising crossings of the midpoint, counting the cycles and the
+    // samples spent high in between
+    let middle = ((min as u32 + max as u32) / 2) as u16;
+    let high_threshold = middle.saturating_add(HYSTERESIS / 2);
+    let low_threshold = middle.saturating_sub(HYSTERESIS / 2);
+    let mut high: Option<bool> = None;
+    let mut first: Option<usize> = None;
+    let mut last = 0;
+    let mut cycles = 0u64;
+    let mut high_samples = 0u64;
+    let mut high_samples_at_last = 0u64;
+    for (i, &sample) in data.iter().enumerate() {
+        let sample = calibration::correct(sample);
+        if sample >= high_threshold {
+            if high == Some(false) {
+                if first.is_none() {
+                    first = Some(i);
+                } else {
+                    cycles += 1;
+                }
+                last = i;
+                high_samples_at_last = high_samples;
+            }
+            high = Some(true);
+        } else if sample <= low_threshold {
+            high = Some(false);
+        }
+        if first.is_some() && high == Some(true) {
+            high_samples += 1;
+        }
+    }
+    let (frequency_mhz, duty_percent) = match first {
+        Some(first) if cycles > 0 && max - min > HYSTERESIS => {
+            let span = (last - first) as u64;
+            (Some(sample_rate as u64 * 1000 * cycles / span),
+             Some((high_samples_at_last * 100 / span) as u32))
+        }
+        _ => (None, None),
+    };
+
+    // the mean, and the standard deviation times len, sqrt(len * sum of squares - sum^2)
+    let mean_uv = input::microvolts(raw_to_microvolts(sum, len));
+    let deviation_uv = input::scale(raw_to_microvolts(isqrt(sum_of_squares * len - sum * sum),
+                                                      len));
+    let rms_uv = isqrt((mean_uv * mean_uv + deviation_uv * deviation_uv) as u64);
+
+    Some(Results {
+        min: raw_to_millivolts(min),
+        max: raw_to_millivolts(max),
+        mean: (mean_uv / 1000) as i32,
+        rms_uv: rms_uv,
+        frequency_mhz: frequency_mhz,
+        duty_percent: duty_percent,
+        clipped_permille: ((clipped * 1000 + len / 2) / len) as u32,
+    })
+}
+
+// format millivolts in volts, with as many decimals as fit, from "d.dd" to "ddd", or for a
+// negative voltage, "-d.d" to "-dd"
+fn format_millivolts(mv: i32, buf: &mut [u8; 4]) {
+    let magnitude = (mv as i64).abs() as u64;
+    let width = if mv < 0 { 3 } else { 4 }; // leaving room for the sign
+    let mut volts_digits = 1;
+    while volts_digits < width && magnitude / 1000 >= [1, 10, 100, 1000][volts_digits] {
+        volts_digits += 1;
+    }
+    let decimals = if width - 1 > volts_digits { width - 1 - volts_digits } else { 0 };
+    let decimals = if decimals > 2 { 2 } else { decimals };
+    let mut value = magnitude;
+    for _ in decimals..3 {
+        value /= 10;
+    }
+    let most = [0, 9, 99, 999, 9999][if decimals > 0 { width - 1 } else { width }];
+    let value = if value > most { most } else { value };
+    format_signed_fixed(if mv < 0 { -(value as i64) } else { value as i64 }, decimals,
+                        &mut buf[..]);
+}
+
+/// Formats `value`, scaled by 10^decimals, right-aligned in `buf`, with leading spaces.
+pub fn format_fixed(value: u64, decimals: usize, buf: &mut [u8]) {
+    let mut value = value;
+    let mut digits = 0;
+    let mut point = false;
+    let mut i = buf.len();
+    while i > 0 {
+        i -= 1;
+        if decimals > 0 && digits == decimals && !point {
+            buf[i] = b'.';
+            point = true;
+            continue;
+        }
+        buf[i] = if value > 0 || digits <= decimals { b'0' + (value % 10) as u8 } else { b' ' };
+        value /= 10;
+        digits += 1;
+    }
+}
+
+/// Formats `value`, scaled by 10^decimals, right-aligned in `buf` as format_fixed(), with a '-'
+/// before the digits if it is negative.
+pub fn format_signed_fixed(value: i64, decimals: usize, buf: &mut [u8]) {
+    format_fixed(value.abs() as u64, decimals, buf);
+    if value < 0 {
+        if let Some(first) = buf.iter().position(|&c| c != b' ') {
+            if first > 0 {
+                buf[first - 1] = b'-';
+            }
+        }
+    }
+}
+
+/// Formats `nano`, a value in billionths of `unit`, to three significant figures with any
+/// trailing zeros dropped and an SI prefix, e.g. "2ms", "-6.4mV", or "125uV", returning the
+/// length. `text` needs room for seven characters and the unit.
+pub fn format_si(nano: i64, unit: &[u8], text: &mut [u8]) -> usize {
+    let magnitude = nano.abs() as u64;
+    let (scale, prefix): (u64, &[u8]) = match magnitude {
+        0 => (1_000_000_000, b""), // "0V", rather than "0nV"
+        1...999 => (1, b"n"),
+        1_000...999_999 => (1_000, b"u"),
+        1_000_000...999_999_999 => (1_000_000, b"m"),
+        1_000_000_000...999_999_999_999 => (1_000_000_000, b""),
+        _ => (1_000_000_000_000, b"k"),
+    };
+    // hundredths of the prefixed unit, rounded to three figures
+    let hundredths = (magnitude * 100 + scale / 2) / scale;
+    let (mut value, mut decimals) = match hundredths {
+        0...999 => (hundredths, 2),
+        1_000...9_999 => ((hundredths + 5) / 10, 1),
+        _ => ((hundredths + 50) / 100, 0),
+    };
+    while decimals > 0 && value % 10 == 0 {
+        value /= 10;
+        decimals -= 1;
+    }
+    let mut len = 0;
+    if nano < 0 {
+        text[0] = b'-';
+        len = 1;
+    }
+    let mut digits = 1;
+    let mut n = value / 10;
+    while n > 0 {
+        digits += 1;
+        n /= 10;
+    }
+    let width = digits + if decimals > 0 { 1 } else { 0 };
+    format_fixed(value, decimals, &mut text[len..len + width]);
+    len += width;
+    for &c in prefix.iter().chain(unit) {
+        text[len] = c;
+        len += 1;
+    }
+    len
+}
+
+// format a frequency in millihertz in four characters, e.g. "3.33", "33.3", " 333", "3.3k",
+// " 33k", "333k", or "3.3M"
+fn format_frequency(mhz: u64, buf: &mut [u8; 4]) {
+    let (value, decimals, suffix) = if mhz < 10_000 {
+        (mhz / 10, 2, None)
+    } else if mhz < 100_000 {
+        (mhz / 100, 1, None)
+    } else if mhz < 1_000_000 {
+        (mhz / 1000, 0, None)
+    } else if mhz < 10_000_000 {
+        (mhz / 100_000, 1, Some(b'k'))
+    } else if mhz < 1_000_000_000 {
+        (mhz / 1_000_000, 0, Some(b'k'))
+    } else {
+        (mhz / 100_000_000, 1, Some(b'M'))
+    };
+    match suffix {
+        Some(suffix) => {
+            buf[3] = suffix;
+            format_fixed(value, decimals, &mut buf[..3]);
+        }
+        None => format_fixed(value, decimals, &mut buf[..]),
+    }
+}
+
+// format a percentage as "ddd%"
+fn format_percent(percent: u32, buf: &mut [u8; 4]) {
+    let percent = if percent > 100 { 100 } else { percent };
+    buf[0] = if percent >= 100 { b'1' } else { b' ' };
+    buf[1] = if percent >= 10 { b'0' + (percent / 10 % 10) as u8 } else { b' ' };
+    buf[2] = b'0' + (percent % 10) as u8;
+    buf[3] = b'%';
+}
+
+// format tenths of a percent as "d.d%" below 10%, otherwise as "ddd%"
+fn format_permille(permille: u32, buf: &mut [u8; 4]) {
+    if permille < 100 {
+        format_fixed(permille as u64, 1, &mut buf[..3]);
+        buf[3] = b'%';
+    } else {
+        format_percent((permille + 5) / 10, buf);
+    }
+}
+
+// format a measurement in `buf`, leaving it as "----" if it isn't possible
+fn format(measurement: Measurement, results: &Results, buf: &mut [u8; 4]) {
+    let auxiliary = capture::auxiliary();
+    match measurement {
+        Measurement::Off => {}
+        Measurement::Frequency => {
+            if let Some(mhz) = results.frequency_mhz { format_frequency(mhz, buf); }
+        }
+        Measurement::PeakToPeak => format_millivolts(results.max - results.min, buf),
+        Measurement::Rms => format_amplitude_slot(results.rms_uv, buf),
+        Measurement::Mean => format_millivolts(results.mean, buf),
+        Measurement::Duty => {
+            if let Some(percent) = results.duty_percent { format_percent(percent, buf); }
+        }
+        Measurement::Min => format_millivolts(results.min, buf),
+        Measurement::Max => format_millivolts(results.max, buf),
+        Measurement::Supply => format_millivolts(auxiliary.supply_mv as i32, buf),
+        Measurement::AuxInput => format_millivolts(auxiliary.input_mv as i32, buf),
+        Measurement::Temperature => {
+            let tenths = if auxiliary.temperature < 0 { 0 } else { auxiliary.temperature };
+            format_fixed(tenths as u64, 1, &mut buf[..]);
+        }
+        Measurement::Clipped => format_permille(results.clipped_permille, buf),
+        Measurement::TriggerRate => {
+            if let Some(mhz) = trigrate::millihz() { format_frequency(mhz, buf); }
+        }
+        Measurement::Phase => match phase::phase() {
+            Some(Phase::Degrees(millidegrees)) => {
+                let half = if millidegrees < 0 { -500 } else { 500 };
+                format_signed_fixed((millidegrees + half) as i64 / 1000, 0, &mut buf[..]);
+            }
+            Some(Phase::PeriodMismatch) => buf.copy_from_slice(b"f<>f"),
+            None => {}
+        },
+    }
+}
+
+/// Draws the selected measurements in the readout row.
+pub fn draw(results: &Results) {
+    let per_row = slots_per_row();
+    for i in 0..SLOTS {
+        let measurement = slot(i);
+        if measurement == Measurement::Off {
+            continue;
+        }
+        let mut buf = [b'-'; 4];
+        format(measurement, results, &mut buf);
+        let x = (i % per_row) as u8 * SLOT_WIDTH;
+        let y = ROW_Y + 12 * (i / per_row) as u8;
+        st7735_print(&[measurement.letter()], x, y, St7735Color::Blue, St7735Color::Black);
+        st7735_print(&buf, x + 8, y, St7735Color::Green, St7735Color::Black);
+    }
+}
+
+/// Draws the meter display, the mean voltage and frequency in large digits.
+pub fn draw_meter(results: &Results) {
+    let mut buf = [b'-'; 4];
+    format(Measurement::Mean, results, &mut buf);
+    let x = segment::draw(&buf, VOLTS_X, VOLTS_Y, VOLTS_HEIGHT, St7735Color::Green,
+                          St7735Color::Black);
+    st7735_print(b"V", (x + 2) as u8, (VOLTS_Y + VOLTS_HEIGHT - 12) as u8, St7735Color::Green,
+                 St7735Color::Black);
+
+    let mut buf = [b'-'; 4];
+    format(Measurement::Frequency, results, &mut buf);
+    // a k or M suffix is printed with the units, rather than as a digit
+    let (digits, units): (&[u8], &[u8]) = match buf[3] {
+        b'k' => (&buf[..3], b"kHz"),
+        b'M' => (&buf[..3], b"MHz"),
+        _ => (&buf[..], b"Hz "),
+    };
+    let x = segment::draw(digits, FREQUENCY_X, FREQUENCY_Y, FREQUENCY_HEIGHT,
+                          St7735Color::Green, St7735Color::Black);
+    // the number of digit cells varies with the decimal point, so clear up to the units
+    let units_x = FREQUENCY_X + 4 * segment::cell_width(FREQUENCY_HEIGHT);
+    if x < units_x {
+        st7735_fill_rect(x, FREQUENCY_Y, units_x - x, FREQUENCY_HEIGHT,
+                         St7735Color::Black as u16);
+    }
+    st7735_print(units, (units_x + 2) as u8, (FREQUENCY_Y + FREQUENCY_HEIGHT - 12) as u8,
+                 St7735Color::Green, St7735Color::Black);
+}
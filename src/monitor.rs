@@ -0,0 +1,92 @@
+// stm32f3-oscilloscope - src/monitor.rs
+// a corner widget showing the DC input's voltage, whatever is displayed
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The DC monitor watches a supply rail, or any slow voltage, on the auxiliary DC input while
+// the trace area shows something else: when it is on, the input's pin and voltage are drawn in
+// the bottom right corner of the trace area every DRAW_MS, over whatever is there, in any
+// display mode but the help screen. The input is converted with the injected conversions, as
+// for the "DC in" measurement (see capture.rs), so the voltage is at the pin, between ground
+// and the ADC reference; a rail above that needs a divider. The pin is one of the board's
+// DC_INPUTS, and selecting another moves the "DC in" measurement with it.
+
+use board::DC_INPUTS;
+use capture;
+use delay::Deadline;
+use font::FONT_5X7;
+use measure;
+use screen;
+use st7735::*;
+
+const DRAW_MS: u32 = 250;
+const WIDTH: usize = 11; // characters: "PC0  3.281V"
+const CHAR_WIDTH: i16 = 6;
+const HEIGHT: i16 = 9; // the font height, plus a pixel either side
+
+static mut ENABLED: bool = false;
+static mut NEXT: Option<Deadline> = None; // when the widget is next drawn
+
+/// Turns the widget on, showing DC_INPUTS[input], or off.
+pub fn set(input: Option<usize>) {
+    match input {
+        Some(i) => capture::set_dc_input(i),
+        None if enabled() => {
+            fill(St7735Color::Black);
+        }
+        None => {}
+    }
+    unsafe {
+        ENABLED = input.is_some();
+        NEXT = Some(Deadline::after_ms(0));
+    }
+}
+
+pub fn enabled() -> bool {
+    unsafe { ENABLED }
+}
+
+/// Draws the widget, if it is on and due.
+pub fn poll() {
+    let due = unsafe { NEXT.map_or(false, |deadline| deadline.passed()) };
+    if !enabled() || !due {
+        return;
+    }
+    unsafe { NEXT = Some(Deadline::after_ms(DRAW_MS)); }
+    let mut text = [b' '; WIDTH];
+    let label = DC_INPUTS[capture::dc_input()].label;
+    text[..label.len()].copy_from_slice(label);
+    let mv = capture::auxiliary().input_mv as u64;
+    measure::format_fixed(mv, 3, &mut text[WIDTH - 6..WIDTH - 1]);
+    text[WIDTH - 1] = b'V';
+    let (x, y) = fill(St7735Color::Blue);
+    FONT_5X7.draw(&text, x as u8, (y + 1) as u8, St7735Color::White, St7735Color::Blue);
+}
+
+// fill the widget's box in `color`, returning where its text goes
+fn fill(color: St7735Color) -> (i16, i16) {
+    let x = screen::width() - WIDTH as i16 * CHAR_WIDTH - 1;
+    let y = screen::trace_height() - HEIGHT;
+    st7735_fill_rect(x - 1, y, WIDTH as i16 * CHAR_WIDTH + 2, HEIGHT, color as u16);
+    (x, y)
+}
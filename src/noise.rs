@@ -0,0 +1,200 @@
+// stm32f3-oscilloscope - src/noise.rs
+// the noise floor: RMS and peak-to-peak noise, and the effective number of bits
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The noise display is for measuring the scope's own noise, or a source's, with the input
+// grounded or terminated: it pools every sample of every sweep since it was entered or reset,
+// up to MAX_SAMPLES, and shows their standard deviation, the RMS noise, their peak-to-peak
+// range, and their mean, the offset. Pooling many sweeps averages out the spread of a single
+// sweep's statistics, so the readings settle, and slow drift shows up in the RMS noise as it
+// would in a long record. Any setting change starts again, as does button 2.
+//
+// The effective number of bits is that of an ideal 12-bit ADC whose quantization noise, of
+// 1/sqrt(12) LSB RMS, equals the measured noise: 12 - log2(sqrt(12) x RMS noise in LSB), so
+// a perfectly quiet input, always the same code, reads 12 bits. The noise is also shown in
+// LSB, and the voltages at the input (see input.rs).
+//
+// The samples are summed as their differences from the first, so the sums of squares stay
+// small for a quiet input, and fit in 64 bits for MAX_SAMPLES of any signal.
+
+use calibration;
+use font::FONT_5X7;
+use input;
+use measure;
+use screen;
+use segment;
+use st7735::*;
+use time;
+
+// the most samples pooled, after which the readings hold
+const MAX_SAMPLES: u64 = 1 << 22;
+// how often the readings are drawn, in milliseconds
+const DRAW_MS: u32 = 400;
+// log2(sqrt(12)), in 65536ths
+const LOG2_ROOT_12: i32 = 117_473;
+
+// the layout: the RMS noise large, then the peak-to-peak and ENOB, then the details
+const LABEL_X: u8 = 0;
+const DIGITS_X: i16 = 32;
+const ROWS: [(&'static [u8], i16, i16); 3] = [ // (label, y, digit height)
+    (b"RMS", 2, 30),
+    (b"p-p", 38, 20),
+    (b"ENOB", 64, 20),
+];
+const DETAILS_Y: u8 = 92; // two lines in the small font
+
+static mut REFERENCE: Option<u16> = None; // the first sample
+static mut COUNT: u64 = 0;
+static mut SUM: i64 = 0; // of the differences from REFERENCE
+static mut SUM_OF_SQUARES: u64 = 0;
+static mut MIN: u16 = 4095;
+static mut MAX: u16 = 0;
+static mut SWEEPS: u32 = 0;
+static mut DRAWN_AT: u32 = 0;
+
+/// Starts again, and clears the trace area.
+pub fn reset() {
+    unsafe {
+        REFERENCE = None;
+        COUNT = 0;
+        SUM = 0;
+        SUM_OF_SQUARES = 0;
+        MIN = 4095;
+        MAX = 0;
+        SWEEPS = 0;
+        DRAWN_AT = time::millis();
+    }
+    screen::clear_trace_area();
+    FONT_5X7.draw(b"ground the input", LABEL_X, DETAILS_Y, St7735Color::White,
+                  St7735Color::Black);
+}
+
+/// Adds a record's samples, then draws the readings when they are due.
+pub fn update(record: &[u16]) {
+    unsafe {
+        if COUNT < MAX_SAMPLES && !record.is_empty() {
+            if REFERENCE.is_none() {
+                REFERENCE = Some(record[0]);
+            }
+            let reference = REFERENCE.unwrap_or(0) as i64;
+            for &sample in record {
+                if COUNT == MAX_SAMPLES {
+                    break;
+                }
+                let difference = sample as i64 - reference;
+                SUM += difference;
+                SUM_OF_SQUARES += (difference * difference) as u64;
+                if sample < MIN { MIN = sample; }
+                if sample > MAX { MAX = sample; }
+                COUNT += 1;
+            }
+            SWEEPS += 1;
+        }
+        if time::since(DRAWN_AT) >= DRAW_MS && COUNT > 0 {
+            DRAWN_AT = time::millis();
+            draw();
+        }
+    }
+}
+
+// microvolts at the input for `lsb` ADC steps, in 256ths
+fn microvolts(lsb_256: u64) -> u64 {
+    let adc_uv = lsb_256 * calibration::nanovolts_per_lsb(0) as u64 / 256 / 1000;
+    input::scale(adc_uv as i64).abs() as u64
+}
+
+// draw a voltage of `uv` microvolts in large digits, in microvolts to a tenth below 1mV, and
+// in millivolts above
+fn draw_voltage(uv: u64, y: i16, height: i16) {
+    let mut buf = [b' '; 5];
+    let unit: &[u8] = if uv < 1000 {
+        measure::format_fixed(uv * 10, 1, &mut buf);
+        b"uV"
+    } else if uv < 100_000 {
+        measure::format_fixed(uv / 10, 2, &mut buf);
+        b"mV"
+    } else {
+        let mv = uv / 1000;
+        measure::format_fixed(if mv > 9999 { 9999 } else { mv }, 0, &mut buf[..4]);
+        b"mV"
+    };
+    let x = segment::draw(&buf, DIGITS_X, y, height, St7735Color::Green, St7735Color::Black);
+    st7735_print(unit, (x + 2) as u8, (y + height - 12) as u8, St7735Color::Green,
+                 St7735Color::Black);
+}
+
+fn draw() {
+    let (count, sum, sum_of_squares, min, max, sweeps, reference) = unsafe {
+        (COUNT, SUM, SUM_OF_SQUARES, MIN, MAX, SWEEPS, REFERENCE.unwrap_or(0))
+    };
+    for &(label, y, height) in ROWS.iter() {
+        st7735_print(label, LABEL_X, (y + height - 12) as u8, St7735Color::Blue,
+                     St7735Color::Black);
+    }
+
+    // the variance in 65536ths of an LSB squared, and the mean in 256ths of an LSB, from the
+    // differences
+    let mean_256 = sum * 256 / count as i64;
+    let variance = (sum_of_squares * 65536 / count) as i64 - mean_256 * mean_256;
+    let rms_256 = measure::isqrt(if variance < 0 { 0 } else { variance as u64 });
+    draw_voltage(microvolts(rms_256), ROWS[0].1, ROWS[0].2);
+    draw_voltage(microvolts((max - min) as u64 * 256), ROWS[1].1, ROWS[1].2);
+
+    // 12 - log2(sqrt(12) x RMS), in hundredths of a bit
+    let enob_q16 = if rms_256 == 0 {
+        12 << 16
+    } else {
+        let q16 = (12 << 16) - (measure::log2_q16(rms_256) - (8 << 16)) - LOG2_ROOT_12;
+        if q16 > 12 << 16 { 12 << 16 } else if q16 < 0 { 0 } else { q16 }
+    };
+    let mut buf = [b' '; 5];
+    measure::format_fixed(enob_q16 as u64 * 100 / 65536, 2, &mut buf);
+    let (y, height) = (ROWS[2].1, ROWS[2].2);
+    let x = segment::draw(&buf, DIGITS_X, y, height, St7735Color::Green, St7735Color::Black);
+    st7735_print(b"bits", (x + 2) as u8, (y + height - 12) as u8, St7735Color::Green,
+                 St7735Color::Black);
+
+    // "0.41 LSB RMS, 3 p-p", then "mean 2047.6, 1.2M in 15"
+    let mut line = [b' '; 26];
+    measure::format_fixed(rms_256 * 100 / 256, 2, &mut line[..6]);
+    line[6..15].copy_from_slice(b" LSB RMS,");
+    measure::format_fixed((max - min) as u64, 0, &mut line[15..20]);
+    line[20..24].copy_from_slice(b" p-p");
+    FONT_5X7.draw(&line, LABEL_X, DETAILS_Y, St7735Color::White, St7735Color::Black);
+    let mut line = [b' '; 26];
+    line[..5].copy_from_slice(b"mean ");
+    let mean_10 = ((reference as i64 * 256 + mean_256) * 10 / 256) as u64;
+    measure::format_fixed(mean_10, 1, &mut line[5..11]);
+    line[11] = b',';
+    let (samples, decimals, suffix) = if count >= 1_000_000 {
+        (count / 100_000, 1, b'M')
+    } else {
+        (count / 100, 1, b'k')
+    };
+    measure::format_fixed(samples, decimals, &mut line[12..18]);
+    line[18] = suffix;
+    line[19..22].copy_from_slice(b" in");
+    measure::format_fixed(sweeps as u64, 0, &mut line[22..26]);
+    FONT_5X7.draw(&line, LABEL_X, DETAILS_Y + 10, St7735Color::White, St7735Color::Black);
+}
@@ -21,10 +21,7 @@
 #include "_rust.h"
 #include "ST7735.h"
 
-static uint16_t _width = ST7735_TFTWIDTH;
-static uint16_t _height = ST7735_TFTHEIGHT;
-static int colstart = 0;
-static int rowstart = 0; // May be overridden in init func
+// The screen size, rotation, and window offsets are handled in Rust, in st7735.rs.
 
 // Rather than a bazillion st7735_send_cmd() and st7735_send_data() calls, screen
 // initialization commands and arguments are organized in these tables
@@ -222,10 +219,7 @@ void _st7735_initR(uint8_t options) {
     commonInit(Rcmd1);
     if(options == INITR_GREENTAB) {
         commandList(Rcmd2green);
-        colstart = 2;
-        rowstart = 1;
     } else {
-        // colstart, rowstart left at default '0' values
         commandList(Rcmd2red);
     }
     commandList(Rcmd3);
@@ -237,108 +231,3 @@ void _st7735_initR(uint8_t options) {
     }
 }
 
-
-void _st7735_setAddrWindow(uint8_t x0, uint8_t y0, uint8_t x1, uint8_t y1) {
-    st7735_send_cmd(ST7735_CASET);          // Column addr set
-    st7735_send_data(0x00);
-    st7735_send_data(x0+colstart);     // XSTART
-    st7735_send_data(0x00);
-    st7735_send_data(x1+colstart);     // XEND
-
-    st7735_send_cmd(ST7735_RASET); // Row addr set
-    st7735_send_data(0x00);
-    st7735_send_data(y0+rowstart);     // YSTART
-    st7735_send_data(0x00);
-    st7735_send_data(y1+rowstart);     // YEND
-
-    st7735_send_cmd(ST7735_RAMWR); // write to RAM
-}
-
-static void _st7735_pushColor(uint16_t color) {
-    st7735_send_data(color >> 8);
-    st7735_send_data(color & 0xff);
-}
-
-// draw color pixel on screen
-void _st7735_drawPixel(int16_t x, int16_t y, uint16_t color) {
-
-    if((x < 0) ||(x >= _width) || (y < 0) || (y >= _height)) return;
-
-    _st7735_setAddrWindow(x,y,x+1,y+1);
-    _st7735_pushColor(color);
-}
-
-void _st7735_drawFastVLine(int16_t x, int16_t y, int16_t h, uint16_t color) {
-    // Rudimentary clipping
-    if((x >= _width) || (y >= _height)) return;
-    if((y+h-1) >= _height) h = _height-y;
-    _st7735_setAddrWindow(x, y, x, y+h-1);
-    while (h--) {
-        _st7735_pushColor(color);
-    }
-}
-
-void _st7735_drawFastHLine(int16_t x, int16_t y, int16_t w, uint16_t color) {
-    // Rudimentary clipping
-    if((x >= _width) || (y >= _height)) return;
-    if((x+w-1) >= _width)  w = _width-x;
-    _st7735_setAddrWindow(x, y, x+w-1, y);
-    while (w--) {
-        _st7735_pushColor(color);
-    }
-}
-
-void _st7735_fillScreen(uint16_t color) {
-    st7735_fill_rect(0, 0, _width, _height, color);
-}
-
-// // Pass 8-bit (each) R,G,B, get back 16-bit packed color
-// uint16_t st7735_Color565(uint8_t r, uint8_t g, uint8_t b) {
-//         return ((r & 0xF8) << 8) | ((g & 0xFC) << 3) | (b >> 3);
-// }
-
-#define MADCTL_MY  0x80
-#define MADCTL_MX  0x40
-#define MADCTL_MV  0x20
-#define MADCTL_ML  0x10
-#define MADCTL_RGB 0x00
-#define MADCTL_BGR 0x08
-#define MADCTL_MH  0x04
-
-void _st7735_setRotation(uint8_t m) {
-        uint8_t rotation = m % 4; // can't be higher than 3
-
-        st7735_send_cmd(ST7735_MADCTL);
-        switch (rotation) {
-   case 0: // portrait
-           st7735_send_data(MADCTL_MX | MADCTL_MY | MADCTL_RGB);
-           _width  = ST7735_TFTWIDTH;
-           _height = ST7735_TFTHEIGHT;
-           break;
-   case 1: // landscape
-           st7735_send_data(MADCTL_MY | MADCTL_MV | MADCTL_RGB);
-           _width  = ST7735_TFTHEIGHT;
-           _height = ST7735_TFTWIDTH;
-           break;
-   case 2: // portrait, inverted
-           st7735_send_data(MADCTL_RGB);
-           _width  = ST7735_TFTWIDTH;
-           _height = ST7735_TFTHEIGHT;
-           break;
-   case 3: // landscape, inverted
-           st7735_send_data(MADCTL_MX | MADCTL_MV | MADCTL_RGB);
-           _width  = ST7735_TFTHEIGHT;
-           _height = ST7735_TFTWIDTH;
-           break;
-   default:
-           return;
-        }
-}
-
-uint8_t _st7735_get_height(void) {
-    return _height;
-}
-
-uint8_t _st7735_get_width(void) {
-    return _width;
-}
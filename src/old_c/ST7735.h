@@ -89,13 +89,7 @@ as well as Adafruit raw 1.8" TFT display
 // extern void st7735_initB(void);
 extern void _st7735_initR(uint8_t options);
 
-extern void _st7735_setAddrWindow(uint8_t x0, uint8_t y0, uint8_t x1, uint8_t y1);
 
 // extern uint16_t st7735_Color565(uint8_t r, uint8_t g, uint8_t b);
-extern void _st7735_drawFastHLine(int16_t x, int16_t y, int16_t w, uint16_t color);
-extern void _st7735_drawFastVLine(int16_t x, int16_t y, int16_t h, uint16_t color);
-extern void _st7735_drawPixel(int16_t x, int16_t y, uint16_t color);
-extern void _st7735_fillScreen(uint16_t color);
-extern void _st7735_setRotation(uint8_t m);
 
 #endif
@@ -0,0 +1,158 @@
+// stm32f3-oscilloscope - src/phase.rs
+// phase of CH2 relative to CH1, from their rising crossings
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Each channel's rising crossings of its own midpoint, between its minimum and maximum, are
+// found to a 256th of a sample by interpolating between the samples either side, with the
+// same hysteresis as the frequency measurement (measure.rs) so noise near the midpoint isn't
+// counted as crossings. Each channel's period is the mean spacing of its crossings, so each
+// needs at least two in the record.
+//
+// The phase only means something if the two channels have the same frequency, so the periods
+// must match to within PERIOD_TOLERANCE_PERMILLE; otherwise the readout shows "f<>f" rather
+// than a phase. Then for each CH1 crossing, the delay to the next CH2 crossing is found, and
+// the delays, unwrapped so that they don't jump by a period around 0 and 360 degrees, are
+// averaged. CH2 lagging CH1 is a negative phase, as for a low-pass filter's output against its
+// input, and the phase is given from -180 to +180 degrees.
+//
+// CH2 is only sampled with the loopback trace shown (see capture.rs), over the first part of
+// each sweep, so the phase is only measured then, over the part of the record both cover.
+
+// hysteresis around the midpoint, as for the frequency measurement
+const HYSTERESIS: u16 = 32;
+// the crossings kept from each channel
+const MAX_CROSSINGS: usize = 64;
+// how closely the periods must match, in thousandths
+const PERIOD_TOLERANCE_PERMILLE: u64 = 20;
+
+/// The outcome of a phase measurement.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Phase {
+    Degrees(i32),   // thousandths of a degree, -180000 to 180000
+    PeriodMismatch, // the channels' frequencies differ
+}
+
+static mut PHASE: Option<Phase> = None;
+
+// the rising crossings of `data`'s midpoint, in 256ths of a sample, returning how many
+fn crossings(data: &[u16], times: &mut [u32; MAX_CROSSINGS]) -> usize {
+    let (mut min, mut max) = (4095, 0);
+    for &sample in data {
+        if sample < min { min = sample; }
+        if sample > max { max = sample; }
+    }
+    if max - min <= HYSTERESIS {
+        return 0;
+    }
+    let middle = ((min as u32 + max as u32) / 2) as u16;
+    let low_threshold = middle - HYSTERESIS / 2;
+    let mut armed = false;
+    let mut n = 0;
+    for i in 1..data.len() {
+        let (before, sample) = (data[i - 1], data[i]);
+        if sample <= low_threshold {
+            armed = true;
+        } else if armed && before < middle && sample >= middle {
+            // the fraction of the sample interval before the midpoint is reached
+            let fraction = (middle - before) as u32 * 256 / (sample - before) as u32;
+            times[n] = (i as u32 - 1) * 256 + fraction;
+            n += 1;
+            if n == MAX_CROSSINGS {
+                break;
+            }
+            armed = false;
+        }
+    }
+    n
+}
+
+/// Measures the phase of `ch2` relative to `ch1`, sampled together, over the samples both
+/// have.
+pub fn measure(ch1: &[u16], ch2: &[u16]) {
+    let len = if ch1.len() < ch2.len() { ch1.len() } else { ch2.len() };
+    let (mut times1, mut times2) = ([0u32; MAX_CROSSINGS], [0u32; MAX_CROSSINGS]);
+    let n1 = crossings(&ch1[..len], &mut times1);
+    let n2 = crossings(&ch2[..len], &mut times2);
+    if n1 < 2 || n2 < 2 {
+        unsafe { PHASE = None; }
+        return;
+    }
+    let period1 = (times1[n1 - 1] - times1[0]) as u64 / (n1 - 1) as u64;
+    let period2 = (times2[n2 - 1] - times2[0]) as u64 / (n2 - 1) as u64;
+    let difference = if period1 > period2 { period1 - period2 } else { period2 - period1 };
+    if period1 == 0 || difference * 1000 > period1 * PERIOD_TOLERANCE_PERMILLE {
+        unsafe { PHASE = Some(Phase::PeriodMismatch); }
+        return;
+    }
+
+    // the mean delay from each CH1 crossing to the next CH2 crossing, unwrapped
+    let period = period1 as i64;
+    let mut first: Option<i64> = None;
+    let (mut sum, mut count) = (0i64, 0i64);
+    let mut j = 0;
+    for &t1 in &times1[..n1] {
+        while j < n2 && times2[j] < t1 {
+            j += 1;
+        }
+        if j == n2 {
+            break;
+        }
+        let mut delay = (times2[j] - t1) as i64;
+        match first {
+            None => first = Some(delay),
+            Some(first) => {
+                if delay > first + period / 2 {
+                    delay -= period;
+                } else if delay < first - period / 2 {
+                    delay += period;
+                }
+            }
+        }
+        sum += delay;
+        count += 1;
+    }
+    if count == 0 {
+        unsafe { PHASE = None; }
+        return;
+    }
+    // a delay is a lag, a negative phase
+    let mut millidegrees = -(sum * 360_000 / count / period);
+    while millidegrees <= -180_000 {
+        millidegrees += 360_000;
+    }
+    while millidegrees > 180_000 {
+        millidegrees -= 360_000;
+    }
+    unsafe { PHASE = Some(Phase::Degrees(millidegrees as i32)); }
+}
+
+/// Forgets the phase, when CH2 isn't being sampled.
+pub fn clear() {
+    unsafe { PHASE = None; }
+}
+
+/// Returns the last phase measured, if the record had enough of both channels.
+pub fn phase() -> Option<Phase> {
+    unsafe { PHASE }
+}
@@ -0,0 +1,92 @@
+// stm32f3-oscilloscope - src/plotter.rs
+// plotter output: samples as decimal text, a line each, for the Arduino IDE's serial plotter
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Each line is one sample of CH1, in millivolts at the input (through the input mapping), or
+// with the second channel, that and the auxiliary DC input, separated by a tab, which is what
+// the Arduino serial plotter and similar tools chart with no setup. A header line names the
+// columns, which the plotter shows as its legend.
+//
+// A record is far more samples than a text link can carry at the rate they are captured, so
+// each record is thinned to POINTS samples, evenly spaced across it, and a record is sent only
+// every INTERVAL_MS at most: about 200 lines a second, well within 115200 baud, and slow enough
+// that the plotter's 500-point window shows a few records. Sending waits on the serial port,
+// as the other outputs do.
+//
+// CH1:         ch1_mv          CH1 and DC:  ch1_mv<tab>dc_mv
+//              1650                         1650<tab>2412
+
+use calibration;
+use capture;
+use delay::Deadline;
+use input;
+use serial;
+
+const POINTS: usize = 100; // samples sent from each record
+const INTERVAL_MS: u32 = 500;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Channels {
+    Off,
+    One, // CH1
+    Two, // CH1 and the auxiliary DC input
+}
+
+static mut CHANNELS: Channels = Channels::Off;
+static mut NEXT: Option<Deadline> = None; // when the next record is due
+
+/// Starts or stops the plotter output, starting with a header line.
+pub fn set_channels(channels: Channels) {
+    unsafe {
+        CHANNELS = channels;
+        NEXT = Some(Deadline::after_ms(0));
+    }
+    match channels {
+        Channels::Off => {}
+        Channels::One => serial::write(b"ch1_mv\r\n"),
+        Channels::Two => serial::write(b"ch1_mv\tdc_mv\r\n"),
+    }
+}
+
+/// Sends POINTS samples of `record`, if the next record is due.
+pub fn send(record: &[u16]) {
+    let channels = unsafe { CHANNELS };
+    let due = unsafe { NEXT.map_or(false, |deadline| deadline.passed()) };
+    if channels == Channels::Off || !due || record.is_empty() {
+        return;
+    }
+    unsafe { NEXT = Some(Deadline::after_ms(INTERVAL_MS)); }
+    let dc_mv = capture::auxiliary().input_mv;
+    let points = if record.len() < POINTS { record.len() } else { POINTS };
+    for i in 0..points {
+        let sample = record[i * record.len() / points];
+        let uv = input::microvolts(calibration::microvolts(sample) as i64);
+        serial::write_signed_fixed(uv / 1000, 0);
+        if channels == Channels::Two {
+            serial::write_byte(b'\t');
+            serial::write_number(dc_mv);
+        }
+        serial::write(b"\r\n");
+    }
+}
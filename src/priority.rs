@@ -0,0 +1,83 @@
+// stm32f3-oscilloscope - src/priority.rs
+// interrupt priorities, in one place, and a measurement of the capture interrupt's latency
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The STM32F3 implements the top four bits of each priority, so priorities go in steps of 0x10,
+// and lower numbers preempt higher ones. From the most urgent:
+//   CAPTURE      the ADC's DMA channel and TIM15, which end and chain sweeps and announce
+//                samples; a late chain loses samples, and a late announcement skews the
+//                trigger time
+//   TRIGGER_OUT  TIM17, ending the trigger output pulse, which only needs to be roughly timed
+//   SERIAL       the USART receiver, which has a byte's time at 115200 baud, 87µs, to respond
+//   COUNTER      TIM1's capture and overflow, for the counter, which only has to take each
+//                capture before the next, and count each overflow within 0.9ms
+//   SYSTICK      the millisecond clock and the pushbuttons, which can wait out everything else
+// Anything new, USB or a touch controller, should go below CAPTURE, so it can't hold up a
+// sweep.
+//
+// The capture interrupt's latency can be measured: TIM15's counter starts from 0 at the
+// update event that raises the interrupt, so its count on entry to the handler is the time
+// taken to get there, in timer ticks. While measuring, the handler keeps the worst case, so
+// whatever else might delay it (critical sections, other interrupts, flash wait states) shows
+// up in the shell's `latency` report.
+
+use core::intrinsics::{volatile_load, volatile_store};
+
+pub const CAPTURE: u8 = 0x00;
+pub const TRIGGER_OUT: u8 = 0x40;
+pub const SERIAL: u8 = 0x80;
+pub const COUNTER: u8 = 0xa0;
+pub const SYSTICK: u8 = 0xf0;
+
+static mut MEASURING: bool = false;
+static mut WORST_NS: u32 = 0;
+
+/// Starts or stops measuring the capture interrupt's latency. Starting clears the worst case.
+pub fn set_measuring(measuring: bool) {
+    unsafe {
+        if measuring {
+            volatile_store(&mut WORST_NS, 0);
+        }
+        volatile_store(&mut MEASURING, measuring);
+    }
+}
+
+/// Returns whether the capture interrupt's latency is being measured.
+pub fn measuring() -> bool {
+    unsafe { volatile_load(&MEASURING) }
+}
+
+/// Counts a latency, in nanoseconds, measured by the capture interrupt handler.
+pub fn record_latency(ns: u32) {
+    unsafe {
+        if ns > volatile_load(&WORST_NS) {
+            volatile_store(&mut WORST_NS, ns);
+        }
+    }
+}
+
+/// Returns the worst latency of the capture interrupt since measuring started, in nanoseconds.
+pub fn worst_latency_ns() -> u32 {
+    unsafe { volatile_load(&WORST_NS) }
+}
@@ -0,0 +1,109 @@
+// stm32f3-oscilloscope - src/probe.rs
+// probe compensation output: a fixed 1kHz square wave from TIM16
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Like the calibrator on a bench scope, this gives a 10:1 probe a square wave to be adjusted
+// against, without tying up the signal generator. TIM16 toggles its channel 1 output on each
+// compare match, so the pin runs at half the timer's update rate, with an exact 50% duty cycle,
+// swinging between ground and the supply (about 3V on the Discovery, 3.3V on the Nucleo). The
+// pin is given by board::PROBE_COMP_PIN. The RC display (rc.rs) slows it down, to step a
+// capacitor through a resistor.
+
+use cortex_m;
+use stm32f30x::{RCC, TIM16};
+
+use board::{PROBE_COMP_AF, PROBE_COMP_LABEL, PROBE_COMP_PIN};
+use clocks;
+use gpio::Mode;
+use timer;
+
+pub const FREQUENCY_HZ: u32 = 1000;
+
+static mut ENABLED: bool = false;
+static mut FREQUENCY_MHZ: u32 = FREQUENCY_HZ * 1000;
+
+/// Sets up TIM16 and the output pin, and starts the output.
+pub fn setup() {
+    PROBE_COMP_PIN.enable_port_clock();
+    PROBE_COMP_PIN.set_alternate_function(PROBE_COMP_AF);
+    cortex_m::interrupt::free(|cs| {
+        let rcc = RCC.borrow(cs);
+        let tim16 = TIM16.borrow(cs);
+
+        rcc.apb2enr.modify(|_, w| w.tim16en().enabled());
+        tim16.cr1.write(|w| unsafe { w.arpe().bits(1) }); // ARR register is buffered
+        // channel 1 output, toggling on a match with CCR1 = 0, i.e. once each update
+        tim16.ccmr1_output.write(|w| unsafe { w.bits(0b011 << 4) }); // OC1M = toggle
+        tim16.ccr1.write(|w| unsafe { w.bits(0) });
+        tim16.ccer.write(|w| unsafe { w.bits(1 << 0) }); // CC1E, active high
+        tim16.bdtr.write(|w| unsafe { w.bits(1 << 15) }); // MOE: TIM16 has a break input
+    });
+    set_rate();
+    set_enabled(true);
+}
+
+/// Sets TIM16's rate from its clock, which changes if the HSE is lost.
+pub fn set_rate() {
+    // toggling twice a period, at least once a second
+    let toggle_hz = unsafe { FREQUENCY_MHZ } / 500;
+    let rate = timer::solve(clocks::apb2_timer_hz(), if toggle_hz == 0 { 1 } else { toggle_hz },
+                            timer::MAX_ARR_16);
+    cortex_m::interrupt::free(|cs| {
+        let tim16 = TIM16.borrow(cs);
+        tim16.arr.write(|w| unsafe { w.bits(rate.arr) });
+        tim16.psc.write(|w| unsafe { w.psc().bits(rate.psc) });
+        tim16.egr.write(|w| unsafe { w.ug().bits(1) }); // immediately update registers
+    });
+}
+
+/// Sets the output's frequency, in millihertz, FREQUENCY_HZ unless slowed down for the RC
+/// display.
+pub fn set_frequency_millihz(mhz: u32) {
+    unsafe { FREQUENCY_MHZ = mhz; }
+    set_rate();
+}
+
+/// Starts or stops the output. When stopped, the pin is held low.
+pub fn set_enabled(enabled: bool) {
+    cortex_m::interrupt::free(|cs| {
+        let tim16 = TIM16.borrow(cs);
+        tim16.cr1.modify(|_, w| unsafe { w.cen().bits(enabled as u8) });
+    });
+    if enabled {
+        PROBE_COMP_PIN.configure(Mode::Alternate);
+    } else {
+        PROBE_COMP_PIN.set_low();
+        PROBE_COMP_PIN.configure(Mode::Output);
+    }
+    unsafe { ENABLED = enabled; }
+}
+
+pub fn enabled() -> bool {
+    unsafe { ENABLED }
+}
+
+/// The menu value for the output: the frequency and pin, or "off".
+pub fn label() -> &'static [u8] {
+    if enabled() { PROBE_COMP_LABEL } else { b"off" }
+}
@@ -0,0 +1,110 @@
+// stm32f3-oscilloscope - src/profile.rs
+// a profiler: the time spent in each named part of the acquisition and display, in cycles
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Each scope is timed on the DWT cycle counter (see delay.rs), which costs a register read at
+// each end, so the scopes can stay in place in normal builds. For each scope, the number of
+// times it ran, the total cycles, and the longest run are kept, from startup or the last reset,
+// and the shell's `profile` reports the mean and longest time of each, in the serial reply and
+// in the console, so they can be read on the screen too.
+//
+// The cycle counter wraps every minute or so at 72MHz, so no one scope can be timed for longer
+// than that.
+
+use clocks;
+use console;
+use cortex_m;
+use delay;
+use measure::format_si;
+use shell;
+
+#[derive(Clone, Copy)]
+pub enum Scope {
+    CaptureInterrupt, // the DMA and TIM15 interrupt handlers, announcing samples
+    TriggerScan,      // searching the samples so far for the trigger event
+    Render,           // plotting a column of the traces
+    StatusFlush,      // sending status line changes to the LCD
+}
+
+const SCOPES: usize = 4;
+
+const LABELS: [&'static [u8]; SCOPES] = [b"isr", b"trigger", b"render", b"status"];
+
+#[derive(Clone, Copy)]
+struct Stats {
+    count: u32,
+    cycles: u64,
+    max: u32,
+}
+
+const ZERO: Stats = Stats { count: 0, cycles: 0, max: 0 };
+
+static mut STATS: [Stats; SCOPES] = [ZERO; SCOPES];
+
+/// Returns the cycle count at the start of a scope, to pass to `end`.
+pub fn start() -> u32 {
+    delay::cycles()
+}
+
+/// Counts the time since `start` against `scope`.
+pub fn end(scope: Scope, start: u32) {
+    let cycles = delay::cycles().wrapping_sub(start);
+    cortex_m::interrupt::free(|_| {
+        let stats = unsafe { &mut STATS[scope as usize] };
+        stats.count = stats.count.wrapping_add(1);
+        stats.cycles += cycles as u64;
+        if cycles > stats.max {
+            stats.max = cycles;
+        }
+    });
+}
+
+/// Clears the counts of every scope.
+pub fn reset() {
+    cortex_m::interrupt::free(|_| unsafe { STATS = [ZERO; SCOPES]; });
+}
+
+/// Sends a line for each scope which has run, with the mean and longest times, e.g.
+/// "render 12.5us max 40us", and logs it to the console.
+pub fn report() {
+    let stats = cortex_m::interrupt::free(|_| unsafe { STATS });
+    let cycles_per_us = (clocks::hclk_hz() / 1_000_000) as u64;
+    for (i, stats) in stats.iter().enumerate() {
+        if stats.count == 0 {
+            continue;
+        }
+        let mean_ns = stats.cycles * 1000 / cycles_per_us / stats.count as u64;
+        let max_ns = stats.max as u64 * 1000 / cycles_per_us;
+        let mut line = [b' '; 32];
+        let mut len = LABELS[i].len();
+        line[..len].copy_from_slice(LABELS[i]);
+        len += 1;
+        len += format_si(mean_ns as i64, b"s", &mut line[len..]);
+        line[len..len + 5].copy_from_slice(b" max ");
+        len += 5;
+        len += format_si(max_ns as i64, b"s", &mut line[len..]);
+        shell::reply(&line[..len]);
+        console::log(&line[..len]);
+    }
+}
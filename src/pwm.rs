@@ -0,0 +1,235 @@
+// stm32f3-oscilloscope - src/pwm.rs
+// PWM analyzer: frequency, duty cycle, and pulse widths, from TIM1 input capture or the record
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The sampled input, PC1, isn't a timer input, so for timer measurements the signal must also
+// be connected to board::PWM_INPUT_PIN, TIM1_CH1. TIM1 runs in PWM input mode: each rising edge
+// of TI1 captures the period into CCR1 and resets the counter, and each falling edge captures
+// the high time into CCR2. The prescaler is chosen from RANGES, moving to a slower range when
+// the counter overflows without an edge, and to a faster one when a period is too few ticks
+// to measure finely.
+//
+// Without a signal on the timer input, the readings are worked out from the record's
+// frequency and duty cycle measurements instead, which are only as fine as the sample rate.
+//
+// Frequencies are kept in millihertz, times in nanoseconds, and the duty cycle in thousandths
+// of a percent.
+
+use cortex_m;
+use stm32f30x::{RCC, TIM1};
+
+use board::{PWM_INPUT_AF, PWM_INPUT_PIN};
+use clocks;
+use gpio::{Mode, Pull};
+use measure::{self, Measurement, Results};
+use segment;
+use st7735::*;
+use time;
+
+// the prescalers, fastest first: 72MHz ticks for periods up to about 0.9ms, 1MHz ticks up to
+// 65ms, and 10kHz ticks up to 6.5s (at 72MHz)
+const RANGES: [u16; 3] = [0, 71, 7199];
+// a period shorter than this many ticks moves to a faster range
+const MIN_TICKS: u32 = 1000;
+// how long a timer reading lasts without a new edge, in milliseconds
+const TIMEOUT_MS: u32 = 7000;
+
+const SR_UIF: u32 = 1 << 0;
+const SR_CC1IF: u32 = 1 << 1;
+
+/// One set of readings.
+#[derive(Clone, Copy)]
+pub struct Reading {
+    pub frequency_mhz: u64,
+    pub duty: u32,    // thousandths of a percent
+    pub high_ns: u64, // +width
+    pub low_ns: u64,  // -width
+    pub from_timer: bool,
+}
+
+static mut RANGE: usize = 0;
+static mut LAST: Option<(Reading, u32)> = None; // the last timer reading, and when it was taken
+
+/// Sets up TIM1 for PWM input on board::PWM_INPUT_PIN.
+pub fn setup() {
+    PWM_INPUT_PIN.enable_port_clock();
+    PWM_INPUT_PIN.set_alternate_function(PWM_INPUT_AF);
+    PWM_INPUT_PIN.set_pull(Pull::Down);
+    PWM_INPUT_PIN.configure(Mode::Alternate);
+    cortex_m::interrupt::free(|cs| {
+        let rcc = RCC.borrow(cs);
+        let tim1 = TIM1.borrow(cs);
+
+        rcc.apb2enr.modify(|_, w| w.tim1en().enabled());
+        // IC1 is TI1, IC2 is also TI1
+        tim1.ccmr1_input.write(|w| unsafe { w.bits(0b10 << 8 | 0b01) }); // CC2S, CC1S
+        // IC1 on rising edges, IC2 on falling edges, both enabled
+        tim1.ccer.write(|w| unsafe { w.bits(1 << 5 | 1 << 4 | 1 << 0) }); // CC2P, CC2E, CC1E
+        // reset the counter on TI1FP1, each rising edge
+        tim1.smcr.write(|w| unsafe { w.bits(0b101 << 4 | 0b100) }); // TS, SMS
+        tim1.arr.write(|w| unsafe { w.bits(0xffff) });
+        tim1.psc.write(|w| unsafe { w.bits(RANGES[0] as u32) });
+        tim1.egr.write(|w| unsafe { w.ug().bits(1) }); // immediately update registers
+        // only overflows set UIF, not the resets on each edge
+        tim1.cr1.write(|w| unsafe { w.urs().bits(1).cen().bits(1) });
+    });
+}
+
+fn set_range(range: usize) {
+    unsafe { RANGE = range; }
+    cortex_m::interrupt::free(|cs| {
+        let tim1 = TIM1.borrow(cs);
+        tim1.psc.write(|w| unsafe { w.bits(RANGES[range] as u32) });
+        tim1.egr.write(|w| unsafe { w.ug().bits(1) });
+        tim1.sr.write(|w| unsafe { w.bits(0) });
+    });
+}
+
+/// Returns the latest reading from the timer input, or None if there has been no signal on
+/// it lately.
+pub fn read() -> Option<Reading> {
+    let range = unsafe { RANGE };
+    let (sr, period, high) = cortex_m::interrupt::free(|cs| {
+        let tim1 = TIM1.borrow(cs);
+        let sr = tim1.sr.read().bits();
+        tim1.sr.write(|w| unsafe { w.bits(!SR_UIF) }); // the others are cleared by writing 0
+        // reading CCR1 clears CC1IF
+        (sr, tim1.ccr1.read().bits(), tim1.ccr2.read().bits())
+    });
+    if sr & SR_CC1IF != 0 && period > 0 && (sr & SR_UIF == 0 || range + 1 == RANGES.len()) {
+        if period < MIN_TICKS && range > 0 {
+            set_range(range - 1);
+        } else {
+            let clock = clocks::apb2_timer_hz() as u64 / (RANGES[range] as u64 + 1);
+            let period = period as u64;
+            let high = if high as u64 > period { period } else { high as u64 };
+            let reading = Reading {
+                frequency_mhz: clock * 1000 / period,
+                duty: (high * 100_000 / period) as u32,
+                high_ns: high * 1_000_000_000 / clock,
+                low_ns: (period - high) * 1_000_000_000 / clock,
+                from_timer: true,
+            };
+            unsafe { LAST = Some((reading, time::millis())); }
+        }
+    } else if sr & SR_UIF != 0 && range + 1 < RANGES.len() {
+        // an overflow: the period is longer than this range can measure
+        set_range(range + 1);
+    }
+    unsafe {
+        match LAST {
+            Some((reading, at)) if time::since(at) < TIMEOUT_MS => Some(reading),
+            _ => None,
+        }
+    }
+}
+
+/// Works out readings from a record's frequency and duty cycle, if it has at least two cycles.
+pub fn from_results(results: &Results) -> Option<Reading> {
+    let frequency_mhz = match measure::value(Measurement::Frequency, results) {
+        Some(mhz) if mhz > 0 => mhz as u64,
+        _ => return None,
+    };
+    let duty = match measure::value(Measurement::Duty, results) {
+        Some(duty) => duty as u64,
+        None => return None,
+    };
+    let period_ns = 1_000_000_000_000 / frequency_mhz;
+    let high_ns = period_ns * duty / 100_000;
+    Some(Reading {
+        frequency_mhz: frequency_mhz,
+        duty: duty as u32,
+        high_ns: high_ns,
+        low_ns: period_ns - high_ns,
+        from_timer: false,
+    })
+}
+
+const ROW_Y: i16 = 2;
+const ROW_PITCH: i16 = 28;
+const DIGITS_X: i16 = 24;
+const DIGITS_HEIGHT: i16 = 24;
+const DIGIT_CELLS: i16 = 4;
+
+// format a value in thousandths of units[0] as four significant digits, "d.ddd", "dd.dd", or
+// "ddd.d", in the unit which keeps it under 1000, returning the unit
+fn format(value: u64, units: [&'static [u8]; 3], buf: &mut [u8; 5]) -> &'static [u8] {
+    let mut value = value;
+    let mut unit = 0;
+    while value >= 1_000_000 && unit + 1 < units.len() {
+        value /= 1000;
+        unit += 1;
+    }
+    if value >= 1_000_000 {
+        value = 999_999; // off the end of the last unit
+    }
+    let (value, decimals) = if value < 10_000 {
+        (value, 3)
+    } else if value < 100_000 {
+        (value / 10, 2)
+    } else {
+        (value / 100, 1)
+    };
+    measure::format_fixed(value, decimals, buf);
+    units[unit]
+}
+
+// draw one row: its label, digits, and unit
+fn draw_row(row: i16, label: &[u8], digits: &[u8], unit: &[u8]) {
+    let y = ROW_Y + row * ROW_PITCH;
+    let label_y = (y + DIGITS_HEIGHT - 12) as u8;
+    st7735_print(label, 0, label_y, St7735Color::Blue, St7735Color::Black);
+    segment::draw(digits, DIGITS_X, y, DIGITS_HEIGHT, St7735Color::Green, St7735Color::Black);
+    let unit_x = DIGITS_X + DIGIT_CELLS * segment::cell_width(DIGITS_HEIGHT) + 2;
+    st7735_print(unit, unit_x as u8, label_y, St7735Color::Green, St7735Color::Black);
+}
+
+/// Draws the PWM analyzer display: frequency, duty cycle, and the high and low widths, with
+/// where they came from.
+pub fn draw(reading: Option<Reading>) {
+    let mut buf = [b'-'; 5];
+    match reading {
+        Some(reading) => {
+            let unit = format(reading.frequency_mhz, [b"Hz ", b"kHz", b"MHz"], &mut buf);
+            draw_row(0, b"F ", &buf, unit);
+            measure::format_fixed(reading.duty as u64 / 100, 1, &mut buf);
+            draw_row(1, b"D ", &buf, b"%  ");
+            let unit = format(reading.high_ns, [b"us", b"ms", b"s "], &mut buf);
+            draw_row(2, b"+W", &buf, unit);
+            let unit = format(reading.low_ns, [b"us", b"ms", b"s "], &mut buf);
+            draw_row(3, b"-W", &buf, unit);
+        }
+        None => {
+            for (row, label) in [b"F ", b"D ", b"+W", b"-W"].iter().enumerate() {
+                draw_row(row as i16, *label, b"----", b"   ");
+            }
+        }
+    }
+    let source: &[u8] = match reading {
+        Some(Reading { from_timer: true, .. }) => b"timer  ",
+        Some(_) => b"samples",
+        None => b"no sig ",
+    };
+    st7735_print(source, 104, ROW_Y as u8, St7735Color::Blue, St7735Color::Black);
+}
@@ -0,0 +1,365 @@
+// stm32f3-oscilloscope - src/qemu/checks.rs
+// checks of the pure modules, run under QEMU or on the host, reported as cargo test does
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// Each check exercises one of the modules that needs no peripherals against answers known in
+// advance: the key-mode escape sequences, the shell's line editing and parsing, the software
+// trigger, and the frame and delta-encoding protocols the exports are sent in. They are built
+// for the board's target and run under QEMU (see src/qemu/main.rs), so the integer widths and
+// code generation are the board's, and the host build runs the same checks natively with
+// `host check`. The shell and frames are checked through the serial stand-in (see
+// sim/serial.rs), fed what would be typed, and reading back what would be sent.
+//
+// The report follows cargo test's, so the same tools can read it: "running N checks", a line
+// for each, "test keys::arrows ... ok" or "test keys::arrows ... FAILED", with the reason on
+// the next line, then "test result: ok. N passed; 0 failed", or FAILED.
+
+use delta;
+use frame::{self, FrameType};
+use keys::{self, Key};
+use serial;
+use shell::{self, Command, Setting};
+use trigger::{self, TriggerConfig, TriggerCoupling, TriggerMode, TriggerSearch, TriggerSlope};
+
+type CheckResult = Result<(), &'static [u8]>;
+
+struct Check {
+    name: &'static [u8],
+    run: fn() -> CheckResult,
+}
+
+const CHECKS: [Check; 14] = [
+    Check { name: b"keys::arrows", run: check_arrows },
+    Check { name: b"keys::unknown_sequences", run: check_unknown_sequences },
+    Check { name: b"keys::quit", run: check_quit },
+    Check { name: b"shell::command", run: check_command },
+    Check { name: b"shell::line_editing", run: check_line_editing },
+    Check { name: b"shell::crlf", run: check_crlf },
+    Check { name: b"shell::errors", run: check_errors },
+    Check { name: b"trigger::rising", run: check_rising },
+    Check { name: b"trigger::falling", run: check_falling },
+    Check { name: b"trigger::hysteresis", run: check_hysteresis },
+    Check { name: b"trigger::incremental", run: check_incremental },
+    Check { name: b"frame::crc16", run: check_crc },
+    Check { name: b"frame::layout", run: check_layout },
+    Check { name: b"delta::round_trip", run: check_round_trip },
+];
+
+/// Runs the checks, writing the report a piece at a time through `write`. Returns whether all
+/// passed.
+pub fn run<F: FnMut(&[u8])>(mut write: F) -> bool {
+    let mut number = [0u8; 10];
+    write(b"running ");
+    write(decimal(CHECKS.len(), &mut number));
+    write(b" checks\n");
+    let mut passed = 0;
+    for check in &CHECKS {
+        write(b"test ");
+        write(check.name);
+        match (check.run)() {
+            Ok(()) => {
+                write(b" ... ok\n");
+                passed += 1;
+            }
+            Err(reason) => {
+                write(b" ... FAILED\n    ");
+                write(reason);
+                write(b"\n");
+            }
+        }
+    }
+    let failed = CHECKS.len() - passed;
+    write(if failed == 0 { b"\ntest result: ok. " } else { b"\ntest result: FAILED. " });
+    write(decimal(passed, &mut number));
+    write(b" passed; ");
+    write(decimal(failed, &mut number));
+    write(b" failed\n");
+    failed == 0
+}
+
+// `n` in decimal, in `buf`
+fn decimal(n: usize, buf: &mut [u8; 10]) -> &[u8] {
+    let mut i = buf.len();
+    let mut n = n;
+    loop {
+        i -= 1;
+        buf[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    &buf[i..]
+}
+
+// ======== keys.rs ========
+
+// type `bytes`, expecting just the last to complete a key
+fn type_key(bytes: &[u8]) -> Option<Key> {
+    let (last, first) = bytes.split_last().unwrap();
+    if first.iter().any(|&byte| keys::decode(byte).is_some()) {
+        return None;
+    }
+    keys::decode(*last)
+}
+
+fn check_arrows() -> CheckResult {
+    keys::start();
+    let arrows: [(&[u8], Key); 4] = [
+        (b"\x1b[A", Key::Up),
+        (b"\x1b[B", Key::Down),
+        (b"\x1bOC", Key::Right), // in application cursor mode
+        (b"\x1bOD", Key::Left),
+    ];
+    for &(bytes, key) in &arrows {
+        if type_key(bytes) != Some(key) {
+            return Err(b"an arrow key's sequence gave the wrong key");
+        }
+    }
+    Ok(())
+}
+
+fn check_unknown_sequences() -> CheckResult {
+    keys::start();
+    if type_key(b"\x1b[Z").is_some() || keys::decode(b'x').is_some() {
+        return Err(b"an unknown sequence or byte gave a key");
+    }
+    // an unknown sequence is forgotten, and a second ESC starts the sequence again
+    if keys::decode(b' ') != Some(Key::RunStop) || type_key(b"\x1b\x1b[A") != Some(Key::Up) {
+        return Err(b"the sequence after an unknown one was lost");
+    }
+    Ok(())
+}
+
+fn check_quit() -> CheckResult {
+    keys::start();
+    if keys::decode(b's') != Some(Key::Single) || !keys::active() {
+        return Err(b"'s' didn't give Single");
+    }
+    if keys::decode(b'q') != Some(Key::Quit) || keys::active() {
+        return Err(b"'q' didn't leave key mode");
+    }
+    Ok(())
+}
+
+// ======== shell.rs ========
+
+// type `line`, returning the command it gives, with what was sent back cleared first
+fn type_line(line: &[u8]) -> Option<Command> {
+    shell::done();
+    serial::clear();
+    serial::feed(line);
+    shell::poll()
+}
+
+// whether what was sent back contains `text`
+fn sent(text: &[u8]) -> bool {
+    serial::output().0.windows(text.len()).any(|window| window == text)
+}
+
+fn check_command() -> CheckResult {
+    match type_line(b"set fps max\r") {
+        Some(Command::Set(Setting::FrameRate, ref value)) if value.matches(b"max") => {}
+        _ => return Err(b"\"set fps max\" didn't parse"),
+    }
+    if !sent(b"> set fps max\r\n") {
+        return Err(b"the prompt and echo weren't sent");
+    }
+    Ok(())
+}
+
+fn check_line_editing() -> CheckResult {
+    // backspace and delete both erase, and control characters are refused
+    match type_line(b"set fps mix\x08\x7fax\x01\r") {
+        Some(Command::Set(Setting::FrameRate, ref value)) if value.matches(b"max") => {}
+        _ => return Err(b"the line wasn't edited"),
+    }
+    if !sent(b"\x08 \x08\x08 \x08ax\x07") {
+        return Err(b"the erasing or the bell wasn't sent");
+    }
+    Ok(())
+}
+
+fn check_crlf() -> CheckResult {
+    match type_line(b"trig level 1.65\r\n") {
+        Some(Command::TriggerLevel(1650)) => {}
+        _ => return Err(b"\"trig level 1.65\" didn't parse"),
+    }
+    // the LF after the CR is ignored, rather than ending an empty line
+    shell::done();
+    serial::clear();
+    if shell::poll().is_some() || serial::output().0 != b"> " {
+        return Err(b"a CR LF ended two lines");
+    }
+    Ok(())
+}
+
+fn check_errors() -> CheckResult {
+    if type_line(b"set colour red\r").is_some() || !sent(b"set what? try help\r\n") {
+        return Err(b"an unknown setting wasn't reported");
+    }
+    if type_line(b"trig level high\r").is_some() || !sent(b"level should be in volts") {
+        return Err(b"a bad level wasn't reported");
+    }
+    Ok(())
+}
+
+// ======== trigger.rs ========
+
+fn config(slope: TriggerSlope) -> TriggerConfig {
+    TriggerConfig {
+        mode: TriggerMode::Normal,
+        slope: slope,
+        coupling: TriggerCoupling::DC,
+        level: 2048,
+        delay: 0,
+        auto_timeout_ms: 0,
+    }
+}
+
+// a ramp from `from`, by `step` each sample
+fn ramp(from: i32, step: i32) -> [u16; 64] {
+    let mut data = [0u16; 64];
+    for (i, sample) in data.iter_mut().enumerate() {
+        let value = from + step * i as i32;
+        *sample = if value < 0 { 0 } else if value > 4095 { 4095 } else { value as u16 };
+    }
+    data
+}
+
+fn check_rising() -> CheckResult {
+    // 0, 100, .. 2000, then 2100, the first at or above the level
+    let data = ramp(0, 100);
+    if TriggerSearch::new().scan(&data, data.len(), &config(TriggerSlope::Rising)) != Some(21) {
+        return Err(b"the rising edge wasn't found at the level");
+    }
+    if TriggerSearch::new().scan(&data, data.len(), &config(TriggerSlope::Falling)).is_some() {
+        return Err(b"a rising ramp triggered on the falling slope");
+    }
+    Ok(())
+}
+
+fn check_falling() -> CheckResult {
+    // 4000, 3900, .. 2100, then 2000, the first at or below the level
+    let data = ramp(4000, -100);
+    if TriggerSearch::new().scan(&data, data.len(), &config(TriggerSlope::Falling)) != Some(20) {
+        return Err(b"the falling edge wasn't found at the level");
+    }
+    if TriggerSearch::new().scan(&data, data.len(), &config(TriggerSlope::Either)) != Some(20) {
+        return Err(b"either slope missed the falling edge");
+    }
+    Ok(())
+}
+
+fn check_hysteresis() -> CheckResult {
+    // noise across the level, but never far enough below it to arm
+    let mut data = [0u16; 64];
+    for (i, sample) in data.iter_mut().enumerate() {
+        *sample = if i % 2 == 0 { 2030 } else { 2060 };
+    }
+    let rising = config(TriggerSlope::Rising);
+    if TriggerSearch::new().scan(&data, data.len(), &rising).is_some() {
+        return Err(b"noise within the hysteresis triggered");
+    }
+    // a square wave between 1000 and 3000 has an event in each period
+    for (i, sample) in data.iter_mut().enumerate() {
+        *sample = if i % 16 < 8 { 1000 } else { 3000 };
+    }
+    if trigger::count_events(&data, &rising) != 4 {
+        return Err(b"a square wave's rising edges weren't counted");
+    }
+    Ok(())
+}
+
+fn check_incremental() -> CheckResult {
+    // the samples arriving a few at a time find the same event as all at once
+    let data = ramp(0, 100);
+    let rising = config(TriggerSlope::Rising);
+    let mut search = TriggerSearch::new();
+    let mut found = None;
+    let mut end = 0;
+    while found.is_none() && end < data.len() {
+        end += 3;
+        found = search.scan(&data, end, &rising);
+    }
+    if found != Some(21) {
+        return Err(b"scanning in pieces found a different event");
+    }
+    // and scanning on finds the next event, not the same one
+    if search.scan(&data, data.len(), &rising).is_some() {
+        return Err(b"the same event was found twice");
+    }
+    Ok(())
+}
+
+// ======== frame.rs and delta.rs ========
+
+fn check_crc() -> CheckResult {
+    // CRC-16/CCITT-FALSE's check value
+    if frame::crc16(0xffff, b"123456789") != 0x29b1 {
+        return Err(b"the CRC of \"123456789\" isn't 0x29b1");
+    }
+    Ok(())
+}
+
+fn check_layout() -> CheckResult {
+    serial::clear();
+    frame::send(FrameType::DumpHeader, &[&[0x01, 0x02], &[0x03]]);
+    let header = [FrameType::DumpHeader as u8, 3, 0, 0x01, 0x02, 0x03];
+    let crc = frame::crc16(0xffff, &header);
+    let mut expected = [0u8; 10];
+    expected[..2].copy_from_slice(&[0xa5, 0x5a]);
+    expected[2..8].copy_from_slice(&header);
+    expected[8..].copy_from_slice(&[crc as u8, (crc >> 8) as u8]);
+    if serial::output().0 != &expected[..] {
+        return Err(b"the frame's bytes weren't sync, type, length, payload, CRC");
+    }
+    Ok(())
+}
+
+fn check_round_trip() -> CheckResult {
+    // small steps, large jumps both ways, and the ends of the range
+    let mut samples = [0u16; delta::FRAME_SAMPLES];
+    for (i, sample) in samples.iter_mut().enumerate() {
+        *sample = match i % 8 {
+            0 => 2048 + i as u16,
+            1...3 => 2048 + 63 * (i % 8) as u16,
+            4 => 4095,
+            5 => 0,
+            6 => 64,
+            _ => 0,
+        };
+    }
+    let mut encoded = [0u8; delta::MAX_FRAME_BYTES];
+    let len = delta::encode_frame(&samples, &mut encoded);
+    let mut decoded = [0u16; delta::FRAME_SAMPLES];
+    if delta::decode_frame(&encoded[..len], &mut decoded) != Some(samples.len()) ||
+       decoded[..] != samples[..] {
+        return Err(b"a frame didn't decode to the samples encoded");
+    }
+    // a frame must start with a keyframe
+    if delta::decode_frame(&encoded[2..len], &mut decoded).is_some() {
+        return Err(b"a frame without its keyframe was decoded");
+    }
+    Ok(())
+}
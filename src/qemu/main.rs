@@ -0,0 +1,122 @@
+// stm32f3-oscilloscope - src/qemu/main.rs
+// the QEMU build: the pure modules' checks, run on an emulated Cortex-M3
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// QEMU has no STM32F3 machine, and its STM32 boards emulate none of the ADCs, DMA, or SPI the
+// firmware needs, so the firmware itself can't run under it. This third crate root builds what
+// can: the modules that use no peripherals, included from src/ as the host build includes
+// them, with the same stand-ins from src/sim/ and st7735.rs drawing into its frame buffer.
+// It is built for thumbv7m-none-eabi, so the integer widths and code generation are the
+// board's, linked for QEMU's mps2-an385 machine with memory-qemu.x, and runs the checks in
+// checks.rs, writing their report and exiting with their result through semihosting. So the
+// usual command does it all, with tools/qemu_tests.py as the runner (see docs/host-build.rst):
+//
+//   tools/qemu_tests.py
+//
+// which runs `cargo run --features qemu --bin qemu-tests` with itself as the runner, starting
+// qemu-system-arm on the ELF and failing if the checks fail, or a panic leaves them hung.
+
+#![feature(asm)]
+#![feature(used)]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt;
+extern crate stm32f30x;
+
+#[path = "../calibration.rs"]
+mod calibration;
+#[path = "../delta.rs"]
+mod delta;
+#[path = "../export.rs"]
+mod export;
+#[path = "../frame.rs"]
+mod frame;
+#[path = "../input.rs"]
+mod input;
+#[path = "../keys.rs"]
+mod keys;
+#[path = "../measure.rs"]
+mod measure;
+#[path = "../parallax_8x12_font.rs"]
+mod parallax_8x12_font;
+#[path = "../phase.rs"]
+mod phase;
+#[path = "../plotter.rs"]
+mod plotter;
+#[path = "../records.rs"]
+mod records;
+#[path = "../screen.rs"]
+mod screen;
+#[path = "../segment.rs"]
+mod segment;
+#[path = "../shell.rs"]
+mod shell;
+#[path = "../st7735.rs"]
+mod st7735;
+#[path = "../telemetry.rs"]
+mod telemetry;
+#[path = "../trigger.rs"]
+mod trigger;
+#[path = "../trigrate.rs"]
+mod trigrate;
+
+// the stand-ins for the modules that use peripherals
+#[path = "../sim/capture.rs"]
+mod capture;
+#[path = "../sim/delay.rs"]
+mod delay;
+#[path = "../sim/flash.rs"]
+mod flash;
+#[path = "../sim/serial.rs"]
+mod serial;
+#[path = "../sim/spiflash.rs"]
+mod spiflash;
+#[path = "../sim/time.rs"]
+mod time;
+
+mod checks;
+mod semihosting;
+
+use cortex_m::exception;
+use stm32f30x::interrupt;
+
+fn main() {
+    semihosting::exit(checks::run(semihosting::write));
+}
+
+// ======== exception and interrupt handlers ========
+
+// nothing is enabled, so the defaults do; a fault ends up looping in one, until the runner's
+// timeout
+#[allow(dead_code)]
+#[used]
+#[link_section = ".rodata.exceptions"]
+static EXCEPTIONS: exception::Handlers = exception::Handlers {
+    ..exception::DEFAULT_HANDLERS
+};
+
+#[allow(dead_code)]
+#[used]
+#[link_section = ".rodata.interrupts"]
+static INTERRUPTS: interrupt::Handlers = interrupt::DEFAULT_HANDLERS;
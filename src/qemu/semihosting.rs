@@ -0,0 +1,65 @@
+// stm32f3-oscilloscope - src/qemu/semihosting.rs
+// writing to the host's console and exiting, through ARM semihosting
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// Under QEMU with -semihosting, a BKPT 0xAB asks the emulator to do something on the guest's
+// behalf: the operation's number is in r0, its argument in r1, and the result comes back in
+// r0. Only two operations are used: SYS_WRITE0, to write a NUL-terminated string to QEMU's
+// standard output, and SYS_EXIT, to end the emulation with an exit status. On the board, with
+// no debugger attached, the BKPT would fault, so this is only for the QEMU build.
+
+const SYS_WRITE0: u32 = 0x04;
+const SYS_EXIT: u32 = 0x18;
+
+// SYS_EXIT's reasons: QEMU exits with 0 for ADP_Stopped_ApplicationExit, and 1 for any other
+const APPLICATION_EXIT: u32 = 0x20026;
+const RUNTIME_ERROR: u32 = 0x20023;
+
+unsafe fn call(operation: u32, argument: u32) -> u32 {
+    let result: u32;
+    asm!("bkpt 0xAB"
+         : "={r0}"(result)
+         : "{r0}"(operation), "{r1}"(argument)
+         : "memory"
+         : "volatile");
+    result
+}
+
+/// Writes `bytes` to the host's standard output. A NUL in them ends the write early.
+pub fn write(bytes: &[u8]) {
+    // SYS_WRITE0 needs the terminating NUL, so copy a piece at a time
+    let mut buf = [0u8; 64];
+    for piece in bytes.chunks(buf.len() - 1) {
+        buf[..piece.len()].copy_from_slice(piece);
+        buf[piece.len()] = 0;
+        unsafe { call(SYS_WRITE0, buf.as_ptr() as u32); }
+    }
+}
+
+/// Ends the emulation, with exit status 0 if `success`, or 1.
+pub fn exit(success: bool) -> ! {
+    let reason = if success { APPLICATION_EXIT } else { RUNTIME_ERROR };
+    unsafe { call(SYS_EXIT, reason); }
+    // not reached under QEMU
+    loop {}
+}
@@ -0,0 +1,221 @@
+// stm32f3-oscilloscope - src/rc.rs
+// capacitance, from an RC circuit's time constant
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The RC display measures a capacitor charged through a known resistor: the probe
+// compensation output (probe.rs) drives the resistor, the capacitor goes from its other end to
+// ground, and the input samples the junction. While the display is shown, the output runs
+// slowly enough that each half period is two records long, so the capacitor charges and
+// discharges fully if the timebase puts around five time constants in a record, and the
+// sweeps trigger on its rising edge, at a tenth of full scale, whatever the trigger settings.
+//
+// Each record is taken to start from ground, the output having been low for long enough, and
+// its last sixteenth gives the level charged to. The times the curve crosses 10% and 63.2% of
+// that level are interpolated between samples; from the step, a charging curve reaches 10% at
+// 0.1054 time constants and 63.2% at one, so the time constant is their difference divided
+// by 0.8946, wherever the step itself fell. The capacitance is the time constant divided by
+// the series resistance, set with the shell's rc command, and shown in large digits.
+//
+// A curve too fast to resolve, crossing both points within MIN_TAU_SAMPLES, asks for a faster
+// timebase; one not settled by the end of the record, within five time constants, asks for a
+// slower one.
+
+use calibration;
+use font::FONT_5X7;
+use measure;
+use probe;
+use screen;
+use segment;
+use st7735::*;
+use trigger::{TriggerConfig, TriggerCoupling, TriggerMode, TriggerSlope};
+
+// the trigger level, a tenth of full scale
+const STEP_LEVEL: u16 = 410;
+// the smallest charged level taken as a step, in conversion units
+const MIN_STEP: i32 = 1000;
+// the time constant needs at least this many samples to be measured
+const MIN_TAU_SAMPLES: u64 = 4;
+// the shortest record measured
+const MIN_LENGTH: usize = 64;
+
+// the layout: the capacitance large, then the time constant and resistance
+const LABEL_X: u8 = 0;
+const DIGITS_X: i16 = 16;
+const DIGITS_Y: i16 = 8;
+const DIGITS_HEIGHT: i16 = 40;
+const TAU_Y: u8 = 62;
+const RESISTANCE_Y: u8 = 74;
+const MESSAGE_Y: u8 = 92;
+
+static mut SERIES_OHMS: u32 = 10_000;
+static mut STEP_RATE: Option<(u32, usize)> = None; // the sample rate and record length set for
+
+/// The outcome of measuring a record.
+pub enum Reading {
+    Capacitance { tau_ns: u64, picofarads: u64 },
+    Message(&'static [u8]), // why there's no reading
+}
+
+/// The series resistance, in ohms.
+pub fn series_ohms() -> u32 {
+    unsafe { SERIES_OHMS }
+}
+
+pub fn set_series_ohms(ohms: u32) {
+    unsafe { SERIES_OHMS = ohms; }
+}
+
+/// Turns on the probe compensation output for the display, its rate set by prepare(), or
+/// with `active` false, puts its rate back.
+pub fn set_active(active: bool) {
+    unsafe { STEP_RATE = None; }
+    if active {
+        probe::set_enabled(true);
+    } else {
+        probe::set_frequency_millihz(probe::FREQUENCY_HZ * 1000);
+    }
+}
+
+/// Returns the trigger for each sweep, given the settings' `trigger`, and keeps the output's
+/// half periods at two records of `length` samples at `sample_rate`.
+pub fn prepare(trigger: TriggerConfig, sample_rate: u32, length: usize) -> TriggerConfig {
+    if unsafe { STEP_RATE } != Some((sample_rate, length)) {
+        unsafe { STEP_RATE = Some((sample_rate, length)); }
+        let mhz = sample_rate as u64 * 1000 / (4 * length as u64);
+        probe::set_frequency_millihz(if mhz == 0 { 1 } else { mhz as u32 });
+    }
+    TriggerConfig {
+        mode: TriggerMode::Normal,
+        slope: TriggerSlope::Rising,
+        coupling: TriggerCoupling::DC,
+        level: STEP_LEVEL,
+        delay: 0,
+        ..trigger
+    }
+}
+
+// the first time `record`, less `offset`, reaches `level`, in 256ths of a sample
+fn crossing(record: &[u16], offset: i32, level: i32) -> Option<u64> {
+    let i = match record.iter().position(|&s| s as i32 - offset >= level) {
+        Some(i) => i,
+        None => return None,
+    };
+    if i == 0 {
+        return Some(0);
+    }
+    let (before, after) = (record[i - 1] as i32 - offset, record[i] as i32 - offset);
+    Some((i as u64 - 1) * 256 + ((level - before) * 256 / (after - before)) as u64)
+}
+
+/// Measures a record captured at `sample_rate`, starting at the trigger.
+pub fn measure(record: &[u16], sample_rate: u32) -> Reading {
+    if record.len() < MIN_LENGTH {
+        return Reading::Message(b"set a longer record");
+    }
+    let offset = calibration::offset(0) as i32;
+    let tail = &record[record.len() - record.len() / 16..];
+    let level = tail.iter().map(|&s| s as i32 - offset).sum::<i32>() / tail.len() as i32;
+    if level < MIN_STEP {
+        return Reading::Message(b"no step: see help");
+    }
+    let (t10, t63) = match (crossing(record, offset, level / 10),
+                            crossing(record, offset, level * 632 / 1000)) {
+        (Some(t10), Some(t63)) => (t10, t63),
+        _ => return Reading::Message(b"no step: see help"),
+    };
+    // in 256ths of a sample
+    let tau = (t63 - t10) * 10_000 / 8946;
+    if tau < MIN_TAU_SAMPLES * 256 {
+        return Reading::Message(b"use a faster timebase");
+    }
+    let end = record.len() as u64 * 256;
+    if end < t63 + 4 * tau {
+        return Reading::Message(b"use a slower timebase");
+    }
+    let tau_ns = tau * 1_000_000_000 / (256 * sample_rate as u64);
+    Reading::Capacitance {
+        tau_ns: tau_ns,
+        picofarads: tau_ns * 1000 / series_ohms() as u64,
+    }
+}
+
+// format `pf` picofarads to three significant figures in `buf`, returning the unit
+fn format_farads(pf: u64, buf: &mut [u8; 5]) -> &'static [u8] {
+    let (scale, unit): (u64, &'static [u8]) = match pf {
+        0...999 => (1, b"pF"),
+        1_000...999_999 => (1_000, b"nF"),
+        1_000_000...999_999_999 => (1_000_000, b"uF"),
+        _ => (1_000_000_000, b"mF"),
+    };
+    let hundredths = (pf * 100 + scale / 2) / scale;
+    match hundredths {
+        0...999 => measure::format_fixed(hundredths, 2, &mut buf[..]),
+        1_000...9_999 => measure::format_fixed((hundredths + 5) / 10, 1, &mut buf[..]),
+        _ => measure::format_fixed((hundredths + 50) / 100, 0, &mut buf[..]),
+    }
+    unit
+}
+
+/// Draws a reading.
+pub fn draw(reading: Reading) {
+    let mut buf = [b'-'; 5];
+    let (unit, tau_ns): (&[u8], Option<u64>) = match reading {
+        Reading::Capacitance { tau_ns, picofarads } => {
+            FONT_5X7.draw(&[b' '; 24], LABEL_X, MESSAGE_Y, St7735Color::White,
+                          St7735Color::Black);
+            (format_farads(picofarads, &mut buf), Some(tau_ns))
+        }
+        Reading::Message(message) => {
+            let mut line = [b' '; 24];
+            line[..message.len()].copy_from_slice(message);
+            FONT_5X7.draw(&line, LABEL_X, MESSAGE_Y, St7735Color::White, St7735Color::Black);
+            (b"  ", None)
+        }
+    };
+    let text_y = (DIGITS_Y + DIGITS_HEIGHT - 12) as u8;
+    st7735_print(b"C", LABEL_X, text_y, St7735Color::Blue, St7735Color::Black);
+    let x = segment::draw(&buf, DIGITS_X, DIGITS_Y, DIGITS_HEIGHT, St7735Color::Green,
+                          St7735Color::Black);
+    st7735_print(unit, (x + 2) as u8, text_y, St7735Color::Green, St7735Color::Black);
+
+    // "tau 1.02ms", "R 10kohm"
+    let mut line = [b' '; 16];
+    line[..4].copy_from_slice(b"tau ");
+    if let Some(ns) = tau_ns {
+        measure::format_si(ns as i64, b"s", &mut line[4..]);
+    } else {
+        line[4..6].copy_from_slice(b"--");
+    }
+    FONT_5X7.draw(&line, LABEL_X, TAU_Y, St7735Color::White, St7735Color::Black);
+    let mut line = [b' '; 16];
+    line[..2].copy_from_slice(b"R ");
+    measure::format_si(series_ohms() as i64 * 1_000_000_000, b"ohm", &mut line[2..]);
+    FONT_5X7.draw(&line, LABEL_X, RESISTANCE_Y, St7735Color::White, St7735Color::Black);
+}
+
+/// Clears the trace area, for the display being entered.
+pub fn enter() {
+    screen::clear_trace_area();
+    draw(Reading::Message(b"waiting for a step"));
+}
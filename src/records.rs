@@ -0,0 +1,262 @@
+// stm32f3-oscilloscope - src/records.rs
+// a record store in the external SPI flash: reference traces, screens, and the data log
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+
+// The external flash (see spiflash.rs) is divided into fixed areas, so there is no directory
+// to keep up to date, or to wear out:
+//   REFERENCE_SLOTS slots of one sector each, for reference traces
+//   SCREEN_SLOTS slots of SCREEN_SLOT_SIZE bytes each, for screens
+//   the rest of the chip, up to LOG_MAX_SIZE, for the data log (see datalog.rs)
+//
+// A slot holds a HEADER_SIZE byte header, then the record's data:
+//   0, 1  the kind of record, REFERENCE or SCREEN; erased, 0xffff
+//   2, 3  for a screen, its width, and for a reference trace, the number of columns
+//   4, 5  for a screen, its height, and otherwise 0
+//   6, 7  the CRC-16 of the data, as for a frame (see frame.rs)
+// with each half-word low byte first. A reference trace is a half-word per column, the raw
+// sample, or 0xffff where there is none, and a screen is its rows, top first, with the red,
+// green, and blue of each pixel, as read back from the display. Storing erases the slot, writes
+// the data, and only then the header, so a store cut short by a reset leaves the slot empty.
+
+use export;
+use frame;
+use serial;
+use spiflash::{self, SpiFlashError, SECTOR_SIZE};
+use st7735::*;
+
+pub const REFERENCE_SLOTS: usize = 8;
+pub const SCREEN_SLOTS: usize = 4;
+
+const REFERENCE_START: u32 = 0;
+const SCREEN_START: u32 = REFERENCE_START + REFERENCE_SLOTS as u32 * SECTOR_SIZE;
+const SCREEN_SLOT_SIZE: u32 = 16 * SECTOR_SIZE; // a 160x128 screen, at 3 bytes a pixel
+const LOG_START: u32 = SCREEN_START + SCREEN_SLOTS as u32 * SCREEN_SLOT_SIZE;
+const LOG_MAX_SIZE: u32 = 256 * SECTOR_SIZE;
+const LOG_MIN_SIZE: u32 = 16 * SECTOR_SIZE;
+
+const HEADER_SIZE: usize = 8;
+const REFERENCE: u16 = 1;
+const SCREEN: u16 = 2;
+const NO_SAMPLE: u16 = 0xffff;
+
+/// The columns of a reference trace.
+pub type Trace = [Option<u16>; 160];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RecordError {
+    SpiFlash(SpiFlashError),
+    Empty,      // nothing is stored in the slot
+    Corrupt,    // the data doesn't match its CRC
+    Unreadable, // the display can't be read back, with software SPI
+}
+
+struct Header {
+    kind: u16,
+    width: u16,
+    height: u16,
+    crc: u16,
+}
+
+/// The start and end addresses of the data log's area, if there is an external flash with
+/// room for one.
+pub fn log_area() -> Option<(u32, u32)> {
+    let size = spiflash::size();
+    if size < LOG_START + LOG_MIN_SIZE {
+        return None;
+    }
+    let end = if size - LOG_START > LOG_MAX_SIZE { LOG_START + LOG_MAX_SIZE } else { size };
+    Some((LOG_START, end))
+}
+
+/// Stores `trace` as reference trace `slot`.
+pub fn store_reference(slot: usize, trace: &Trace) -> Result<(), RecordError> {
+    let address = reference_address(slot);
+    let mut data = [0u8; 2 * 160];
+    for (i, value) in trace.iter().enumerate() {
+        let sample = value.unwrap_or(NO_SAMPLE);
+        data[2 * i] = sample as u8;
+        data[2 * i + 1] = (sample >> 8) as u8;
+    }
+    erase(address, SECTOR_SIZE)?;
+    write(address + HEADER_SIZE as u32, &data)?;
+    let crc = frame::crc16(0xffff, &data);
+    let header = Header { kind: REFERENCE, width: 160, height: 0, crc: crc };
+    write_header(address, &header)
+}
+
+/// Reads reference trace `slot`.
+pub fn recall_reference(slot: usize) -> Result<Trace, RecordError> {
+    let address = reference_address(slot);
+    let header = read_header(address, REFERENCE)?;
+    let mut data = [0u8; 2 * 160];
+    read(address + HEADER_SIZE as u32, &mut data)?;
+    if frame::crc16(0xffff, &data) != header.crc {
+        return Err(RecordError::Corrupt);
+    }
+    let mut trace = [None; 160];
+    for (i, value) in trace.iter_mut().enumerate() {
+        let sample = data[2 * i] as u16 | (data[2 * i + 1] as u16) << 8;
+        *value = if sample == NO_SAMPLE { None } else { Some(sample) };
+    }
+    Ok(trace)
+}
+
+/// Stores the screen, as read back from the display, as screen `slot`.
+pub fn store_screen(slot: usize) -> Result<(), RecordError> {
+    let address = screen_address(slot);
+    let (width, height) = (st7735_get_width(), st7735_get_height());
+    let mut rgb = [0u8; 3 * 160];
+    let row = &mut rgb[..3 * width as usize];
+    if !st7735_read_row(0, 0, width, row) {
+        return Err(RecordError::Unreadable);
+    }
+    erase(address, SCREEN_SLOT_SIZE)?;
+    let mut crc = 0xffff;
+    let mut at = address + HEADER_SIZE as u32;
+    for y in 0..height {
+        st7735_read_row(0, y, width, row);
+        write(at, row)?;
+        crc = frame::crc16(crc, row);
+        at += row.len() as u32;
+    }
+    let header = Header { kind: SCREEN, width: width as u16, height: height as u16, crc: crc };
+    write_header(address, &header)
+}
+
+/// Sends screen `slot` on the serial port, as a screenshot would be.
+pub fn send_screen(slot: usize) -> Result<(), RecordError> {
+    let address = screen_address(slot);
+    let header = read_header(address, SCREEN)?;
+    let (width, height) = (header.width as u8, header.height as u8);
+    let row_size = 3 * width as u32;
+    // check it all before sending any of it
+    let mut rgb = [0u8; 3 * 160];
+    let mut crc = 0xffff;
+    for y in 0..height as u32 {
+        let row = &mut rgb[..row_size as usize];
+        read(address + HEADER_SIZE as u32 + y * row_size, row)?;
+        crc = frame::crc16(crc, row);
+    }
+    if crc != header.crc {
+        return Err(RecordError::Corrupt);
+    }
+    export::send_screen(width, height, |y, row| {
+        let at = address + HEADER_SIZE as u32 + y as u32 * row_size;
+        if spiflash::read(at, row).is_err() {
+            for byte in row.iter_mut() {
+                *byte = 0;
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Writes the chip's size, and what is in each slot, to the serial port.
+pub fn report() {
+    let size = spiflash::size();
+    if size == 0 {
+        serial::write(b"no external flash\r\n");
+        return;
+    }
+    serial::write(b"external flash ");
+    serial::write_number(size >> 10);
+    serial::write(b"K\r\n");
+    for slot in 0..REFERENCE_SLOTS {
+        serial::write(b"ref ");
+        serial::write_number(slot as u32 + 1);
+        match read_header(reference_address(slot), REFERENCE) {
+            Ok(_) => serial::write(b": stored\r\n"),
+            Err(_) => serial::write(b": empty\r\n"),
+        }
+    }
+    for slot in 0..SCREEN_SLOTS {
+        serial::write(b"screen ");
+        serial::write_number(slot as u32 + 1);
+        match read_header(screen_address(slot), SCREEN) {
+            Ok(header) => {
+                serial::write(b": ");
+                serial::write_number(header.width as u32);
+                serial::write(b"x");
+                serial::write_number(header.height as u32);
+                serial::write(b"\r\n");
+            }
+            Err(_) => serial::write(b": empty\r\n"),
+        }
+    }
+}
+
+fn reference_address(slot: usize) -> u32 {
+    debug_assert!(slot < REFERENCE_SLOTS);
+    REFERENCE_START + slot as u32 * SECTOR_SIZE
+}
+
+fn screen_address(slot: usize) -> u32 {
+    debug_assert!(slot < SCREEN_SLOTS);
+    SCREEN_START + slot as u32 * SCREEN_SLOT_SIZE
+}
+
+fn erase(address: u32, size: u32) -> Result<(), RecordError> {
+    let mut sector = address;
+    while sector < address + size {
+        spiflash::erase_sector(sector).map_err(RecordError::SpiFlash)?;
+        sector += SECTOR_SIZE;
+    }
+    Ok(())
+}
+
+fn read(address: u32, data: &mut [u8]) -> Result<(), RecordError> {
+    spiflash::read(address, data).map_err(RecordError::SpiFlash)
+}
+
+fn write(address: u32, data: &[u8]) -> Result<(), RecordError> {
+    spiflash::write(address, data).map_err(RecordError::SpiFlash)
+}
+
+// the header at `address`, if it is of a record of `kind`
+fn read_header(address: u32, kind: u16) -> Result<Header, RecordError> {
+    let mut bytes = [0u8; HEADER_SIZE];
+    read(address, &mut bytes)?;
+    let half_word = |i: usize| bytes[2 * i] as u16 | (bytes[2 * i + 1] as u16) << 8;
+    let header = Header { kind: half_word(0), width: half_word(1), height: half_word(2),
+                          crc: half_word(3) };
+    let fits = match kind {
+        SCREEN => header.width <= 160 && header.height <= 160,
+        _ => header.width == 160,
+    };
+    if header.kind != kind || !fits {
+        return Err(RecordError::Empty);
+    }
+    Ok(header)
+}
+
+fn write_header(address: u32, header: &Header) -> Result<(), RecordError> {
+    let mut bytes = [0u8; HEADER_SIZE];
+    let half_words = [header.kind, header.width, header.height, header.crc];
+    for (i, &half_word) in half_words.iter().enumerate() {
+        bytes[2 * i] = half_word as u8;
+        bytes[2 * i + 1] = (half_word >> 8) as u8;
+    }
+    write(address, &bytes)
+}
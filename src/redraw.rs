@@ -0,0 +1,144 @@
+// stm32f3-oscilloscope - src/redraw.rs
+// tracking which parts of the screen need repainting
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The SPI link to the LCD is slow enough that clearing and redrawing the whole trace area is
+// visible, so when something is drawn over the traces (help, the zoom view, another display
+// mode) and then removed, the columns it covered are marked dirty instead, and repainted from
+// the stored trace values a few at a time between the other work of the main loop. The
+// traces stay visible, and the sweep is never held up for long.
+//
+// Other regions are tracked by a flag each, and are repainted whole.
+//
+// The frame rate can be capped, so a fast timebase doesn't spend the SPI link redrawing
+// screens faster than they can be seen: each sweep waits to begin until the frame interval
+// since the last one has passed, leaving the main loop free for the pushbuttons and the shell
+// in the meantime. A sweep's columns are also only redrawn where the traces have changed.
+
+use delay::Deadline;
+use screen;
+
+const COLUMNS: usize = screen::MAX_WIDTH;
+const WORDS: usize = (COLUMNS + 31) / 32;
+
+/// The number of dirty columns repainted each time through the main loop.
+pub const COLUMNS_PER_PASS: usize = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Region {
+    Status = 1 << 0, // the status line, showing the selected parameter
+}
+
+static mut DIRTY_COLUMNS: [u32; WORDS] = [0; WORDS];
+static mut DIRTY_REGIONS: u8 = 0;
+
+/// A cap on the frame rate, as the most frames a second, or none.
+pub struct FrameRate {
+    pub fps: Option<u32>,
+    pub label: &'static [u8],
+}
+
+pub const FRAME_RATES: [FrameRate; 5] = [
+    FrameRate { fps: None, label: b"max" },
+    FrameRate { fps: Some(50), label: b"50" },
+    FrameRate { fps: Some(25), label: b"25" },
+    FrameRate { fps: Some(10), label: b"10" },
+    FrameRate { fps: Some(5), label: b"5" },
+];
+
+static mut FRAME_RATE: usize = 0; // an index into FRAME_RATES
+static mut NEXT_FRAME: Option<Deadline> = None;
+
+/// Marks `width` trace columns starting at `x` as needing to be repainted.
+pub fn invalidate_columns(x: usize, width: usize) {
+    let end = if x + width > COLUMNS { COLUMNS } else { x + width };
+    for column in x..end {
+        unsafe { DIRTY_COLUMNS[column / 32] |= 1 << (column % 32); }
+    }
+}
+
+/// Marks the whole trace area as needing to be repainted.
+pub fn invalidate_trace_area() {
+    invalidate_columns(0, screen::width() as usize);
+}
+
+/// Returns whether column `x` needs repainting, and marks it clean, for when it is about to be
+/// repainted anyway.
+pub fn take_column(x: usize) -> bool {
+    let bit = 1 << (x % 32);
+    unsafe {
+        let dirty = DIRTY_COLUMNS[x / 32] & bit != 0;
+        DIRTY_COLUMNS[x / 32] &= !bit;
+        dirty
+    }
+}
+
+/// Returns the leftmost column needing repainting, if any, and marks it clean.
+pub fn next_column() -> Option<usize> {
+    for word in 0..WORDS {
+        let bits = unsafe { DIRTY_COLUMNS[word] };
+        if bits != 0 {
+            let x = word * 32 + bits.trailing_zeros() as usize;
+            take_column(x);
+            return Some(x);
+        }
+    }
+    None
+}
+
+/// Sets the frame rate cap, as an index into FRAME_RATES.
+pub fn set_frame_rate(i: usize) {
+    unsafe {
+        FRAME_RATE = i;
+        NEXT_FRAME = None;
+    }
+}
+
+/// Returns whether the next frame may begin, under the frame rate cap, and if so, starts the
+/// interval before the one after it.
+pub fn start_frame() -> bool {
+    unsafe {
+        if let Some(deadline) = NEXT_FRAME {
+            if !deadline.passed() {
+                return false;
+            }
+        }
+        NEXT_FRAME = FRAME_RATES[FRAME_RATE].fps.map(|fps| Deadline::after_ms(1000 / fps));
+    }
+    true
+}
+
+/// Marks a region as needing to be repainted.
+pub fn invalidate(region: Region) {
+    unsafe { DIRTY_REGIONS |= region as u8; }
+}
+
+/// Returns whether a region needs repainting, and marks it clean.
+pub fn take(region: Region) -> bool {
+    unsafe {
+        let dirty = DIRTY_REGIONS & region as u8 != 0;
+        DIRTY_REGIONS &= !(region as u8);
+        dirty
+    }
+}
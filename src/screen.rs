@@ -0,0 +1,204 @@
+// stm32f3-oscilloscope - src/screen.rs
+// screen orientation, and the layout of the trace area and status line
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The screen can be used in any of the four orientations, turning it a quarter turn at a time.
+// Plotting and the main screen layout go through this module, rather than assuming the
+// original 160x128 landscape screen: the status line is always the bottom STATUS_HEIGHT
+// pixels, the trace area is everything above it, and voltages are scaled to the screen height.
+// The vertical gain magnifies part of the ADC's range, 0V to its reference (nominally 3.3V,
+// see calibration.rs), to fill the screen height.
+// The other displays size themselves from width() too, with buffers of MAX_WIDTH columns: the
+// zoom and histogram use a column per pixel, the readout wraps its slots onto a second row,
+// and the console shows as many lines as fit. The meter and help are laid out for landscape,
+// and are clipped in portrait.
+
+use calibration;
+use st7735::*;
+
+/// The most columns the screen has, in any orientation.
+pub const MAX_WIDTH: usize = 160;
+/// The height of the status line, one line of the 8x12 font.
+pub const STATUS_HEIGHT: i16 = 12;
+
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Orientation {
+    Landscape = 0,
+    Portrait = 1,         // turned a quarter turn clockwise
+    LandscapeFlipped = 2, // upside down
+    PortraitFlipped = 3,
+}
+
+impl Orientation {
+    pub fn next(self) -> Orientation {
+        match self {
+            Orientation::Landscape => Orientation::Portrait,
+            Orientation::Portrait => Orientation::LandscapeFlipped,
+            Orientation::LandscapeFlipped => Orientation::PortraitFlipped,
+            Orientation::PortraitFlipped => Orientation::Landscape,
+        }
+    }
+
+    pub fn label(self) -> &'static [u8] {
+        match self {
+            Orientation::Landscape => b"0",
+            Orientation::Portrait => b"90",
+            Orientation::LandscapeFlipped => b"180",
+            Orientation::PortraitFlipped => b"270",
+        }
+    }
+
+    // the ST7735 driver's rotation
+    fn rotation(self) -> u8 {
+        match self {
+            Orientation::Landscape => 3,
+            Orientation::Portrait => 0,
+            Orientation::LandscapeFlipped => 1,
+            Orientation::PortraitFlipped => 2,
+        }
+    }
+}
+
+static mut ORIENTATION: Orientation = Orientation::Landscape;
+
+pub struct Gain {
+    pub tenths: u32, // the magnification, in tenths
+    pub label: &'static [u8],
+}
+
+/// The vertical gains, each no more than 1.5 times the one before, so auto-ranging can always
+/// find one that the signal fills 60% to 90% of the screen at (see autorange.rs).
+pub const GAINS: [Gain; 8] = [
+    Gain { tenths: 10, label: b"1x" },
+    Gain { tenths: 15, label: b"1.5x" },
+    Gain { tenths: 20, label: b"2x" },
+    Gain { tenths: 30, label: b"3x" },
+    Gain { tenths: 45, label: b"4.5x" },
+    Gain { tenths: 60, label: b"6x" },
+    Gain { tenths: 80, label: b"8x" },
+    Gain { tenths: 100, label: b"10x" },
+];
+
+static mut GAIN_INDEX: usize = 0;
+static mut CENTER_MICROVOLTS: u32 = calibration::NOMINAL_REFERENCE_MV * 1000 / 2;
+
+/// Sets the screen orientation. The screen contents are not redrawn.
+pub fn set_orientation(orientation: Orientation) {
+    st7735_setRotation(orientation.rotation());
+    unsafe { ORIENTATION = orientation; }
+}
+
+pub fn orientation() -> Orientation {
+    unsafe { ORIENTATION }
+}
+
+/// Sets the vertical gain to GAINS[`index`], centering the screen as near `center`
+/// microvolts as keeps it within the ADC's range. The screen contents are not redrawn.
+pub fn set_vertical(index: usize, center: u32) {
+    let half_span = half_span(index);
+    let full_scale = calibration::full_scale_microvolts();
+    let center = if center < half_span {
+        half_span
+    } else if center > full_scale - half_span {
+        full_scale - half_span
+    } else {
+        center
+    };
+    unsafe {
+        GAIN_INDEX = index;
+        CENTER_MICROVOLTS = center;
+    }
+}
+
+pub fn gain_index() -> usize {
+    unsafe { GAIN_INDEX }
+}
+
+/// The voltage at the middle of the screen, in microvolts.
+pub fn center_microvolts() -> u32 {
+    unsafe { CENTER_MICROVOLTS }
+}
+
+// half the voltage span of the screen at GAINS[`index`], in microvolts
+fn half_span(index: usize) -> u32 {
+    (calibration::full_scale_microvolts() as u64 * 10 / GAINS[index].tenths as u64 / 2) as u32
+}
+
+/// The voltages at the bottom and top of the screen, in microvolts. After the reference
+/// changes, set_vertical() is needed to bring the range back within the ADC's.
+pub fn vertical_range() -> (u32, u32) {
+    let half_span = half_span(gain_index());
+    let center = center_microvolts();
+    let bottom = if center > half_span { center - half_span } else { 0 };
+    (bottom, center + half_span)
+}
+
+/// The width of the screen in the current orientation.
+pub fn width() -> i16 {
+    st7735_get_width() as i16
+}
+
+/// The height of the screen in the current orientation.
+pub fn height() -> i16 {
+    st7735_get_height() as i16
+}
+
+/// The height of the trace area, above the status line.
+pub fn trace_height() -> i16 {
+    height() - STATUS_HEIGHT
+}
+
+/// The top of the status line.
+pub fn status_y() -> u8 {
+    trace_height() as u8
+}
+
+/// Returns the screen row for `microvolts`, at the current vertical gain. The row may be
+/// off-screen.
+pub fn microvolts_to_y(microvolts: u32) -> i16 {
+    let (bottom, top) = vertical_range();
+    let height = height() as i64;
+    let up = (microvolts as i64 - bottom as i64) * height / (top - bottom) as i64;
+    height as i16 - 1 - up as i16
+}
+
+/// Returns the voltage shown `y` pixels up from the bottom of the screen, in microvolts.
+pub fn microvolts_at(y: i16) -> u32 {
+    let (bottom, top) = vertical_range();
+    bottom + (y as u64 * (top - bottom) as u64 / height() as u64) as u32
+}
+
+/// Returns the screen row `y` pixels up from the bottom of the screen.
+pub fn from_bottom(y: i16) -> i16 {
+    height() - 1 - y
+}
+
+pub fn clear_trace_area() {
+    st7735_fill_rect(0, 0, width(), trace_height(), St7735Color::Black as u16);
+}
+
+pub fn clear_status_line() {
+    st7735_fill_rect(0, status_y() as i16, width(), STATUS_HEIGHT, St7735Color::Black as u16);
+}
@@ -0,0 +1,152 @@
+// stm32f3-oscilloscope - src/search.rs
+// waveform search: finding edges, glitches, and amplitude excursions in a captured record
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// All searches are made relative to a level (the trigger level):
+// - an edge is a crossing of the level in either direction, with hysteresis,
+// - a glitch is a pulse (above or below the level) narrower than GLITCH_WIDTH samples, and
+// - an excursion is the signal moving more than EXCURSION away from the level.
+// Records are at most about twelve thousand samples, so rather than keeping a list of hits, each
+// search just rescans the record from the start.
+
+// hysteresis around the level for edge and glitch detection
+const HYSTERESIS: u16 = 32;
+
+/// Pulses narrower than this many samples are glitches.
+pub const GLITCH_WIDTH: usize = 4;
+
+/// Excursions are this far from the level: one vertical division.
+pub const EXCURSION: u16 = 1024;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SearchKind {
+    Off,
+    Edge,
+    Glitch,
+    Excursion,
+}
+
+impl SearchKind {
+    pub fn next(self) -> SearchKind {
+        match self {
+            SearchKind::Off => SearchKind::Edge,
+            SearchKind::Edge => SearchKind::Glitch,
+            SearchKind::Glitch => SearchKind::Excursion,
+            SearchKind::Excursion => SearchKind::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static [u8] {
+        match self {
+            SearchKind::Off => b"off",
+            SearchKind::Edge => b"edge",
+            SearchKind::Glitch => b"glitch",
+            SearchKind::Excursion => b"excursion",
+        }
+    }
+}
+
+// call `hit` with the index of each hit in turn, until it returns false
+fn scan(data: &[u16], kind: SearchKind, level: u16, hit: &mut FnMut(usize) -> bool) {
+    let high_threshold = level.saturating_add(HYSTERESIS / 2);
+    let low_threshold = level.saturating_sub(HYSTERESIS / 2);
+    let excursion_high = level.saturating_add(EXCURSION);
+    let excursion_low = level.saturating_sub(EXCURSION);
+    let mut high: Option<bool> = None; // signal state, once known
+    let mut last_edge: Option<usize> = None;
+    let mut outside = false;
+    for (i, &sample) in data.iter().enumerate() {
+        match kind {
+            SearchKind::Off => return,
+            SearchKind::Edge | SearchKind::Glitch => {
+                let now_high = if sample >= high_threshold {
+                    true
+                } else if sample <= low_threshold {
+                    false
+                } else {
+                    continue; // within the hysteresis band
+                };
+                if high.is_some() && high != Some(now_high) {
+                    if kind == SearchKind::Edge {
+                        if !hit(i) { return; }
+                    } else if let Some(edge) = last_edge {
+                        if i - edge < GLITCH_WIDTH && !hit(edge) { return; }
+                    }
+                    last_edge = Some(i);
+                }
+                high = Some(now_high);
+            }
+            SearchKind::Excursion => {
+                let now_outside = sample > excursion_high || sample < excursion_low;
+                if now_outside && !outside && !hit(i) {
+                    return;
+                }
+                outside = now_outside;
+            }
+        }
+    }
+}
+
+/// Returns the index of the first hit at or after `from`.
+pub fn find_next(data: &[u16], from: usize, kind: SearchKind, level: u16) -> Option<usize> {
+    let mut found = None;
+    scan(data, kind, level, &mut |i| {
+        if i >= from {
+            found = Some(i);
+            false
+        } else {
+            true
+        }
+    });
+    found
+}
+
+/// Returns the index of the last hit before `before`.
+pub fn find_previous(data: &[u16], before: usize, kind: SearchKind, level: u16)
+        -> Option<usize> {
+    let mut found = None;
+    scan(data, kind, level, &mut |i| {
+        if i < before {
+            found = Some(i);
+            true
+        } else {
+            false
+        }
+    });
+    found
+}
+
+/// Returns the number of hits at or before `position`, and the total number of hits.
+pub fn hit_number(data: &[u16], position: usize, kind: SearchKind, level: u16)
+        -> (usize, usize) {
+    let mut number = 0;
+    let mut total = 0;
+    scan(data, kind, level, &mut |i| {
+        total += 1;
+        if i <= position {
+            number += 1;
+        }
+        true
+    });
+    (number, total)
+}
@@ -0,0 +1,123 @@
+// stm32f3-oscilloscope - src/segment.rs
+// seven-segment style numbers, drawn at any size
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// Each digit is drawn as filled rectangles, with the unlit segments drawn in the background
+// color, so a readout can be updated in place without first being cleared. As on a real
+// seven-segment display, a decimal point belongs to the digit before it.
+//
+// Segments are bits 0..6 in the usual order: a (top), b (top right), c (bottom right),
+// d (bottom), e (bottom left), f (top left), g (middle).
+
+use st7735::*;
+
+const A: u8 = 1 << 0;
+const B: u8 = 1 << 1;
+const C: u8 = 1 << 2;
+const D: u8 = 1 << 3;
+const E: u8 = 1 << 4;
+const F: u8 = 1 << 5;
+const G: u8 = 1 << 6;
+
+const DIGITS: [u8; 10] = [
+    A | B | C | D | E | F,     // 0
+    B | C,                     // 1
+    A | B | D | E | G,         // 2
+    A | B | C | D | G,         // 3
+    B | C | F | G,             // 4
+    A | C | D | F | G,         // 5
+    A | C | D | E | F | G,     // 6
+    A | B | C,                 // 7
+    A | B | C | D | E | F | G, // 8
+    A | B | C | D | F | G,     // 9
+];
+
+fn segments(c: u8) -> u8 {
+    match c {
+        b'0'...b'9' => DIGITS[(c - b'0') as usize],
+        b'-' => G,
+        _ => 0, // blank
+    }
+}
+
+/// Returns the width of a digit cell for digits `height` pixels high, including the decimal
+/// point and the gap before the next digit.
+pub fn cell_width(height: i16) -> i16 {
+    height / 2 + thickness(height) * 2
+}
+
+fn thickness(height: i16) -> i16 {
+    if height >= 20 { height / 10 } else { 2 }
+}
+
+// draw one digit cell, with the given segments and decimal point
+fn draw_cell(segments: u8, point: bool, x: i16, y: i16, height: i16, fg: St7735Color,
+             bg: St7735Color) {
+    let t = thickness(height);
+    let w = height / 2; // the digit, without the point or gap
+    let half = (height - t) / 2; // the y offset of the middle segment
+    let color = |segment: u8| if segments & segment != 0 { fg as u16 } else { bg as u16 };
+    // horizontal segments, inset so they don't overlap the verticals
+    st7735_fill_rect(x + t, y, w - 2 * t, t, color(A));
+    st7735_fill_rect(x + t, y + half, w - 2 * t, t, color(G));
+    st7735_fill_rect(x + t, y + height - t, w - 2 * t, t, color(D));
+    // vertical segments
+    st7735_fill_rect(x + w - t, y + t, t, half - t, color(B));
+    st7735_fill_rect(x + w - t, y + half + t, t, height - half - 2 * t, color(C));
+    st7735_fill_rect(x, y + half + t, t, height - half - 2 * t, color(E));
+    st7735_fill_rect(x, y + t, t, half - t, color(F));
+    // the corners, lit if either segment meeting there is
+    let corner = |a: u8, b: u8| if segments & (a | b) != 0 { fg as u16 } else { bg as u16 };
+    st7735_fill_rect(x, y, t, t, corner(A, F));
+    st7735_fill_rect(x + w - t, y, t, t, corner(A, B));
+    st7735_fill_rect(x, y + half, t, t, corner(E | F, G));
+    st7735_fill_rect(x + w - t, y + half, t, t, corner(B | C, G));
+    st7735_fill_rect(x, y + height - t, t, t, corner(D, E));
+    st7735_fill_rect(x + w - t, y + height - t, t, t, corner(C, D));
+    // the decimal point, and the gap after it
+    st7735_fill_rect(x + w, y, 2 * t, height - t, bg as u16);
+    st7735_fill_rect(x + w, y + height - t, t, t, if point { fg as u16 } else { bg as u16 });
+    st7735_fill_rect(x + w + t, y + height - t, t, t, bg as u16);
+}
+
+/// Draws `text` (digits, '-', '.', and spaces) as seven-segment digits `height` pixels high,
+/// with the top left corner at (`x`, `y`). Returns the x coordinate following the text.
+pub fn draw(text: &[u8], x: i16, y: i16, height: i16, fg: St7735Color, bg: St7735Color) -> i16 {
+    let mut x = x;
+    let mut i = 0;
+    while i < text.len() {
+        let c = text[i];
+        let point = i + 1 < text.len() && text[i + 1] == b'.';
+        if c == b'.' {
+            draw_cell(0, true, x, y, height, fg, bg); // a point with no digit before it
+        } else {
+            draw_cell(segments(c), point, x, y, height, fg, bg);
+            if point {
+                i += 1;
+            }
+        }
+        x += cell_width(height);
+        i += 1;
+    }
+    x
+}
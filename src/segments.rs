@@ -0,0 +1,109 @@
+// stm32f3-oscilloscope - src/segments.rs
+// segmented capture: a run of short triggered segments, kept back to back for review
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Once armed, each triggered sweep's displayed window of LENGTH samples is kept as a segment,
+// with the time it triggered, until SEGMENTS have been kept, and then the sweeps stop so the
+// segments can be flipped through. Sweeps that the auto trigger mode free-ran aren't kept, so
+// infrequent packets or pulses can be caught without keeping the dead time between them.
+//
+// While segments are being kept, sweeps are limited to the longest that a segment needs, and
+// the segments are stored in the rest of the capture buffer, so they take no extra RAM. Each is
+// copied there by DMA, memory to memory, while the main loop gets on with the sweep's display;
+// the next sweep waits only for the copy to finish, which takes microseconds.
+
+use capture;
+use trigger;
+
+/// The length of a segment, in samples: one screen.
+pub const LENGTH: usize = 160;
+// the capture buffer space used by the sweeps, ahead of the segments
+const SWEEP_SPACE: usize = trigger::SEARCH_LENGTH + trigger::MAX_DELAY + LENGTH;
+/// The number of segments kept before the sweeps stop.
+pub const SEGMENTS: usize = (capture::BUFFER_LENGTH - SWEEP_SPACE) / LENGTH;
+
+static mut TIMESTAMPS: [u32; SEGMENTS] = [0; SEGMENTS]; // millis() at each segment's trigger
+static mut COUNT: usize = 0; // the number of segments kept
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Segmented {
+    Off,
+    Armed,          // keeping segments
+    Viewing(usize), // stopped, and showing a segment
+}
+
+impl Segmented {
+    /// Steps from off, to armed, to each segment kept in turn, and back to off.
+    pub fn next(self) -> Segmented {
+        match self {
+            Segmented::Off => Segmented::Armed,
+            Segmented::Armed if count() > 0 => Segmented::Viewing(0),
+            Segmented::Viewing(i) if i + 1 < count() => Segmented::Viewing(i + 1),
+            _ => Segmented::Off,
+        }
+    }
+
+    /// Returns whether the sweeps are stopped, to view a segment.
+    pub fn stopped(self) -> bool {
+        match self {
+            Segmented::Viewing(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Discards any segments kept, ready to keep more.
+pub fn clear() {
+    unsafe { COUNT = 0; }
+}
+
+/// Returns the number of segments kept.
+pub fn count() -> usize {
+    unsafe { COUNT }
+}
+
+/// Keeps the LENGTH samples of the capture buffer starting at `start`, the displayed window of
+/// a sweep triggered at `millis`. Returns false once there is no room for any more.
+pub fn keep(start: usize, millis: u32) -> bool {
+    unsafe {
+        if COUNT < SEGMENTS {
+            capture::copy_samples(start, SWEEP_SPACE + COUNT * LENGTH, LENGTH);
+            TIMESTAMPS[COUNT] = millis;
+            COUNT += 1;
+        }
+        COUNT < SEGMENTS
+    }
+}
+
+/// Returns the samples of segment `i`.
+pub fn samples(i: usize) -> &'static [u16] {
+    capture::wait_for_copy();
+    let start = SWEEP_SPACE + i * LENGTH;
+    &capture::channel_1_data()[start..start + LENGTH]
+}
+
+/// Returns the time from the first segment's trigger to segment `i`'s, in milliseconds.
+pub fn offset_ms(i: usize) -> u32 {
+    unsafe { TIMESTAMPS[i].wrapping_sub(TIMESTAMPS[0]) }
+}
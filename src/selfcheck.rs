@@ -0,0 +1,227 @@
+// stm32f3-oscilloscope - src/selfcheck.rs
+// on-target self-checks, built with the "self-check" feature and run at startup
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Each check exercises one module against answers known in advance: the timer solver against
+// worked solutions, the clock frequencies and capture rate against what the RCC and TIM15
+// registers say, the ADC scaling against the factory calibration values, and a DMA round
+// trip through a spare channel. A line for each is written to the serial port, "check timer:
+// pass", or "check timer: FAIL" and the reason, then a summary line, "self-check: 5 of 5
+// passed", which is also shown in the console. A host script can watch for the summary after
+// a reset, making this a repeatable hardware regression suite; unlike selftest.rs, it needs
+// nothing connected but the serial port.
+
+use core::ptr;
+
+use cortex_m;
+use stm32f30x::TIM15;
+
+use calibration;
+use capture;
+use clocks;
+use console;
+use delay::Deadline;
+use dma;
+use frame;
+use shell;
+use timer;
+
+type CheckResult = Result<(), &'static [u8]>;
+
+struct Check {
+    name: &'static [u8],
+    run: fn() -> CheckResult,
+}
+
+const CHECKS: [Check; 5] = [
+    Check { name: b"timer", run: check_timer },
+    Check { name: b"clocks", run: check_clocks },
+    Check { name: b"scaling", run: check_scaling },
+    Check { name: b"dma", run: check_dma },
+    Check { name: b"crc", run: check_crc },
+];
+
+// the timer solver's answers for a 72MHz clock: target Hz, largest ARR, then PSC, ARR, the
+// rate achieved, and whether it is exact
+const SOLUTIONS: [(u32, u32, u16, u32, u32, bool); 6] = [
+    (1000, timer::MAX_ARR_16, 1, 35_999, 1000, true),
+    (1_600_000, timer::MAX_ARR_16, 0, 44, 1_600_000, true),
+    (1, timer::MAX_ARR_16, 1124, 63_999, 1, true), // the first prescaler dividing exactly
+    (44_100, timer::MAX_ARR_16, 0, 1632, 44_091, false),
+    (7, timer::MAX_ARR_16, 352, 29_137, 7, false),
+    (1, timer::MAX_ARR_32, 0, 71_999_999, 1, true),
+];
+
+// the DMA channel for the round trip, one no other module uses
+const SPARE_DMA_CHANNEL: dma::Channel = dma::Channel {
+    controller: dma::Controller::Dma2,
+    number: 2,
+};
+const DMA_WORDS: usize = 64;
+const DMA_TIMEOUT_MS: u32 = 10;
+
+/// Runs the checks, reporting each. Returns whether all passed.
+pub fn run() -> bool {
+    let mut passed = 0;
+    for check in &CHECKS {
+        let mut line = [0u8; 64];
+        let mut len = append(&mut line, 0, b"check ");
+        len = append(&mut line, len, check.name);
+        match (check.run)() {
+            Ok(()) => {
+                len = append(&mut line, len, b": pass");
+                passed += 1;
+            }
+            Err(reason) => {
+                len = append(&mut line, len, b": FAIL, ");
+                len = append(&mut line, len, reason);
+            }
+        }
+        shell::reply(&line[..len]);
+    }
+    let mut line = *b"self-check: 0 of 0 passed";
+    line[12] = b'0' + passed as u8;
+    line[17] = b'0' + CHECKS.len() as u8;
+    shell::reply(&line);
+    console::log(&line);
+    passed == CHECKS.len()
+}
+
+// copies as much of `text` as fits into `line` at `len`, returning the new length
+fn append(line: &mut [u8], len: usize, text: &[u8]) -> usize {
+    let n = if text.len() < line.len() - len { text.len() } else { line.len() - len };
+    line[len..len + n].copy_from_slice(&text[..n]);
+    len + n
+}
+
+fn check_timer() -> CheckResult {
+    for &(target_hz, max_arr, psc, arr, hz, exact) in &SOLUTIONS {
+        let rate = timer::solve(72_000_000, target_hz, max_arr);
+        if rate.psc != psc || rate.arr != arr {
+            return Err(b"a solution differs");
+        }
+        if rate.hz() != hz || rate.is_exact(target_hz) != exact {
+            return Err(b"a rate differs");
+        }
+    }
+    Ok(())
+}
+
+// the frequencies worked out from the RCC registers, against the configuration set_sys_clock()
+// makes: 72MHz from the HSE, or 64MHz from the HSI, with PCLK1 at half that
+fn check_clocks() -> CheckResult {
+    let sysclk = if clocks::from_hse() { 72_000_000 } else { 64_000_000 };
+    if clocks::sysclk_hz() != sysclk || clocks::hclk_hz() != sysclk {
+        return Err(b"SYSCLK or HCLK");
+    }
+    if clocks::pclk1_hz() != sysclk / 2 || clocks::pclk2_hz() != sysclk {
+        return Err(b"PCLK1 or PCLK2");
+    }
+    if clocks::apb1_timer_hz() != sysclk || clocks::apb2_timer_hz() != sysclk {
+        return Err(b"the timer clocks");
+    }
+    // the sample rate capture.rs reports, against TIM15's prescaler and auto-reload
+    let (psc, arr) = cortex_m::interrupt::free(|cs| {
+        let tim15 = TIM15.borrow(cs);
+        (tim15.psc.read().bits() as u64, tim15.arr.read().bits() as u64)
+    });
+    let divisor = (psc + 1) * (arr + 1);
+    let hz = (clocks::apb2_timer_hz() as u64 + divisor / 2) / divisor;
+    if hz != capture::sample_rate() as u64 {
+        return Err(b"TIM15 disagrees with the sample rate");
+    }
+    Ok(())
+}
+
+// the conversions between ADC readings and volts, and the auxiliary measurements made with the
+// factory calibration values
+fn check_scaling() -> CheckResult {
+    let mut last = 0;
+    for raw in 0..4096 {
+        let uv = calibration::microvolts(raw as u16);
+        if uv < last {
+            return Err(b"microvolts() isn't monotonic");
+        }
+        last = uv;
+        if uv > 0 && calibration::microvolts(calibration::raw_conversion(uv)) != uv {
+            return Err(b"raw_conversion() doesn't invert");
+        }
+    }
+    let auxiliary = capture::auxiliary();
+    if auxiliary.supply_mv < 2000 || auxiliary.supply_mv > 3600 {
+        return Err(b"the supply isn't 2.0 to 3.6V");
+    }
+    if auxiliary.temperature < -400 || auxiliary.temperature > 1050 {
+        return Err(b"the temperature isn't -40 to 105C");
+    }
+    Ok(())
+}
+
+// a memory-to-memory copy, checked word by word
+fn check_dma() -> CheckResult {
+    let mut source = [0u32; DMA_WORDS];
+    let mut destination = [0u32; DMA_WORDS];
+    for (i, word) in source.iter_mut().enumerate() {
+        *word = 0x5a00_0000 ^ (i as u32 * 0x0101_0101);
+    }
+    let config = dma::Config {
+        direction: dma::Direction::PeripheralToMemory,
+        priority: dma::Priority::Low,
+        size: dma::Size::Bits32,
+        circular: false,
+        interrupt_on_complete: false,
+        interrupt_on_half: false,
+        memory_to_memory: true,
+    };
+    SPARE_DMA_CHANNEL.enable_clock();
+    SPARE_DMA_CHANNEL.configure(&config, source.as_ptr() as u32,
+                                destination.as_mut_ptr() as u32, DMA_WORDS as u16);
+    SPARE_DMA_CHANNEL.start();
+    let deadline = Deadline::after_ms(DMA_TIMEOUT_MS);
+    while !SPARE_DMA_CHANNEL.is_complete() && !deadline.passed() {}
+    let complete = SPARE_DMA_CHANNEL.is_complete();
+    SPARE_DMA_CHANNEL.stop();
+    SPARE_DMA_CHANNEL.clear_complete();
+    if SPARE_DMA_CHANNEL.take_error() {
+        return Err(b"transfer error");
+    }
+    if !complete {
+        return Err(b"the copy didn't finish");
+    }
+    for i in 0..DMA_WORDS {
+        // read through volatile, since the compiler didn't see the DMA write
+        if unsafe { ptr::read_volatile(&destination[i]) } != source[i] {
+            return Err(b"the copy differs");
+        }
+    }
+    Ok(())
+}
+
+// the frame CRC, against the standard check value for CRC-16/CCITT-FALSE
+fn check_crc() -> CheckResult {
+    if frame::crc16(0xffff, b"123456789") != 0x29b1 {
+        return Err(b"wrong check value");
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+// stm32f3-oscilloscope - src/selftest.rs
+// loopback self-test: the signal generator, measured through the capture input
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// With either signal generator output (PA4 or PA5) jumpered to the signal input, each
+// frequency is generated in turn and captured at SAMPLES_PER_CYCLE, for CYCLES cycles, and
+// the measured frequency and amplitude checked. The generator and the capture timer run from
+// the same clock, so the frequency should be within a sample or so of exact, whatever the
+// clock; the amplitude should be nearly the DAC's full scale, the supply voltage, less what
+// the DAC's output buffer can't reach at each end. A report line for each frequency is
+// written to the serial port.
+//
+// Frequencies are tested from highest to lowest, so the rest of the previous frequency's
+// cycle, which plays out before a new rate takes effect, is shorter than the wait for it.
+
+use capture;
+use delay::delay_ms;
+use measure;
+use serial;
+use siggen::siggen_set_freq;
+
+const SAMPLES_PER_CYCLE: u32 = 64;
+const CYCLES: u32 = 5;
+
+const FREQUENCY_TOLERANCE_PER_MILLE: u64 = 10; // 1%
+const MIN_AMPLITUDE_PERCENT: u32 = 80;         // of the supply voltage
+const MAX_AMPLITUDE_PERCENT: u32 = 105;
+
+/// Tests each of `frequencies`, in hertz, and returns whether all passed. Leaves the signal
+/// generator and the capture timebase for the caller to set back.
+pub fn run(frequencies: &[u32]) -> bool {
+    serial::write(b"loopback test, with PA4 or PA5 connected to the input\r\n");
+    let supply_mv = capture::auxiliary().supply_mv;
+    let mut passed = true;
+    for &frequency in frequencies.iter().rev() {
+        siggen_set_freq(frequency);
+        capture::set_timebase(frequency * SAMPLES_PER_CYCLE);
+        delay_ms(1000 / frequency + 1); // for the new frequency to take effect
+        let samples = (CYCLES * SAMPLES_PER_CYCLE) as usize;
+        let record = capture::capture_now(samples);
+        let results = measure::measure(record, frequency * SAMPLES_PER_CYCLE);
+        let measured_mhz = results.as_ref().and_then(|r| r.frequency_millihz());
+        let vpp_mv = results.as_ref().map_or(0, |r| r.peak_to_peak_millivolts());
+
+        let expected_mhz = frequency as u64 * 1000;
+        let frequency_ok = match measured_mhz {
+            Some(mhz) => {
+                let error = if mhz > expected_mhz {
+                    mhz - expected_mhz
+                } else {
+                    expected_mhz - mhz
+                };
+                error * 1000 <= expected_mhz * FREQUENCY_TOLERANCE_PER_MILLE
+            }
+            None => false,
+        };
+        let amplitude_ok = vpp_mv * 100 >= supply_mv * MIN_AMPLITUDE_PERCENT &&
+                           vpp_mv * 100 <= supply_mv * MAX_AMPLITUDE_PERCENT;
+        passed &= frequency_ok && amplitude_ok;
+
+        // e.g. "1000Hz: 999.874Hz 2934mV pass"
+        serial::write_number(frequency);
+        serial::write(b"Hz: ");
+        match measured_mhz {
+            Some(mhz) => {
+                serial::write_number((mhz / 1000) as u32);
+                serial::write(b".");
+                let fraction = (mhz % 1000) as u32;
+                serial::write(&[b'0' + (fraction / 100) as u8, b'0' + (fraction / 10 % 10) as u8,
+                                b'0' + (fraction % 10) as u8]);
+                serial::write(b"Hz ");
+            }
+            None => serial::write(b"no frequency "),
+        }
+        serial::write_number(vpp_mv);
+        serial::write(b"mV ");
+        serial::write(match (frequency_ok, amplitude_ok) {
+            (true, true) => b"pass\r\n" as &[u8],
+            (false, true) => b"FAIL, frequency\r\n",
+            (true, false) => b"FAIL, amplitude\r\n",
+            (false, false) => b"FAIL, frequency and amplitude\r\n",
+        });
+    }
+    serial::write(if passed { b"passed\r\n" as &[u8] } else { b"FAILED\r\n" });
+    passed
+}
@@ -0,0 +1,210 @@
+// stm32f3-oscilloscope - src/serial.rs
+// serial port: a USART, with interrupt-driven receive and polled transmit
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The USART and its pins are given by board::SERIAL. Received bytes are queued by the interrupt
+// handler, so none are lost while the main loop is busy drawing, as long as it reads them before
+// RX_BUFFER_LENGTH more arrive. Transmission just waits for room in the USART, which is slow
+// (about 87us a byte), but fine for replies to commands typed at a terminal.
+
+use core::intrinsics::{volatile_load, volatile_store};
+
+use cortex_m;
+use stm32f30x::{RCC, USART1, USART2};
+use stm32f30x::interrupt::Interrupt;
+use stm32f30x::usart1;
+
+use board::{SERIAL, SERIAL_AF, Usart};
+use clocks;
+use gpio;
+use priority;
+
+pub const BAUD_RATE: u32 = 115_200;
+
+const RX_BUFFER_LENGTH: usize = 128;
+
+static mut RX_BUFFER: [u8; RX_BUFFER_LENGTH] = [0; RX_BUFFER_LENGTH];
+static mut RX_HEAD: usize = 0; // the next to be written, by the interrupt handler
+static mut RX_TAIL: usize = 0; // the next to be read
+static mut RX_LOST: bool = false; // bytes have been dropped, by the USART or a full buffer
+
+// register bits
+const CR1_UE: u32 = 1 << 0;
+const CR1_RE: u32 = 1 << 2;
+const CR1_TE: u32 = 1 << 3;
+const CR1_RXNEIE: u32 = 1 << 5;
+const ISR_ORE: u32 = 1 << 3;
+const ISR_RXNE: u32 = 1 << 5;
+const ISR_TC: u32 = 1 << 6;
+const ISR_TXE: u32 = 1 << 7;
+const ICR_ORECF: u32 = 1 << 3;
+
+// USART2's registers are the same as USART1's
+fn usart() -> &'static usart1::RegisterBlock {
+    unsafe {
+        match SERIAL.usart {
+            Usart::Usart1 => &*USART1.get(),
+            Usart::Usart2 => &*(USART2.get() as *const usart1::RegisterBlock),
+        }
+    }
+}
+
+/// Sets up the USART for 8 data bits, no parity, and one stop bit, and starts receiving.
+pub fn init() {
+    for &pin in &[SERIAL.tx, SERIAL.rx] {
+        pin.enable_port_clock();
+        pin.set_alternate_function(SERIAL_AF);
+        pin.configure(gpio::Mode::Alternate);
+    }
+    // so a disconnected input idles rather than picking up noise
+    SERIAL.rx.set_pull(gpio::Pull::Up);
+
+    cortex_m::interrupt::free(|cs| {
+        let rcc = RCC.borrow(cs);
+        // each USART is clocked from its bus, as selected at reset
+        let (clock_hz, interrupt) = match SERIAL.usart {
+            Usart::Usart1 => {
+                rcc.apb2enr.modify(|_, w| w.usart1en().enabled());
+                (clocks::pclk2_hz(), Interrupt::Usart1Exti25)
+            }
+            Usart::Usart2 => {
+                rcc.apb1enr.modify(|_, w| w.usart2en().enabled());
+                (clocks::pclk1_hz(), Interrupt::Usart2Exti26)
+            }
+        };
+        let usart = usart();
+        usart.cr1.write(|w| unsafe { w.bits(0) });
+        usart.brr.write(|w| unsafe { w.bits((clock_hz + BAUD_RATE / 2) / BAUD_RATE) });
+        usart.cr1.write(|w| unsafe { w.bits(CR1_UE | CR1_RE | CR1_TE | CR1_RXNEIE) });
+
+        // below the capture interrupts (see priority.rs)
+        let nvic = cortex_m::peripheral::NVIC.borrow(cs);
+        unsafe { nvic.set_priority(interrupt, priority::SERIAL); }
+        nvic.enable(interrupt);
+    });
+}
+
+/// Returns the next received byte, if there is one.
+pub fn read() -> Option<u8> {
+    unsafe {
+        let tail = RX_TAIL;
+        if tail == volatile_load(&RX_HEAD) {
+            return None;
+        }
+        let byte = volatile_load(&RX_BUFFER[tail]);
+        volatile_store(&mut RX_TAIL, (tail + 1) % RX_BUFFER_LENGTH);
+        Some(byte)
+    }
+}
+
+/// Returns whether received bytes have been lost since the last call.
+pub fn take_rx_lost() -> bool {
+    cortex_m::interrupt::free(|_| unsafe {
+        let lost = RX_LOST;
+        RX_LOST = false;
+        lost
+    })
+}
+
+pub fn write_byte(byte: u8) {
+    let usart = usart();
+    while usart.isr.read().bits() & ISR_TXE == 0 {}
+    usart.tdr.write(|w| unsafe { w.bits(byte as u32) });
+}
+
+pub fn write(bytes: &[u8]) {
+    for &byte in bytes {
+        write_byte(byte);
+    }
+}
+
+/// Writes `n` in decimal.
+pub fn write_number(n: u32) {
+    let mut buf = [0u8; 10];
+    let mut i = buf.len();
+    let mut n = n;
+    loop {
+        i -= 1;
+        buf[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    write(&buf[i..]);
+}
+
+/// Writes `value`, scaled by 10^decimals, in decimal with a point: 1650 with 3 decimals is
+/// "1.650".
+pub fn write_fixed(value: u64, decimals: usize) {
+    let mut scale = 1;
+    for _ in 0..decimals {
+        scale *= 10;
+    }
+    write_number((value / scale) as u32);
+    if decimals > 0 {
+        write_byte(b'.');
+        let fraction = value % scale;
+        let mut digit_scale = scale / 10;
+        while digit_scale > 0 {
+            write_byte(b'0' + (fraction / digit_scale % 10) as u8);
+            digit_scale /= 10;
+        }
+    }
+}
+
+/// Writes `value`, scaled by 10^decimals, as write_fixed(), with a '-' if it is negative.
+pub fn write_signed_fixed(value: i64, decimals: usize) {
+    if value < 0 {
+        write_byte(b'-');
+    }
+    write_fixed(value.abs() as u64, decimals);
+}
+
+/// Waits until everything written has been sent.
+pub fn flush() {
+    while usart().isr.read().bits() & ISR_TC == 0 {}
+}
+
+/// The USART interrupt handler's work: queue the received byte.
+pub fn interrupt() {
+    let usart = usart();
+    let isr = usart.isr.read().bits();
+    unsafe {
+        if isr & ISR_ORE != 0 {
+            usart.icr.write(|w| w.bits(ICR_ORECF));
+            RX_LOST = true;
+        }
+        if isr & ISR_RXNE != 0 {
+            let byte = usart.rdr.read().bits() as u8; // which clears RXNE
+            let next = (RX_HEAD + 1) % RX_BUFFER_LENGTH;
+            if next == volatile_load(&RX_TAIL) {
+                RX_LOST = true;
+            } else {
+                RX_BUFFER[RX_HEAD] = byte;
+                volatile_store(&mut RX_HEAD, next);
+            }
+        }
+    }
+}
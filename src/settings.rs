@@ -0,0 +1,303 @@
+// stm32f3-oscilloscope - src/settings.rs
+// the user's settings, gathered in one place, and stored in flash
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The settings are those main() adjusts from the buttons and the shell, along with the display
+// preferences. Every change goes through main.rs's change_settings(), which applies whatever
+// has changed to the hardware and the display, and which applies them all at once when they
+// are loaded at startup. The timebase, record length, and signal generator frequency are
+// indexes into main.rs's tables, so it also checks that loaded settings are in range.
+//
+// The stored record follows the calibration record in the storage page: MAGIC, VERSION, then
+// a half-word for each setting, the signal generator frequency (low half-word first), the
+// input mapping's millivolts as signed half-words, the ADC reference in millivolts, or 0 to
+// follow the supply, the button map as four bits for each button, each trace's style (see
+// TraceStyle::stored()), the measurement in each readout slot, then a checksum, chosen so
+// that the sum of all the half-words is 0xffff. Enums are stored by their numbers, which stay
+// the same when a variant is added, rather than by their place in the next() cycle. A missing
+// or corrupt record, or one of another VERSION, leaves the defaults in place; VERSION goes up
+// whenever the record's layout changes.
+
+use capture::Gate;
+use flash;
+use flash::FlashError;
+use interp::Interpolation;
+use measure::{Measurement, Units, DEFAULT_SLOTS, IMPEDANCES, SLOTS};
+use redraw::FRAME_RATES;
+use screen::Orientation;
+use trigger::{TriggerConfig, TriggerCoupling, TriggerMode, TriggerSlope};
+use trace::{TraceStyle, DEFAULT_STYLES, TRACES};
+use trigger::{AUTO_TIMEOUTS, TRIGGER_DELAYS};
+
+const MAGIC: u16 = 0x5354; // "ST"
+const VERSION: u16 = 3;
+const OFFSET: usize = 16; // half-words into the storage page, after the calibration record
+const RECORD_LENGTH: usize = 35;
+
+/// The number of buttons in the button map.
+pub const BUTTONS: usize = 4;
+
+/// What is drawn in the trace area.
+#[derive(Clone, Copy, PartialEq)]
+pub enum DisplayMode {
+    Trace = 0,
+    Histogram = 1,           // histogram of each record
+    HistogramAccumulate = 2, // histogram accumulated over many records
+    Meter = 3,               // large voltmeter and frequency counter readouts
+    Volts = 4,               // DMM-style voltmeter, integrating and autoranging
+    Pwm = 5,                 // frequency, duty cycle, and pulse widths
+    Log = 6,                 // the data log's history
+    Console = 7,             // the message console
+    Envelope = 8,            // the range of each column over many sweeps
+    Counter = 9,             // reciprocal frequency counter, through the comparator
+    Mains = 10,              // the counter with long gates, and a trend of mains frequency
+    Harmonics = 11,          // THD and the harmonics' levels, from an FFT
+    Noise = 12,              // RMS and peak-to-peak noise, pooled over many sweeps
+    Rc = 13,                 // capacitance, from an RC time constant
+}
+
+impl DisplayMode {
+    pub fn next(self) -> DisplayMode {
+        match self {
+            DisplayMode::Trace => DisplayMode::Histogram,
+            DisplayMode::Histogram => DisplayMode::HistogramAccumulate,
+            DisplayMode::HistogramAccumulate => DisplayMode::Meter,
+            DisplayMode::Meter => DisplayMode::Volts,
+            DisplayMode::Volts => DisplayMode::Pwm,
+            DisplayMode::Pwm => DisplayMode::Log,
+            DisplayMode::Log => DisplayMode::Console,
+            DisplayMode::Console => DisplayMode::Envelope,
+            DisplayMode::Envelope => DisplayMode::Counter,
+            DisplayMode::Counter => DisplayMode::Mains,
+            DisplayMode::Mains => DisplayMode::Harmonics,
+            DisplayMode::Harmonics => DisplayMode::Noise,
+            DisplayMode::Noise => DisplayMode::Rc,
+            DisplayMode::Rc => DisplayMode::Trace,
+        }
+    }
+
+    pub fn label(self) -> &'static [u8] {
+        match self {
+            DisplayMode::Trace => b"trace",
+            DisplayMode::Histogram => b"histogram",
+            DisplayMode::HistogramAccumulate => b"hist accum",
+            DisplayMode::Meter => b"meter",
+            DisplayMode::Volts => b"volts",
+            DisplayMode::Pwm => b"pwm",
+            DisplayMode::Log => b"log",
+            DisplayMode::Console => b"console",
+            DisplayMode::Envelope => b"envelope",
+            DisplayMode::Counter => b"counter",
+            DisplayMode::Mains => b"mains",
+            DisplayMode::Harmonics => b"harmonics",
+            DisplayMode::Noise => b"noise",
+            DisplayMode::Rc => b"rc",
+        }
+    }
+}
+
+/// The user's settings.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub timebase_index: usize,
+    pub timebase_fine: u32,   // fine steps slower than the selected timebase
+    pub timebase_track: bool, // the timebase follows the measured frequency
+    pub record_length_index: usize,
+    pub interpolation: Interpolation,
+    pub display_mode: DisplayMode,
+    pub trigger: TriggerConfig,
+    pub trigger_delay_index: usize,
+    pub auto_timeout_index: usize,
+    pub gate: Gate,
+    pub siggen_freq_index: usize, // the frequency last chosen with the buttons
+    pub siggen_hz: u32,           // the frequency, as chosen with the buttons or the shell
+    pub input_low_mv: i32,        // the input mapping (see input.rs)
+    pub input_high_mv: i32,
+    pub reference_mv: Option<u32>, // the ADC reference, or None to follow the supply
+    pub orientation: Orientation,
+    pub frame_rate_index: usize, // the frame rate cap, an index into redraw::FRAME_RATES
+    pub units: Units,            // for RMS amplitudes
+    pub impedance_index: usize,  // the reference impedance for dBm, into measure::IMPEDANCES
+    pub button_map: [usize; BUTTONS], // the physical button doing each button's job
+    pub trace_styles: [TraceStyle; TRACES], // by TraceId
+    pub measurements: [Measurement; SLOTS],  // in the readout's slots
+}
+
+// the value, of those reached from `first` by `next`, whose number is `stored`, or None if
+// there is none. Each enum stored gives its variants explicit numbers, so a new variant takes
+// the next unused number, wherever next() puts it in the cycle.
+fn decode<T: Copy + PartialEq, F: Fn(T) -> u16>(first: T, next: fn(T) -> T, number: F,
+                                                 stored: u16) -> Option<T> {
+    let mut t = first;
+    loop {
+        if number(t) == stored {
+            return Some(t);
+        }
+        t = next(t);
+        if t == first {
+            return None;
+        }
+    }
+}
+
+// the button map, as four bits for each button, or None if it isn't a rearrangement of the
+// buttons
+fn decode_button_map(stored: u16) -> Option<[usize; BUTTONS]> {
+    let mut map = [0; BUTTONS];
+    for i in 0..BUTTONS {
+        map[i] = (stored >> (4 * i) & 0xf) as usize;
+        if map[i] >= BUTTONS || map[..i].contains(&map[i]) {
+            return None;
+        }
+    }
+    Some(map)
+}
+
+/// Returns the settings stored in flash, if there is a valid record. The indexes into main.rs's
+/// tables are not checked.
+pub fn load() -> Option<Settings> {
+    let mut record = [0u16; RECORD_LENGTH];
+    let mut sum = 0u16;
+    for i in 0..RECORD_LENGTH {
+        record[i] = flash::read(OFFSET + i);
+        sum = sum.wrapping_add(record[i]);
+    }
+    if record[0] != MAGIC || record[1] != VERSION || sum != 0xffff {
+        return None;
+    }
+    let (delay, auto) = (record[12] as usize, record[13] as usize);
+    let (frame_rate, impedance) = (record[22] as usize, record[24] as usize);
+    if delay >= TRIGGER_DELAYS.len() || auto >= AUTO_TIMEOUTS.len() ||
+       frame_rate >= FRAME_RATES.len() || impedance >= IMPEDANCES.len() {
+        return None;
+    }
+    let enums = (decode(TriggerMode::Off, TriggerMode::next, |m| m as u16, record[8]),
+                 decode(TriggerSlope::Rising, TriggerSlope::next, |s| s as u16, record[9]),
+                 decode(TriggerCoupling::DC, TriggerCoupling::next, |c| c as u16, record[10]),
+                 decode(Interpolation::Sinc, Interpolation::next, |i| i as u16, record[6]),
+                 decode(DisplayMode::Trace, DisplayMode::next, |d| d as u16, record[7]),
+                 decode(Gate::Off, Gate::next, |g| g as u16, record[14]));
+    let (mode, slope, coupling, interpolation, display_mode, gate) = match enums {
+        (Some(m), Some(s), Some(c), Some(i), Some(d), Some(g)) => (m, s, c, i, d, g),
+        _ => return None,
+    };
+    let preferences = (decode(Orientation::Landscape, Orientation::next, |o| o as u16,
+                              record[21]),
+                       decode(Units::Volts, Units::next, |u| u as u16, record[23]),
+                       decode_button_map(record[25]));
+    let (orientation, units, button_map) = match preferences {
+        (Some(o), Some(u), Some(b)) => (o, u, b),
+        _ => return None,
+    };
+    let mut trace_styles = DEFAULT_STYLES;
+    for (i, style) in trace_styles.iter_mut().enumerate() {
+        match TraceStyle::from_stored(record[26 + i]) {
+            Some(s) => *style = s,
+            None => return None,
+        }
+    }
+    let mut measurements = DEFAULT_SLOTS;
+    for (i, measurement) in measurements.iter_mut().enumerate() {
+        match decode(Measurement::Off, Measurement::next, |m| m as u16, record[30 + i]) {
+            Some(m) => *measurement = m,
+            None => return None,
+        }
+    }
+    Some(Settings {
+        timebase_index: record[2] as usize,
+        timebase_fine: record[3] as u32,
+        timebase_track: record[4] != 0,
+        record_length_index: record[5] as usize,
+        interpolation: interpolation,
+        display_mode: display_mode,
+        trigger: TriggerConfig {
+            mode: mode,
+            slope: slope,
+            coupling: coupling,
+            level: record[11],
+            delay: TRIGGER_DELAYS[delay].samples,
+            auto_timeout_ms: AUTO_TIMEOUTS[auto].ms,
+        },
+        trigger_delay_index: delay,
+        auto_timeout_index: auto,
+        gate: gate,
+        siggen_freq_index: record[15] as usize,
+        siggen_hz: record[16] as u32 | (record[17] as u32) << 16,
+        input_low_mv: record[18] as i16 as i32,
+        input_high_mv: record[19] as i16 as i32,
+        reference_mv: if record[20] == 0 { None } else { Some(record[20] as u32) },
+        orientation: orientation,
+        frame_rate_index: frame_rate,
+        units: units,
+        impedance_index: impedance,
+        button_map: button_map,
+        trace_styles: trace_styles,
+        measurements: measurements,
+    })
+}
+
+/// Stores `settings` in flash, to be loaded at the next startup.
+pub fn store(settings: &Settings) -> Result<(), FlashError> {
+    let mut record = [0u16; RECORD_LENGTH];
+    record[0] = MAGIC;
+    record[1] = VERSION;
+    record[2] = settings.timebase_index as u16;
+    record[3] = settings.timebase_fine as u16;
+    record[4] = settings.timebase_track as u16;
+    record[5] = settings.record_length_index as u16;
+    record[6] = settings.interpolation as u16;
+    record[7] = settings.display_mode as u16;
+    record[8] = settings.trigger.mode as u16;
+    record[9] = settings.trigger.slope as u16;
+    record[10] = settings.trigger.coupling as u16;
+    record[11] = settings.trigger.level;
+    record[12] = settings.trigger_delay_index as u16;
+    record[13] = settings.auto_timeout_index as u16;
+    record[14] = settings.gate as u16;
+    record[15] = settings.siggen_freq_index as u16;
+    record[16] = settings.siggen_hz as u16;
+    record[17] = (settings.siggen_hz >> 16) as u16;
+    record[18] = settings.input_low_mv as i16 as u16;
+    record[19] = settings.input_high_mv as i16 as u16;
+    record[20] = settings.reference_mv.unwrap_or(0) as u16;
+    record[21] = settings.orientation as u16;
+    record[22] = settings.frame_rate_index as u16;
+    record[23] = settings.units as u16;
+    record[24] = settings.impedance_index as u16;
+    for (i, &button) in settings.button_map.iter().enumerate() {
+        record[25] |= (button as u16) << (4 * i);
+    }
+    for (i, style) in settings.trace_styles.iter().enumerate() {
+        record[26 + i] = style.stored();
+    }
+    for (i, &measurement) in settings.measurements.iter().enumerate() {
+        record[30 + i] = measurement as u16;
+    }
+    let mut sum = 0u16;
+    for i in 0..RECORD_LENGTH - 1 {
+        sum = sum.wrapping_add(record[i]);
+    }
+    record[RECORD_LENGTH - 1] = 0xffff - sum;
+    flash::store(OFFSET, &record)
+}
@@ -0,0 +1,734 @@
+// stm32f3-oscilloscope - src/shell.rs
+// command shell: line-edited commands from the serial port
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Characters are echoed as they are typed, backspace (or delete) erases the last one, and
+// return ends the line, which is then parsed into a Command. The shell only parses: the
+// settings are kept by main(), which applies each Command that poll() returns, writes any
+// reply, and calls done() to have the next prompt written. Setting values are given as they
+// are shown on the status line, so a setting's value is the rest of the line, which may have
+// spaces in it ("set display hist accum").
+//
+// The capture command gives a list of settings, separated by commas. main() checks them all
+// before calling start_capture(), then poll() returns them one at a time, as if they had been
+// typed, followed by Command::Single, without writing a prompt in between. So the settings
+// are all applied, or none are, and the capture is armed only once they have been.
+//
+// The keys command leaves the line editing for single keystrokes, see keys.rs, which poll()
+// returns as Command::Key until 'q' is typed, holding back the prompt until then.
+
+use core::{i32, u16, u32};
+
+use keys::{self, Key};
+use plotter::Channels;
+use records::{REFERENCE_SLOTS, SCREEN_SLOTS};
+use serial;
+use telemetry::Format;
+
+const LINE_LENGTH: usize = 96;
+const VALUE_LENGTH: usize = 16;
+
+static mut LINE: [u8; LINE_LENGTH] = [0; LINE_LENGTH];
+static mut LEN: usize = 0;
+static mut PROMPT_DUE: bool = true; // write a prompt on the next poll()
+static mut LAST_WAS_CR: bool = false; // so a CR LF line ending ends only one line
+
+const PROMPT: &'static [u8] = b"> ";
+
+/// The most settings a capture command can give.
+pub const MAX_CAPTURE_SETTINGS: usize = 8;
+
+// a capture whose settings are being applied, and its next step: the settings, the trigger
+// level, then the capture itself
+static mut PENDING: Option<(CaptureSettings, usize)> = None;
+
+const HELP: [&'static [u8]; 54] = [
+    b"set timebase <t>     e.g. 1ms, .5ms, 50us, or track, following the frequency",
+    b"set record <n>       160, 1k, 4k, max",
+    b"set interp <i>       dots, linear, sin(x)/x",
+    b"set display <d>      trace, histogram, hist accum, meter, volts, pwm, log, console,",
+    b"                     envelope, counter, mains, harmonics, noise, rc",
+    b"set gate <g>         off, high, low: sample only while the gate input is at that level",
+    b"set fps <f>          the most frames a second: max, 50, 25, 10, 5",
+    b"set buttons <order>  the buttons doing the jobs of buttons 1 to 4, e.g. 4231; 1234 resets",
+    b"set units <u>        RMS amplitudes in V, mV, dBV, or dBm",
+    b"set ohms <r>         the impedance dBm is relative to: 50, 75, 600",
+    b"trig mode <m>        off, auto, normal",
+    b"trig slope <s>       rising, falling, either",
+    b"trig coupling <c>    dc, hf rej, noise rej",
+    b"trig delay <d>       0div, 1div, 2div, 5div, 10div",
+    b"trig auto <t>        auto, 50ms, 100ms, 200ms, 500ms, 1s, 2s",
+    b"trig level <volts>   e.g. 1.65, at the input",
+    b"input <lo> <hi>      the volts at an external front end's input when the ADC sees 0V",
+    b"                     and 3.3V, e.g. input -5 5; input off maps them to 0 and 3.3",
+    b"vref [<volts>|auto]  the ADC reference, VDDA, e.g. 3.0, or auto, following the supply",
+    b"                     as measured through VREFINT; 3.3 by default",
+    b"siggen [sine|ramp] <Hz>  both outputs share the one frequency",
+    b"dump [ack]           send the last record, with ack, each frame acknowledged",
+    b"capture [<s> <v>, ...]  apply the settings, then dump the next triggered record",
+    b"                     s: timebase, record, gate, mode, slope, coupling, delay, auto, level",
+    b"screenshot           send the screen",
+    b"store ref|screen <n> store the reference trace, or CH1 if none is shown, in slot 1 to 8,",
+    b"                     or the screen, in 1 to 4, in an external SPI flash; store lists them",
+    b"recall ref|screen <n>  show a stored reference trace, or send a stored screen",
+    b"stream on|off        send each record, delta encoded, as it is captured",
+    b"                     all are sent in binary frames, see src/frame.rs",
+    b"telemetry csv|json|off  a line of measurements a second, as text",
+    b"plot on|dc|off       samples in mV as text, a line each, for the Arduino serial plotter;",
+    b"                     dc adds the DC input as a second, tab-separated, column",
+    b"alarm <m> above|below <x>, or outside <lo> <hi>, or off  e.g. alarm Vpp above 2",
+    b"                     m: a measurement, as on the status line; x: V, Hz, %, or C",
+    b"alarm [ack|off]      list the alarms, acknowledge the one tripped, or remove all",
+    b"log <m> every <t>    log a measurement to flash, e.g. log temp every 10m (s, m, h)",
+    b"log [off|erase]      show the log's state, stop logging, or stop and erase the log",
+    b"log dump             send the log as CSV text: session, seconds, measurement, value",
+    b"settings [save]      list the settings as commands, or save them for the next startup",
+    b"lock                 ignore the pushbuttons until local",
+    b"local                give control back to the pushbuttons",
+    b"selftest             test the siggen, with PA4 or PA5 connected to the input",
+    b"loopback on|off      show the sine as the CH2 trace, sampled internally, with no wiring",
+    b"trace <t> <color> on|off dots|vectors  a trace's style, e.g. trace ref cyan on dots",
+    b"rc [<ohms>]          the series resistance for the rc display, e.g. 10000",
+    b"monitor [<pin>|off]  show the DC input's volts in a corner, on a pin such as PC0",
+    b"keys                 single keys: arrows for the trigger level and the timebase, space",
+    b"                     to run or stop, s for a single sweep, q to go back to commands",
+    b"profile [reset]      interrupt, trigger scan, and plotting times, and the stack used",
+    b"latency [on|off]     measure the capture interrupt's worst latency, or report it",
+    b"bootloader           start the STM32 bootloader, for a firmware update",
+    b"help                 this list",
+    b"values are as on the status line, in any case, and without any ~",
+];
+
+/// A setting adjusted by name, with the `set` or `trig` commands.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Setting {
+    Timebase,
+    RecordLength,
+    Interpolation,
+    Display,
+    Gate,
+    TriggerMode,
+    TriggerSlope,
+    TriggerCoupling,
+    TriggerDelay,
+    AutoTimeout,
+    FrameRate,
+    Buttons,
+    Units,
+    Impedance,
+}
+
+/// A setting's value, as typed.
+#[derive(Clone, Copy)]
+pub struct Value {
+    bytes: [u8; VALUE_LENGTH],
+    len: usize,
+}
+
+impl Value {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    /// Returns whether this is `label`, ignoring case, and a leading '~' on the label, which
+    /// marks an approximate value.
+    pub fn matches(&self, label: &[u8]) -> bool {
+        let label = if label.first() == Some(&b'~') { &label[1..] } else { label };
+        label.len() == self.len &&
+            label.iter().zip(self.as_bytes()).all(|(&a, &b)| lower(a) == lower(b))
+    }
+}
+
+/// The settings for a single capture, applied before it is armed.
+#[derive(Clone, Copy)]
+pub struct CaptureSettings {
+    pub settings: [Option<(Setting, Value)>; MAX_CAPTURE_SETTINGS],
+    pub level: Option<i32>, // millivolts
+}
+
+pub enum AlarmCommand {
+    List,
+    Acknowledge,
+    ClearAll,
+    Set(Value, Option<i32>, Option<i32>), // a measurement's low and high limits; neither removes
+}
+
+pub enum VrefCommand {
+    Show,
+    Set(Option<u32>), // millivolts, or None to follow the supply
+}
+
+/// A kind of record kept in the external flash (see records.rs).
+#[derive(Clone, Copy, PartialEq)]
+pub enum Record {
+    Reference,
+    Screen,
+}
+
+pub enum StoreCommand {
+    List,
+    Store(Record, usize), // the slot, from 0
+    Recall(Record, usize),
+}
+
+pub enum LogCommand {
+    Status,
+    Stop,
+    Erase,
+    Dump,
+    Start(Value, u16), // a measurement, and the interval in seconds
+}
+
+pub enum Command {
+    Set(Setting, Value),
+    TriggerLevel(i32), // millivolts
+    Input(Option<(i32, i32)>), // millivolts at 0V and 3.3V on the ADC, or None to show them
+    Vref(VrefCommand),
+    Siggen(u32),       // Hz
+    Dump(bool), // with the handshake
+    Capture(CaptureSettings),
+    Single, // capture and dump the next triggered record, after the settings of a Capture
+    Screenshot,
+    Store(StoreCommand),
+    Stream(bool),
+    Telemetry(Format),
+    Plot(Channels),
+    Profile(bool), // reset
+    Latency(Option<bool>), // measuring on or off, or None to report
+    Alarm(AlarmCommand),
+    Log(LogCommand),
+    Settings(bool), // list the settings, or with true, save them
+    Lock,  // lock out the pushbuttons
+    Local, // leave remote control
+    SelfTest,
+    Loopback(bool), // show CH2
+    Trace(Value, Value, bool, bool), // a trace's name, color, visibility, and vectors style
+    Rc(Option<u32>), // the RC display's series resistance in ohms, or None to show it
+    Monitor(Option<Value>), // the DC monitor's pin, or off, or None to show it
+    Keys,
+    Key(Key),
+    Bootloader,
+}
+
+/// Reads what has been typed, and returns the command at the end of a line, if there is one
+/// and it parses. Errors are reported on the serial port.
+pub fn poll() -> Option<Command> {
+    if let Some(command) = next_pending() {
+        return Some(command);
+    }
+    if keys::active() {
+        while let Some(byte) = serial::read() {
+            if let Some(key) = keys::decode(byte) {
+                return Some(Command::Key(key));
+            }
+        }
+        return None;
+    }
+    unsafe {
+        if PROMPT_DUE {
+            PROMPT_DUE = false;
+            serial::write(PROMPT);
+        }
+        while let Some(byte) = serial::read() {
+            let was_cr = LAST_WAS_CR;
+            LAST_WAS_CR = byte == b'\r';
+            match byte {
+                b'\n' if was_cr => {}
+                b'\r' | b'\n' => {
+                    serial::write(b"\r\n");
+                    let len = LEN;
+                    LEN = 0;
+                    match parse(&LINE[..len]) {
+                        Ok(Some(command)) => return Some(command),
+                        Ok(None) => {}
+                        Err(message) => reply(message),
+                    }
+                    done();
+                    return None;
+                }
+                0x08 | 0x7f => {
+                    if LEN > 0 {
+                        LEN -= 1;
+                        serial::write(b"\x08 \x08");
+                    }
+                }
+                b' '...b'~' if LEN < LINE_LENGTH => {
+                    LINE[LEN] = byte;
+                    LEN += 1;
+                    serial::write_byte(byte);
+                }
+                _ => serial::write_byte(0x07), // bell: line full, or a control character
+            }
+        }
+    }
+    None
+}
+
+/// Marks the last command as finished, so a prompt is written for the next, unless it was a
+/// step of a capture.
+pub fn done() {
+    unsafe {
+        if PENDING.is_none() {
+            PROMPT_DUE = true;
+        }
+    }
+}
+
+/// Starts applying a capture's settings, which must all be valid, and then arming it.
+pub fn start_capture(capture: CaptureSettings) {
+    unsafe { PENDING = Some((capture, 0)); }
+}
+
+// the next step of the capture being applied, if there is one
+fn next_pending() -> Option<Command> {
+    unsafe {
+        while let Some((capture, step)) = PENDING {
+            PENDING = Some((capture, step + 1));
+            let command = match step {
+                s if s < MAX_CAPTURE_SETTINGS => {
+                    capture.settings[s].map(|(setting, value)| Command::Set(setting, value))
+                }
+                MAX_CAPTURE_SETTINGS => capture.level.map(Command::TriggerLevel),
+                _ => {
+                    PENDING = None;
+                    Some(Command::Single)
+                }
+            };
+            if command.is_some() {
+                return command;
+            }
+        }
+    }
+    None
+}
+
+/// Writes a line of reply to a command.
+pub fn reply(text: &[u8]) {
+    serial::write(text);
+    serial::write(b"\r\n");
+}
+
+// Returns the command on a line, None for a blank line or help, or an error message.
+fn parse(line: &[u8]) -> Result<Option<Command>, &'static [u8]> {
+    let (command, rest) = next_word(line);
+    match command {
+        b"" => Ok(None),
+        b"help" | b"?" => {
+            for line in &HELP {
+                reply(line);
+            }
+            Ok(None)
+        }
+        b"set" => {
+            let (name, value) = next_word(rest);
+            let setting = match name {
+                b"timebase" => Setting::Timebase,
+                b"record" => Setting::RecordLength,
+                b"interp" => Setting::Interpolation,
+                b"display" => Setting::Display,
+                b"gate" => Setting::Gate,
+                b"fps" => Setting::FrameRate,
+                b"buttons" => Setting::Buttons,
+                b"units" => Setting::Units,
+                b"ohms" => Setting::Impedance,
+                _ => return Err(b"set what? try help"),
+            };
+            Ok(Some(Command::Set(setting, parse_value(value)?)))
+        }
+        b"trig" => {
+            let (name, value) = next_word(rest);
+            let setting = match name {
+                b"mode" => Setting::TriggerMode,
+                b"slope" => Setting::TriggerSlope,
+                b"coupling" => Setting::TriggerCoupling,
+                b"delay" => Setting::TriggerDelay,
+                b"auto" => Setting::AutoTimeout,
+                b"level" => return Ok(Some(Command::TriggerLevel(parse_level(value)?))),
+                _ => return Err(b"trig what? try help"),
+            };
+            Ok(Some(Command::Set(setting, parse_value(value)?)))
+        }
+        b"siggen" => {
+            let (mut word, mut rest) = next_word(rest);
+            if word == b"sine" || word == b"ramp" {
+                let next = next_word(rest);
+                word = next.0;
+                rest = next.1;
+            }
+            match parse_decimal(word, 0) {
+                Some(hz) if hz > 0 && trim(rest).is_empty() => Ok(Some(Command::Siggen(hz))),
+                _ => Err(b"frequency should be in Hz, e.g. 1000"),
+            }
+        }
+        b"dump" => {
+            match trim(rest) {
+                b"" => Ok(Some(Command::Dump(false))),
+                b"ack" => Ok(Some(Command::Dump(true))),
+                _ => Err(b"dump, or dump ack"),
+            }
+        }
+        b"capture" => {
+            let mut capture = CaptureSettings { settings: [None; MAX_CAPTURE_SETTINGS],
+                                                level: None };
+            let mut count = 0;
+            if !trim(rest).is_empty() {
+                for part in rest.split(|&c| c == b',') {
+                    let (name, value) = next_word(part);
+                    let setting = match name {
+                        b"timebase" => Setting::Timebase,
+                        b"record" => Setting::RecordLength,
+                        b"gate" => Setting::Gate,
+                        b"mode" => Setting::TriggerMode,
+                        b"slope" => Setting::TriggerSlope,
+                        b"coupling" => Setting::TriggerCoupling,
+                        b"delay" => Setting::TriggerDelay,
+                        b"auto" => Setting::AutoTimeout,
+                        b"level" => {
+                            capture.level = Some(parse_level(value)?);
+                            continue;
+                        }
+                        _ => return Err(b"capture with what? try help"),
+                    };
+                    if count == MAX_CAPTURE_SETTINGS {
+                        return Err(b"too many settings");
+                    }
+                    capture.settings[count] = Some((setting, parse_value(value)?));
+                    count += 1;
+                }
+            }
+            Ok(Some(Command::Capture(capture)))
+        }
+        b"screenshot" => Ok(Some(Command::Screenshot)),
+        b"store" if trim(rest).is_empty() => Ok(Some(Command::Store(StoreCommand::List))),
+        b"store" | b"recall" => {
+            let (kind, number) = next_word(rest);
+            let (record, slots) = match kind {
+                b"ref" => (Record::Reference, REFERENCE_SLOTS),
+                b"screen" => (Record::Screen, SCREEN_SLOTS),
+                _ => return Err(b"store or recall ref <n>, or screen <n>"),
+            };
+            let slot = match parse_decimal(trim(number), 0) {
+                Some(n) if n >= 1 && n as usize <= slots => n as usize - 1,
+                _ => return Err(b"no such slot: ref 1 to 8, or screen 1 to 4"),
+            };
+            if command == b"store" {
+                Ok(Some(Command::Store(StoreCommand::Store(record, slot))))
+            } else {
+                Ok(Some(Command::Store(StoreCommand::Recall(record, slot))))
+            }
+        }
+        b"stream" => {
+            match trim(rest) {
+                b"on" => Ok(Some(Command::Stream(true))),
+                b"off" => Ok(Some(Command::Stream(false))),
+                _ => Err(b"stream on, or stream off"),
+            }
+        }
+        b"telemetry" => {
+            match trim(rest) {
+                b"csv" => Ok(Some(Command::Telemetry(Format::Csv))),
+                b"json" => Ok(Some(Command::Telemetry(Format::Json))),
+                b"off" => Ok(Some(Command::Telemetry(Format::Off))),
+                _ => Err(b"telemetry csv, json, or off"),
+            }
+        }
+        b"plot" => {
+            match trim(rest) {
+                b"on" => Ok(Some(Command::Plot(Channels::One))),
+                b"dc" => Ok(Some(Command::Plot(Channels::Two))),
+                b"off" => Ok(Some(Command::Plot(Channels::Off))),
+                _ => Err(b"plot on, dc, or off"),
+            }
+        }
+        b"input" => {
+            let (low, high) = next_word(rest);
+            match (low, trim(high)) {
+                (b"", b"") => Ok(Some(Command::Input(None))),
+                (b"off", b"") => Ok(Some(Command::Input(Some((0, 3300))))),
+                (low, high) => {
+                    match (parse_signed(low, 3), parse_signed(high, 3)) {
+                        (Some(low), Some(high)) if low < high => {
+                            Ok(Some(Command::Input(Some((low, high)))))
+                        }
+                        (Some(_), Some(_)) => Err(b"the volts at 0V should be first"),
+                        _ => Err(b"input <volts at 0V> <volts at 3.3V>, e.g. input -5 5"),
+                    }
+                }
+            }
+        }
+        b"vref" => {
+            match trim(rest) {
+                b"" => Ok(Some(Command::Vref(VrefCommand::Show))),
+                b"auto" => Ok(Some(Command::Vref(VrefCommand::Set(None)))),
+                text => {
+                    match parse_decimal(text, 3) {
+                        Some(mv) => Ok(Some(Command::Vref(VrefCommand::Set(Some(mv))))),
+                        None => Err(b"vref in volts, e.g. 3.0, or vref auto"),
+                    }
+                }
+            }
+        }
+        b"alarm" => {
+            match trim(rest) {
+                b"" => Ok(Some(Command::Alarm(AlarmCommand::List))),
+                b"ack" => Ok(Some(Command::Alarm(AlarmCommand::Acknowledge))),
+                b"off" => Ok(Some(Command::Alarm(AlarmCommand::ClearAll))),
+                text => Ok(Some(Command::Alarm(parse_alarm(text)?))),
+            }
+        }
+        b"log" => {
+            match trim(rest) {
+                b"" => Ok(Some(Command::Log(LogCommand::Status))),
+                b"off" => Ok(Some(Command::Log(LogCommand::Stop))),
+                b"erase" => Ok(Some(Command::Log(LogCommand::Erase))),
+                b"dump" => Ok(Some(Command::Log(LogCommand::Dump))),
+                text => Ok(Some(Command::Log(parse_log(text)?))),
+            }
+        }
+        b"settings" => {
+            match trim(rest) {
+                b"" => Ok(Some(Command::Settings(false))),
+                b"save" => Ok(Some(Command::Settings(true))),
+                _ => Err(b"settings, or settings save"),
+            }
+        }
+        b"lock" => Ok(Some(Command::Lock)),
+        b"local" => Ok(Some(Command::Local)),
+        b"selftest" => Ok(Some(Command::SelfTest)),
+        b"loopback" => {
+            match trim(rest) {
+                b"on" => Ok(Some(Command::Loopback(true))),
+                b"off" => Ok(Some(Command::Loopback(false))),
+                _ => Err(b"loopback on, or loopback off"),
+            }
+        }
+        b"trace" => {
+            let (name, rest) = next_word(rest);
+            let (color, rest) = next_word(rest);
+            let (visible, rest) = next_word(rest);
+            let visible = match visible {
+                b"on" => true,
+                b"off" => false,
+                _ => return Err(b"trace <t> <color> on|off dots|vectors"),
+            };
+            let vectors = match trim(rest) {
+                b"dots" => false,
+                b"vectors" => true,
+                _ => return Err(b"trace <t> <color> on|off dots|vectors"),
+            };
+            Ok(Some(Command::Trace(parse_value(name)?, parse_value(color)?, visible, vectors)))
+        }
+        b"rc" => {
+            match trim(rest) {
+                b"" => Ok(Some(Command::Rc(None))),
+                text => match parse_decimal(text, 0) {
+                    Some(ohms) if ohms > 0 => Ok(Some(Command::Rc(Some(ohms)))),
+                    _ => Err(b"the resistance should be in ohms, e.g. 10000"),
+                },
+            }
+        }
+        b"monitor" => {
+            match trim(rest) {
+                b"" => Ok(Some(Command::Monitor(None))),
+                text => Ok(Some(Command::Monitor(Some(parse_value(text)?)))),
+            }
+        }
+        b"keys" => Ok(Some(Command::Keys)),
+        b"profile" => {
+            match trim(rest) {
+                b"" => Ok(Some(Command::Profile(false))),
+                b"reset" => Ok(Some(Command::Profile(true))),
+                _ => Err(b"profile, or profile reset"),
+            }
+        }
+        b"latency" => {
+            match trim(rest) {
+                b"" => Ok(Some(Command::Latency(None))),
+                b"on" => Ok(Some(Command::Latency(Some(true)))),
+                b"off" => Ok(Some(Command::Latency(Some(false)))),
+                _ => Err(b"latency on, off, or latency"),
+            }
+        }
+        b"bootloader" => Ok(Some(Command::Bootloader)),
+        _ => Err(b"unknown command, try help"),
+    }
+}
+
+// Parses "<measurement> above|below <x>", "<measurement> outside <lo> <hi>", or
+// "<measurement> off". The measurement is everything before the keyword, since it may have a
+// space in it ("DC in").
+fn parse_alarm(text: &[u8]) -> Result<AlarmCommand, &'static [u8]> {
+    let mut rest = text;
+    loop {
+        let (word, after) = next_word(rest);
+        let (low, high) = match word {
+            b"" => return Err(b"alarm above, below, outside, or off what? try help"),
+            b"above" => (None, Some(parse_limit(after)?)),
+            b"below" => (Some(parse_limit(after)?), None),
+            b"outside" => {
+                let (low, high) = next_word(after);
+                let (low, high) = (parse_limit(low)?, parse_limit(high)?);
+                if low >= high {
+                    return Err(b"the low limit should be first");
+                }
+                (Some(low), Some(high))
+            }
+            b"off" if trim(after).is_empty() => (None, None),
+            _ => {
+                rest = after;
+                continue;
+            }
+        };
+        let name = parse_value(&text[..text.len() - rest.len()])?;
+        return Ok(AlarmCommand::Set(name, low, high));
+    }
+}
+
+// Parses "<measurement> every <interval>", the measurement being everything before the
+// keyword, as for an alarm.
+fn parse_log(text: &[u8]) -> Result<LogCommand, &'static [u8]> {
+    let mut rest = text;
+    loop {
+        let (word, after) = next_word(rest);
+        match word {
+            b"" => return Err(b"log what every how often? try help"),
+            b"every" => {
+                let name = parse_value(&text[..text.len() - rest.len()])?;
+                return Ok(LogCommand::Start(name, parse_interval(after)?));
+            }
+            _ => rest = after,
+        }
+    }
+}
+
+// an interval in seconds, from a number of seconds, minutes, or hours, e.g. 30s, 5m, or 1h
+fn parse_interval(text: &[u8]) -> Result<u16, &'static [u8]> {
+    let text = trim(text);
+    let (number, scale) = match text.last() {
+        Some(&b's') => (&text[..text.len() - 1], 1),
+        Some(&b'm') => (&text[..text.len() - 1], 60),
+        Some(&b'h') => (&text[..text.len() - 1], 3600),
+        _ => (text, 1),
+    };
+    match parse_decimal(number, 0).and_then(|n| n.checked_mul(scale)) {
+        Some(seconds) if seconds >= 1 && seconds <= u16::MAX as u32 => Ok(seconds as u16),
+        _ => Err(b"intervals are 1s to 18h, e.g. 10s, 5m, or 1h"),
+    }
+}
+
+// a limit, in thousandths
+fn parse_limit(text: &[u8]) -> Result<i32, &'static [u8]> {
+    match parse_signed(trim(text), 3) {
+        Some(limit) => Ok(limit),
+        None => Err(b"limits are numbers, e.g. 2 or 1.65"),
+    }
+}
+
+fn parse_value(text: &[u8]) -> Result<Value, &'static [u8]> {
+    let text = trim(text);
+    if text.is_empty() {
+        Err(b"missing value")
+    } else if text.len() > VALUE_LENGTH {
+        Err(b"value too long")
+    } else {
+        let mut value = Value { bytes: [0; VALUE_LENGTH], len: text.len() };
+        value.bytes[..text.len()].copy_from_slice(text);
+        Ok(value)
+    }
+}
+
+fn parse_level(text: &[u8]) -> Result<i32, &'static [u8]> {
+    match parse_signed(trim(text), 3) {
+        Some(millivolts) => Ok(millivolts),
+        None => Err(b"level should be in volts, e.g. 1.65"),
+    }
+}
+
+// Parses a decimal number with up to `places` digits after the point, as an integer in units
+// of 10^-places, so "1.65" with 3 places is 1650.
+fn parse_decimal(text: &[u8], places: u32) -> Option<u32> {
+    let mut value = 0u32;
+    let mut digits = 0;
+    let mut fraction_digits: Option<u32> = None;
+    for &c in text {
+        match c {
+            b'0'...b'9' => {
+                if fraction_digits == Some(places) {
+                    continue; // ignore the excess precision
+                }
+                value = match value.checked_mul(10) {
+                    Some(v) if v <= u32::MAX - 9 => v + (c - b'0') as u32,
+                    _ => return None,
+                };
+                digits += 1;
+                fraction_digits = fraction_digits.map(|n| n + 1);
+            }
+            b'.' if fraction_digits.is_none() && places > 0 => fraction_digits = Some(0),
+            _ => return None,
+        }
+    }
+    if digits == 0 {
+        return None;
+    }
+    for _ in fraction_digits.unwrap_or(0)..places {
+        value = match value.checked_mul(10) {
+            Some(v) => v,
+            None => return None,
+        };
+    }
+    Some(value)
+}
+
+// Parses a decimal number as parse_decimal(), with an optional leading '-'.
+fn parse_signed(text: &[u8], places: u32) -> Option<i32> {
+    let (negative, digits) = match text.first() {
+        Some(&b'-') => (true, &text[1..]),
+        _ => (false, text),
+    };
+    match parse_decimal(digits, places) {
+        Some(value) if value <= i32::MAX as u32 => {
+            Some(if negative { -(value as i32) } else { value as i32 })
+        }
+        _ => None,
+    }
+}
+
+// split off the first space-separated word
+fn next_word(text: &[u8]) -> (&[u8], &[u8]) {
+    let text = trim(text);
+    match text.iter().position(|&c| c == b' ') {
+        Some(i) => (&text[..i], &text[i..]),
+        None => (text, &text[text.len()..]),
+    }
+}
+
+fn trim(text: &[u8]) -> &[u8] {
+    let start = text.iter().position(|&c| c != b' ').unwrap_or(text.len());
+    let end = text.iter().rposition(|&c| c != b' ').map_or(start, |i| i + 1);
+    &text[start..end]
+}
+
+fn lower(c: u8) -> u8 {
+    if c >= b'A' && c <= b'Z' { c + (b'a' - b'A') } else { c }
+}
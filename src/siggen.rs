@@ -22,13 +22,19 @@
 // OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
 // WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-use core;
-
 use cortex_m;
-use stm32f30x::{DAC, DMA2, GPIOA, RCC, TIM2};
+use stm32f30x::{DAC, RCC, TIM2};
+
+use board::DAC_PINS;
+use clocks;
+use dma;
+use gpio::{Mode, Pull};
+use timer;
 
 /* With 72- or 144-sample tables, output can be set to exactly 1Hz, 10Hz, 1kHz, etc. */
-const SINE_12BIT: [u16; 144] = [
+pub const TABLE_LENGTH: usize = 144;
+
+const SINE_12BIT: [u16; TABLE_LENGTH] = [
     2047, 2136, 2225, 2314, 2402, 2490, 2577, 2663, 2747, 2830, 2912, 2992, 3071, 3147, 3221, 3293, 
     3363, 3430, 3494, 3556, 3615, 3671, 3724, 3773, 3820, 3863, 3902, 3938, 3971, 3999, 4024, 4045, 
     4063, 4076, 4086, 4092, 4094, 4092, 4086, 4076, 4063, 4045, 4024, 3999, 3971, 3938, 3902, 3863, 
@@ -40,7 +46,7 @@ const SINE_12BIT: [u16; 144] = [
      731,  801,  873,  947, 1024, 1102, 1182, 1264, 1347, 1431, 1517, 1604, 1692, 1780, 1869, 1958, 
 ];
 
-const RAMP_8BIT: [u8; 144] = [
+const RAMP_8BIT: [u8; TABLE_LENGTH] = [
       0,   2,   4,   5,   7,   9,  11,  12,  14,  16,  18,  20,  21,  23,  25,  27, 
      29,  30,  32,  34,  36,  37,  39,  41,  43,  45,  46,  48,  50,  52,  53,  55, 
      57,  59,  61,  62,  64,  66,  68,  70,  71,  73,  75,  77,  78,  80,  82,  84, 
@@ -52,31 +58,35 @@ const RAMP_8BIT: [u8; 144] = [
     228, 230, 232, 234, 235, 237, 239, 241, 243, 244, 246, 248, 250, 251, 253, 255, 
 ];
 
+// the DMA channels feeding the sine to DAC channel 2, and the ramp to DAC channel 1
+const SINE_DMA_CHANNEL: dma::Channel = dma::Channel {
+    controller: dma::Controller::Dma2,
+    number: 3,
+};
+const RAMP_DMA_CHANNEL: dma::Channel = dma::Channel {
+    controller: dma::Controller::Dma2,
+    number: 4,
+};
+
 pub fn siggen_setup() {
     cortex_m::interrupt::free(|cs| {
         let rcc = RCC.borrow(cs);
-        let gpioa = GPIOA.borrow(cs);
         let tim2 = TIM2.borrow(cs);
         let dac = DAC.borrow(cs);
-        let dma2 = DMA2.borrow(cs);
 
-        // enable clock to DMA2 and GPIOA
-        rcc.ahbenr.modify(|_, w| w.dma2en().enabled()
-                                  .iopaen().enabled());
+        // enable clock to DMA2
+        SINE_DMA_CHANNEL.enable_clock();
         // enable clock to DAC1 and TIM2
         rcc.apb1enr.modify(|_, w| w.dacen().enabled()
                                    .tim2en().enabled());
 
-        // configure PA4 and PA5 as analog inputs with no pull, so they don't
+        // configure the DAC pins, PA4 and PA5, as analog inputs with no pull, so they don't
         // fight the DAC output
-        gpioa.moder.modify(|_, w|
-            w.moder4().analog()
-             .moder5().analog()
-        );
-        gpioa.pupdr.modify(|_, w| unsafe {
-            w.pupdr4().bits(0b00) // no pull
-             .pupdr5().bits(0b00)
-        });
+        for pin in &DAC_PINS {
+            pin.enable_port_clock();
+            pin.configure(Mode::Analog);
+            pin.set_pull(Pull::None);
+        }
 
         // configure TIM2 for triggering DAC/DMA
         tim2.cr1.modify(|_, w| unsafe {
@@ -90,8 +100,11 @@ pub fn siggen_setup() {
              .arpe().bits(1)   // ARR register is buffered
         });
         tim2.cr2.modify(|_, w| unsafe { w.mms().bits(0b010) }); // trigger output: update event
-        tim2.arr.write(|w| unsafe { w.bits(249) }); // 1kHz, can be changed by siggen_set_freq()
-        tim2.psc.write(|w| unsafe { w.psc().bits(0) }); // prescaler of 1
+        // 1kHz, can be changed by siggen_set_freq()
+        let rate_hz = 1000 * TABLE_LENGTH as u32;
+        let rate = timer::solve(clocks::apb1_timer_hz(), rate_hz, timer::MAX_ARR_32);
+        tim2.arr.write(|w| unsafe { w.bits(rate.arr) });
+        tim2.psc.write(|w| unsafe { w.psc().bits(rate.psc) });
         tim2.egr.write(|w| unsafe { w.ug().bits(1) }); // immediately update registers
 
         // configure DAC
@@ -113,56 +126,34 @@ pub fn siggen_setup() {
              .boff1().bits(0)      // output buffer enabled
         });
 
-        // configure DMA2 channel 3 for DAC channel 2
-        // - assuming reset state
-        dma2.ccr3.modify(|_, w| unsafe {
-            w.mem2mem().bits(0)  // memory-to-memory mode disabled
-             .pl().bits(0b01)    // medium priority
-             .msize().bits(0b01) // memory data size 16 bits
-             .psize().bits(0b01) // peripheral data size 16 bits
-             .minc().bits(1)     // memory increment enabled
-             .pinc().bits(0)     // peripheral increment disabled
-             .circ().bits(1)     // circular mode
-             .dir().bits(1)      // transfer direction: memory -> peripheral
-        });
-        dma2.cndtr3.write(|w| unsafe { w.ndt().bits(144) });  // buffer size
+        // configure DMA2 channel 3 for DAC channel 2, 16 bits at a time
         let dac_dhr12r2_address: u32 = &dac.dhr12r2 as *const _ as u32;
         debug_assert_eq!(dac_dhr12r2_address, 0x40007414);
-        dma2.cpar3.write(|w| unsafe {
-            w.bits(dac_dhr12r2_address) // peripheral base address
-        });
-        dma2.cmar3.write(|w| unsafe {
-            w.bits(&SINE_12BIT as *const _ as u32) // memory base address
-        });
-
-        // configure DMA2 channel 4 for DAC channel 1
-        // - assuming reset state
-        dma2.ccr4.modify(|_, w| unsafe {
-            w.mem2mem().bits(0)  // memory-to-memory mode disabled
-             .pl().bits(0b01)    // medium priority
-             .msize().bits(0b00) // memory data size 8 bits
-             .psize().bits(0b00) // peripheral data size 8 bits
-             .minc().bits(1)     // memory increment enabled
-             .pinc().bits(0)     // peripheral increment disabled
-             .circ().bits(1)     // circular mode
-             .dir().bits(1)      // transfer direction: memory -> peripheral
-        });
-        dma2.cndtr4.write(|w| unsafe { w.ndt().bits(144) });  // buffer size
+        let mut config = dma::Config {
+            direction: dma::Direction::MemoryToPeripheral,
+            priority: dma::Priority::Medium,
+            size: dma::Size::Bits16,
+            circular: true,
+            interrupt_on_complete: false,
+            interrupt_on_half: false,
+            memory_to_memory: false,
+        };
+        SINE_DMA_CHANNEL.configure(&config, dac_dhr12r2_address,
+                                   &SINE_12BIT as *const _ as u32, SINE_12BIT.len() as u16);
+
+        // configure DMA2 channel 4 for DAC channel 1, 8 bits at a time
         let dac_dhr8r1_address: u32 = &dac.dhr8r1 as *const _ as u32;
         debug_assert_eq!(dac_dhr8r1_address, 0x40007410);
-        dma2.cpar4.write(|w| unsafe {
-            w.bits(dac_dhr8r1_address) // peripheral base address
-        });
-        dma2.cmar4.write(|w| unsafe {
-            w.bits(&RAMP_8BIT as *const _ as u32) // memory base address
-        });
+        config.size = dma::Size::Bits8;
+        RAMP_DMA_CHANNEL.configure(&config, dac_dhr8r1_address,
+                                   &RAMP_8BIT as *const _ as u32, RAMP_8BIT.len() as u16);
 
         // enable DAC channels 1 and 2
         dac.cr.modify(|_, w| unsafe { w.en1().bits(1).en2().bits(1) });
 
         // enable DMA2 channels 3 and 4
-        dma2.ccr3.modify(|_, w| unsafe { w.en().bits(1) });
-        dma2.ccr4.modify(|_, w| unsafe { w.en().bits(1) });
+        SINE_DMA_CHANNEL.start();
+        RAMP_DMA_CHANNEL.start();
 
         // enable DMA for DAC channels 1 and 2
         dac.cr.modify(|_, w| unsafe { w.dmaen1().bits(1).dmaen2().bits(1) });
@@ -172,10 +163,39 @@ pub fn siggen_setup() {
     });
 }
 
-pub fn siggen_set_freq(freq: u32) {
-    let arr = core::cmp::max(36_000_000 / 144 / freq - 1, 1);
+/// Sets the output frequency as close to `freq` as TIM2 can manage. Returns the TIM2 rate
+/// achieved, which is TABLE_LENGTH times the output frequency.
+pub fn siggen_set_freq(freq: u32) -> timer::Rate {
+    let rate = timer::solve(clocks::apb1_timer_hz(), freq * TABLE_LENGTH as u32, timer::MAX_ARR_32);
     cortex_m::interrupt::free(|cs| {
         let tim2 = TIM2.borrow(cs);
-        tim2.arr.write(|w| unsafe { w.bits(arr) });
+        tim2.arr.write(|w| unsafe { w.bits(rate.arr) });
+        tim2.psc.write(|w| unsafe { w.psc().bits(rate.psc) });
+    });
+    rate
+}
+
+/// Stops the ramp, leaving DAC channel 1 to be set by set_channel_1(), e.g. as the comparator's
+/// threshold; or with `on`, starts it again. PA4 follows channel 1 either way.
+pub fn set_ramp(on: bool) {
+    cortex_m::interrupt::free(|cs| {
+        let dac = DAC.borrow(cs);
+        if on {
+            dac.cr.modify(|_, w| unsafe { w.ten1().bits(1) });
+            RAMP_DMA_CHANNEL.start();
+            dac.cr.modify(|_, w| unsafe { w.dmaen1().bits(1) });
+        } else {
+            dac.cr.modify(|_, w| unsafe { w.dmaen1().bits(0) });
+            RAMP_DMA_CHANNEL.stop();
+            // without the trigger, a value written is output a clock later
+            dac.cr.modify(|_, w| unsafe { w.ten1().bits(0) });
+        }
     });
-}
\ No newline at end of file
+}
+
+/// Sets DAC channel 1 to `value`, 12 bits, once the ramp is stopped.
+pub fn set_channel_1(value: u16) {
+    cortex_m::interrupt::free(|cs| {
+        DAC.borrow(cs).dhr12r1.write(|w| unsafe { w.dacc1dhr().bits(value) });
+    });
+}
@@ -0,0 +1,59 @@
+// stm32f3-oscilloscope - src/sim/capture.rs
+// a stand-in for capture.rs, for the host and QEMU builds: the capture buffer, without the ADC
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// There is no ADC: the sweeps come from a replayed capture or a test pattern (see source.rs),
+// written into the capture buffer here just as they would be on the board. The sample rate is
+// whatever the harness sets, and the auxiliary conversions read as a nominal board would.
+
+/// The capacity of the capture buffer, in samples, as on the board.
+pub const BUFFER_LENGTH: usize = 12 * 1024;
+
+pub static mut CAPTURE_CHANNEL_1: [u16; BUFFER_LENGTH] = [0; BUFFER_LENGTH];
+
+static mut SAMPLE_RATE: u32 = 1000;
+
+/// The latest auxiliary measurements.
+#[derive(Clone, Copy)]
+pub struct Auxiliary {
+    pub temperature: i32, // tenths of a degree Celsius
+    pub supply_mv: u32,   // VDDA, the ADC reference, in millivolts
+    pub input_mv: u32,    // the auxiliary DC input, in millivolts
+}
+
+/// There is no DMA to stop.
+pub fn abandon_sweep() {}
+
+/// Returns a board at room temperature, on a 3.3V supply, with nothing on the DC input.
+pub fn auxiliary() -> Auxiliary {
+    Auxiliary { temperature: 250, supply_mv: 3300, input_mv: 0 }
+}
+
+/// Sets the sample rate the sweeps are taken to have been captured at.
+pub fn set_sample_rate(samples_per_second: u32) {
+    unsafe { SAMPLE_RATE = samples_per_second; }
+}
+
+pub fn sample_rate() -> u32 {
+    unsafe { SAMPLE_RATE }
+}
@@ -0,0 +1,56 @@
+// stm32f3-oscilloscope - src/sim/delay.rs
+// a stand-in for delay.rs, for the host and QEMU builds
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// Waiting moves the harness's clock on (see sim/time.rs) rather than spinning, so code that
+// waits out a delay sees the time pass, and a deadline works as it does on the board.
+
+use time::{self, millis};
+
+/// Waits for at least `us` microseconds, rounded up to the clock's whole milliseconds.
+pub fn delay_us(us: u32) {
+    time::advance((us + 999) / 1000);
+}
+
+/// Waits for at least `ms` milliseconds.
+pub fn delay_ms(ms: u32) {
+    time::advance(ms);
+}
+
+/// A time to wait for, without blocking.
+#[derive(Clone, Copy)]
+pub struct Deadline {
+    at: u32, // in milliseconds since the harness started
+}
+
+impl Deadline {
+    /// Returns a deadline `ms` milliseconds from now, up to about 24 days.
+    pub fn after_ms(ms: u32) -> Deadline {
+        Deadline { at: millis().wrapping_add(ms) }
+    }
+
+    /// Returns whether the deadline has passed.
+    pub fn passed(&self) -> bool {
+        (millis().wrapping_sub(self.at) as i32) >= 0
+    }
+}
@@ -0,0 +1,50 @@
+// stm32f3-oscilloscope - src/sim/flash.rs
+// a stand-in for flash.rs, for the host and QEMU builds: the storage page in RAM
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// The storage page starts out erased, so calibration.rs and settings.rs find no record and
+// keep their defaults, and a record stored is read back until the harness exits.
+
+/// The number of half-words at the start of the storage page that are kept when a record is
+/// stored.
+pub const STORED_LENGTH: usize = 64;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FlashError {
+    Program,        // PGERR: the location was not erased
+    WriteProtected, // WRPRTERR
+}
+
+static mut PAGE: [u16; STORED_LENGTH] = [0xffff; STORED_LENGTH];
+
+/// Writes `data` at `offset` half-words into the storage page, keeping the rest.
+pub fn store(offset: usize, data: &[u16]) -> Result<(), FlashError> {
+    debug_assert!(offset + data.len() <= STORED_LENGTH);
+    unsafe { PAGE[offset..offset + data.len()].copy_from_slice(data); }
+    Ok(())
+}
+
+/// Returns the half-word at `offset` half-words into the storage page.
+pub fn read(offset: usize) -> u16 {
+    if offset < STORED_LENGTH { unsafe { PAGE[offset] } } else { 0xffff }
+}
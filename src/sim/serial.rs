@@ -0,0 +1,138 @@
+// stm32f3-oscilloscope - src/sim/serial.rs
+// a stand-in for serial.rs, for the host and QEMU builds: output kept for checking, input fed in
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// Whatever would be sent on the serial port is kept in a buffer instead, so the harness can
+// check the frames and replies the firmware sends (see frame.rs). Bytes past the buffer's
+// end are dropped, and counted. What the harness feeds in is read back as if it had been
+// typed, for the shell (see shell.rs).
+
+const OUTPUT_LENGTH: usize = 4096;
+
+static mut OUTPUT: [u8; OUTPUT_LENGTH] = [0; OUTPUT_LENGTH];
+static mut WRITTEN: usize = 0; // including any dropped
+
+const INPUT_LENGTH: usize = 256;
+
+static mut INPUT: [u8; INPUT_LENGTH] = [0; INPUT_LENGTH];
+static mut INPUT_END: usize = 0;
+static mut INPUT_READ: usize = 0;
+
+pub fn write_byte(byte: u8) {
+    unsafe {
+        if WRITTEN < OUTPUT_LENGTH {
+            OUTPUT[WRITTEN] = byte;
+        }
+        WRITTEN += 1;
+    }
+}
+
+pub fn write(bytes: &[u8]) {
+    for &byte in bytes {
+        write_byte(byte);
+    }
+}
+
+/// Writes `n` in decimal.
+pub fn write_number(n: u32) {
+    let mut buf = [0u8; 10];
+    let mut i = buf.len();
+    let mut n = n;
+    loop {
+        i -= 1;
+        buf[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    write(&buf[i..]);
+}
+
+/// Writes `value`, scaled by 10^decimals, in decimal with a point: 1650 with 3 decimals is
+/// "1.650".
+pub fn write_fixed(value: u64, decimals: usize) {
+    let mut scale = 1;
+    for _ in 0..decimals {
+        scale *= 10;
+    }
+    write_number((value / scale) as u32);
+    if decimals > 0 {
+        write_byte(b'.');
+        let fraction = value % scale;
+        let mut digit_scale = scale / 10;
+        while digit_scale > 0 {
+            write_byte(b'0' + (fraction / digit_scale % 10) as u8);
+            digit_scale /= 10;
+        }
+    }
+}
+
+/// Writes `value`, scaled by 10^decimals, as write_fixed(), with a '-' if it is negative.
+pub fn write_signed_fixed(value: i64, decimals: usize) {
+    if value < 0 {
+        write_byte(b'-');
+    }
+    write_fixed(value.abs() as u64, decimals);
+}
+
+/// Returns what has been written since the last clear(), up to the buffer's length, and the
+/// number of bytes written in all.
+pub fn output() -> (&'static [u8], usize) {
+    unsafe {
+        let kept = if WRITTEN < OUTPUT_LENGTH { WRITTEN } else { OUTPUT_LENGTH };
+        (&OUTPUT[..kept], WRITTEN)
+    }
+}
+
+/// Empties the output buffer.
+pub fn clear() {
+    unsafe { WRITTEN = 0; }
+}
+
+/// Adds `bytes` to what is waiting to be read, as if they had been typed. Any that were fed
+/// before and not yet read are kept, and what doesn't fit is dropped.
+pub fn feed(bytes: &[u8]) {
+    unsafe {
+        let kept = INPUT_END - INPUT_READ;
+        for i in 0..kept {
+            INPUT[i] = INPUT[INPUT_READ + i];
+        }
+        let room = INPUT_LENGTH - kept;
+        let n = if bytes.len() < room { bytes.len() } else { room };
+        INPUT[kept..kept + n].copy_from_slice(&bytes[..n]);
+        INPUT_READ = 0;
+        INPUT_END = kept + n;
+    }
+}
+
+/// Returns the next byte fed in, if there is one.
+pub fn read() -> Option<u8> {
+    unsafe {
+        if INPUT_READ == INPUT_END {
+            return None;
+        }
+        INPUT_READ += 1;
+        Some(INPUT[INPUT_READ - 1])
+    }
+}
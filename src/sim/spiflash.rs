@@ -0,0 +1,55 @@
+// stm32f3-oscilloscope - src/sim/spiflash.rs
+// a stand-in for spiflash.rs, for the host and QEMU builds: no external flash chip
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// The harness has no chip fitted, as a board without one, so setup() finds none and the
+// records that would be kept in it (see records.rs) report it absent.
+
+/// The size of a sector, the smallest erasable unit, in bytes.
+pub const SECTOR_SIZE: u32 = 4096;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SpiFlashError {
+    Absent,  // no chip was found at startup
+    Timeout, // the chip stayed busy
+}
+
+pub fn setup() -> Option<u32> {
+    None
+}
+
+pub fn size() -> u32 {
+    0
+}
+
+pub fn read(_address: u32, _data: &mut [u8]) -> Result<(), SpiFlashError> {
+    Err(SpiFlashError::Absent)
+}
+
+pub fn write(_address: u32, _data: &[u8]) -> Result<(), SpiFlashError> {
+    Err(SpiFlashError::Absent)
+}
+
+pub fn erase_sector(_address: u32) -> Result<(), SpiFlashError> {
+    Err(SpiFlashError::Absent)
+}
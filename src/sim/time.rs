@@ -0,0 +1,48 @@
+// stm32f3-oscilloscope - src/sim/time.rs
+// a stand-in for time.rs, for the host and QEMU builds: a clock the harness moves on
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// There is no SysTick to count milliseconds, so time stands still until the harness calls
+// advance(), and a sweep's samples arrive, or a deadline passes, only as it does.
+
+static mut TICKS: u64 = 0; // milliseconds since the harness started
+
+/// Moves the clock on by `ms` milliseconds.
+pub fn advance(ms: u32) {
+    unsafe { TICKS += ms as u64; }
+}
+
+/// Returns the number of milliseconds since the harness started, without wrapping.
+pub fn ticks() -> u64 {
+    unsafe { TICKS }
+}
+
+/// Returns the number of milliseconds since the harness started, wrapping after about 49 days.
+pub fn millis() -> u32 {
+    ticks() as u32
+}
+
+/// Returns the number of milliseconds since `then`, a previous reading of millis().
+pub fn since(then: u32) -> u32 {
+    millis().wrapping_sub(then)
+}
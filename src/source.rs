@@ -0,0 +1,126 @@
+// stm32f3-oscilloscope - src/source.rs
+// acquisition sources: where a sweep's samples come from
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+
+// The main loop takes each sweep from the active AcquisitionSource, rather than from capture.rs
+// directly, so the trigger search, plotting, and measurements work the same whatever fills the
+// record. A source fills a buffer of raw 12-bit samples, as ADC1 would give them, and says how
+// many of the sweep's samples are ready so far, so plotting can follow the sweep as it fills.
+//
+// The sources keep their state in their own modules' statics, like the rest of the firmware,
+// so each is a unit struct, and active() returns one as a trait object: the ADC capture path,
+// or while a test pattern is selected, the pattern (see testpattern.rs). Chaining, the loopback
+// channel, and the auxiliary conversions are particular to the ADC, and are still reached
+// through capture.rs.
+//
+// The host build has no ADC, so there the input is a capture replayed from a file instead (see
+// src/host/replay.rs), filling the same buffer.
+
+#[cfg(not(feature = "host"))]
+use capture;
+#[cfg(feature = "host")]
+use replay::Replay;
+use testpattern::{self, Pattern, TestPattern};
+
+/// Something the sweeps' samples can be taken from.
+pub trait AcquisitionSource {
+    /// Begins a sweep of `samples` samples.
+    fn start(&self, samples: usize);
+
+    /// Stops the sweep, discarding the rest of it, e.g. when no trigger was found.
+    fn stop(&self);
+
+    /// Returns the number of the sweep's samples ready so far.
+    fn samples_ready(&self) -> usize;
+
+    /// Returns the sweep's samples, from its start, of which samples_ready() are valid.
+    fn data(&self) -> &'static [u16];
+
+    /// Ends the sweep, once its record is complete. A source that can carry straight on into
+    /// the next sweep may leave it running; by default, the sweep is stopped.
+    fn finish(&self) {
+        self.stop();
+    }
+
+    /// Keeps the finished sweep's record, samples `start` to `end`, out of the next sweep's way,
+    /// so that sweep can start while the record is still being worked on. By default, the
+    /// record stays where it is, and the next sweep starts as usual.
+    fn snapshot(&self, _start: usize, _end: usize) {}
+
+    /// Returns the finished sweep's record, samples `start` to `end`.
+    fn record(&self, start: usize, end: usize) -> &'static [u16] {
+        &self.data()[start..end]
+    }
+}
+
+/// The input pin, sampled by ADC1 under DMA (see capture.rs).
+#[cfg(not(feature = "host"))]
+pub struct AdcCapture;
+
+#[cfg(not(feature = "host"))]
+impl AcquisitionSource for AdcCapture {
+    fn start(&self, samples: usize) {
+        capture::begin_sweep(samples);
+    }
+
+    fn stop(&self) {
+        capture::abandon_sweep();
+    }
+
+    fn samples_ready(&self) -> usize {
+        capture::get_transferred_sample_count()
+    }
+
+    fn data(&self) -> &'static [u16] {
+        capture::channel_1_data()
+    }
+
+    fn finish(&self) {
+        capture::finish_sweep(); // a chained sweep carries on
+    }
+
+    fn snapshot(&self, start: usize, end: usize) {
+        capture::snapshot(start, end - start);
+    }
+
+    fn record(&self, start: usize, end: usize) -> &'static [u16] {
+        capture::record(start, end)
+    }
+}
+
+#[cfg(not(feature = "host"))]
+static INPUT: AdcCapture = AdcCapture;
+#[cfg(feature = "host")]
+static INPUT: Replay = Replay;
+static TEST_PATTERN: TestPattern = TestPattern;
+
+/// Returns the source the sweeps are taken from.
+pub fn active() -> &'static AcquisitionSource {
+    if testpattern::pattern() == Pattern::Off {
+        &INPUT
+    } else {
+        &TEST_PATTERN
+    }
+}
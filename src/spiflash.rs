@@ -0,0 +1,197 @@
+// stm32f3-oscilloscope - src/spiflash.rs
+// external SPI NOR flash: a W25Qxx, or a chip with the same commands
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+
+// An optional W25Qxx (or compatible) SPI NOR flash, adding megabytes of storage beside the
+// on-chip flash, which has little room and wears out after 10k erases a page (see records.rs
+// for what is kept in it). SPI2 belongs to the LCD, so the chip is driven in software, in SPI
+// mode 0, on any four GPIO pins listed in board.rs. Each clock edge is a single write to the
+// port's BSRR or BRR, a few megahertz at the usual clock, far below the chip's limit, so no
+// delays are needed.
+//
+// setup() reads the JEDEC ID: with no chip, MISO floats, and is pulled up, so the ID reads as
+// all ones, and the external storage is left unused. The capacity is in the ID's third byte,
+// as a power of two, up to the 16MB reached by three address bytes.
+//
+// Like the on-chip flash, a bit can only be programmed from one to zero, and only erased a
+// whole 4K sector at a time, back to ones. Programming is a page, 256 bytes, at most, so
+// write() splits its data at page boundaries. See the W25Q16 datasheet, sections 8 and 9.
+
+use board::SPI_FLASH_PINS;
+use delay::{self, Deadline};
+use gpio::{Mode, Pull};
+
+/// The size of a sector, the smallest erasable unit, in bytes.
+pub const SECTOR_SIZE: u32 = 4096;
+const PAGE_SIZE: u32 = 256;
+
+const WRITE_ENABLE: u8 = 0x06;
+const READ_STATUS_1: u8 = 0x05;
+const READ_DATA: u8 = 0x03;
+const PAGE_PROGRAM: u8 = 0x02;
+const SECTOR_ERASE: u8 = 0x20;
+const RELEASE_POWER_DOWN: u8 = 0xab;
+const JEDEC_ID: u8 = 0x9f;
+
+const BUSY: u8 = 0x01; // in status register 1
+
+// the longest a page program and a sector erase take, with some margin
+const PROGRAM_TIMEOUT_MS: u32 = 10;
+const ERASE_TIMEOUT_MS: u32 = 1000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SpiFlashError {
+    Absent,  // no chip was found at startup
+    Timeout, // the chip stayed busy
+}
+
+static mut SIZE: u32 = 0; // bytes, 0 with no chip
+
+/// Sets up the pins and looks for the chip, returning its size in bytes, if there is one.
+pub fn setup() -> Option<u32> {
+    let pins = &SPI_FLASH_PINS;
+    for pin in &[pins.cs, pins.sck, pins.mosi, pins.miso] {
+        pin.enable_port_clock();
+    }
+    pins.cs.set_high();
+    pins.sck.set_low();
+    for pin in &[pins.cs, pins.sck, pins.mosi] {
+        pin.configure(Mode::Output);
+    }
+    pins.miso.set_pull(Pull::Up);
+    pins.miso.configure(Mode::Input);
+    // wake the chip, in case it was left powered down, then give it the 3us it needs
+    command(&[RELEASE_POWER_DOWN], &mut []);
+    delay::delay_us(5);
+    let mut id = [0u8; 3];
+    command(&[JEDEC_ID], &mut id);
+    let size = match (id[0], id[2]) {
+        (0x00, _) | (0xff, _) => return None,
+        (_, capacity @ 0x10...0x18) => 1 << capacity,
+        _ => return None,
+    };
+    unsafe { SIZE = size; }
+    Some(size)
+}
+
+/// The chip's size in bytes, or 0 if there is none.
+pub fn size() -> u32 {
+    unsafe { SIZE }
+}
+
+/// Reads `data.len()` bytes from `address`.
+pub fn read(address: u32, data: &mut [u8]) -> Result<(), SpiFlashError> {
+    present()?;
+    command(&[READ_DATA, (address >> 16) as u8, (address >> 8) as u8, address as u8], data);
+    Ok(())
+}
+
+/// Programs `data` at `address`, which must have been erased.
+pub fn write(address: u32, data: &[u8]) -> Result<(), SpiFlashError> {
+    present()?;
+    let mut address = address;
+    let mut rest = data;
+    while !rest.is_empty() {
+        // up to the end of the page
+        let room = (PAGE_SIZE - address % PAGE_SIZE) as usize;
+        let n = if rest.len() < room { rest.len() } else { room };
+        command(&[WRITE_ENABLE], &mut []);
+        let pins = &SPI_FLASH_PINS;
+        pins.cs.set_low();
+        for &byte in &[PAGE_PROGRAM, (address >> 16) as u8, (address >> 8) as u8, address as u8] {
+            transfer(byte);
+        }
+        for &byte in &rest[..n] {
+            transfer(byte);
+        }
+        pins.cs.set_high();
+        wait(PROGRAM_TIMEOUT_MS)?;
+        address += n as u32;
+        rest = &rest[n..];
+    }
+    Ok(())
+}
+
+/// Erases the sector at `address`, a multiple of SECTOR_SIZE.
+pub fn erase_sector(address: u32) -> Result<(), SpiFlashError> {
+    debug_assert!(address % SECTOR_SIZE == 0);
+    present()?;
+    command(&[WRITE_ENABLE], &mut []);
+    command(&[SECTOR_ERASE, (address >> 16) as u8, (address >> 8) as u8, address as u8],
+            &mut []);
+    wait(ERASE_TIMEOUT_MS)
+}
+
+fn present() -> Result<(), SpiFlashError> {
+    if size() == 0 { Err(SpiFlashError::Absent) } else { Ok(()) }
+}
+
+// wait for a program or erase to finish
+fn wait(timeout_ms: u32) -> Result<(), SpiFlashError> {
+    let deadline = Deadline::after_ms(timeout_ms);
+    loop {
+        let mut status = [0u8];
+        command(&[READ_STATUS_1], &mut status);
+        if status[0] & BUSY == 0 {
+            return Ok(());
+        }
+        if deadline.passed() {
+            return Err(SpiFlashError::Timeout);
+        }
+    }
+}
+
+// send the bytes of a command, then read the bytes of its reply, with the chip selected
+fn command(out: &[u8], reply: &mut [u8]) {
+    let pins = &SPI_FLASH_PINS;
+    pins.cs.set_low();
+    for &byte in out {
+        transfer(byte);
+    }
+    for byte in reply.iter_mut() {
+        *byte = transfer(0xff);
+    }
+    pins.cs.set_high();
+}
+
+// shift a byte out and one in, most significant bit first: the chip samples MOSI on the rising
+// edge of SCK, and changes MISO on the falling edge
+fn transfer(out: u8) -> u8 {
+    let pins = &SPI_FLASH_PINS;
+    let mut byte = out;
+    let mut received = 0u8;
+    for _ in 0..8 {
+        if byte & 0x80 != 0 {
+            pins.mosi.set_high();
+        } else {
+            pins.mosi.set_low();
+        }
+        byte <<= 1;
+        pins.sck.set_high();
+        received = received << 1 | pins.miso.is_high() as u8;
+        pins.sck.set_low();
+    }
+    received
+}
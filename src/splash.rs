@@ -0,0 +1,73 @@
+// stm32f3-oscilloscope - src/splash.rs
+// boot splash screen
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use st7735::*;
+
+use delay::delay_ms;
+
+/// How long the splash screen is shown, in milliseconds.
+const SPLASH_MS: u32 = 1000;
+
+// a little oscilloscope screen, showing a sine wave: 64x40 pixels, 309 runs
+const LOGO: RleBitmap = RleBitmap {
+    width: 64,
+    height: 40,
+    runs: &[
+        1, 62, 1, 66, 60, 4, 60, 4, 60, 4, 60, 4, 60, 4, 8, 4,
+        24, 4, 20, 4, 6, 7, 1, 1, 7, 1, 7, 1, 4, 6, 5, 1,
+        7, 1, 5, 4, 7, 2, 2, 2, 22, 2, 2, 2, 19, 4, 6, 2,
+        4, 2, 20, 2, 4, 2, 18, 4, 6, 2, 4, 2, 20, 2, 4, 2,
+        18, 4, 5, 2, 6, 2, 18, 2, 6, 2, 17, 4, 5, 2, 6, 2,
+        18, 2, 6, 2, 17, 4, 4, 2, 8, 2, 16, 2, 8, 2, 16, 4,
+        4, 2, 8, 2, 16, 2, 8, 2, 16, 4, 3, 2, 1, 1, 7, 3,
+        5, 1, 7, 3, 5, 1, 4, 2, 1, 1, 7, 1, 5, 4, 3, 2,
+        10, 2, 14, 2, 10, 2, 15, 4, 3, 1, 12, 1, 14, 1, 12, 1,
+        15, 4, 2, 2, 12, 2, 12, 2, 12, 2, 14, 4, 2, 2, 12, 2,
+        12, 2, 12, 2, 14, 4, 17, 1, 12, 1, 14, 1, 14, 4, 17, 2,
+        10, 2, 14, 2, 10, 1, 2, 4, 17, 2, 10, 2, 14, 2, 10, 1,
+        2, 4, 6, 1, 7, 1, 3, 2, 2, 1, 5, 3, 7, 1, 7, 2,
+        6, 1, 1, 2, 2, 4, 18, 2, 8, 2, 16, 2, 8, 2, 2, 4,
+        19, 2, 6, 2, 18, 2, 6, 2, 3, 4, 19, 2, 6, 2, 18, 2,
+        6, 2, 3, 4, 20, 2, 4, 2, 20, 2, 4, 2, 4, 4, 20, 2,
+        4, 2, 20, 2, 4, 2, 4, 4, 21, 2, 2, 2, 22, 2, 2, 2,
+        5, 4, 21, 6, 22, 6, 5, 4, 6, 1, 7, 1, 7, 4, 4, 1,
+        7, 1, 7, 1, 3, 5, 5, 4, 60, 4, 60, 4, 60, 4, 60, 4,
+        60, 66, 1, 62, 1,
+    ],
+};
+
+/// Shows the splash screen: the logo, name, and version. This leaves the screen for the caller
+/// to clear.
+pub fn show() {
+    let width = st7735_get_width(); // centered on any size of screen
+    st7735_fillScreen(St7735Color::Black as u16);
+    st7735_draw_rle_bitmap(&LOGO, (width - LOGO.width) / 2, 20, St7735Color::Green,
+                           St7735Color::Black);
+    st7735_print(b"stm-scope", (width - 9 * 8) / 2, 72, St7735Color::White, St7735Color::Black);
+    let version = env!("CARGO_PKG_VERSION").as_bytes();
+    let x = (width - 8 * (version.len() as u8 + 1)) / 2;
+    st7735_print(b"v", x, 88, St7735Color::Green, St7735Color::Black);
+    st7735_print(version, x + 8, 88, St7735Color::Green, St7735Color::Black);
+    delay_ms(SPLASH_MS);
+}
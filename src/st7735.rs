@@ -23,33 +23,50 @@
 
 #![allow(non_snake_case)]
 
+use core::cmp::{max, min};
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
 use core::ptr;
 
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
 use cortex_m;
-use stm32f30x::{GPIOB, RCC};
-#[cfg(not(feature = "software-spi"))]
-use stm32f30x::SPI2;
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
+use stm32f30x::{RCC, SPI2};
+
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
+use board::LCD_SPI_AF;
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
+use clocks;
+#[cfg(not(feature = "host"))]
+use board::LCD_PINS;
+#[cfg(not(feature = "host"))]
+use gpio::Mode;
 
 use parallax_8x12_font;
-use { // C functions
-    _st7735_drawFastHLine,
-    _st7735_drawFastVLine,
-    _st7735_drawPixel,
-    _st7735_fillScreen,
-    _st7735_get_height,
-    _st7735_get_width,
-    _st7735_initR,
-    _st7735_setAddrWindow,
-    _st7735_setRotation
-};
+#[cfg(not(feature = "host"))]
+use _st7735_initR; // C function
 
 // ======== ST7735 "type" and color enums ========
 
 #[allow(unused)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum St7735Type {
     GreenTab = 0,
     RedTab,
     BlackTab,
+    GreenTab128, // the 1.44" 128x128 green tab module
+}
+
+impl St7735Type {
+    /// The fastest SPI clock worth trying with this module. The red tab module, which this was
+    /// developed with, is kept within the datasheet's limit; the others may try the next step
+    /// up, as st7735_probe_spi_clock() falls back if it doesn't work.
+    #[allow(unused)]
+    pub fn max_spi_hz(self) -> u32 {
+        match self {
+            St7735Type::RedTab => 15_000_000,
+            St7735Type::GreenTab | St7735Type::BlackTab | St7735Type::GreenTab128 => 18_000_000,
+        }
+    }
 }
 
 #[allow(unused)]
@@ -60,48 +77,33 @@ pub enum St7735Color {
     Green = 0x07e0,
     Red = 0xf800,
     White = 0xffff,
+    Cyan = 0x07ff,
+    Magenta = 0xf81f,
+    Yellow = 0xffe0,
 }
 
 // ======== hardware SPI ========
 
-// set up the hardware to use hardware SPI: SPI2 on PB13 (SCK/SCL) and PB15 (SDA/MOSI)
-#[cfg(not(feature = "software-spi"))]
+// set up the hardware to use hardware SPI: SPI2, on the SCK and MOSI pins in board.rs
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
 pub fn st7735_setup() {
+    // configure GPIO pins
+    for pin in &[LCD_PINS.cs, LCD_PINS.dc, LCD_PINS.rst] {
+        pin.enable_port_clock();
+        pin.configure(Mode::Output);
+    }
+    // set alternate function on SPI2 CLK and MOSI pins
+    for pin in &[LCD_PINS.sck, LCD_PINS.mosi] {
+        pin.enable_port_clock();
+        pin.set_alternate_function(LCD_SPI_AF);
+        pin.configure(Mode::Alternate);
+    }
+
     cortex_m::interrupt::free(|cs| {
         let rcc = RCC.borrow(cs);
-        let gpiob = GPIOB.borrow(cs);
         let spi2 = SPI2.borrow(cs);
-        rcc.ahbenr.modify(|_, w| w.iopben().enabled());
         rcc.apb1enr.modify(|_, w| w.spi2en().enabled());
 
-        // configure GPIO pins
-        gpiob.moder.modify(|_, w|
-            w.moder10().output()    // PB10: CSE/CS
-             .moder12().output()    // PB12: A0/RS/DC
-             .moder13().alternate() // PB13: SCK/SCL
-             .moder14().output()    // PB14: RST
-             .moder15().alternate() // PB15: SDA/MOSI
-        );
-        gpiob.otyper.modify(|_, w| unsafe {
-            w.ot10().bits(0) // push-pull
-             .ot12().bits(0)
-             .ot13().bits(0)
-             .ot14().bits(0)
-             .ot15().bits(0)
-        });
-        gpiob.ospeedr.modify(|_, w| unsafe {
-            w.ospeedr10().bits(0b11) // fast
-             .ospeedr12().bits(0b11)
-             .ospeedr13().bits(0b11)
-             .ospeedr14().bits(0b11)
-             .ospeedr15().bits(0b11)
-        });
-        // set alternate function on SPI2 CLK and MOSI pins
-        gpiob.afrh.modify(|_, w| unsafe {
-            w.afrh13().bits(0b0101) // PB13 AF5: SPI2_CLK - SCK/SCL
-             .afrh15().bits(0b0101) // PB15 AF5: SPI2_MOSI - SDA
-        });
-
         // configure SPI2
         rcc.apb1rstr.modify(|_, w| unsafe { w.spi2rst().bits(1) }); // set SPI2 reset bit
         rcc.apb1rstr.modify(|_, w| unsafe { w.spi2rst().bits(0) }); // clear reset bit
@@ -111,12 +113,15 @@ pub fn st7735_setup() {
              .ssm().bits(1)      // SPI_NSS_Soft
              .ssi().bits(1)      // part of 'SPI_Mode_Master'! will mode fault without this!
              .lsbfirst().bits(0) // SPI_FirstBit_MSB
-             .br().bits(0b010)   // f_PCLK/8 - 72MHz/2/8 = 4.5MHz, or near the ST7735's limit
+             .br().bits(SPI_SAFE_BR) // until st7735_probe_spi_clock() finds a faster one
              .mstr().bits(1)     // SPI_Mode_Master
              .cpol().bits(0)     // SPI_CPOL_Low
              .cpha().bits(0)     // SPI_CPHA_1Edge
         });
-        spi2.cr2.modify(|_, w| unsafe { w.ds().bits(0b0111) }); // SPI_DataSize_8b
+        spi2.cr2.modify(|_, w| unsafe {
+            w.ds().bits(0b0111)   // SPI_DataSize_8b
+             .frxth().bits(1)     // RXNE on each byte received, for st7735_read_byte()
+        });
         spi2.i2scfgr.modify(|_, w| unsafe { w.i2smod().bits(0) }); // SPI mode
         // enable SPI2
         spi2.cr1.modify(|_, w| unsafe { w.spe().bits(1) });
@@ -126,7 +131,7 @@ pub fn st7735_setup() {
 }
 
 // send a byte of data to the LCD via hardware SPI
-#[cfg(not(feature = "software-spi"))]
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
 fn st7735_send_byte(data_in: u8) {
     unsafe {
         while (*SPI2.get()).sr.read().txe().bits() == 0 {}
@@ -142,20 +147,135 @@ fn st7735_send_byte(data_in: u8) {
     }
 }
 
-#[cfg(not(feature = "software-spi"))]
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
 fn spi2_wait_while_busy() {
     unsafe {
         while (*SPI2.get()).sr.read().bsy().bits() != 0 {}
     }
 }
 
-#[cfg(not(feature = "software-spi"))]
+// ======== hardware SPI clock ========
+
+// SPI2 is clocked from PCLK1, and divides it by 2 << BR. Writes to the ST7735 are specified up
+// to 15MHz, and reads to 6.6MHz, but many modules manage more, so st7735_probe_spi_clock() tries
+// the faster settings and keeps the fastest that works. Reads are always done at SPI_SAFE_BR.
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
+const SPI_SAFE_BR: u8 = 0b010; // f_PCLK/8 - 72MHz/2/8 = 4.5MHz, at the usual clock
+
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
+const ST7735_RDDMADCTL: u8 = 0x0b;
+
+// MADCTL values to write and read back; the low two bits always read as zero
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
+const PROBE_PATTERNS: [u8; 2] = [0xa8, 0x54];
+
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
+fn spi2_clock_hz(br: u8) -> u32 {
+    clocks::pclk1_hz() / (2 << br)
+}
+
+// change the SPI2 baud rate divider, which may only be done while SPI2 is disabled
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
+fn spi2_set_br(br: u8) {
+    unsafe {
+        let spi2 = &*SPI2.get();
+        while spi2.sr.read().txe().bits() == 0 {}
+        spi2_wait_while_busy();
+        spi2.cr1.modify(|_, w| w.spe().bits(0));
+        spi2.cr1.modify(|_, w| w.br().bits(br));
+        spi2.cr1.modify(|_, w| w.spe().bits(1));
+    }
+}
+
+// Send a command, then read its reply into `buf`, after `dummy_bytes` to be discarded, by
+// turning the bidirectional data line around. In receive mode SPI2 clocks continuously, so it
+// is disabled as soon as the last byte arrives, and any extra received is discarded; the ST7735
+// ignores the extra clocks, and the next command ends the read.
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
+fn st7735_read(cmd: u8, dummy_bytes: usize, buf: &mut [u8]) {
+    st7735_send_cmd(cmd);
+    unsafe {
+        let spi2 = &*SPI2.get();
+        while spi2.sr.read().txe().bits() == 0 {}
+        spi2_wait_while_busy();
+        let dr = &spi2.dr as *const _ as *const u8;
+        let total = dummy_bytes + buf.len();
+        cortex_m::interrupt::free(|_| {
+            spi2.cr1.modify(|_, w| w.bidioe().bits(0)); // receive, which starts the clock
+            for i in 0..total {
+                while spi2.sr.read().rxne().bits() == 0 {}
+                if i + 1 == total {
+                    spi2.cr1.modify(|_, w| w.spe().bits(0));
+                }
+                let byte = ptr::read_volatile(dr);
+                if i >= dummy_bytes {
+                    buf[i - dummy_bytes] = byte;
+                }
+            }
+        });
+        while spi2.sr.read().bsy().bits() != 0 {}
+        while spi2.sr.read().rxne().bits() != 0 {
+            ptr::read_volatile(dr);
+        }
+        spi2.cr1.modify(|_, w| w.bidioe().bits(1).spe().bits(1));
+    }
+}
+
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
+fn st7735_read_byte(cmd: u8) -> u8 {
+    let mut value = [0];
+    st7735_read(cmd, 0, &mut value);
+    value[0]
+}
+
+// write each pattern to MADCTL at the given speed, and check it reads back at the safe speed
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
+fn spi2_probe(br: u8) -> bool {
+    PROBE_PATTERNS.iter().all(|&pattern| {
+        spi2_set_br(br);
+        st7735_send_cmd(ST7735_MADCTL);
+        st7735_send_data(pattern);
+        spi2_set_br(SPI_SAFE_BR);
+        st7735_read_byte(ST7735_RDDMADCTL) == pattern
+    })
+}
+
+/// Sets the fastest SPI clock, up to `max_hz` and the module's own limit, at which the display
+/// can be written reliably, and returns it in Hz. Each setting is tested by writing a register
+/// and reading it back; if the module can't be read at all, the safe 4.5MHz is kept. Call this
+/// after st7735_initR() and before st7735_setRotation(), since the test overwrites MADCTL.
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
+pub fn st7735_probe_spi_clock(lcd_type: St7735Type, max_hz: u32) -> Option<u32> {
+    let max_hz = min(max_hz, lcd_type.max_spi_hz());
+    let mut br = 0;
+    while br < SPI_SAFE_BR && (spi2_clock_hz(br) > max_hz || !spi2_probe(br)) {
+        br += 1;
+    }
+    spi2_set_br(br);
+    Some(spi2_clock_hz(br))
+}
+
+/// Reads `w` pixels of row `y`, starting at column `x`, back from the display into `rgb`, as
+/// three bytes of red, green, and blue for each, with the 6 bits the display keeps in the high
+/// bits of each byte. Returns false if the display can't be read.
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
+pub fn st7735_read_row(x: u8, y: u8, w: u8, rgb: &mut [u8]) -> bool {
+    let br = unsafe { (*SPI2.get()).cr1.read().br().bits() };
+    spi2_set_br(SPI_SAFE_BR);
+    st7735_setAddrWindow(x, y, x + w - 1, y);
+    // RAMRD replies with a dummy byte, then the pixels in 18-bit color
+    st7735_read(ST7735_RAMRD, 1, &mut rgb[..3 * w as usize]);
+    spi2_set_br(br);
+    true
+}
+
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
 fn lcd_dc() -> bool {
-    0 != unsafe { (*GPIOB.get()).odr.read().odr12().bits() } // read PB12: A0/RS/DC
+    LCD_PINS.dc.is_set_high()
 }
 
 // send a command byte to the LCD controller
-#[cfg(not(feature = "software-spi"))]
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
 #[no_mangle]
 #[used]
 pub extern "C" fn st7735_send_cmd(cmd: u8) {
@@ -168,7 +288,7 @@ pub extern "C" fn st7735_send_cmd(cmd: u8) {
 }
 
 // send a data byte to the LCD controller
-#[cfg(not(feature = "software-spi"))]
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
 #[no_mangle]
 #[used]
 pub extern "C" fn st7735_send_data(data: u8) {
@@ -182,49 +302,28 @@ pub extern "C" fn st7735_send_data(data: u8) {
 
 // ======== software SPI ========
 
-// set up the hardware to use software SPI: bit-banging on PB13 (SCK/SCL) and PB15 (SDA/MOSI)
-#[cfg(feature = "software-spi")]
+// set up the hardware to use software SPI: bit-banging on the SCK and MOSI pins in board.rs
+#[cfg(all(feature = "software-spi", not(feature = "host")))]
 pub fn st7735_setup() {
-    cortex_m::interrupt::free(|cs| {
-        let rcc = RCC.borrow(cs);
-        let gpiob = GPIOB.borrow(cs);
-        rcc.ahbenr.modify(|_, w| w.iopben().enabled());
-
-        lcd_sck0(); // set PB13 (SCK/SCL) to idle (low)
-
-        gpiob.moder.modify(|_, w|
-            w.moder10().output() // PB10: CSE/CS
-             .moder12().output() // PB12: A0/RS/DC
-             .moder13().output() // PB13: SCK/SCL
-             .moder14().output() // PB14: RST
-             .moder15().output() // PB15: SDA/MOSI
-        );
-        gpiob.otyper.modify(|_, w| unsafe {
-            w.ot10().bits(0) // push-pull
-             .ot12().bits(0)
-             .ot13().bits(0)
-             .ot14().bits(0)
-             .ot15().bits(0)
-        });
-        gpiob.ospeedr.modify(|_, w| unsafe {
-            w.ospeedr10().bits(0b11) // fast
-             .ospeedr12().bits(0b11)
-             .ospeedr13().bits(0b11)
-             .ospeedr14().bits(0b11)
-             .ospeedr15().bits(0b11)
-        });
-    });
+    let pins = [LCD_PINS.cs, LCD_PINS.dc, LCD_PINS.sck, LCD_PINS.rst, LCD_PINS.mosi];
+    for pin in &pins {
+        pin.enable_port_clock();
+    }
+    lcd_sck0(); // set SCK/SCL to idle (low)
+    for pin in &pins {
+        pin.configure(Mode::Output);
+    }
 }
 
 // send a byte of data to the LCD controller via bit-banged SPI
-#[cfg(feature = "software-spi")]
+#[cfg(all(feature = "software-spi", not(feature = "host")))]
 fn st7735_send_byte(data_in: u8) {
     let mut data = data_in;
     for _ in 0..8 {
         if (data & 0x80) != 0 {
-            unsafe { (*GPIOB.get()).bsrr.write(|w| w.bs15().set()); } // set PB15: SDA/MOSI
+            LCD_PINS.mosi.set_high();
         } else {
-            unsafe { (*GPIOB.get()).brr.write(|w| w.br15().bits(1)); } // reset PB15: SDA/MOSI
+            LCD_PINS.mosi.set_low();
         }
         data = data << 1;
         // output clock pulse
@@ -233,18 +332,30 @@ fn st7735_send_byte(data_in: u8) {
     }
 }
 
-#[cfg(feature = "software-spi")]
+/// With software SPI the clock is as fast as the bit-banging, so there's nothing to set.
+#[cfg(all(feature = "software-spi", not(feature = "host")))]
+pub fn st7735_probe_spi_clock(_lcd_type: St7735Type, _max_hz: u32) -> Option<u32> {
+    None
+}
+
+// the data line is output only, so there is no reading back
+#[cfg(all(feature = "software-spi", not(feature = "host")))]
+pub fn st7735_read_row(_x: u8, _y: u8, _w: u8, _rgb: &mut [u8]) -> bool {
+    false
+}
+
+#[cfg(all(feature = "software-spi", not(feature = "host")))]
 fn lcd_sck1() {
-    unsafe { (*GPIOB.get()).bsrr.write(|w| w.bs13().set()); } // set PB13: SCK/SCL
+    LCD_PINS.sck.set_high();
 }
 
-#[cfg(feature = "software-spi")]
+#[cfg(all(feature = "software-spi", not(feature = "host")))]
 fn lcd_sck0() {
-    unsafe { (*GPIOB.get()).brr.write(|w| w.br13().bits(1)); } // reset PB13: SCK/SCL
+    LCD_PINS.sck.set_low();
 }
 
 // send a command byte to the LCD controller
-#[cfg(feature = "software-spi")]
+#[cfg(all(feature = "software-spi", not(feature = "host")))]
 #[no_mangle]
 #[used]
 pub extern "C" fn st7735_send_cmd(cmd: u8) {
@@ -253,7 +364,7 @@ pub extern "C" fn st7735_send_cmd(cmd: u8) {
 }
 
 // send a data byte to the LCD controller
-#[cfg(feature = "software-spi")]
+#[cfg(all(feature = "software-spi", not(feature = "host")))]
 #[no_mangle]
 #[used]
 pub extern "C" fn st7735_send_data(data: u8) {
@@ -261,67 +372,297 @@ pub extern "C" fn st7735_send_data(data: u8) {
     st7735_send_byte(data);
 }
 
+// ======== host frame buffer ========
+
+// With the host feature, the drawing runs on the desktop (see docs/host-build.rst). The bytes
+// that would be sent to the controller are interpreted here instead, just far enough to write
+// the pixels into a frame buffer the size of the controller's RAM: the window set by CASET and
+// RASET, and the pixels that follow RAMWR. The frame buffer is addressed as the window is, so
+// it holds the screen as the current rotation shows it, and MADCTL is ignored.
+
+#[cfg(feature = "host")]
+const RAM_SIZE: usize = 162; // the most rows or columns of any module's controller RAM
+
+#[cfg(feature = "host")]
+struct Controller {
+    command: u8,
+    parameters: [u8; 4], // CASET's or RASET's, as they arrive
+    received: usize,
+    columns: (u8, u8), // the window, inclusive
+    rows: (u8, u8),
+    x: u8, // the next pixel written
+    y: u8,
+    high_byte: Option<u8>, // the first half of a pixel
+}
+
+#[cfg(feature = "host")]
+static mut CONTROLLER: Controller = Controller {
+    command: 0,
+    parameters: [0; 4],
+    received: 0,
+    columns: (0, 0),
+    rows: (0, 0),
+    x: 0,
+    y: 0,
+    high_byte: None,
+};
+
+#[cfg(feature = "host")]
+static mut FRAME: [[u16; RAM_SIZE]; RAM_SIZE] = [[0; RAM_SIZE]; RAM_SIZE];
+
+/// There is no hardware to set up.
+#[cfg(feature = "host")]
+pub fn st7735_setup() {}
+
+/// There is no SPI clock to set.
+#[cfg(feature = "host")]
+pub fn st7735_probe_spi_clock(_lcd_type: St7735Type, _max_hz: u32) -> Option<u32> {
+    None
+}
+
+/// Reads `w` pixels of row `y`, starting at column `x`, from the frame buffer into `rgb`, as
+/// the display would give them back: three bytes of red, green, and blue for each, with 6 bits
+/// in the high bits of each byte, the 5-bit red and blue widened as the controller does.
+#[cfg(feature = "host")]
+pub fn st7735_read_row(x: u8, y: u8, w: u8, rgb: &mut [u8]) -> bool {
+    let (xstart, ystart) = unsafe { (XSTART as usize, YSTART as usize) };
+    let row = unsafe { &FRAME[y as usize + ystart] };
+    for i in 0..w as usize {
+        let color = row[x as usize + xstart + i];
+        let red = (color >> 11) as u8;
+        let green = (color >> 5) as u8 & 0x3f;
+        let blue = color as u8 & 0x1f;
+        rgb[3 * i] = (red << 1 | red >> 4) << 2;
+        rgb[3 * i + 1] = green << 2;
+        rgb[3 * i + 2] = (blue << 1 | blue >> 4) << 2;
+    }
+    true
+}
+
+#[cfg(feature = "host")]
+pub fn st7735_send_cmd(cmd: u8) {
+    let controller = unsafe { &mut CONTROLLER };
+    controller.command = cmd;
+    controller.received = 0;
+    if cmd == ST7735_RAMWR {
+        controller.x = controller.columns.0;
+        controller.y = controller.rows.0;
+        controller.high_byte = None;
+    }
+}
+
+#[cfg(feature = "host")]
+pub fn st7735_send_data(data: u8) {
+    let controller = unsafe { &mut CONTROLLER };
+    match controller.command {
+        ST7735_CASET | ST7735_RASET if controller.received < 4 => {
+            controller.parameters[controller.received] = data;
+            controller.received += 1;
+            if controller.received == 4 {
+                // the start and end are big-endian, and always under 256 here
+                let window = (controller.parameters[1], controller.parameters[3]);
+                if controller.command == ST7735_CASET {
+                    controller.columns = window;
+                } else {
+                    controller.rows = window;
+                }
+            }
+        }
+        ST7735_RAMWR => match controller.high_byte.take() {
+            None => controller.high_byte = Some(data),
+            Some(high) => {
+                let (x, y) = (controller.x as usize, controller.y as usize);
+                if x < RAM_SIZE && y < RAM_SIZE {
+                    unsafe { FRAME[y][x] = (high as u16) << 8 | data as u16; }
+                }
+                // on along the row, then down, wrapping within the window
+                if controller.x < controller.columns.1 {
+                    controller.x += 1;
+                } else {
+                    controller.x = controller.columns.0;
+                    controller.y = if controller.y < controller.rows.1 {
+                        controller.y + 1
+                    } else {
+                        controller.rows.0
+                    };
+                }
+            }
+        },
+        _ => {} // the rest only configure the panel
+    }
+}
+
 // ======== SPI/GPIO manipulation functions for both hardware and software modes ========
 
+#[cfg(not(feature = "host"))]
 #[no_mangle]
 #[used]
 pub extern "C" fn lcd_cs1() {
-    unsafe { (*GPIOB.get()).bsrr.write(|w| w.bs10().set()); } // set PB10: CSE/CS
+    LCD_PINS.cs.set_high();
 }
 
+#[cfg(not(feature = "host"))]
 #[no_mangle]
 #[used]
 pub extern "C" fn lcd_cs0() {
-    unsafe { (*GPIOB.get()).brr.write(|w| w.br10().bits(1)); } // reset PB10: CSE/CS
+    LCD_PINS.cs.set_low();
 }
 
+#[cfg(not(feature = "host"))]
 fn lcd_dc1() {
-    unsafe { (*GPIOB.get()).bsrr.write(|w| w.bs12().set()); } // set PB12: A0/RS/DC
+    LCD_PINS.dc.set_high();
 }
 
+#[cfg(not(feature = "host"))]
 fn lcd_dc0() {
-    unsafe { (*GPIOB.get()).brr.write(|w| w.br12().bits(1)); } // reset PB12: A0/RS/DC
+    LCD_PINS.dc.set_low();
 }
 
+#[cfg(not(feature = "host"))]
 #[no_mangle]
 #[used]
 pub extern "C" fn lcd_rst1() {
-    unsafe { (*GPIOB.get()).bsrr.write(|w| w.bs14().set()); } // set PB14: RST
+    LCD_PINS.rst.set_high();
 }
 
+#[cfg(not(feature = "host"))]
 #[no_mangle]
 #[used]
 pub extern "C" fn lcd_rst0() {
-    unsafe { (*GPIOB.get()).brr.write(|w| w.br14().bits(1)); } // reset PB14: RST
+    LCD_PINS.rst.set_low();
 }
 
-// ======== wrappers for (unsafe) C functions ========
+// ======== panel geometry ========
+
+const ST7735_CASET: u8 = 0x2a;
+const ST7735_RASET: u8 = 0x2b;
+const ST7735_RAMWR: u8 = 0x2c;
+#[cfg(not(any(feature = "software-spi", feature = "host")))]
+const ST7735_RAMRD: u8 = 0x2e;
+const ST7735_MADCTL: u8 = 0x36;
+
+const MADCTL_MY: u8 = 0x80;
+const MADCTL_MX: u8 = 0x40;
+const MADCTL_MV: u8 = 0x20;
+const MADCTL_RGB: u8 = 0x00;
+
+// The controller's RAM may be larger than the panel connected to it, depending on the module,
+// so the drawing window must be offset to where the panel is. The offsets here are for rotation
+// 0, in which both axes are mirrored; when an axis isn't mirrored, the offset is from the other
+// end of the RAM. With the rows and columns exchanged for landscape, so are the offsets.
+struct Panel {
+    width: u8,  // in portrait, rotation 0
+    height: u8,
+    ram_columns: u8,
+    ram_rows: u8,
+    colstart: u8,
+    rowstart: u8,
+}
 
-pub fn st7735_initR(lcd_type: u8) { unsafe { _st7735_initR(lcd_type) } }
+const PANEL_RED_TAB: Panel = Panel {
+    width: 128, height: 160, ram_columns: 128, ram_rows: 160, colstart: 0, rowstart: 0,
+};
+const PANEL_GREEN_TAB: Panel = Panel {
+    width: 128, height: 160, ram_columns: 132, ram_rows: 162, colstart: 2, rowstart: 1,
+};
+const PANEL_GREEN_TAB_128: Panel = Panel {
+    width: 128, height: 128, ram_columns: 132, ram_rows: 162, colstart: 2, rowstart: 3,
+};
 
-#[allow(unused)]
-pub fn st7735_drawFastHLine(x: i16, y: i16, w: i16, color: u16) {
-    unsafe { _st7735_drawFastHLine(x, y, w, color) }
+static mut PANEL: Panel = PANEL_RED_TAB;
+// the screen size and window offsets in the current rotation
+static mut WIDTH: u8 = 128;
+static mut HEIGHT: u8 = 160;
+static mut XSTART: u8 = 0;
+static mut YSTART: u8 = 0;
+
+// send the controller its initialization sequence, from the C code
+#[cfg(not(feature = "host"))]
+fn init_controller(lcd_type: St7735Type) {
+    unsafe { _st7735_initR(lcd_type as u8); }
 }
 
-#[allow(unused)]
-pub fn st7735_drawFastVLine(x: i16, y: i16, h: i16, color: u16) {
-    unsafe { _st7735_drawFastVLine(x, y, h, color) }
+// the frame buffer starts out black, as a freshly reset controller's RAM is undefined
+#[cfg(feature = "host")]
+fn init_controller(_lcd_type: St7735Type) {
+    unsafe { FRAME = [[0; RAM_SIZE]; RAM_SIZE]; }
 }
 
-pub fn st7735_drawPixel(x: i16, y: i16, color: u16) { unsafe { _st7735_drawPixel(x, y, color) } }
-
-pub fn st7735_fillScreen(color: u16) { unsafe { _st7735_fillScreen(color) } }
+/// Initializes an ST7735R-based module. The modules differ in their initialization, and in the
+/// size and position of the panel in the controller's RAM.
+pub fn st7735_initR(lcd_type: St7735Type) {
+    let panel = match lcd_type {
+        St7735Type::GreenTab => PANEL_GREEN_TAB,
+        St7735Type::RedTab | St7735Type::BlackTab => PANEL_RED_TAB,
+        St7735Type::GreenTab128 => PANEL_GREEN_TAB_128,
+    };
+    // the 128x128 module initializes as the green tab, apart from the window set here
+    let c_type = if lcd_type == St7735Type::GreenTab128 { St7735Type::GreenTab } else { lcd_type };
+    init_controller(c_type);
+    unsafe {
+        WIDTH = panel.width;
+        HEIGHT = panel.height;
+        XSTART = panel.colstart;
+        YSTART = panel.rowstart;
+        PANEL = panel;
+    }
+}
 
-pub fn st7735_setAddrWindow(x0: u8, y0: u8, x1: u8, y1: u8) {
-    unsafe { _st7735_setAddrWindow(x0, y0, x1, y1) }
+/// Sets the screen rotation, in quarter turns: 0 and 2 are portrait, 1 and 3 landscape.
+pub fn st7735_setRotation(rotation: u8) {
+    let madctl = match rotation % 4 {
+        0 => MADCTL_MX | MADCTL_MY | MADCTL_RGB, // portrait
+        1 => MADCTL_MY | MADCTL_MV | MADCTL_RGB, // landscape
+        2 => MADCTL_RGB,                         // portrait, inverted
+        _ => MADCTL_MX | MADCTL_MV | MADCTL_RGB, // landscape, inverted
+    };
+    st7735_send_cmd(ST7735_MADCTL);
+    st7735_send_data(madctl);
+    unsafe {
+        let p = &PANEL;
+        let column = if madctl & MADCTL_MX != 0 {
+            p.colstart
+        } else {
+            p.ram_columns - p.width - p.colstart
+        };
+        let row = if madctl & MADCTL_MY != 0 {
+            p.rowstart
+        } else {
+            p.ram_rows - p.height - p.rowstart
+        };
+        if madctl & MADCTL_MV == 0 {
+            WIDTH = p.width;
+            HEIGHT = p.height;
+            XSTART = column;
+            YSTART = row;
+        } else {
+            WIDTH = p.height;
+            HEIGHT = p.width;
+            XSTART = row;
+            YSTART = column;
+        }
+    }
 }
 
-pub fn st7735_setRotation(rotation: u8) { unsafe { _st7735_setRotation(rotation) } }
+pub fn st7735_get_height() -> u8 { unsafe { HEIGHT } }
 
-pub fn st7735_get_height() -> u8 { unsafe { _st7735_get_height() } }
+pub fn st7735_get_width() -> u8 { unsafe { WIDTH } }
 
-pub fn st7735_get_width() -> u8 { unsafe { _st7735_get_width() } }
+/// Sets the window that following colors are written to, inclusive of (`x1`, `y1`).
+pub fn st7735_setAddrWindow(x0: u8, y0: u8, x1: u8, y1: u8) {
+    let (xstart, ystart) = unsafe { (XSTART, YSTART) };
+    st7735_send_cmd(ST7735_CASET);
+    st7735_send_data(0x00);
+    st7735_send_data(x0 + xstart);
+    st7735_send_data(0x00);
+    st7735_send_data(x1 + xstart);
+    st7735_send_cmd(ST7735_RASET);
+    st7735_send_data(0x00);
+    st7735_send_data(y0 + ystart);
+    st7735_send_data(0x00);
+    st7735_send_data(y1 + ystart);
+    st7735_send_cmd(ST7735_RAMWR);
+}
 
 // ======== drawing routines ========
 
@@ -330,32 +671,227 @@ pub fn st7735_send_color(color: u16) {
     st7735_send_data((color & 0xff) as u8);
 }
 
-#[no_mangle]
-#[used]
-pub extern "C" fn st7735_fill_rect(x: i16, y: i16, w0: i16, h0: i16, color: u16) {
-    let width = st7735_get_width() as i16;
-    let height = st7735_get_height() as i16;
-    let mut w = w0;
-    let mut x_end = x + w - 1;
-    if x_end >= width {
-        if x >= width {
-            return;
+pub fn st7735_drawPixel(x: i16, y: i16, color: u16) {
+    if x < 0 || x >= st7735_get_width() as i16 || y < 0 || y >= st7735_get_height() as i16 {
+        return;
+    }
+    st7735_setAddrWindow(x as u8, y as u8, x as u8, y as u8);
+    st7735_send_color(color);
+}
+
+#[allow(unused)]
+pub fn st7735_drawFastHLine(x: i16, y: i16, w: i16, color: u16) {
+    st7735_fill_rect(x, y, w, 1, color);
+}
+
+pub fn st7735_drawFastVLine(x: i16, y: i16, h: i16, color: u16) {
+    st7735_fill_rect(x, y, 1, h, color);
+}
+
+pub fn st7735_fillScreen(color: u16) {
+    st7735_fill_rect(0, 0, st7735_get_width() as i16, st7735_get_height() as i16, color);
+}
+
+/// Fills a rectangle, clipped to the screen.
+pub fn st7735_fill_rect(x: i16, y: i16, w: i16, h: i16, color: u16) {
+    let x0 = max(x, 0);
+    let y0 = max(y, 0);
+    let x1 = min(x + w, st7735_get_width() as i16); // exclusive
+    let y1 = min(y + h, st7735_get_height() as i16);
+    if x1 <= x0 || y1 <= y0 {
+        return;
+    }
+    st7735_setAddrWindow(x0 as u8, y0 as u8, (x1 - 1) as u8, (y1 - 1) as u8);
+    for _ in 0..(x1 - x0) as u32 * (y1 - y0) as u32 {
+        st7735_send_color(color)
+    }
+}
+
+// ======== shapes ========
+
+/// Draws the outline of a rectangle.
+#[allow(unused)]
+pub fn st7735_draw_rect(x: i16, y: i16, w: i16, h: i16, color: u16) {
+    st7735_drawFastHLine(x, y, w, color);
+    st7735_drawFastHLine(x, y + h - 1, w, color);
+    st7735_drawFastVLine(x, y, h, color);
+    st7735_drawFastVLine(x + w - 1, y, h, color);
+}
+
+/// Draws a line from (`x0`, `y0`) to (`x1`, `y1`), using Bresenham's algorithm.
+#[allow(unused)]
+pub fn st7735_draw_line(x0: i16, y0: i16, x1: i16, y1: i16, color: u16) {
+    if x0 == x1 {
+        let (top, bottom) = if y0 < y1 { (y0, y1) } else { (y1, y0) };
+        st7735_drawFastVLine(x0, top, bottom - top + 1, color);
+        return;
+    }
+    if y0 == y1 {
+        let (left, right) = if x0 < x1 { (x0, x1) } else { (x1, x0) };
+        st7735_drawFastHLine(left, y0, right - left + 1, color);
+        return;
+    }
+    let dx = (x1 - x0).abs();
+    let dy = -(y1 - y0).abs();
+    let sx = if x0 < x1 { 1 } else { -1 };
+    let sy = if y0 < y1 { 1 } else { -1 };
+    let mut error = dx + dy;
+    let (mut x, mut y) = (x0, y0);
+    loop {
+        st7735_drawPixel(x, y, color);
+        if x == x1 && y == y1 {
+            break;
+        }
+        let e2 = 2 * error;
+        if e2 >= dy {
+            error += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            error += dx;
+            y += sy;
         }
-        x_end = width - 1;
-        w = width - x;
     }
-    let mut h = h0;
-    let mut y_end = y + h - 1;
-    if y_end >= height {
-        if y >= height {
-            return;
+}
+
+/// Draws the outline of a circle of radius `r` centered on (`x0`, `y0`).
+#[allow(unused)]
+pub fn st7735_draw_circle(x0: i16, y0: i16, r: i16, color: u16) {
+    // the midpoint circle algorithm, drawing the eight octants together
+    let mut x = r;
+    let mut y = 0;
+    let mut error = 1 - r;
+    while x >= y {
+        st7735_drawPixel(x0 + x, y0 + y, color);
+        st7735_drawPixel(x0 - x, y0 + y, color);
+        st7735_drawPixel(x0 + x, y0 - y, color);
+        st7735_drawPixel(x0 - x, y0 - y, color);
+        st7735_drawPixel(x0 + y, y0 + x, color);
+        st7735_drawPixel(x0 - y, y0 + x, color);
+        st7735_drawPixel(x0 + y, y0 - x, color);
+        st7735_drawPixel(x0 - y, y0 - x, color);
+        y += 1;
+        if error < 0 {
+            error += 2 * y + 1;
+        } else {
+            x -= 1;
+            error += 2 * (y - x) + 1;
         }
-        y_end = height - 1;
-        h = height - y;
     }
-    st7735_setAddrWindow(x as u8, y as u8, x_end as u8, y_end as u8);
-    for _ in 0..(w * h) {
-        st7735_send_color(color)
+}
+
+/// Draws a filled circle of radius `r` centered on (`x0`, `y0`).
+#[allow(unused)]
+pub fn st7735_fill_circle(x0: i16, y0: i16, r: i16, color: u16) {
+    // as st7735_draw_circle, but joining each pair of points with a vertical line
+    let mut x = r;
+    let mut y = 0;
+    let mut error = 1 - r;
+    while x >= y {
+        st7735_drawFastVLine(x0 + x, y0 - y, 2 * y + 1, color);
+        st7735_drawFastVLine(x0 - x, y0 - y, 2 * y + 1, color);
+        st7735_drawFastVLine(x0 + y, y0 - x, 2 * x + 1, color);
+        st7735_drawFastVLine(x0 - y, y0 - x, 2 * x + 1, color);
+        y += 1;
+        if error < 0 {
+            error += 2 * y + 1;
+        } else {
+            x -= 1;
+            error += 2 * (y - x) + 1;
+        }
+    }
+}
+
+/// Draws the outline of a triangle.
+#[allow(unused)]
+pub fn st7735_draw_triangle(x0: i16, y0: i16, x1: i16, y1: i16, x2: i16, y2: i16, color: u16) {
+    st7735_draw_line(x0, y0, x1, y1, color);
+    st7735_draw_line(x1, y1, x2, y2, color);
+    st7735_draw_line(x2, y2, x0, y0, color);
+}
+
+/// Draws a filled triangle.
+#[allow(unused)]
+pub fn st7735_fill_triangle(x0: i16, y0: i16, x1: i16, y1: i16, x2: i16, y2: i16, color: u16) {
+    // sort the corners by y, then fill each row between the long edge (0 to 2) and the two
+    // short ones (0 to 1, then 1 to 2)
+    let mut p = [(x0, y0), (x1, y1), (x2, y2)];
+    if p[0].1 > p[1].1 { p.swap(0, 1); }
+    if p[1].1 > p[2].1 { p.swap(1, 2); }
+    if p[0].1 > p[1].1 { p.swap(0, 1); }
+    let (x0, y0) = p[0];
+    let (x1, y1) = p[1];
+    let (x2, y2) = p[2];
+    // x along the edge from (xa, ya) to (xb, yb) at row y, rounding to nearest
+    let edge_x = |xa: i16, ya: i16, xb: i16, yb: i16, y: i16| -> i16 {
+        if yb == ya {
+            return xa;
+        }
+        let n = (xb - xa) as i32 * (y - ya) as i32;
+        let d = (yb - ya) as i32;
+        xa + ((2 * n + if n < 0 { -d } else { d }) / (2 * d)) as i16
+    };
+    for y in y0..y2 + 1 {
+        let a = edge_x(x0, y0, x2, y2, y);
+        let b = if y < y1 { edge_x(x0, y0, x1, y1, y) } else { edge_x(x1, y1, x2, y2, y) };
+        let (left, right) = if a < b { (a, b) } else { (b, a) };
+        st7735_drawFastHLine(left, y, right - left + 1, color);
+    }
+}
+
+// ======== bitmaps ========
+
+/// A one-bit-per-pixel bitmap, run-length encoded. Starting at the top left and working across
+/// each row in turn, `runs` gives the lengths of alternating runs of background and foreground
+/// pixels, beginning with background. Runs longer than 255 pixels are split by a zero-length run
+/// of the other color.
+pub struct RleBitmap {
+    pub width: u8,
+    pub height: u8,
+    pub runs: &'static [u8],
+}
+
+/// Draws an RLE bitmap with its top left corner at (`x`, `y`). The bitmap must fit on the
+/// screen.
+pub fn st7735_draw_rle_bitmap(bitmap: &RleBitmap, x: u8, y: u8, fg: St7735Color,
+                              bg: St7735Color) {
+    if x as u16 + bitmap.width as u16 > st7735_get_width() as u16 ||
+       y as u16 + bitmap.height as u16 > st7735_get_height() as u16 {
+        return;
+    }
+    st7735_setAddrWindow(x, y, x + bitmap.width - 1, y + bitmap.height - 1);
+    let mut remaining = bitmap.width as usize * bitmap.height as usize;
+    let mut foreground = false;
+    for &run in bitmap.runs {
+        let color = if foreground { fg } else { bg };
+        let run = if (run as usize) < remaining { run as usize } else { remaining };
+        for _ in 0..run {
+            st7735_send_color(color as u16);
+        }
+        remaining -= run;
+        foreground = !foreground;
+    }
+    // in case the runs came up short, fill out the window
+    for _ in 0..remaining {
+        st7735_send_color(bg as u16);
+    }
+}
+
+/// Draws a one-bit-per-pixel bitmap eight pixels wide, such as an icon, with its top left
+/// corner at (`x`, `y`). Each byte is a row, with the least significant bit on the left, as in
+/// the 8x12 font.
+pub fn st7735_draw_bitmap(rows: &[u8], x: u8, y: u8, fg: St7735Color, bg: St7735Color) {
+    if rows.is_empty() || x > st7735_get_width() - 8 ||
+       y as usize + rows.len() > st7735_get_height() as usize {
+        return;
+    }
+    st7735_setAddrWindow(x, y, x + 7, y + rows.len() as u8 - 1);
+    for &row in rows {
+        let mut bits = row;
+        for _ in 0..8 {
+            st7735_send_color(if bits & 0b1 == 0b1 { fg as u16 } else { bg as u16 });
+            bits >>= 1;
+        }
     }
 }
 
@@ -0,0 +1,113 @@
+// stm32f3-oscilloscope - src/stack.rs
+// stack usage monitoring: the deepest the stack has reached, against the room it has
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// There is no heap, so the stack has all the RAM between the end of .bss and the top of RAM,
+// where cortex-m-rt starts it (_stack_start in memory.x), growing down. Nothing stops it
+// growing into .bss, and the capture buffer there, so at startup the unused part of the stack
+// is painted with a pattern, and every so often the main loop checks how far down the pattern
+// has been overwritten: the high-water mark. When that passes WARNING_PERCENT of the room, a
+// warning is logged and shown on the status line, once, while there is still some margin.
+//
+// A function that reserves stack without writing to all of it could skip over some of the
+// pattern, so the mark can read a little low, but no large buffer on the stack goes unwritten.
+
+use core::ptr;
+
+use cortex_m;
+
+use console;
+use measure;
+use serial;
+
+extern "C" {
+    static _ebss: u32; // the end of .bss, the lowest address the stack may reach
+    static _stack_start: u32; // defined in memory.x
+}
+
+const PAINT: u32 = 0x5a5a_a5a5;
+/// The share of the stack's room, in percent, used before a warning.
+pub const WARNING_PERCENT: usize = 80;
+
+static mut WARNED: bool = false;
+
+// the lowest and highest addresses of the stack's room
+fn bounds() -> (usize, usize) {
+    unsafe { (&_ebss as *const u32 as usize, &_stack_start as *const u32 as usize) }
+}
+
+/// Paints the stack's room below the current stack pointer, less a margin for this function's
+/// own frame. Call once, first thing at startup.
+#[inline(never)]
+pub fn paint() {
+    let (bottom, _) = bounds();
+    let top = cortex_m::register::msp::read() as usize - 64;
+    let mut address = (bottom + 3) & !3;
+    while address < top {
+        unsafe { ptr::write_volatile(address as *mut u32, PAINT); }
+        address += 4;
+    }
+}
+
+/// Returns the room the stack has, in bytes.
+pub fn size() -> usize {
+    let (bottom, top) = bounds();
+    top - bottom
+}
+
+/// Returns the most stack used since startup, in bytes.
+pub fn used() -> usize {
+    let (bottom, top) = bounds();
+    let mut address = (bottom + 3) & !3;
+    while address < top && unsafe { ptr::read_volatile(address as *const u32) } == PAINT {
+        address += 4;
+    }
+    top - address
+}
+
+/// Returns true, once, when the stack used first passes WARNING_PERCENT of its room.
+pub fn check() -> bool {
+    if unsafe { WARNED } || used() * 100 < size() * WARNING_PERCENT {
+        return false;
+    }
+    unsafe { WARNED = true; }
+    true
+}
+
+/// Reports the stack used and its room, as "stack 2140/8192", to the serial port, and to the
+/// console with the figures right-aligned in fields wide enough for all of RAM.
+pub fn report() {
+    let (used, size) = (used(), size());
+    serial::write(b"stack ");
+    serial::write_number(used as u32);
+    serial::write_byte(b'/');
+    serial::write_number(size as u32);
+    serial::write(b"\r\n");
+    let mut line = [b' '; 17];
+    line[..6].copy_from_slice(b"stack ");
+    measure::format_fixed(used as u64, 0, &mut line[6..11]);
+    line[11] = b'/';
+    measure::format_fixed(size as u64, 0, &mut line[12..]);
+    console::log(&line);
+}
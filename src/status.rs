@@ -0,0 +1,160 @@
+// stm32f3-oscilloscope - src/status.rs
+// the status line, drawn from a queue between the time-critical parts of a sweep
+
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Printing to the LCD blocks on SPI for a millisecond or more, so the status line isn't drawn
+// when it changes; instead the changes are queued, and drawn by flush() when the main loop has
+// time. Since a clear overwrites whatever was queued before it, clearing also empties the
+// queue, so the queue only has to hold one status line's worth. Positions are taken as the
+// status line's at the time of drawing, so a rotation in between does no harm.
+//
+// While the oscilloscope is under remote control from the serial shell, a "REM" indicator is
+// kept at the right end of the line, drawn over anything else there, and redrawn after every
+// clear. It is red while the pushbuttons are locked out.
+//
+// While the last record had clipped samples, a "CLIP" warning is kept just left of where the
+// REM indicator goes, flashing between red text and a red background.
+
+use icon::Icon;
+use screen;
+use st7735::*;
+use time::millis;
+
+const QUEUE_LENGTH: usize = 8;
+const COLUMNS: usize = 20; // a landscape screen's width of characters
+
+#[derive(Clone, Copy)]
+enum Op {
+    Clear,
+    Text { text: [u8; COLUMNS], len: u8, x: u8, fg: St7735Color },
+    Icon { icon: Icon, x: u8 },
+}
+
+static mut QUEUE: [Op; QUEUE_LENGTH] = [Op::Clear; QUEUE_LENGTH];
+static mut QUEUED: usize = 0;
+
+static mut REMOTE: bool = false;
+static mut LOCKED: bool = false;
+static mut REMOTE_CHANGED: bool = false;
+
+const REMOTE_LABEL: &'static [u8] = b"REM";
+
+static mut CLIPPING: bool = false;
+static mut CLIP_SHOWN: Option<bool> = None; // whether the warning is drawn, and which way
+
+const CLIP_LABEL: &'static [u8] = b"CLIP";
+const CLIP_FLASH_MS: u32 = 250;
+
+fn push(op: Op) {
+    unsafe {
+        debug_assert!(QUEUED < QUEUE_LENGTH);
+        if QUEUED < QUEUE_LENGTH {
+            QUEUE[QUEUED] = op;
+            QUEUED += 1;
+        }
+    }
+}
+
+/// Clears the status line.
+pub fn clear() {
+    unsafe { QUEUED = 0; }
+    push(Op::Clear);
+}
+
+/// Prints `text` in green at `x` on the status line, clipped at COLUMNS characters.
+pub fn print(text: &[u8], x: u8) {
+    print_color(text, x, St7735Color::Green);
+}
+
+pub fn print_color(text: &[u8], x: u8, fg: St7735Color) {
+    let len = if text.len() < COLUMNS { text.len() } else { COLUMNS };
+    let mut buf = [0; COLUMNS];
+    buf[..len].copy_from_slice(&text[..len]);
+    push(Op::Text { text: buf, len: len as u8, x: x, fg: fg });
+}
+
+/// Draws `icon` in green at `x` on the status line.
+pub fn icon(icon: Icon, x: u8) {
+    push(Op::Icon { icon: icon, x: x });
+}
+
+/// Shows or hides the remote-control indicator, with whether the pushbuttons are locked out.
+pub fn set_remote(remote: bool, locked: bool) {
+    unsafe {
+        if remote != REMOTE || locked != LOCKED {
+            REMOTE = remote;
+            LOCKED = locked;
+            REMOTE_CHANGED = true;
+        }
+    }
+}
+
+/// Shows or hides the flashing clipping warning.
+pub fn set_clipping(clipping: bool) {
+    unsafe { CLIPPING = clipping; }
+}
+
+/// Draws everything queued.
+pub fn flush() {
+    let y = screen::status_y();
+    unsafe {
+        let drawn = QUEUED > 0;
+        for op in &QUEUE[..QUEUED] {
+            match *op {
+                Op::Clear => screen::clear_status_line(),
+                Op::Text { ref text, len, x, fg } => {
+                    st7735_print(&text[..len as usize], x, y, fg, St7735Color::Black);
+                }
+                Op::Icon { icon, x } => icon.draw(x, y, St7735Color::Green, St7735Color::Black),
+            }
+        }
+        QUEUED = 0;
+        if drawn || REMOTE_CHANGED {
+            let x = screen::width() as u8 - 8 * REMOTE_LABEL.len() as u8;
+            if REMOTE {
+                let fg = if LOCKED { St7735Color::Red } else { St7735Color::Yellow };
+                st7735_print(REMOTE_LABEL, x, y, fg, St7735Color::Black);
+            } else if REMOTE_CHANGED {
+                st7735_print(b"   ", x, y, St7735Color::Black, St7735Color::Black);
+            }
+            REMOTE_CHANGED = false;
+        }
+        let phase = if CLIPPING { Some(millis() / CLIP_FLASH_MS % 2 == 0) } else { None };
+        if drawn || phase != CLIP_SHOWN {
+            let x = screen::width() as u8 - 8 * (REMOTE_LABEL.len() + 1 + CLIP_LABEL.len()) as u8;
+            match phase {
+                Some(true) => st7735_print(CLIP_LABEL, x, y, St7735Color::Red, St7735Color::Black),
+                Some(false) => {
+                    st7735_print(CLIP_LABEL, x, y, St7735Color::Black, St7735Color::Red);
+                }
+                None if CLIP_SHOWN.is_some() => {
+                    st7735_print(b"    ", x, y, St7735Color::Black, St7735Color::Black);
+                }
+                None => {}
+            }
+            CLIP_SHOWN = phase;
+        }
+    }
+}
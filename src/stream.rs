@@ -0,0 +1,79 @@
+// stm32f3-oscilloscope - src/stream.rs
+// streaming mode: sending each record over the serial port as it is captured
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Raw 12-bit samples, at two bytes each, fill a 115200 baud link at under 6000 samples per
+// second, so records are sent delta encoded (see delta.rs), at about one byte a sample. Each
+// delta-encoded frame is the payload of a Stream frame (see frame.rs), or StreamRecordStart
+// for the first of a record. Sending waits on the serial
+// port, so while streaming, a sweep faster than the link stalls until its samples are sent,
+// and the next sweep starts only after the last one is sent.
+
+use delta;
+use frame::{self, FrameType};
+
+static mut ENABLED: bool = false;
+static mut SENT: usize = 0; // the number of samples of the current record sent so far
+
+pub fn set_enabled(enabled: bool) {
+    unsafe { ENABLED = enabled; }
+}
+
+pub fn enabled() -> bool {
+    unsafe { ENABLED }
+}
+
+/// Starts sending a new record, at the beginning of a sweep.
+pub fn begin_record() {
+    unsafe { SENT = 0; }
+}
+
+/// Sends the next frame of `record`, of which the first `available` samples have been
+/// captured, if a whole frame is waiting; or if `complete`, sends all that is left.
+pub fn send(record: &[u16], available: usize, complete: bool) {
+    if !enabled() {
+        return;
+    }
+    unsafe {
+        while available - SENT >= delta::FRAME_SAMPLES || (complete && SENT < available) {
+            let end = if available - SENT > delta::FRAME_SAMPLES {
+                SENT + delta::FRAME_SAMPLES
+            } else {
+                available
+            };
+            let mut frame = [0u8; delta::MAX_FRAME_BYTES];
+            let len = delta::encode_frame(&record[SENT..end], &mut frame);
+            let frame_type = if SENT == 0 {
+                FrameType::StreamRecordStart
+            } else {
+                FrameType::Stream
+            };
+            frame::send(frame_type, &[&frame[..len]]);
+            SENT = end;
+            if !complete {
+                break; // one frame at a time, while the sweep is in progress
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
-// sysclk.rs -- configure the STM32F303 system clock and flash for 72MHz operation
+// sysclk.rs -- configure the STM32F303 system clock and flash for 72MHz operation,
+// falling back to 64MHz from the HSI clock if the HSE clock fails
 
 // Copyright © 2017 Sean Bolton
 //
@@ -23,14 +24,23 @@
 
 extern crate cortex_m;
 
-use cortex_m::asm;
+use cortex_m;
 use stm32f30x::{FLASH, RCC};
+use stm32f30x::rcc;
+
+use clocks;
+
+// set when the clock security system has found the HSE clock failed, for the main loop
+static mut HSE_LOST: bool = false;
 
 // set_sys_clock()
 // Set the system clock to 72MHz, using the 8MHz external clock from ST-Link.
-// This assumes the clock and PLL are still in their reset state, and turns
-// off the HSI clock when no longer needed, but otherwise follows the
-// STM32F3-Discovery_FW_V1.1.0 library procedure.
+// This assumes the clock and PLL are still in their reset state, but otherwise
+// follows the STM32F3-Discovery_FW_V1.1.0 library procedure. If the HSE clock
+// never becomes ready, as on a board not powered through its ST-Link, this
+// falls back to 64MHz from the HSI clock; otherwise it turns the HSI clock off
+// and enables the clock security system, so an HSE failure later on will bring
+// a clock security interrupt (see hse_failure()).
 pub fn set_sys_clock() {
     cortex_m::interrupt::free(|cs| {
         let rcc = RCC.borrow(cs);
@@ -41,11 +51,13 @@ pub fn set_sys_clock() {
                                        .hsebyp().bits(1) });
         // wait for HSE to become ready
         let mut startup_count = 0x500;
-        while rcc.cr.read().hserdy().bits() == 0 {
+        while rcc.cr.read().hserdy().bits() == 0 && startup_count > 0 {
             startup_count -= 1;
-            if startup_count == 0 {
-                asm::bkpt();  // HSE did not become ready; halt
-            }
+        }
+        let hse_ready = rcc.cr.read().hserdy().bits() != 0;
+        if !hse_ready {
+            // HSE did not become ready; turn it off
+            rcc.cr.modify(|_, w| unsafe { w.hseon().bits(0) });
         }
         // set flash prefetch and latency
         flash.acr.modify(|_, w| unsafe { w.prftbe().bits(1)
@@ -56,19 +68,80 @@ pub fn set_sys_clock() {
              .ppre2().bits(0) // PCLK2 = HCLK
              .ppre1().bits(0b100) // PCLK1 = HCLK / 2
         });
-        // set PLL for 9 times HSE input
-        rcc.cfgr.modify(|_, w| unsafe {
+        start_pll(rcc, hse_ready);
+        if hse_ready {
+            // turn off HSI, and watch for HSE failing
+            rcc.cr.modify(|_, w| unsafe { w.hsion().bits(0).csson().bits(1) });
+        }
+    });
+    clocks::update();
+}
+
+// Set the PLL for 9 times HSE input, or if `hse` is false, 16 times HSI/2, and switch the
+// system clock to it. The PLL must be off.
+fn start_pll(rcc: &rcc::RegisterBlock, hse: bool) {
+    rcc.cfgr.modify(|_, w| unsafe {
+        if hse {
             w.pllsrc().bits(1) // PLL source HSE/PREDIV
-            .pllmul().bits(0b0111) // PLL multiplier 9
-        });
-        // enable PLL and wait for it to ready
-        rcc.cr.modify(|_, w| unsafe { w.pllon().bits(1) });
-        while rcc.cr.read().pllrdy().bits() == 0 {}
-        // select PLL as system clock
-        rcc.cfgr.modify(|_, w| unsafe { w.sw().bits(0b10) });
-        // wait until PLL is used
-        while rcc.cfgr.read().sws().bits() != 0b10 {}
-        // turn off HSI
-        rcc.cr.modify(|_, w| unsafe { w.hsion().bits(0) });
+             .pllmul().bits(0b0111) // PLL multiplier 9
+        } else {
+            w.pllsrc().bits(0) // PLL source HSI/2
+             .pllmul().bits(0b1110) // PLL multiplier 16
+        }
+    });
+    // enable PLL and wait for it to ready
+    rcc.cr.modify(|_, w| unsafe { w.pllon().bits(1) });
+    while rcc.cr.read().pllrdy().bits() == 0 {}
+    // select PLL as system clock
+    rcc.cfgr.modify(|_, w| unsafe { w.sw().bits(0b10) });
+    // wait until PLL is used
+    while rcc.cfgr.read().sws().bits() != 0b10 {}
+}
+
+/// Handles a clock security interrupt, which arrives as an NMI. When the HSE clock fails, the
+/// hardware turns it and the PLL off, and runs the system from the 8MHz HSI; this restarts the
+/// PLL from the HSI, for 64MHz. Returns whether the clock was switched, in which case anything
+/// timed from the clocks needs setting again.
+pub fn hse_failure() -> bool {
+    cortex_m::interrupt::free(|cs| {
+        let rcc = RCC.borrow(cs);
+        if rcc.cir.read().cssf().bits() == 0 {
+            return false;
+        }
+        rcc.cir.write(|w| unsafe { w.cssc().bits(1) }); // clear the interrupt
+        while rcc.cr.read().pllrdy().bits() != 0 {}
+        start_pll(rcc, false);
+        clocks::update();
+        unsafe { HSE_LOST = true; }
+        true
+    })
+}
+
+/// Returns whether the HSE clock has failed since this was last called, and the system clock
+/// switched to the HSI.
+pub fn take_hse_lost() -> bool {
+    cortex_m::interrupt::free(|_| unsafe {
+        let lost = HSE_LOST;
+        HSE_LOST = false;
+        lost
+    })
+}
+
+/// Returns the clocks to their reset state: the system clock from the 8MHz HSI, with the PLL,
+/// HSE, and clock security system off. For handing over to the system memory bootloader, which
+/// expects to start from reset.
+pub fn reset_sys_clock() {
+    cortex_m::interrupt::free(|cs| {
+        let rcc = RCC.borrow(cs);
+        let flash = FLASH.borrow(cs);
+        rcc.cr.modify(|_, w| unsafe { w.csson().bits(0).hsion().bits(1) });
+        while rcc.cr.read().hsirdy().bits() == 0 {}
+        rcc.cfgr.modify(|_, w| unsafe { w.sw().bits(0b00) }); // select HSI
+        while rcc.cfgr.read().sws().bits() != 0b00 {}
+        rcc.cr.modify(|_, w| unsafe { w.pllon().bits(0).hseon().bits(0).hsebyp().bits(0) });
+        rcc.cfgr.write(|w| unsafe { w.bits(0) });
+        rcc.cfgr2.write(|w| unsafe { w.bits(0) });
+        flash.acr.modify(|_, w| unsafe { w.latency().bits(0) });
     });
+    clocks::update();
 }
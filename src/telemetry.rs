@@ -0,0 +1,139 @@
+// stm32f3-oscilloscope - src/telemetry.rs
+// measurement telemetry: a line of measurements a second on the serial port, as CSV or JSON
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Each line gives the time since reset in milliseconds, then the frequency in hertz, the
+// peak-to-peak, RMS, and mean voltages of the last record, the supply voltage, and the chip
+// temperature in °C. The RMS voltage is in the selected units (see measure.rs), and named to
+// match: rms_v, rms_mv, rms_dbv, or rms_dbm. Lines are sent after a record is measured, so
+// they come once a second, or once a record when records take longer than that, and stop
+// while nothing triggers. A frequency that can't be measured, or an RMS of zero in dB, is
+// left empty in CSV, and null in JSON.
+//
+// CSV:  ms,freq_hz,vpp_v,rms_v,mean_v,supply_v,temp_c
+//       12000,1000.000,2.912,1.030,1.456,2.958,31.5
+// JSON: {"ms":12000,"freq_hz":1000.000,"vpp_v":2.912,...,"temp_c":31.5}
+
+use capture;
+use delay::Deadline;
+use measure::{self, Results};
+use serial;
+use time::millis;
+
+const INTERVAL_MS: u32 = 1000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Off,
+    Csv,
+    Json,
+}
+
+const NAMES: [&'static [u8]; 7] = [
+    b"ms", b"freq_hz", b"vpp_v", b"rms_v", b"mean_v", b"supply_v", b"temp_c",
+];
+
+static mut FORMAT: Format = Format::Off;
+static mut NEXT: Option<Deadline> = None; // when the next line is due
+
+/// Starts or stops the telemetry. CSV starts with a header line.
+pub fn set_format(format: Format) {
+    unsafe {
+        FORMAT = format;
+        NEXT = Some(Deadline::after_ms(0));
+    }
+    if format == Format::Csv {
+        for i in 0..NAMES.len() {
+            if i > 0 {
+                serial::write_byte(b',');
+            }
+            serial::write(name(i));
+        }
+        serial::write(b"\r\n");
+    }
+}
+
+/// Returns whether a line is due.
+pub fn due() -> bool {
+    unsafe {
+        match NEXT {
+            Some(deadline) => FORMAT != Format::Off && deadline.passed(),
+            None => false,
+        }
+    }
+}
+
+/// Sends a line of the measurements of the last record, if it could be measured, and the
+/// auxiliary measurements.
+pub fn send(results: Option<&Results>) {
+    let format = unsafe { FORMAT };
+    unsafe { NEXT = Some(Deadline::after_ms(INTERVAL_MS)); }
+    let auxiliary = capture::auxiliary();
+    let frequency = results.and_then(|r| r.frequency_millihz());
+    let volts = |f: fn(&Results) -> u32| results.map(|r| f(r) as i64);
+    // each field, as a value scaled by 10^decimals, and the number of decimals
+    let fields: [(Option<i64>, usize); 5] = [
+        (frequency.map(|mhz| mhz as i64), 3),
+        (volts(Results::peak_to_peak_millivolts), 3),
+        (results.and_then(|r| measure::amplitude(r.rms_microvolts())), 3),
+        (results.map(|r| r.mean_millivolts() as i64), 3),
+        (Some(auxiliary.supply_mv as i64), 3),
+    ];
+    if format == Format::Json {
+        serial::write_byte(b'{');
+    }
+    write_name(format, 0);
+    serial::write_number(millis());
+    for (i, &(value, decimals)) in fields.iter().enumerate() {
+        write_name(format, i + 1);
+        match value {
+            Some(value) => serial::write_signed_fixed(value, decimals),
+            None if format == Format::Json => serial::write(b"null"),
+            None => {}
+        }
+    }
+    write_name(format, NAMES.len() - 1);
+    serial::write_signed_fixed(auxiliary.temperature as i64, 1);
+    if format == Format::Json {
+        serial::write_byte(b'}');
+    }
+    serial::write(b"\r\n");
+}
+
+// the name of field `i`, the RMS voltage's depending on its units
+fn name(i: usize) -> &'static [u8] {
+    if i == 3 { measure::units().field_name() } else { NAMES[i] }
+}
+
+// write what comes before field `i`: a comma, and in JSON, the field's name
+fn write_name(format: Format, i: usize) {
+    if i > 0 {
+        serial::write_byte(b',');
+    }
+    if format == Format::Json {
+        serial::write_byte(b'"');
+        serial::write(name(i));
+        serial::write(b"\":");
+    }
+}
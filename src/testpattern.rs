@@ -0,0 +1,165 @@
+// stm32f3-oscilloscope - src/testpattern.rs
+// a synthetic acquisition source: known patterns, for checking the display with no wiring
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+
+// With a pattern selected in the menu, the sweeps are taken from TestPattern instead of the
+// ADC (see source.rs). Each sweep's samples are written straight into the capture buffer, so
+// the trigger search, plotting, and measurements see them exactly as they would a capture, and
+// they are announced at the timebase's sample rate, so the sweep fills the screen at the same
+// pace. The pattern runs on from one sweep to the next, as a signal would, so the trigger has
+// to find it. Each has a period of PERIOD samples, two divisions at a magnification of 1x:
+//   sine      a full-scale sine, from code 1 to 4095, for the vertical scale and RMS
+//   stairs    eight equal steps up, from code 0 to 4095, for the ADC codes' mapping to pixels
+//   impulses  a single sample at full scale, on mid-scale, which must still show as a pixel
+//   jitter    a square wave whose edges move randomly by up to MAX_JITTER samples each period,
+//             the worst case for the trigger's stability
+
+use capture::{self, CAPTURE_CHANNEL_1};
+use source::AcquisitionSource;
+use time::{self, millis};
+
+const PERIOD: usize = 40;
+const STEPS: usize = 8;
+const FULL_SCALE: u16 = 4095;
+const MID_SCALE: u16 = 2048;
+const MAX_JITTER: usize = 7;
+
+// sin(2 pi k / PERIOD) for the first quarter wave, scaled by 2047
+const QUARTER_SINE: [u16; PERIOD / 4 + 1] = [
+    0, 320, 633, 929, 1203, 1447, 1656, 1824, 1947, 2022, 2047,
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pattern {
+    Off, // sweeps from the ADC
+    Sine,
+    Stairs,
+    Impulses,
+    Jitter,
+}
+
+impl Pattern {
+    pub fn next(self) -> Pattern {
+        match self {
+            Pattern::Off => Pattern::Sine,
+            Pattern::Sine => Pattern::Stairs,
+            Pattern::Stairs => Pattern::Impulses,
+            Pattern::Impulses => Pattern::Jitter,
+            Pattern::Jitter => Pattern::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static [u8] {
+        match self {
+            Pattern::Off => b"off",
+            Pattern::Sine => b"sine",
+            Pattern::Stairs => b"stairs",
+            Pattern::Impulses => b"impulses",
+            Pattern::Jitter => b"jitter",
+        }
+    }
+}
+
+static mut PATTERN: Pattern = Pattern::Off;
+static mut PHASE: usize = 0; // where in its period the next sweep starts
+static mut LENGTH: usize = 0; // the sweep's samples
+static mut STARTED_AT: u32 = 0; // in milliseconds since reset
+static mut STOPPED: bool = true;
+
+/// The pattern the sweeps are taken from, or Off for the ADC.
+pub fn pattern() -> Pattern {
+    unsafe { PATTERN }
+}
+
+pub fn set_pattern(pattern: Pattern) {
+    unsafe { PATTERN = pattern; }
+}
+
+/// The selected pattern, written into the capture buffer.
+pub struct TestPattern;
+
+impl AcquisitionSource for TestPattern {
+    fn start(&self, samples: usize) {
+        capture::abandon_sweep(); // so DMA doesn't write over the pattern
+        unsafe {
+            let pattern = PATTERN;
+            for (i, sample) in CAPTURE_CHANNEL_1[..samples].iter_mut().enumerate() {
+                *sample = value(pattern, PHASE + i);
+            }
+            // the next sweep carries on from the end of this one, wrapping at a whole number
+            // of periods
+            PHASE = (PHASE + samples) % (PERIOD << 16);
+            LENGTH = samples;
+            STARTED_AT = millis();
+            STOPPED = false;
+        }
+    }
+
+    fn stop(&self) {
+        unsafe { STOPPED = true; }
+    }
+
+    fn samples_ready(&self) -> usize {
+        unsafe {
+            if STOPPED {
+                return LENGTH;
+            }
+            let elapsed = time::since(STARTED_AT) as u64 * capture::sample_rate() as u64 / 1000;
+            if elapsed < LENGTH as u64 { elapsed as usize } else { LENGTH }
+        }
+    }
+
+    fn data(&self) -> &'static [u16] {
+        unsafe { &CAPTURE_CHANNEL_1 }
+    }
+}
+
+// the pattern's sample `n` samples from its start
+fn value(pattern: Pattern, n: usize) -> u16 {
+    let i = n % PERIOD;
+    match pattern {
+        Pattern::Off => MID_SCALE,
+        Pattern::Sine => {
+            let quarter = PERIOD / 4;
+            let j = i % quarter;
+            match i / quarter {
+                0 => MID_SCALE + QUARTER_SINE[j],
+                1 => MID_SCALE + QUARTER_SINE[quarter - j],
+                2 => MID_SCALE - QUARTER_SINE[j],
+                _ => MID_SCALE - QUARTER_SINE[quarter - j],
+            }
+        }
+        Pattern::Stairs => (i * STEPS / PERIOD) as u16 * (FULL_SCALE / (STEPS as u16 - 1)),
+        Pattern::Impulses => if i == 0 { FULL_SCALE } else { MID_SCALE },
+        Pattern::Jitter => {
+            // the period's rising edge, moved by a hash of the period's number
+            let mut hash = (n / PERIOD) as u32;
+            hash = hash.wrapping_mul(0x9e37_79b9);
+            hash ^= hash >> 16;
+            let edge = hash as usize % (MAX_JITTER + 1);
+            if i >= edge && i < edge + PERIOD / 2 { FULL_SCALE } else { 0 }
+        }
+    }
+}
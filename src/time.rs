@@ -0,0 +1,56 @@
+// stm32f3-oscilloscope - src/time.rs
+// the millisecond clock, counted by the SysTick interrupt
+
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Everything timed in milliseconds (timeouts, holds, intervals, deadlines) reads this one clock,
+// rather than keeping its own counter. millis() is the cheap, wrapping form, for intervals of
+// less than 49 days, which is all the UI needs; ticks() doesn't wrap, for timestamps.
+
+use core::intrinsics::{volatile_load, volatile_store};
+
+use cortex_m;
+
+static mut TICKS: u64 = 0; // milliseconds since reset
+
+/// Counts one millisecond; called only by the SysTick handler.
+pub fn tick() {
+    unsafe { volatile_store(&mut TICKS, volatile_load(&TICKS) + 1); }
+}
+
+/// Returns the number of milliseconds since reset, without wrapping.
+pub fn ticks() -> u64 {
+    // a 64-bit read isn't atomic, so keep SysTick out of the middle of it
+    cortex_m::interrupt::free(|_| unsafe { volatile_load(&TICKS) })
+}
+
+/// Returns the number of milliseconds since reset, wrapping after about 49 days.
+pub fn millis() -> u32 {
+    ticks() as u32
+}
+
+/// Returns the number of milliseconds since `then`, a previous reading of millis().
+pub fn since(then: u32) -> u32 {
+    millis().wrapping_sub(then)
+}
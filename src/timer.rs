@@ -0,0 +1,108 @@
+// stm32f3-oscilloscope - src/timer.rs
+// timer rate solver: the prescaler and auto-reload values closest to a target rate
+
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// A timer's update rate is its clock divided by (PSC + 1) * (ARR + 1). PSC is 16 bits on all
+// the timers, and ARR is 16 bits except on TIM2, where it is 32. `solve` first finds the
+// smallest prescaler that lets ARR fit, then tries larger ones, since a larger prescaler may
+// divide the clock exactly where the smallest can't. The timer clocks are given by clocks.rs.
+
+use core::cmp::{max, min};
+use core::u64;
+
+pub const MAX_ARR_16: u32 = 0xffff;
+pub const MAX_ARR_32: u32 = 0xffff_ffff;
+
+// the number of prescalers tried, above the smallest
+const SEARCH: u64 = 256;
+
+/// Timer prescaler and auto-reload register values, and the rate they give.
+#[derive(Clone, Copy)]
+pub struct Rate {
+    pub psc: u16,
+    pub arr: u32,
+    clock_hz: u32,
+}
+
+impl Rate {
+    // the total division of the timer clock
+    fn divisor(&self) -> u64 {
+        (self.psc as u64 + 1) * (self.arr as u64 + 1)
+    }
+
+    /// Returns the achieved rate, rounded to the nearest Hz.
+    pub fn hz(&self) -> u32 {
+        ((self.clock_hz as u64 + self.divisor() / 2) / self.divisor()) as u32
+    }
+
+    /// Returns the achieved rate in millihertz, rounded, for rates too low to show in Hz.
+    #[allow(unused)]
+    pub fn millihz(&self) -> u64 {
+        (self.clock_hz as u64 * 1000 + self.divisor() / 2) / self.divisor()
+    }
+
+    /// Returns the time taken by `periods` periods at the achieved rate, in nanoseconds,
+    /// rounded.
+    pub fn nanoseconds(&self, periods: u32) -> u64 {
+        (periods as u64 * self.divisor() * 1_000_000_000 + self.clock_hz as u64 / 2) /
+            self.clock_hz as u64
+    }
+
+    /// Returns whether the achieved rate is exactly `target_hz`.
+    pub fn is_exact(&self, target_hz: u32) -> bool {
+        target_hz as u64 * self.divisor() == self.clock_hz as u64
+    }
+}
+
+/// Returns the prescaler and auto-reload values giving the rate closest to `target_hz`, from a
+/// timer clock of `clock_hz`, with ARR no more than `max_arr`.
+pub fn solve(clock_hz: u32, target_hz: u32, max_arr: u32) -> Rate {
+    let clock = clock_hz as u64;
+    let target = max(target_hz, 1) as u64;
+    let arr_range = max_arr as u64 + 1;
+    // the ideal division, rounded, and limited to what the timer can do
+    let divisor = min(max((clock + target / 2) / target, 1), arr_range * 0x1_0000);
+    let min_psc = (divisor - 1) / arr_range;
+    let mut best = (min_psc, 0);
+    // the best error so far, as |clock - target * d| over d, kept as a fraction
+    let mut best_error = (u64::MAX, 1);
+    let mut psc = min_psc;
+    while psc <= min(min_psc + SEARCH, 0xffff) && psc < divisor {
+        let arr_plus_1 = min(max((divisor + (psc + 1) / 2) / (psc + 1), 1), arr_range);
+        let d = (psc + 1) * arr_plus_1;
+        let achieved = target * d;
+        let error = if achieved > clock { achieved - clock } else { clock - achieved };
+        // compare error / d with best_error.0 / best_error.1
+        if best_error.0 == u64::MAX || error * best_error.1 < best_error.0 * d {
+            best = (psc, arr_plus_1 - 1);
+            best_error = (error, d);
+            if error == 0 {
+                break;
+            }
+        }
+        psc += 1;
+    }
+    Rate { psc: best.0 as u16, arr: best.1 as u32, clock_hz: clock_hz }
+}
@@ -0,0 +1,360 @@
+// stm32f3-oscilloscope - src/trace.rs
+// trace plotting, with per-trace color, visibility, and dots or vectors style
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// Traces are plotted a column at a time, erasing that column's previous plot first, so the
+// display updates smoothly as samples arrive. In dots style each column is a single pixel; in
+// vectors style each column is a vertical line joining the previous column's value to this
+// one, so fast edges show as solid lines.
+
+use cursors;
+use font::FONT_5X7;
+use screen;
+use st7735::*;
+
+const WIDTH: usize = screen::MAX_WIDTH;
+
+/// The colors a trace can be set to, in the order they are cycled through.
+pub const COLORS: [St7735Color; 6] = [
+    St7735Color::White,
+    St7735Color::Yellow,
+    St7735Color::Cyan,
+    St7735Color::Magenta,
+    St7735Color::Green,
+    St7735Color::Blue,
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TraceId {
+    Ch1,
+    Ch2,       // the siggen's sine, sampled internally by ADC2, see capture.rs
+    Reference, // a stored copy of CH1
+    Math,      // the derivative or integral of CH1, see math.rs
+}
+
+pub const TRACES: usize = 4;
+
+impl TraceId {
+    pub fn next(self) -> TraceId {
+        match self {
+            TraceId::Ch1 => TraceId::Ch2,
+            TraceId::Ch2 => TraceId::Reference,
+            TraceId::Reference => TraceId::Math,
+            TraceId::Math => TraceId::Ch1,
+        }
+    }
+
+    pub fn label(self) -> &'static [u8] {
+        match self {
+            TraceId::Ch1 => b"CH1",
+            TraceId::Ch2 => b"CH2",
+            TraceId::Reference => b"ref",
+            TraceId::Math => b"math",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct TraceStyle {
+    pub color: St7735Color,
+    pub visible: bool,
+    pub vectors: bool, // join the samples with lines, rather than plotting dots
+}
+
+impl PartialEq for TraceStyle {
+    fn eq(&self, other: &TraceStyle) -> bool {
+        self.color as u16 == other.color as u16 && self.visible == other.visible &&
+            self.vectors == other.vectors
+    }
+}
+
+/// The styles of the traces at startup, before any stored settings are loaded.
+pub const DEFAULT_STYLES: [TraceStyle; TRACES] = [
+    TraceStyle { color: St7735Color::White, visible: true, vectors: false },
+    TraceStyle { color: St7735Color::Green, visible: false, vectors: false },
+    TraceStyle { color: St7735Color::Yellow, visible: false, vectors: false },
+    TraceStyle { color: St7735Color::Cyan, visible: true, vectors: true },
+];
+
+/// Returns the label of `color`.
+pub fn color_label(color: St7735Color) -> &'static [u8] {
+    match color {
+        St7735Color::White => b"white",
+        St7735Color::Yellow => b"yellow",
+        St7735Color::Cyan => b"cyan",
+        St7735Color::Magenta => b"magenta",
+        St7735Color::Green => b"green",
+        St7735Color::Blue => b"blue",
+        St7735Color::Red => b"red",
+        St7735Color::Black => b"black",
+    }
+}
+
+impl TraceStyle {
+    /// Returns the style with the next color in the palette, or with `back`, the previous one.
+    pub fn step_color(self, back: bool) -> TraceStyle {
+        let mut i = 0;
+        while i < COLORS.len() && COLORS[i] as u16 != self.color as u16 {
+            i += 1;
+        }
+        let step = if back { COLORS.len() - 1 } else { 1 };
+        TraceStyle { color: COLORS[(i + step) % COLORS.len()], ..self }
+    }
+
+    pub fn color_label(&self) -> &'static [u8] {
+        color_label(self.color)
+    }
+
+    pub fn style_label(&self) -> &'static [u8] {
+        if self.vectors { b"vectors" } else { b"dots" }
+    }
+
+    /// Returns the style as it is stored in the settings: the color's index in COLORS in the
+    /// low four bits, then a bit each for visible and vectors.
+    pub fn stored(&self) -> u16 {
+        let i = COLORS.iter().position(|&c| c as u16 == self.color as u16).unwrap_or(0);
+        i as u16 | (self.visible as u16) << 4 | (self.vectors as u16) << 5
+    }
+
+    /// Returns the style stored as `stored`, or None if it isn't one.
+    pub fn from_stored(stored: u16) -> Option<TraceStyle> {
+        let i = (stored & 0xf) as usize;
+        if i >= COLORS.len() || stored >> 6 != 0 {
+            return None;
+        }
+        Some(TraceStyle { color: COLORS[i], visible: stored & 0x10 != 0,
+                          vectors: stored & 0x20 != 0 })
+    }
+}
+
+// the styles in use, as in the settings
+static mut STYLES: [TraceStyle; TRACES] = DEFAULT_STYLES;
+
+pub fn style(id: TraceId) -> TraceStyle {
+    unsafe { STYLES[id as usize] }
+}
+
+/// Sets the styles of all the traces, by TraceId, as they are in the settings.
+pub fn set_styles(styles: [TraceStyle; TRACES]) {
+    unsafe { STYLES = styles; }
+}
+
+// ======== graticule ========
+
+fn is_graticule_column(x: i16) -> bool {
+    x >= 32 && x < screen::width() && x % 32 == 0
+}
+
+/// Paints the graticule dots.
+pub fn paint_graticule() {
+    let mut x = 32;
+    while x < screen::width() {
+        repair_graticule(x, 0, screen::height() - 1);
+        x += 32;
+    }
+}
+
+/// Clears column `x` of the trace area, and repaints its graticule dots.
+pub fn clear_column(x: usize) {
+    let height = screen::trace_height();
+    st7735_drawFastVLine(x as i16, 0, height, St7735Color::Black as u16);
+    repair_graticule(x as i16, 0, height - 1);
+}
+
+// repaint any graticule dots, axis label pixels, or cursor dots in column `x` between `top`
+// and `bottom`, after erasing
+fn repair_graticule(x: i16, top: i16, bottom: i16) {
+    repair_labels(x, top, bottom);
+    cursors::repair(x, top, bottom);
+    if !is_graticule_column(x) {
+        return;
+    }
+    let mut y = 32;
+    while y < screen::height() {
+        let screen_y = screen::from_bottom(y);
+        if screen_y >= top && screen_y <= bottom {
+            st7735_drawPixel(x, screen_y, St7735Color::Red as u16);
+        }
+        y += 32;
+    }
+}
+
+// ======== axis labels ========
+
+// The labels are in the small font. The time labels are under the bottom row of graticule
+// dots, each starting just right of a vertical graticule line (or the left edge), and the
+// voltage labels are at the left edge, just above each row of dots. Like the graticule dots,
+// they are repaired whenever a column is erased, so they are kept as a bitmap of each column's
+// pixels.
+
+const LABEL_GAP: i16 = 2; // pixels between a row of graticule dots and the labels
+const LABEL_WIDTH: usize = 31; // the room between graticule lines
+const VOLTAGE_LABEL_WIDTH: usize = 34; // room for "-12.5V", just past the first line
+const VOLTAGE_ROWS: usize = 4; // the most rows of graticule dots, in portrait
+
+static mut LABEL_COLUMNS: [u8; WIDTH] = [0; WIDTH]; // top pixel in bit 0
+static mut VOLTAGE_COLUMNS: [[u8; VOLTAGE_LABEL_WIDTH]; VOLTAGE_ROWS] =
+    [[0; VOLTAGE_LABEL_WIDTH]; VOLTAGE_ROWS];
+
+/// Sets the time axis label under division `division`, counting from the left edge. The
+/// label is shown as the trace area's columns are next cleared.
+pub fn set_time_label(division: usize, text: &[u8]) {
+    let x = division * 32 + 1;
+    if x >= WIDTH {
+        return;
+    }
+    let end = if x + LABEL_WIDTH > WIDTH { WIDTH } else { x + LABEL_WIDTH };
+    let columns = unsafe { &mut LABEL_COLUMNS[x..end] };
+    for column in columns.iter_mut() {
+        *column = 0;
+    }
+    FONT_5X7.render_columns(text, columns);
+}
+
+/// Sets the voltage label above graticule row `row`, counting up from the lowest row. The
+/// label is shown as the trace area's columns are next cleared.
+pub fn set_voltage_label(row: usize, text: &[u8]) {
+    let columns = unsafe { &mut VOLTAGE_COLUMNS[row] };
+    *columns = [0; VOLTAGE_LABEL_WIDTH];
+    FONT_5X7.render_columns(text, columns);
+}
+
+// repaint any axis label pixels in column `x` between `top` and `bottom`
+fn repair_labels(x: i16, top: i16, bottom: i16) {
+    repair_label_column(x, screen::from_bottom(32) + LABEL_GAP, top, bottom,
+                        unsafe { LABEL_COLUMNS[x as usize] });
+    if x as usize >= VOLTAGE_LABEL_WIDTH {
+        return;
+    }
+    for row in 0..VOLTAGE_ROWS {
+        let dots_y = 32 * (row as i16 + 1);
+        if dots_y < screen::height() {
+            let label_top = screen::from_bottom(dots_y) - LABEL_GAP - 7;
+            repair_label_column(x, label_top, top, bottom,
+                                unsafe { VOLTAGE_COLUMNS[row][x as usize] });
+        }
+    }
+}
+
+// repaint the pixels `bits` of a label column starting at `label_top`, between `top` and
+// `bottom`
+fn repair_label_column(x: i16, label_top: i16, top: i16, bottom: i16, bits: u8) {
+    for row in 0..8 {
+        let y = label_top + row;
+        if bits & (1 << row) != 0 && y >= top && y <= bottom {
+            st7735_drawPixel(x, y, St7735Color::Red as u16);
+        }
+    }
+}
+
+// ======== plotting ========
+
+// clip a screen coordinate to the top or bottom edge
+fn clip(y: i16) -> i16 {
+    let height = screen::height();
+    if y < 0 { 0 } else if y >= height { height - 1 } else { y }
+}
+
+/// The plotting state of one trace: what was drawn in each column, so it can be erased.
+pub struct TracePlot {
+    top: [u8; WIDTH],    // 255 if nothing is drawn in the column
+    bottom: [u8; WIDTH],
+    color: [u16; WIDTH],
+    last_y: Option<i16>, // the previous column's value, for vectors
+}
+
+impl TracePlot {
+    pub fn new() -> TracePlot {
+        TracePlot { top: [255; WIDTH], bottom: [255; WIDTH], color: [0; WIDTH], last_y: None }
+    }
+
+    // what plotting `y` in column `x` would draw: its top and bottom rows, and its color
+    fn span(&self, x: usize, y: Option<i16>, style: TraceStyle) -> Option<(i16, i16, u16)> {
+        let y = match y {
+            Some(y) if style.visible => y,
+            _ => return None,
+        };
+        let color = if y < 0 || y >= screen::height() { St7735Color::Red } else { style.color };
+        let y = clip(y);
+        let (top, bottom) = match self.last_y {
+            // join to the previous column's value
+            Some(last) if style.vectors && x > 0 => if last < y { (last, y) } else { (y, last) },
+            _ => (y, y),
+        };
+        Some((top, bottom, color as u16))
+    }
+
+    /// Returns whether plotting `y` in column `x` would draw just what is drawn there now, so
+    /// the column can be left alone, and `keep` called instead of erasing and plotting.
+    pub fn unchanged(&self, x: usize, y: Option<i16>, style: TraceStyle) -> bool {
+        let drawn = if self.top[x] < 255 {
+            Some((self.top[x] as i16, self.bottom[x] as i16, self.color[x]))
+        } else {
+            None
+        };
+        self.span(x, y, style) == drawn
+    }
+
+    /// Moves on past column `x`, left as it is, as if `y` had been plotted in it.
+    pub fn keep(&mut self, y: Option<i16>) {
+        self.last_y = y.map(clip);
+    }
+
+    /// Erases the previous plot in column `x`.
+    pub fn erase_column(&mut self, x: usize) {
+        if self.top[x] < 255 {
+            let top = self.top[x] as i16;
+            let bottom = self.bottom[x] as i16;
+            st7735_drawFastVLine(x as i16, top, bottom - top + 1, St7735Color::Black as u16);
+            repair_graticule(x as i16, top, bottom);
+            self.top[x] = 255;
+        }
+    }
+
+    /// Plots screen coordinate `y` (which may be off-screen, or `None` for no value) in column
+    /// `x`, which should have been erased first. Off-screen values are clipped to the top or
+    /// bottom edge, and shown in red.
+    pub fn plot(&mut self, x: usize, y: Option<i16>, style: TraceStyle) {
+        if let Some((top, bottom, color)) = self.span(x, y, style) {
+            if top == bottom {
+                st7735_drawPixel(x as i16, top, color);
+            } else {
+                st7735_drawFastVLine(x as i16, top, bottom - top + 1, color);
+            }
+            self.top[x] = top as u8;
+            self.bottom[x] = bottom as u8;
+            self.color[x] = color;
+        }
+        self.keep(y);
+    }
+
+    /// Plots column `x` again, after it has been drawn over and cleared, joining to
+    /// `previous`, the previous column's value, in vectors style. Unlike `plot`, this doesn't
+    /// disturb a sweep being plotted in another column.
+    pub fn replot(&mut self, x: usize, previous: Option<i16>, y: Option<i16>, style: TraceStyle) {
+        let last_y = self.last_y;
+        self.last_y = previous.map(clip);
+        self.plot(x, y, style);
+        self.last_y = last_y;
+    }
+}
@@ -0,0 +1,260 @@
+// stm32f3-oscilloscope - src/trigger.rs
+// software trigger, searching the capture record as it arrives
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// Each sweep captures samples made up of three parts:
+// - the search window, in which the trigger event is looked for,
+// - the trigger delay, which is skipped over, and
+// - the record, of which the first screen width is displayed (the rest is viewable by zooming).
+// The capture simply continues for as many extra samples as the delay requires, so the delay
+// is limited only by the size of the capture buffer.
+
+/// The number of samples searched for a trigger event at the start of each record.
+pub const SEARCH_LENGTH: usize = 160;
+
+/// The number of samples displayed after the trigger point (plus any delay), and so the
+/// shortest record.
+pub const DISPLAY_LENGTH: usize = 160;
+
+// how far past the trigger level the signal must go to re-arm the trigger
+const HYSTERESIS: u16 = 32;
+const NOISE_REJECT_HYSTERESIS: u16 = 256;
+
+// the HF-reject low-pass filter is a one-pole IIR, y += (x - y) / 2^HF_REJECT_SHIFT, giving a
+// -3dB point of about 1/50 of the sample rate
+const HF_REJECT_SHIFT: u32 = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TriggerMode {
+    Off = 0,    // free run, no trigger search
+    Auto = 1,   // free run if no trigger event is found before the auto timeout
+    Normal = 2, // only display records which contain a trigger event
+}
+
+impl TriggerMode {
+    pub fn next(self) -> TriggerMode {
+        match self {
+            TriggerMode::Off => TriggerMode::Auto,
+            TriggerMode::Auto => TriggerMode::Normal,
+            TriggerMode::Normal => TriggerMode::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static [u8] {
+        match self {
+            TriggerMode::Off => b"off",
+            TriggerMode::Auto => b"auto",
+            TriggerMode::Normal => b"normal",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TriggerSlope {
+    Rising = 0,
+    Falling = 1,
+    Either = 2,
+}
+
+impl TriggerSlope {
+    pub fn next(self) -> TriggerSlope {
+        match self {
+            TriggerSlope::Rising => TriggerSlope::Falling,
+            TriggerSlope::Falling => TriggerSlope::Either,
+            TriggerSlope::Either => TriggerSlope::Rising,
+        }
+    }
+
+    pub fn label(self) -> &'static [u8] {
+        match self {
+            TriggerSlope::Rising => b"rising",
+            TriggerSlope::Falling => b"falling",
+            TriggerSlope::Either => b"either",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TriggerCoupling {
+    DC = 0,          // trigger on the samples as captured
+    HFReject = 1,    // trigger on low-pass filtered samples
+    NoiseReject = 2, // trigger with wider hysteresis
+}
+
+impl TriggerCoupling {
+    pub fn next(self) -> TriggerCoupling {
+        match self {
+            TriggerCoupling::DC => TriggerCoupling::HFReject,
+            TriggerCoupling::HFReject => TriggerCoupling::NoiseReject,
+            TriggerCoupling::NoiseReject => TriggerCoupling::DC,
+        }
+    }
+
+    pub fn label(self) -> &'static [u8] {
+        match self {
+            TriggerCoupling::DC => b"DC",
+            TriggerCoupling::HFReject => b"HF rej",
+            TriggerCoupling::NoiseReject => b"noise rej",
+        }
+    }
+}
+
+// trigger delays, after the trigger event and before the displayed window, in samples at 32
+// samples per division. At an interpolated timebase the delay applied is divided by the
+// interpolation factor (see update_settings() in main.rs), to the nearest sample.
+pub struct TriggerDelay {
+    pub samples: usize,
+    pub label: &'static [u8],
+}
+
+pub const TRIGGER_DELAYS: [TriggerDelay; 5] = [
+    TriggerDelay { samples:   0, label: b"0div" },
+    TriggerDelay { samples:  32, label: b"1div" },
+    TriggerDelay { samples:  64, label: b"2div" },
+    TriggerDelay { samples: 160, label: b"5div" },
+    TriggerDelay { samples: 320, label: b"10div" },
+];
+
+/// The longest trigger delay, in samples.
+pub const MAX_DELAY: usize = 320;
+
+// how long auto mode waits for a trigger event before free running
+pub struct AutoTimeout {
+    pub ms: u32, // zero for a timeout derived from the sample rate
+    pub label: &'static [u8],
+}
+
+pub const AUTO_TIMEOUTS: [AutoTimeout; 7] = [
+    AutoTimeout { ms:    0, label: b"auto" },
+    AutoTimeout { ms:   50, label: b"50ms" },
+    AutoTimeout { ms:  100, label: b"100ms" },
+    AutoTimeout { ms:  200, label: b"200ms" },
+    AutoTimeout { ms:  500, label: b"500ms" },
+    AutoTimeout { ms: 1000, label: b"1s" },
+    AutoTimeout { ms: 2000, label: b"2s" },
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct TriggerConfig {
+    pub mode: TriggerMode,
+    pub slope: TriggerSlope,
+    pub coupling: TriggerCoupling,
+    pub level: u16,    // raw ADC conversion value
+    pub delay: usize,  // samples between the trigger event and the displayed window
+    pub auto_timeout_ms: u32, // zero to derive the timeout from the sample rate
+}
+
+impl TriggerConfig {
+    /// Returns the number of samples to search for a trigger event.
+    pub fn search_length(&self) -> usize {
+        if self.mode == TriggerMode::Off { 0 } else { SEARCH_LENGTH }
+    }
+
+    /// Returns the auto mode timeout as a number of samples at `sample_rate`. The default is the
+    /// time taken to capture two screens' worth of samples, limited to 50ms..2s.
+    pub fn auto_timeout_samples(&self, sample_rate: u32) -> u32 {
+        let ms = if self.auto_timeout_ms != 0 {
+            self.auto_timeout_ms
+        } else {
+            let sweep_ms = (2 * DISPLAY_LENGTH as u64 * 1000 / sample_rate as u64) as u32;
+            if sweep_ms < 50 { 50 } else if sweep_ms > 2000 { 2000 } else { sweep_ms }
+        };
+        (ms as u64 * sample_rate as u64 / 1000) as u32
+    }
+
+    /// Returns the total number of samples to capture per sweep, for a record of
+    /// `record_length` samples.
+    pub fn sweep_length(&self, record_length: usize) -> usize {
+        self.search_length() + self.delay + record_length
+    }
+}
+
+/// Incremental trigger search, fed with the samples transferred so far.
+pub struct TriggerSearch {
+    position: usize,     // next sample to examine
+    armed_rising: bool,  // signal has been below the rising edge re-arm threshold
+    armed_falling: bool, // signal has been above the falling edge re-arm threshold
+    filtered: Option<u32>, // HF-reject filter state, scaled by 2^HF_REJECT_SHIFT
+}
+
+impl TriggerSearch {
+    pub fn new() -> TriggerSearch {
+        TriggerSearch { position: 0, armed_rising: false, armed_falling: false, filtered: None }
+    }
+
+    // pass a sample through the trigger coupling filter
+    fn couple(&mut self, sample: u16, coupling: TriggerCoupling) -> u16 {
+        if coupling != TriggerCoupling::HFReject {
+            return sample;
+        }
+        let x = (sample as u32) << HF_REJECT_SHIFT;
+        let y = match self.filtered {
+            Some(y) => y + (x >> HF_REJECT_SHIFT) - (y >> HF_REJECT_SHIFT),
+            None => x,
+        };
+        self.filtered = Some(y);
+        (y >> HF_REJECT_SHIFT) as u16
+    }
+
+    /// Examines the samples from where the previous call left off up to (but not including)
+    /// `end`, returning the index of the trigger event, if found.
+    pub fn scan(&mut self, data: &[u16], end: usize, config: &TriggerConfig) -> Option<usize> {
+        let hysteresis = if config.coupling == TriggerCoupling::NoiseReject {
+            NOISE_REJECT_HYSTERESIS
+        } else {
+            HYSTERESIS
+        };
+        let rearm_rising = config.level.saturating_sub(hysteresis);
+        let rearm_falling = config.level.saturating_add(hysteresis);
+        let rising = config.slope != TriggerSlope::Falling;
+        let falling = config.slope != TriggerSlope::Rising;
+        while self.position < end {
+            let sample = self.couple(data[self.position], config.coupling);
+            self.position += 1;
+            // an event disarms its slope, so scanning on finds the next event, not the same one
+            if sample < rearm_rising {
+                self.armed_rising = true;
+            } else if rising && self.armed_rising && sample >= config.level {
+                self.armed_rising = false;
+                return Some(self.position - 1);
+            }
+            if sample > rearm_falling {
+                self.armed_falling = true;
+            } else if falling && self.armed_falling && sample <= config.level {
+                self.armed_falling = false;
+                return Some(self.position - 1);
+            }
+        }
+        None
+    }
+}
+
+/// Returns the number of trigger events in `data`, as `config` would trigger on them.
+pub fn count_events(data: &[u16], config: &TriggerConfig) -> u32 {
+    let mut search = TriggerSearch::new();
+    let mut count = 0;
+    while search.scan(data, data.len(), config).is_some() {
+        count += 1;
+    }
+    count
+}
@@ -0,0 +1,98 @@
+// stm32f3-oscilloscope - src/trigger_out.rs
+// trigger output: a short pulse on a GPIO pin each time the trigger fires
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// The trigger is found in software, by scanning the captured samples, so the pulse comes when
+// the main loop finds the trigger event, not at the event itself: it lags by anything from a
+// few microseconds to a main loop pass. It marks which events were captured, for a second
+// scope, a camera, or a device under test, rather than timing them precisely.
+//
+// pulse() sets the pin, and starts TIM17 in one-pulse mode; its update interrupt, at the end of
+// PULSE_US, clears the pin. The pin is given by board::TRIGGER_OUT_PIN.
+
+use cortex_m;
+use stm32f30x::{Interrupt, RCC, TIM17};
+
+use board::TRIGGER_OUT_PIN;
+use clocks;
+use gpio::Mode;
+use priority;
+use timer;
+
+/// The length of the pulse, in microseconds.
+pub const PULSE_US: u32 = 10;
+
+/// Sets up TIM17 and the output pin, which is left low.
+pub fn setup() {
+    TRIGGER_OUT_PIN.enable_port_clock();
+    TRIGGER_OUT_PIN.set_low();
+    TRIGGER_OUT_PIN.configure(Mode::Output);
+    cortex_m::interrupt::free(|cs| {
+        let rcc = RCC.borrow(cs);
+        let tim17 = TIM17.borrow(cs);
+        let nvic = cortex_m::peripheral::NVIC.borrow(cs);
+
+        rcc.apb2enr.modify(|_, w| w.tim17en().enabled());
+        tim17.cr1.write(|w| unsafe {
+            w.opm().bits(1) // one-pulse mode: stop at the update event
+             .urs().bits(1) // only overflow interrupts, not UG
+        });
+        tim17.dier.write(|w| unsafe { w.uie().bits(1) });
+        // TIM17 shares its interrupt with TIM1's trigger and commutation interrupts, which are
+        // unused
+        unsafe { nvic.set_priority(Interrupt::Tim1TrgComTim17, priority::TRIGGER_OUT); }
+        nvic.enable(Interrupt::Tim1TrgComTim17);
+    });
+    set_rate();
+}
+
+/// Sets TIM17's rate from its clock, which changes if the HSE is lost.
+pub fn set_rate() {
+    let rate = timer::solve(clocks::apb2_timer_hz(), 1_000_000 / PULSE_US, timer::MAX_ARR_16);
+    cortex_m::interrupt::free(|cs| {
+        let tim17 = TIM17.borrow(cs);
+        tim17.arr.write(|w| unsafe { w.bits(rate.arr) });
+        tim17.psc.write(|w| unsafe { w.psc().bits(rate.psc) });
+        tim17.egr.write(|w| unsafe { w.ug().bits(1) }); // immediately update registers
+    });
+}
+
+/// Starts a pulse, or restarts the one in progress.
+pub fn pulse() {
+    cortex_m::interrupt::free(|cs| {
+        let tim17 = TIM17.borrow(cs);
+        TRIGGER_OUT_PIN.set_high();
+        tim17.cnt.write(|w| unsafe { w.bits(0) });
+        tim17.cr1.modify(|_, w| unsafe { w.cen().bits(1) });
+    });
+}
+
+/// Ends the pulse. Called from the TIM17 interrupt handler.
+pub fn interrupt() {
+    cortex_m::interrupt::free(|cs| {
+        let tim17 = TIM17.borrow(cs);
+        tim17.sr.modify(|_, w| unsafe { w.uif().bits(0) });
+    });
+    TRIGGER_OUT_PIN.set_low();
+}
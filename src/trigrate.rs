@@ -0,0 +1,70 @@
+// stm32f3-oscilloscope - src/trigrate.rs
+// trigger rate: the trigger events a second, counted in each record
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Each record is searched for every event the trigger settings would trigger on, not just the
+// first, and the events and the time the records span are added up over WINDOW_MS; the rate
+// is their ratio. For a periodic signal, that is its frequency, counted over many cycles, or
+// for an either-slope trigger, twice it, even where the record is too short or the timebase too
+// slow for the frequency measurement's two crossings of the midpoint to be told apart. With
+// the settings wrong, it goes to zero. Only the sampled time counts, since the trigger sees
+// nothing between records.
+
+use time::millis;
+use trigger::{self, TriggerConfig, TriggerMode};
+
+const WINDOW_MS: u32 = 1000;
+
+static mut EVENTS: u64 = 0;
+static mut NANOSECONDS: u64 = 0; // the sampled time counted in the window
+static mut WINDOW_START: u32 = 0;
+static mut RATE: Option<u64> = None; // millihertz, over the last window
+
+/// Counts the trigger events in `record`, captured at `sample_rate`, for the rate.
+pub fn count(record: &[u16], sample_rate: u32, config: &TriggerConfig) {
+    unsafe {
+        if config.mode == TriggerMode::Off {
+            // nothing is triggered on
+            EVENTS = 0;
+            NANOSECONDS = 0;
+            RATE = None;
+            return;
+        }
+        EVENTS += trigger::count_events(record, config) as u64;
+        NANOSECONDS += record.len() as u64 * 1_000_000_000 / sample_rate as u64;
+        if millis().wrapping_sub(WINDOW_START) >= WINDOW_MS {
+            if NANOSECONDS > 0 {
+                RATE = Some(EVENTS * 1_000_000_000_000 / NANOSECONDS);
+            }
+            EVENTS = 0;
+            NANOSECONDS = 0;
+            WINDOW_START = millis();
+        }
+    }
+}
+
+/// Returns the trigger rate in millihertz, or None if the trigger is off or not yet counted.
+pub fn millihz() -> Option<u64> {
+    unsafe { RATE }
+}
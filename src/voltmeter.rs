@@ -0,0 +1,188 @@
+// stm32f3-oscilloscope - src/voltmeter.rs
+// DMM-style voltmeter: DC mean, AC RMS, minimum, and maximum, autoranging
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// Unlike the meter display, which shows each record's measurements as they come, the
+// voltmeter integrates the samples of all the records in each READING_MS period into one
+// reading, as a DMM does, so the last digits are steady and finer than one ADC step. The
+// offset and scale come from the calibration for the range's gain. The AC RMS is that of the
+// signal with its DC mean removed, i.e. the standard deviation of the samples. The readings
+// are mapped to the actual input voltages (see input.rs), so the mean, minimum, and maximum
+// may be negative, shown with a '-' in place of the first digit, losing the last one.
+//
+// Each range has an input gain, and a resolution for the readout. The input has only the one
+// gain for now (see calibration.rs), so the ranges differ only in their resolution. The range
+// moves up as soon as the maximum or minimum is beyond its full scale, and down when both have
+// been within RANGE_DOWN_PERCENT of the lower range's full scale for a whole reading. The 30V
+// range is only reached through an input mapping.
+
+use input;
+
+use calibration;
+use measure;
+use segment;
+use st7735::*;
+use time;
+
+struct Range {
+    gain: usize,
+    full_scale_uv: u64,
+    step_uv: u64,     // microvolts per count of the last digit
+    decimals: usize,
+    unit: &'static [u8],
+    label: &'static [u8],
+}
+
+const RANGES: [Range; 3] = [
+    Range { gain: 0, full_scale_uv: 300_000, step_uv: 100, decimals: 1, unit: b"mV",
+            label: b"300mV" },
+    Range { gain: 0, full_scale_uv: 3_600_000, step_uv: 1000, decimals: 3, unit: b"V ",
+            label: b"3V   " },
+    Range { gain: 0, full_scale_uv: 36_000_000, step_uv: 10_000, decimals: 2, unit: b"V ",
+            label: b"30V  " },
+];
+const HIGHEST_RANGE: usize = 2;
+const RANGE_DOWN_PERCENT: u64 = 90;
+
+// how long the samples are integrated for each reading, in milliseconds
+const READING_MS: u32 = 400;
+// the most samples integrated in one reading, so the sums can't overflow
+const MAX_SAMPLES: u64 = 1 << 19;
+
+// the layout: the DC mean large, the AC RMS below it, then the minimum and maximum
+const LABEL_X: u8 = 0;
+const DIGITS_X: i16 = 28;
+const ROWS: [(&'static [u8], i16, i16); 4] = [ // (label, y, digit height)
+    (b"DC ", 4, 36),
+    (b"AC ", 48, 24),
+    (b"min", 78, 14),
+    (b"max", 96, 14),
+];
+const RANGE_X: u8 = 112;
+const RANGE_Y: u8 = 98;
+
+// the running sums for the reading in progress, of the offset-corrected conversions
+static mut COUNT: u64 = 0;
+static mut SUM: u64 = 0;
+static mut SUM_OF_SQUARES: u64 = 0;
+static mut MIN: u16 = 4095;
+static mut MAX: u16 = 0;
+static mut STARTED_AT: u32 = 0; // when the reading in progress was started
+static mut RANGE: usize = HIGHEST_RANGE;
+
+/// Starts a new reading, e.g. when the voltmeter is selected, starting from the highest range.
+pub fn reset() {
+    unsafe {
+        RANGE = HIGHEST_RANGE;
+    }
+    start_reading();
+}
+
+fn start_reading() {
+    unsafe {
+        COUNT = 0;
+        SUM = 0;
+        SUM_OF_SQUARES = 0;
+        MIN = 4095;
+        MAX = 0;
+        STARTED_AT = time::millis();
+    }
+}
+
+// the conversion corrected for the offset of the range's gain
+fn correct(raw_conversion: u16, gain: usize) -> u16 {
+    let corrected = raw_conversion as i32 - calibration::offset(gain) as i32;
+    if corrected < 0 { 0 } else if corrected > 4095 { 4095 } else { corrected as u16 }
+}
+
+/// Adds a record to the reading in progress, then draws the reading when its time is up.
+pub fn update(record: &[u16]) {
+    unsafe {
+        let gain = RANGES[RANGE].gain;
+        for &sample in record {
+            if COUNT == MAX_SAMPLES {
+                break;
+            }
+            let sample = correct(sample, gain);
+            if sample < MIN { MIN = sample; }
+            if sample > MAX { MAX = sample; }
+            SUM += sample as u64;
+            SUM_OF_SQUARES += sample as u64 * sample as u64;
+            COUNT += 1;
+        }
+        if time::since(STARTED_AT) < READING_MS || COUNT == 0 {
+            return;
+        }
+        let nanovolts = calibration::nanovolts_per_lsb(gain) as u64;
+        let n = COUNT;
+        let microvolts = |sum: u64, n: u64| (sum * nanovolts / n / 1000) as i64;
+        let mean_uv = input::microvolts(microvolts(SUM, n));
+        // the standard deviation, times n, is sqrt(n * sum of squares - sum^2)
+        let deviation = measure::isqrt(SUM_OF_SQUARES * n - SUM * SUM);
+        let rms_uv = input::scale(microvolts(deviation, n));
+        let min_uv = input::microvolts(microvolts(MIN as u64, 1));
+        let max_uv = input::microvolts(microvolts(MAX as u64, 1));
+
+        // choose the range for this reading, and the next, from the largest magnitude
+        let peak_uv = if -min_uv > max_uv { -min_uv } else { max_uv } as u64;
+        if peak_uv > RANGES[RANGE].full_scale_uv {
+            while RANGE + 1 < RANGES.len() && peak_uv > RANGES[RANGE].full_scale_uv {
+                RANGE += 1;
+            }
+        } else if RANGE > 0 {
+            let lower = RANGES[RANGE - 1].full_scale_uv;
+            if peak_uv < lower * RANGE_DOWN_PERCENT / 100 {
+                RANGE -= 1;
+            }
+        }
+        draw([mean_uv, rms_uv, min_uv, max_uv], &RANGES[RANGE]);
+        start_reading();
+    }
+}
+
+fn draw(values: [i64; 4], range: &Range) {
+    for (&(label, y, height), &value) in ROWS.iter().zip(values.iter()) {
+        let text_y = (y + height - 12) as u8;
+        st7735_print(label, LABEL_X, text_y, St7735Color::Blue, St7735Color::Black);
+        // "ddd.d" or "d.ddd", four digit cells, or for a negative value, "-ddd" or "-d.dd"
+        let mut buf = [b' '; 5];
+        let count = value.abs() as u64 / range.step_uv;
+        let len = if value < 0 {
+            let count = count / 10;
+            let count = if count > 999 { 999 } else { count } as i64;
+            let decimals = range.decimals - 1;
+            let len = if decimals > 0 { 5 } else { 4 }; // without a point, one less character
+            measure::format_signed_fixed(-count, decimals, &mut buf[..len]);
+            len
+        } else {
+            measure::format_fixed(if count > 9999 { 9999 } else { count }, range.decimals,
+                                  &mut buf);
+            5
+        };
+        let x = segment::draw(&buf[..len], DIGITS_X, y, height, St7735Color::Green,
+                              St7735Color::Black);
+        st7735_print(range.unit, (x + 2) as u8, text_y, St7735Color::Green, St7735Color::Black);
+    }
+    st7735_print(range.label, RANGE_X, RANGE_Y, St7735Color::Blue, St7735Color::Black);
+}
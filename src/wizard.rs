@@ -0,0 +1,211 @@
+// stm32f3-oscilloscope - src/wizard.rs
+// the calibration wizard: the supply, offset, and gain calibrations, in turn
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+
+// With the "cal:" parameter selected, each press of button 4 is OK for the step shown on the
+// status line:
+//
+//   1/3 VDDA 2.98V, 4=OK   the supply measured through VREFINT, which becomes the ADC
+//                          reference, unless that is already following the supply
+//   2/3 ground in, 4=OK    then the offset is measured, over CALIBRATION_SWEEPS sweeps
+//   3/3 3.000V in, 4=OK    then the gain, with calibration::REFERENCE_MICROVOLTS applied
+//
+// The measurements show their progress, "2/3 measuring 50%", and are checked as they finish:
+// a supply outside 2V to 3.6V, an offset the grounded input can't explain, or a gain more than
+// 10% from nominal stops the wizard with "out of range", leaving the calibration as it was.
+// Only when all three pass is the calibration saved to flash, with a summary of the offset and
+// the gain relative to nominal, e.g. "saved off 12 x1.003". Selecting another parameter
+// part-way through abandons the wizard, and any measurements made so far.
+
+use calibration;
+use capture;
+use console;
+use measure;
+use st7735::St7735Color;
+use status;
+
+const STEPS: u8 = 3;
+const PROGRESS_COLOR: St7735Color = St7735Color::Yellow;
+const LINE_LENGTH: usize = 20; // a status line of the large font
+
+#[derive(Clone, Copy, PartialEq)]
+enum Measurement {
+    Offset,
+    Gain,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Supply(u32),         // showing the supply measured, in millivolts
+    Prompt(Measurement), // asking for the input to be connected
+    // measuring, with the sweeps remaining, and the sum and count of the samples so far
+    Measuring(Measurement, u32, u64, u64),
+}
+
+static mut STATE: State = State::Idle;
+
+/// Returns whether a measurement is being made, during which sweeps shouldn't wait for a
+/// trigger, as a grounded input won't give one.
+pub fn measuring() -> bool {
+    match unsafe { STATE } {
+        State::Measuring(..) => true,
+        _ => false,
+    }
+}
+
+/// Handles a press of OK, button 4. Returns true if the ADC reference was changed, which the
+/// caller must record as its setting, and redraw the screen's scale for.
+pub fn press() -> bool {
+    match unsafe { STATE } {
+        State::Idle => {
+            let supply_mv = capture::auxiliary().supply_mv;
+            let mut text = *b"VDDA 0.00V, 4=OK";
+            measure::format_fixed(supply_mv as u64 / 10, 2, &mut text[5..9]);
+            show(1, &text);
+            unsafe { STATE = State::Supply(supply_mv); }
+            false
+        }
+        State::Supply(supply_mv) => {
+            if !calibration::reference_in_range(supply_mv) {
+                fail(1);
+                return false;
+            }
+            // with the reference following the supply, it already has the measurement
+            let changed = calibration::reference().is_some();
+            if changed {
+                calibration::set_reference(Some(supply_mv));
+            }
+            show(2, b"ground in, 4=OK");
+            unsafe { STATE = State::Prompt(Measurement::Offset); }
+            changed
+        }
+        State::Prompt(measurement) => {
+            show(step(measurement), b"measuring 0%");
+            unsafe {
+                STATE = State::Measuring(measurement, calibration::CALIBRATION_SWEEPS, 0, 0);
+            }
+            false
+        }
+        State::Measuring(..) => false, // already measuring
+    }
+}
+
+/// Adds a captured record to the measurement being made, if there is one, finishing it after
+/// the last of its sweeps.
+pub fn add_record(record: &[u16]) {
+    if let State::Measuring(measurement, sweeps, sum, count) = unsafe { STATE } {
+        let sum = record.iter().fold(sum, |sum, &sample| sum + sample as u64);
+        let count = count + record.len() as u64;
+        if sweeps > 1 {
+            let mut text = *b"measuring   %";
+            let done = calibration::CALIBRATION_SWEEPS - sweeps + 1;
+            measure::format_fixed((100 * done / calibration::CALIBRATION_SWEEPS) as u64, 0,
+                                  &mut text[10..13]);
+            show(step(measurement), &text);
+            unsafe { STATE = State::Measuring(measurement, sweeps - 1, sum, count); }
+        } else {
+            finish(measurement, sum, count);
+        }
+    }
+}
+
+/// Abandons the wizard, if it is running, and any measurements it has made.
+pub fn cancel() {
+    if unsafe { STATE } != State::Idle {
+        calibration::revert();
+        unsafe { STATE = State::Idle; }
+    }
+}
+
+// the step number of a measurement
+fn step(measurement: Measurement) -> u8 {
+    match measurement {
+        Measurement::Offset => 2,
+        Measurement::Gain => 3,
+    }
+}
+
+// show a step's progress and text on the status line
+fn show(step: u8, text: &[u8]) {
+    status::clear();
+    status::print_color(&[b'0' + step, b'/', b'0' + STEPS], 0, PROGRESS_COLOR);
+    status::print(text, 32);
+}
+
+fn fail(step: u8) {
+    show(step, b"out of range");
+    console::log(b"calibration failed");
+    cancel();
+}
+
+// complete a measurement, then go on to the next step, or save the calibration
+fn finish(measurement: Measurement, sum: u64, count: u64) {
+    let result = match measurement {
+        Measurement::Offset => calibration::set_offset(0, sum, count),
+        Measurement::Gain => calibration::set_scale(0, sum, count),
+    };
+    if result.is_err() {
+        fail(step(measurement));
+        return;
+    }
+    if measurement == Measurement::Offset {
+        // e.g. "3.000V in, 4=OK"
+        let mut text = *b"0.000V in, 4=OK";
+        measure::format_fixed(calibration::REFERENCE_MICROVOLTS as u64 / 1000, 3,
+                              &mut text[..5]);
+        show(3, &text);
+        unsafe { STATE = State::Prompt(Measurement::Gain); }
+        return;
+    }
+    unsafe { STATE = State::Idle; }
+    status::clear();
+    match calibration::save() {
+        Ok(()) => {
+            // e.g. "saved off 12 x1.003"
+            let mut line = [0u8; LINE_LENGTH];
+            let mut offset = [b' '; 3];
+            let mut scale = [b' '; 5];
+            measure::format_fixed(calibration::offset(0) as u64, 0, &mut offset);
+            measure::format_fixed(calibration::scale_permille(0) as u64, 3, &mut scale);
+            let mut len = 0;
+            for part in &[&b"saved off "[..], trim(&offset), &b" x"[..], trim(&scale)] {
+                line[len..len + part.len()].copy_from_slice(part);
+                len += part.len();
+            }
+            status::print(&line[..len], 0);
+            console::log(b"calibration saved");
+        }
+        Err(_) => {
+            status::print(b"cal: flash error", 0);
+            console::log(b"cal flash error");
+        }
+    }
+}
+
+// a right-aligned number, without its leading spaces
+fn trim(text: &[u8]) -> &[u8] {
+    let start = text.iter().position(|&c| c != b' ').unwrap_or(text.len());
+    &text[start..]
+}
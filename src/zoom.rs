@@ -0,0 +1,159 @@
+// stm32f3-oscilloscope - src/zoom.rs
+// zoom (delayed sweep) display: the whole record in a strip, plus an expanded window
+
+// Copyright © 2017 Sean Bolton
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be
+// included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+// EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE
+// LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+// OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION
+// WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// The screen is split into two regions while zoomed:
+// - the overview strip at the top shows the entire record, compressed vertically, and for
+//   records longer than the screen is wide, compressed horizontally by drawing the minimum to
+//   maximum of the samples in each column. A bar underneath marks the part of the record shown
+//   in the main area.
+// - the main area shows the window of the record starting at `position`, expanded
+//   horizontally by `magnification`, using the same interpolation as the fast timebases.
+
+use calibration;
+use interp;
+use interp::Interpolation;
+use screen;
+use st7735::*;
+
+const STRIP_TOP: i16 = 0;
+const STRIP_HEIGHT: i16 = 24;
+const BAR_Y: i16 = STRIP_TOP + STRIP_HEIGHT + 1;
+const MAIN_TOP: i16 = BAR_Y + 3;
+
+/// The available magnifications, in pixels per sample.
+pub const MAGNIFICATIONS: [usize; 4] = [1, 2, 5, 10];
+
+// the extent of the previous plot in each column of the strip, for erasing
+static mut STRIP_TOP_Y: [u8; screen::MAX_WIDTH] = [255; screen::MAX_WIDTH];
+static mut STRIP_BOTTOM_Y: [u8; screen::MAX_WIDTH] = [255; screen::MAX_WIDTH];
+static mut MAIN_Y: [u8; screen::MAX_WIDTH] = [255; screen::MAX_WIDTH];
+
+// the width of the screen, in the current orientation
+fn width() -> usize {
+    screen::width() as usize
+}
+
+// the height of the main area, which stops above the status line
+fn main_height() -> i16 {
+    screen::trace_height() - MAIN_TOP
+}
+
+/// Returns the number of samples shown in the main area at the given magnification.
+pub fn window_width(magnification: usize) -> usize {
+    width() / magnification
+}
+
+/// Returns the window position following `position`, stepping half a window (or a sixteenth of
+/// a long record) at a time and wrapping back to the start of a record of `len` samples.
+pub fn next_position(position: usize, magnification: usize, len: usize) -> usize {
+    let width = window_width(magnification);
+    let step = if len / 16 > width / 2 { len / 16 } else { width / 2 };
+    let next = position + step;
+    if next >= len { 0 } else { clamp_position(next, magnification, len) }
+}
+
+/// Returns `position`, moved if needed so that the window lies within a record of `len` samples.
+pub fn clamp_position(position: usize, magnification: usize, len: usize) -> usize {
+    let width = window_width(magnification);
+    if position + width > len { len - width } else { position }
+}
+
+/// Clears the trace area in preparation for zoomed display.
+pub fn enter() {
+    screen::clear_trace_area();
+    unsafe {
+        STRIP_TOP_Y = [255; screen::MAX_WIDTH];
+        STRIP_BOTTOM_Y = [255; screen::MAX_WIDTH];
+        MAIN_Y = [255; screen::MAX_WIDTH];
+    }
+}
+
+// scale a raw 12-bit conversion into a region `height` pixels high starting at `top`
+fn scale_y(raw_conversion: u16, top: i16, height: i16) -> u8 {
+    let y = (calibration::microvolts(raw_conversion) as u64 * height as u64 /
+             calibration::full_scale_microvolts() as u64) as i32;
+    let y = if y >= height as i32 { height - 1 } else { y as i16 };
+    (top + height - 1 - y) as u8
+}
+
+fn replot(previous: &mut u8, x: usize, y: Option<u8>, color: St7735Color) {
+    if *previous < 255 {
+        st7735_drawPixel(x as i16, *previous as i16, St7735Color::Black as u16);
+    }
+    match y {
+        Some(y) => {
+            st7735_drawPixel(x as i16, y as i16, color as u16);
+            *previous = y;
+        }
+        None => *previous = 255,
+    }
+}
+
+/// Draws the overview strip and the magnified window from a completed record.
+pub fn draw(data: &[u16], position: usize, magnification: usize, interpolation: Interpolation) {
+    let strip_top_y = unsafe { &mut STRIP_TOP_Y };
+    let strip_bottom_y = unsafe { &mut STRIP_BOTTOM_Y };
+    let main_y = unsafe { &mut MAIN_Y };
+    let len = data.len();
+    let (width, main_height) = (width(), main_height());
+
+    for x in 0..width {
+        // overview strip
+        if strip_top_y[x] < 255 {
+            st7735_drawFastVLine(x as i16, strip_top_y[x] as i16,
+                                 (strip_bottom_y[x] - strip_top_y[x] + 1) as i16,
+                                 St7735Color::Black as u16);
+            strip_top_y[x] = 255;
+        }
+        let first = x * len / width;
+        let last = (x + 1) * len / width; // exclusive
+        if first < len {
+            let mut min = data[first];
+            let mut max = min;
+            for &sample in &data[first..if last > first { last } else { first + 1 }] {
+                if sample < min { min = sample; }
+                if sample > max { max = sample; }
+            }
+            let top = scale_y(max, STRIP_TOP, STRIP_HEIGHT);
+            let bottom = scale_y(min, STRIP_TOP, STRIP_HEIGHT);
+            st7735_drawFastVLine(x as i16, top as i16, (bottom - top + 1) as i16,
+                                 St7735Color::Blue as u16);
+            strip_top_y[x] = top;
+            strip_bottom_y[x] = bottom;
+        }
+
+        // magnified window; passing the whole record lets the interpolation use the real
+        // samples either side of the window
+        let y = interp::value_at(data, position * magnification + x, magnification, interpolation)
+                .map(|raw| scale_y(raw, MAIN_TOP, main_height));
+        replot(&mut main_y[x], x, y, St7735Color::White);
+    }
+
+    // mark the window position under the strip
+    let bar_x = position * width / len;
+    let bar_width = window_width(magnification) * width / len;
+    st7735_fill_rect(0, BAR_Y, width as i16, 2, St7735Color::Black as u16);
+    st7735_fill_rect(bar_x as i16, BAR_Y, if bar_width > 0 { bar_width as i16 } else { 1 }, 2,
+                     St7735Color::Green as u16);
+}
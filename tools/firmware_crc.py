@@ -0,0 +1,59 @@
+#!/usr/bin/env python3
+# stm32f3-oscilloscope - tools/firmware_crc.py
+# record the CRC32 of a built image in it, for the check at startup (see src/integrity.rs)
+#
+# Usage: tools/firmware_crc.py target/thumbv7em-none-eabihf/release/stm32f3-oscilloscope
+#
+# This adds a .firmware_crc section, holding the CRC, at the _firmware_crc address, writing the
+# result next to the input with a "-crc" suffix, ready to be flashed. Running it again on its
+# own output just gives the same result.
+#
+# Copyright © 2017 Sean Bolton. MIT licensed; see the LICENSE file.
+
+import os
+import struct
+import subprocess
+import sys
+import tempfile
+import zlib
+
+OBJCOPY = os.environ.get('OBJCOPY', 'arm-none-eabi-objcopy')
+NM = os.environ.get('NM', 'arm-none-eabi-nm')
+SECTION = '.firmware_crc'
+
+
+def symbol_address(elf, name):
+    output = subprocess.check_output([NM, elf]).decode()
+    for line in output.splitlines():
+        fields = line.split()
+        if len(fields) == 3 and fields[2] == name:
+            return int(fields[0], 16)
+    sys.exit('%s: no %s symbol; is memory.x up to date?' % (elf, name))
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit('usage: %s ELF' % sys.argv[0])
+    elf = sys.argv[1]
+    address = symbol_address(elf, '_firmware_crc')
+    with tempfile.TemporaryDirectory() as tmp:
+        # the image as it is in flash, from the start of flash to the end of .data's initial
+        # values, with the gaps between sections as erased flash
+        image = os.path.join(tmp, 'image.bin')
+        subprocess.check_call([OBJCOPY, '-O', 'binary', '--gap-fill', '0xff',
+                               '--remove-section', SECTION, elf, image])
+        with open(image, 'rb') as f:
+            crc = zlib.crc32(f.read()) & 0xffffffff
+        crc_bin = os.path.join(tmp, 'crc.bin')
+        with open(crc_bin, 'wb') as f:
+            f.write(struct.pack('<I', crc))
+        subprocess.check_call([OBJCOPY, '--remove-section', SECTION,
+                               '--add-section', '%s=%s' % (SECTION, crc_bin),
+                               '--set-section-flags', '%s=alloc,load,readonly,data' % SECTION,
+                               '--change-section-address', '%s=0x%08x' % (SECTION, address),
+                               elf, elf + '-crc'])
+    print('CRC32 %08x at 0x%08x, in %s' % (crc, address, elf + '-crc'))
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,185 @@
+#!/usr/bin/env python3
+# stm32f3-oscilloscope - tools/golden_screens.py
+# rendering regression tests: screenshots of known signals, compared with stored references
+#
+# Usage: tools/golden_screens.py /dev/ttyUSB0 [--update] [case ...]
+#
+# This runs on the board, so it covers the analog path and the LCD as well as the rendering;
+# `host golden` (see docs/host-build.rst) checks the rendering alone, exactly, from fixed
+# captures replayed on the host, with its references in tools/golden/host/. Each case
+# is a list of shell commands setting up a known signal, from the siggen looped back to the
+# input (PA4 to the input, as for the selftest command), and the display settings; then a
+# screenshot is taken (see src/export.rs) and compared with the reference in
+# tools/golden/<case>.ppm. --update records the references instead, to be checked by eye and
+# committed after a change that is meant to alter the rendering.
+#
+# A looped-back signal is a little noisy, so a few pixels differ from sweep to sweep; a case
+# fails when more than TOLERANCE of them do, and its screenshot is written next to the
+# reference, with a .new.ppm suffix, for comparing.
+#
+# Needs pyserial. Copyright © 2017 Sean Bolton. MIT licensed; see the LICENSE file.
+
+import os
+import struct
+import sys
+import time
+
+import serial
+
+BAUD = 115200
+PROMPT = b'> '
+GOLDEN = os.path.join(os.path.dirname(os.path.abspath(__file__)), 'golden')
+TOLERANCE = 0.01  # the share of pixels that may differ from the reference
+SETTLE_S = 1.0  # time for the settings to take and a few sweeps to be drawn
+
+SETUP = ['trig mode normal', 'trig slope rising', 'trig level 1.65', 'set fps max']
+
+CASES = {
+    'sine-1k-trace': ['siggen sine 1000', 'set display trace', 'set interp linear',
+                      'set timebase .5ms'],
+    'sine-1k-dots': ['siggen sine 1000', 'set display trace', 'set interp dots',
+                     'set timebase .5ms'],
+    'sine-10k-sinc': ['siggen sine 10000', 'set display trace', 'set interp sin(x)/x',
+                      'set timebase 20us'],
+    'sine-1k-histogram': ['siggen sine 1000', 'set display histogram', 'set timebase .5ms'],
+    'sine-1k-meter': ['siggen sine 1000', 'set display meter', 'set timebase .5ms'],
+}
+
+SYNC = b'\xa5\x5a'
+SCREENSHOT_HEADER = 0x20
+SCREENSHOT_ROW = 0x21
+
+
+def crc16(data, crc=0xffff):
+    # CRC-16/CCITT-FALSE, as in src/frame.rs
+    for byte in data:
+        crc ^= byte << 8
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x1021 if crc & 0x8000 else crc << 1) & 0xffff
+    return crc
+
+
+def read_exact(port, n):
+    data = port.read(n)
+    if len(data) != n:
+        sys.exit('timed out reading from %s' % port.port)
+    return data
+
+
+def read_until_prompt(port):
+    reply = port.read_until(PROMPT)
+    if not reply.endswith(PROMPT):
+        sys.exit('no prompt from %s' % port.port)
+    return reply[:-len(PROMPT)]
+
+
+def command(port, line):
+    port.write(line.encode() + b'\r')
+    reply = read_until_prompt(port).decode(errors='replace')
+    # the reply starts with the echoed command
+    lines = [l for l in reply.splitlines()[1:] if l.strip()]
+    if lines:
+        sys.exit('%s: %s' % (line, ' / '.join(lines)))
+
+
+def read_frame(port):
+    # skip anything before the sync word, then check the CRC before trusting the frame
+    while True:
+        if read_exact(port, 1) == SYNC[:1] and read_exact(port, 1) == SYNC[1:]:
+            break
+    header = read_exact(port, 3)
+    frame_type, length = header[0], struct.unpack('<H', header[1:])[0]
+    payload = read_exact(port, length)
+    crc = struct.unpack('<H', read_exact(port, 2))[0]
+    if crc16(header + payload) != crc:
+        sys.exit('bad frame CRC; try again')
+    return frame_type, payload
+
+
+def screenshot(port):
+    port.write(b'screenshot\r')
+    frame_type, payload = read_frame(port)
+    if frame_type != SCREENSHOT_HEADER:
+        sys.exit('expected a screenshot header, got frame type 0x%02x' % frame_type)
+    width, height = struct.unpack('<HH', payload)
+    rows = [None] * height
+    for _ in range(height):
+        frame_type, payload = read_frame(port)
+        y = struct.unpack('<H', payload[:2])[0]
+        if frame_type != SCREENSHOT_ROW or y >= height:
+            sys.exit('bad screenshot row')
+        rows[y] = payload[2:]
+    read_until_prompt(port)
+    return width, height, b''.join(rows)
+
+
+def read_ppm(path):
+    with open(path, 'rb') as f:
+        data = f.read()
+    # the header is as write_ppm() writes it: four fields, each ending in a newline or space,
+    # so the pixels start after the fourth, even if the first pixel's bytes look like spaces
+    fields, start = [], 0
+    for _ in range(4):
+        end = min(i for i in (data.find(b'\n', start), data.find(b' ', start)) if i >= 0)
+        fields.append(data[start:end])
+        start = end + 1
+    if fields[0] != b'P6' or fields[3] != b'255':
+        sys.exit('%s: not an 8-bit binary PPM' % path)
+    return int(fields[1]), int(fields[2]), data[start:]
+
+
+def write_ppm(path, width, height, pixels):
+    with open(path, 'wb') as f:
+        f.write(b'P6\n%d %d\n255\n' % (width, height))
+        f.write(pixels)
+
+
+def differing_pixels(a, b):
+    return sum(1 for i in range(0, len(a), 3) if a[i:i + 3] != b[i:i + 3])
+
+
+def main():
+    args = [a for a in sys.argv[1:] if a != '--update']
+    update = len(args) != len(sys.argv) - 1
+    if not args:
+        sys.exit('usage: %s PORT [--update] [case ...]\ncases: %s' %
+                 (sys.argv[0], ' '.join(sorted(CASES))))
+    names = args[1:] or sorted(CASES)
+    for name in names:
+        if name not in CASES:
+            sys.exit('no case %s' % name)
+    os.makedirs(GOLDEN, exist_ok=True)
+    port = serial.Serial(args[0], BAUD, timeout=5)
+    port.write(b'\r')
+    read_until_prompt(port)
+    failed = 0
+    for name in names:
+        for line in SETUP + CASES[name]:
+            command(port, line)
+        time.sleep(SETTLE_S)
+        width, height, pixels = screenshot(port)
+        reference = os.path.join(GOLDEN, name + '.ppm')
+        if update:
+            write_ppm(reference, width, height, pixels)
+            print('%s: recorded' % name)
+            continue
+        if not os.path.exists(reference):
+            print('%s: no reference; run with --update' % name)
+            failed += 1
+            continue
+        ref_width, ref_height, ref_pixels = read_ppm(reference)
+        if (ref_width, ref_height) != (width, height):
+            diff = width * height
+        else:
+            diff = differing_pixels(pixels, ref_pixels)
+        if diff > TOLERANCE * width * height:
+            write_ppm(os.path.join(GOLDEN, name + '.new.ppm'), width, height, pixels)
+            print('%s: FAIL, %d pixels differ' % (name, diff))
+            failed += 1
+        else:
+            print('%s: pass, %d pixels differ' % (name, diff))
+    sys.exit(1 if failed else 0)
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,54 @@
+#!/usr/bin/env python3
+# stm32f3-oscilloscope - tools/qemu_tests.py
+# run the QEMU build's checks under qemu-system-arm, as cargo's runner (see src/qemu/main.rs)
+#
+# Usage: tools/qemu_tests.py [cargo options]
+#        tools/qemu_tests.py ELF
+#
+# Without an ELF, this runs `cargo run --features qemu --bin qemu-tests`, with the cargo
+# options given, setting itself as the runner for the build's target. Given the ELF cargo
+# built, it starts it on QEMU's mps2-an385 machine, a Cortex-M3, with semihosting for the
+# checks' report and exit status. It exits with the checks' status, so 0 only if all passed,
+# or fails if they haven't finished within TIMEOUT seconds, as after a panic or fault.
+#
+# Copyright © 2017 Sean Bolton. MIT licensed; see the LICENSE file.
+
+import os
+import subprocess
+import sys
+
+QEMU = os.environ.get('QEMU', 'qemu-system-arm')
+TARGET = 'thumbv7m-none-eabi'
+TIMEOUT = 60
+
+
+def run_checks(elf):
+    command = [QEMU, '-cpu', 'cortex-m3', '-machine', 'mps2-an385', '-nographic',
+               '-monitor', 'none', '-serial', 'none',
+               '-semihosting-config', 'enable=on,target=native', '-kernel', elf]
+    try:
+        return subprocess.call(command, timeout=TIMEOUT)
+    except subprocess.TimeoutExpired:
+        print('qemu_tests: the checks didn\'t finish within {}s'.format(TIMEOUT),
+              file=sys.stderr)
+        return 1
+
+
+def run_cargo(options):
+    runner = 'CARGO_TARGET_{}_RUNNER'.format(TARGET.upper().replace('-', '_'))
+    env = dict(os.environ, **{runner: os.path.abspath(__file__)})
+    command = ['cargo', 'run', '--features', 'qemu', '--bin', 'qemu-tests',
+               '--target', TARGET] + options
+    return subprocess.call(command, env=env)
+
+
+def main():
+    args = sys.argv[1:]
+    # as the runner, cargo gives the ELF's path, and nothing else
+    if len(args) == 1 and os.path.isfile(args[0]):
+        return run_checks(args[0])
+    return run_cargo(args)
+
+
+if __name__ == '__main__':
+    sys.exit(main())